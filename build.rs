@@ -0,0 +1,80 @@
+// Without the shaderc feature shaders are loaded as SPIR-V, so every GLSL
+// shader in src/shaders is compiled with glslc into a .spv file next to it,
+// which is what `RenderContext` loads and release builds ship. With shaderc
+// they're compiled at runtime and there's nothing to do
+use std::path::Path;
+use std::process::Command;
+
+// glslc can't tell a shader's stage from its source, and the file names don't
+// always say it either. Matches the `ShaderKind` each shader is loaded with
+const SHADER_STAGES: &[(&str, &str)] = &[
+    ("bloom_downsample", "frag"),
+    ("bloom_upsample", "frag"),
+    ("cull", "comp"),
+    ("debug_fragment", "frag"),
+    ("debug_vertex", "vert"),
+    ("depth_pyramid", "comp"),
+    ("depth_pyramid_msaa", "comp"),
+    ("equirect_to_cube", "comp"),
+    ("fragment", "frag"),
+    ("fullscreen", "vert"),
+    ("gbuffer_fragment", "frag"),
+    ("light_cull", "comp"),
+    ("post_chromatic_aberration", "comp"),
+    ("post_fxaa", "comp"),
+    ("post_vignette", "comp"),
+    ("shadow", "vert"),
+    ("shadow_skinned", "vert"),
+    ("skinned_vertex", "vert"),
+    ("skybox_fragment", "frag"),
+    ("skybox_vertex", "vert"),
+    ("ssao", "comp"),
+    ("ssao_blur", "comp"),
+    ("taa_resolve", "comp"),
+    ("tonemap", "frag"),
+    ("vertex", "vert"),
+    ("vertex_pulling", "vert"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if std::env::var_os("CARGO_FEATURE_SHADERC").is_some() {
+        return;
+    }
+
+    // Set to use a glslc that isn't on the path, e.g. the Vulkan SDK's
+    println!("cargo:rerun-if-env-changed=GLSLC");
+    let glslc = std::env::var("GLSLC").unwrap_or_else(|_| "glslc".to_string());
+
+    let shader_dir = Path::new("src/shaders");
+    let entries = std::fs::read_dir(shader_dir).expect("failed to read shader directory");
+    for entry in entries {
+        let path = entry.expect("failed to read shader directory").path();
+        if path.extension().is_none_or(|x| x != "glsl") {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let name = path.file_stem().unwrap().to_string_lossy();
+        let Some((_, stage)) = SHADER_STAGES.iter().find(|(x, _)| *x == name) else {
+            panic!("no stage for {}, add it to SHADER_STAGES", path.display());
+        };
+
+        let output = path.with_extension("spv");
+        let status = Command::new(&glslc)
+            .arg(format!("-fshader-stage={}", stage))
+            .arg(&path)
+            .arg("-o")
+            .arg(&output)
+            .status()
+            .unwrap_or_else(|error| {
+                panic!(
+                    "failed to run {} ({}), install it or build with the shaderc feature",
+                    glslc, error
+                )
+            });
+        if !status.success() {
+            panic!("failed to compile {}", path.display());
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use ash::vk;
+use gilrs::Gilrs;
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use tracing_subscriber::EnvFilter;
+use vulka::camera::{CameraAction, FlyCameraController};
+use vulka::input::{
+    AnalogFilter, BindingProfile, Chord, Composite, GamepadConfigs, GamepadConnectionEvent,
+    InputClock, InputRouter, Modifier, ResponseCurve, TextInput,
+};
+use vulka::input::{GamepadControl, InputControl, MouseControl};
+use vulka::lights::{Light, LightManager, PointLight, SpotLight};
+use vulka::model::ModelData;
+use vulka::render_world::{Renderable, RenderableId, TransformHandle};
+use vulka::window_controller::WindowController;
+use vulka::{Config, ModelId, Renderer};
+use winit::dpi::LogicalSize;
+use winit::event::{self, ElementState, MouseButton};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{Key, KeyCode, NamedKey, PhysicalKey};
+use winit::window::WindowBuilder;
+
+const GAMEPAD_CONFIG_PATH: &str = "gamepads.toml";
+const BINDINGS_PATH: &str = "bindings.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Action {
+    Confirm,
+    ToggleFullscreen,
+    Camera(CameraAction),
+}
+
+// Used when there's no bindings file yet. F10 saves the current bindings
+// so they can be edited
+fn default_bindings() -> BindingProfile<InputControl, Action> {
+    let mut profile = BindingProfile::new();
+    profile
+        .bind(PhysicalKey::Code(KeyCode::Space).into(), Action::Confirm)
+        .bind(
+            MouseControl::Button(MouseButton::Left).into(),
+            Action::Confirm,
+        )
+        .bind(
+            GamepadControl::Button(gilrs::Button::South).into(),
+            Action::Confirm,
+        )
+        .bind_composite(
+            Action::Camera(CameraAction::Move),
+            Composite::Digital {
+                up: PhysicalKey::Code(KeyCode::KeyW).into(),
+                down: PhysicalKey::Code(KeyCode::KeyS).into(),
+                left: PhysicalKey::Code(KeyCode::KeyA).into(),
+                right: PhysicalKey::Code(KeyCode::KeyD).into(),
+            },
+        )
+        .bind_composite(
+            Action::Camera(CameraAction::MoveStick),
+            Composite::Axes {
+                x: GamepadControl::Axis(gilrs::Axis::LeftStickX).into(),
+                y: GamepadControl::Axis(gilrs::Axis::LeftStickY).into(),
+            },
+        )
+        .bind_composite(
+            Action::Camera(CameraAction::LookStick),
+            Composite::Axes {
+                x: GamepadControl::Axis(gilrs::Axis::RightStickX).into(),
+                y: GamepadControl::Axis(gilrs::Axis::RightStickY).into(),
+            },
+        )
+        .bind(
+            PhysicalKey::Code(KeyCode::KeyE).into(),
+            Action::Camera(CameraAction::Rise),
+        )
+        .bind(
+            PhysicalKey::Code(KeyCode::KeyQ).into(),
+            Action::Camera(CameraAction::Fall),
+        )
+        .bind(
+            MouseControl::Delta.into(),
+            Action::Camera(CameraAction::Look),
+        );
+    profile
+}
+
+// A spiral of colored point lights around the origin and a spot light from
+// above, for trying out clustered lighting
+fn add_demo_lights(lights: &mut LightManager) {
+    let count = 256;
+    for i in 0..count {
+        let t = i as f32 / count as f32;
+        let angle = t * 12.0 * std::f32::consts::TAU;
+        let distance = 1.0 + 3.0 * t;
+        let hue = t * std::f32::consts::TAU;
+        let color = Vec3::new(hue.cos(), (hue + 2.1).cos(), (hue + 4.2).cos()) * 0.5 + 0.5;
+        lights.insert(Light::Point(PointLight {
+            position: Vec3::new(angle.cos() * distance, angle.sin() * distance, 0.25),
+            color: color * 2.0,
+            radius: 1.0,
+        }));
+    }
+
+    lights.insert(Light::Spot(SpotLight {
+        position: Vec3::new(0.0, 0.0, 3.0),
+        direction: Vec3::NEG_Z,
+        color: Vec3::splat(20.0),
+        radius: 6.0,
+        inner_angle: 15_f32.to_radians(),
+        outer_angle: 25_f32.to_radians(),
+    }));
+}
+
+// The cube stands in for the model until it's loaded, and is removed then.
+// Returns whichever is shown
+fn update_placeholder(
+    renderer: &mut Renderer,
+    model: Option<ModelId>,
+    cube: &mut Option<ModelId>,
+) -> ModelId {
+    match (model, *cube) {
+        (Some(model), Some(id)) if !renderer.model_instances(model).is_empty() => {
+            renderer.remove_model(id);
+            *cube = None;
+            model
+        }
+        (_, Some(id)) => id,
+        (Some(model), None) => model,
+        (None, None) => unreachable!("the cube is only removed for the model"),
+    }
+}
+
+// Points the ring's copies at the shown model's first mesh, which changes
+// when the loaded model replaces the placeholder cube
+fn update_ring(
+    renderer: &mut Renderer,
+    model: ModelId,
+    ring: &[TransformHandle],
+    renderables: &mut Vec<RenderableId>,
+) {
+    let Some(first) = renderer.model_instances(model).first() else {
+        return;
+    };
+    let first = *renderer
+        .world()
+        .renderable(first.renderable)
+        .expect("model renderable was removed");
+
+    let world = renderer.world_mut();
+    let current = renderables.first().and_then(|x| world.renderable(*x));
+    if current.is_some_and(|x| x.mesh == first.mesh) {
+        return;
+    }
+    for renderable in renderables.drain(..) {
+        world.remove(renderable);
+    }
+    renderables.extend(ring.iter().map(|transform| {
+        world.add(Renderable {
+            transform: *transform,
+            ..first
+        })
+    }));
+}
+
+fn main() {
+    // Verbosity is set with RUST_LOG, e.g. `RUST_LOG=vulka::gpu=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = Config::from_args(std::env::args().skip(1));
+
+    let clock = InputClock::new(Instant::now());
+    let event_loop = EventLoop::new().expect("failed to create event loop");
+
+    event_loop.set_control_flow(ControlFlow::Poll);
+
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_inner_size(LogicalSize::new(config.width, config.height))
+            .with_title("vulka")
+            .with_resizable(true)
+            .with_decorations(true)
+            .build(&event_loop)
+            .expect("failed to create window"),
+    );
+
+    let mesh_import_settings = config.mesh_import_settings();
+    let model_path = config.model_path.clone();
+    let mut renderer = Renderer::builder().config(config).build(window.clone());
+    let mut cube = Some(renderer.add_model(ModelData::cube(&mesh_import_settings)));
+    let model = model_path.map(|x| renderer.load_model(x));
+
+    let mut text_input = TextInput::new(window.clone());
+    let mut window_controller = WindowController::new(window.clone());
+    let mut input = InputRouter::new(
+        clock.clone(),
+        Gilrs::new().unwrap(),
+        GamepadConfigs::load(GAMEPAD_CONFIG_PATH),
+        4,
+    );
+    let input_manager = input.manager_mut();
+
+    BindingProfile::load(BINDINGS_PATH)
+        .unwrap_or_else(default_bindings)
+        .apply(input_manager.active_context_mut());
+
+    // Chords aren't part of binding profiles
+    input_manager.set_chord_action(
+        PhysicalKey::Code(KeyCode::Enter).into(),
+        Chord::new().modifier(Modifier::Alt),
+        Action::ToggleFullscreen,
+        None,
+    );
+
+    input_manager.set_coalescing(true);
+    input_manager.on_action(Action::Confirm, |event| debug!("{:?}", event));
+
+    let camera_events = input_manager.subscribe(None);
+    let mut camera_controller = FlyCameraController::new(2.0, 0.002, 2.5);
+    let mut last_frame = Instant::now();
+    let start = Instant::now();
+
+    // A ring of small copies of the model's first mesh, orbiting it
+    let ring = (0..8)
+        .map(|_| renderer.world_mut().add_transform(Mat4::IDENTITY))
+        .collect::<Vec<_>>();
+    let mut ring_renderables = vec![];
+
+    // The window title doubles as a stats HUD while it's on
+    let mut show_stats = false;
+    let mut last_stats_update = Instant::now();
+
+    let gamepad_analog = input.gamepad_analog_mut();
+    let stick_filter = AnalogFilter::new(0.15, 0.95, ResponseCurve::Exponential(2.0));
+    gamepad_analog.set_radial_filter(
+        gilrs::Axis::LeftStickX,
+        gilrs::Axis::LeftStickY,
+        stick_filter,
+    );
+    gamepad_analog.set_radial_filter(
+        gilrs::Axis::RightStickX,
+        gilrs::Axis::RightStickY,
+        stick_filter,
+    );
+
+    event_loop
+        .run(move |event, target| match event {
+            // Escape and focus loss release a grabbed cursor before anything
+            // else sees them, and keyboard input goes to text entry while
+            // it's active
+            event::Event::WindowEvent { event, .. }
+                if input.handle_cursor_event(&event) || text_input.handle_window_event(&event) => {}
+            event::Event::WindowEvent { event, .. } => {
+                input.handle_window_event(&event);
+                match event {
+                    event::WindowEvent::CloseRequested => target.exit(),
+                    event::WindowEvent::KeyboardInput { event, .. } => {
+                        let escape = event.logical_key == Key::Named(NamedKey::Escape);
+                        let pressed = event.state == ElementState::Pressed;
+                        let dump = pressed && event.logical_key == Key::Named(NamedKey::F1);
+                        let frame_dump = pressed && event.logical_key == Key::Named(NamedKey::F2);
+                        let toggle_msaa = pressed && event.logical_key == Key::Named(NamedKey::F3);
+                        let next_tonemap = pressed && event.logical_key == Key::Named(NamedKey::F4);
+                        let toggle_pcf = pressed && event.logical_key == Key::Named(NamedKey::F5);
+                        let toggle_lights =
+                            pressed && event.logical_key == Key::Named(NamedKey::F6);
+                        let toggle_stats = pressed && event.logical_key == Key::Named(NamedKey::F7);
+                        let toggle_occlusion =
+                            pressed && event.logical_key == Key::Named(NamedKey::F8);
+                        let toggle_debug_overlay =
+                            pressed && event.logical_key == Key::Named(NamedKey::F9);
+                        let save_bindings =
+                            pressed && event.logical_key == Key::Named(NamedKey::F10);
+                        let toggle_vertex_pulling =
+                            pressed && event.logical_key == Key::Named(NamedKey::F11);
+                        let toggle_depth_prepass =
+                            pressed && event.logical_key == Key::Named(NamedKey::F12);
+                        let exposure_scale = match event.logical_key.as_ref() {
+                            Key::Character("[") if pressed => Some(0.5),
+                            Key::Character("]") if pressed => Some(2.0),
+                            _ => None,
+                        };
+
+                        if dump {
+                            print!("{}", input.manager().debug_dump());
+                        }
+                        if save_bindings {
+                            match BindingProfile::from_context(input.manager().active_context())
+                                .save(BINDINGS_PATH)
+                            {
+                                Ok(()) => info!("saved bindings to {}", BINDINGS_PATH),
+                                Err(error) => warn!("failed to save bindings: {}", error),
+                            }
+                        }
+                        if frame_dump {
+                            renderer.request_frame_dump("frame");
+                        }
+                        if toggle_msaa {
+                            let samples = if renderer.msaa_samples() == vk::SampleCountFlags::TYPE_1
+                            {
+                                vk::SampleCountFlags::TYPE_4
+                            } else {
+                                vk::SampleCountFlags::TYPE_1
+                            };
+                            if renderer.supports_msaa_samples(samples) {
+                                renderer.set_msaa_samples(samples);
+                            }
+                        }
+
+                        if next_tonemap {
+                            let operator = renderer.tonemap_operator().next();
+                            info!(?operator, "tonemap operator");
+                            renderer.set_tonemap_operator(operator);
+                        }
+                        if toggle_pcf {
+                            let shadow_pcf = !renderer.shadow_pcf();
+                            info!(shadow_pcf);
+                            renderer.set_shadow_pcf(shadow_pcf);
+                        }
+                        if toggle_lights {
+                            let lights = renderer.lights_mut();
+                            if lights.is_empty() {
+                                add_demo_lights(lights);
+                            } else {
+                                lights.clear();
+                            }
+                            info!(lights = lights.len());
+                        }
+                        if toggle_stats {
+                            show_stats = !show_stats;
+                            if show_stats {
+                                println!("{}", renderer.frame_stats().report());
+                            } else {
+                                window.set_title("vulka");
+                            }
+                        }
+                        // Cycles through no occlusion culling, the queries and
+                        // Hi-Z
+                        if toggle_occlusion {
+                            let (occlusion_culling, hi_z_culling) =
+                                match (renderer.occlusion_culling(), renderer.hi_z_culling()) {
+                                    (false, false) => (true, false),
+                                    (true, _) => (false, true),
+                                    (false, true) => (false, false),
+                                };
+                            info!(occlusion_culling, hi_z_culling);
+                            renderer.set_occlusion_culling(occlusion_culling);
+                            renderer.set_hi_z_culling(hi_z_culling);
+                        }
+                        if toggle_debug_overlay {
+                            let debug_overlay = !renderer.debug_overlay();
+                            info!(debug_overlay);
+                            renderer.set_debug_overlay(debug_overlay);
+                        }
+                        if toggle_vertex_pulling && renderer.supports_vertex_pulling() {
+                            let vertex_pulling = !renderer.vertex_pulling();
+                            info!(vertex_pulling);
+                            renderer.set_vertex_pulling(vertex_pulling);
+                        }
+                        if toggle_depth_prepass {
+                            let depth_prepass = !renderer.depth_prepass();
+                            info!(depth_prepass);
+                            renderer.set_depth_prepass(depth_prepass);
+                        }
+                        if let Some(scale) = exposure_scale {
+                            let exposure = renderer.exposure() * scale;
+                            info!(exposure);
+                            renderer.set_exposure(exposure);
+                        }
+
+                        if escape {
+                            target.exit()
+                        }
+                    }
+                    event::WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        input.set_cursor_captured(&window, true);
+                    }
+                    event::WindowEvent::Resized(inner_size) => {
+                        renderer.resize(inner_size.width, inner_size.height);
+                    }
+                    event::WindowEvent::RedrawRequested => {
+                        clock.advance_frame();
+
+                        // Gamepads and holds are polled here, and events and
+                        // per-frame mouse deltas are accumulated until then
+                        input.end_frame();
+
+                        if input.manager().just_pressed(&Action::ToggleFullscreen) {
+                            window_controller.toggle_fullscreen();
+                            info!(fullscreen = ?window_controller.fullscreen());
+                            // Not every platform sends a resize for this
+                            let size = window_controller.inner_size();
+                            renderer.resize(size.width, size.height);
+                        }
+
+                        for event in input.connection_events() {
+                            info!("{:?}", event);
+                            if let GamepadConnectionEvent::Connected { player, .. } = event {
+                                let status =
+                                    input.gamepad_registry().status(input.gilrs(), *player);
+                                info!("{:?}", status);
+                            }
+                        }
+                        text_input.flush();
+
+                        // Mouse look only gets deltas while the cursor is
+                        // captured
+                        for event in camera_events.try_iter() {
+                            if let Action::Camera(action) = event.action {
+                                camera_controller.handle_input(action, event.value)
+                            }
+                        }
+
+                        let now = Instant::now();
+                        let dt = now.duration_since(last_frame).as_secs_f32();
+                        last_frame = now;
+                        camera_controller.update(renderer.camera_mut(), dt);
+
+                        let shown = update_placeholder(&mut renderer, model, &mut cube);
+                        update_ring(&mut renderer, shown, &ring, &mut ring_renderables);
+
+                        // The model spins around the Z axis
+                        let time = start.elapsed().as_secs_f32();
+                        renderer
+                            .place_model(shown, Mat4::from_rotation_z(time * 90_f32.to_radians()));
+                        for (i, transform) in ring.iter().enumerate() {
+                            let angle =
+                                time * 0.5 + i as f32 * std::f32::consts::TAU / ring.len() as f32;
+                            renderer.world_mut().set_transform(
+                                *transform,
+                                Mat4::from_translation(
+                                    Vec3::new(angle.cos(), angle.sin(), 0.0) * 3.0,
+                                ) * Mat4::from_scale(Vec3::splat(0.3)),
+                            );
+                        }
+
+                        renderer.draw_next_frame();
+
+                        if show_stats && last_stats_update.elapsed() >= Duration::from_millis(500) {
+                            let summary = renderer.frame_stats().summary();
+                            window.set_title(&format!("vulka | {}", summary));
+                            last_stats_update = Instant::now();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            event::Event::DeviceEvent { device_id, event } => {
+                input.handle_device_event(device_id, event);
+            }
+            _ => {}
+        })
+        .expect("event loop failed")
+}
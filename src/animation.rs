@@ -0,0 +1,400 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+use tracing::warn;
+
+// A node of a model's hierarchy, with its transform relative to its parent
+// when it isn't animated
+#[derive(Debug, Clone, Copy)]
+pub struct SkeletonNode {
+    pub parent: Option<usize>,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+// The joints a skinned mesh's vertices are weighted to. A vertex's joint
+// indices index `joints`, which are node indices
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    // Transform mesh space into each joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+// Every node of a glTF model, which is more than the joints, since joints can
+// be parented to other nodes, and the model's skins
+#[derive(Debug, Clone, Default)]
+pub struct Skeleton {
+    nodes: Vec<SkeletonNode>,
+    // Node indices with parents before their children
+    order: Vec<usize>,
+    skins: Vec<Skin>,
+}
+
+impl Skeleton {
+    pub fn load(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Self {
+        let mut nodes = document
+            .nodes()
+            .map(|x| {
+                let (translation, rotation, scale) = x.transform().decomposed();
+                SkeletonNode {
+                    parent: None,
+                    translation: Vec3::from_array(translation),
+                    rotation: Quat::from_array(rotation),
+                    scale: Vec3::from_array(scale),
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut children = vec![vec![]; nodes.len()];
+        for node in document.nodes() {
+            for child in node.children() {
+                nodes[child.index()].parent = Some(node.index());
+                children[node.index()].push(child.index());
+            }
+        }
+
+        let mut order = vec![];
+        let mut stack = (0..nodes.len())
+            .filter(|x| nodes[*x].parent.is_none())
+            .collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            order.push(node);
+            stack.extend(&children[node]);
+        }
+
+        let skins = document
+            .skins()
+            .map(|x| {
+                let joints = x.joints().map(|x| x.index()).collect::<Vec<_>>();
+                // Missing matrices are identities
+                let inverse_bind_matrices = match x
+                    .reader(|x| Some(&buffers[x.index()]))
+                    .read_inverse_bind_matrices()
+                {
+                    Some(matrices) => matrices.map(|x| Mat4::from_cols_array_2d(&x)).collect(),
+                    None => vec![Mat4::IDENTITY; joints.len()],
+                };
+                Skin {
+                    joints,
+                    inverse_bind_matrices,
+                }
+            })
+            .collect();
+
+        Self {
+            nodes,
+            order,
+            skins,
+        }
+    }
+
+    pub fn nodes(&self) -> &[SkeletonNode] {
+        &self.nodes
+    }
+
+    pub fn skins(&self) -> &[Skin] {
+        &self.skins
+    }
+
+    // Model space transforms of every node, given each node's local
+    // translation, rotation and scale
+    fn _global_transforms(&self, local: &[(Vec3, Quat, Vec3)], globals: &mut Vec<Mat4>) {
+        globals.clear();
+        globals.resize(self.nodes.len(), Mat4::IDENTITY);
+        for &i in &self.order {
+            let (translation, rotation, scale) = local[i];
+            let transform = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+            globals[i] = match self.nodes[i].parent {
+                Some(parent) => globals[parent] * transform,
+                None => transform,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationProperty {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // Each keyframe has an in tangent, a value and an out tangent
+    CubicSpline,
+}
+
+// Keyframes of one property of one node. Values are xyz for translations and
+// scales, and quaternions for rotations
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    pub node: usize,
+    pub property: AnimationProperty,
+    pub interpolation: Interpolation,
+    // Keyframe times in seconds, ascending
+    pub times: Vec<f32>,
+    pub values: Vec<Vec4>,
+}
+
+impl AnimationChannel {
+    // Holds the first and last keyframes before and after them
+    pub fn sample(&self, time: f32) -> Vec4 {
+        let next = self.times.partition_point(|x| *x <= time);
+        if next == 0 {
+            return self._value(0);
+        }
+        if next == self.times.len() {
+            return self._value(next - 1);
+        }
+
+        let previous = next - 1;
+        let duration = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / duration.max(1e-6);
+        let (a, b) = (self._value(previous), self._value(next));
+        let value = match self.interpolation {
+            Interpolation::Step => a,
+            Interpolation::Linear if self.property == AnimationProperty::Rotation => {
+                Vec4::from(Quat::from_vec4(a).slerp(Quat::from_vec4(b), t))
+            }
+            Interpolation::Linear => a.lerp(b, t),
+            Interpolation::CubicSpline => {
+                // Hermite spline, with the tangents scaled to the interval
+                let out_tangent = self.values[previous * 3 + 2] * duration;
+                let in_tangent = self.values[next * 3] * duration;
+                let (t2, t3) = (t * t, t * t * t);
+                a * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * (t3 - 2.0 * t2 + t)
+                    + b * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * (t3 - t2)
+            }
+        };
+        match self.property {
+            AnimationProperty::Rotation => value.normalize(),
+            _ => value,
+        }
+    }
+
+    fn _value(&self, keyframe: usize) -> Vec4 {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[keyframe * 3 + 1],
+            _ => self.values[keyframe],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    // Time of the last keyframe of any channel
+    pub duration: f32,
+    pub channels: Vec<AnimationChannel>,
+}
+
+impl AnimationClip {
+    // Morph target weights aren't supported and are skipped
+    pub fn load(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Self {
+        use gltf::animation::util::ReadOutputs;
+
+        let mut channels = vec![];
+        for channel in animation.channels() {
+            let reader = channel.reader(|x| Some(&buffers[x.index()]));
+            let (Some(times), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+                warn!(
+                    "skipping channel without keyframes in animation {}",
+                    animation.index()
+                );
+                continue;
+            };
+            let times = times.collect::<Vec<_>>();
+            let (property, values) = match outputs {
+                ReadOutputs::Translations(x) => (
+                    AnimationProperty::Translation,
+                    x.map(|x| Vec3::from_array(x).extend(0.0)).collect(),
+                ),
+                ReadOutputs::Rotations(x) => (
+                    AnimationProperty::Rotation,
+                    x.into_f32().map(Vec4::from_array).collect(),
+                ),
+                ReadOutputs::Scales(x) => (
+                    AnimationProperty::Scale,
+                    x.map(|x| Vec3::from_array(x).extend(0.0))
+                        .collect::<Vec<_>>(),
+                ),
+                ReadOutputs::MorphTargetWeights(_) => {
+                    warn!(
+                        "skipping morph target channel in animation {}",
+                        animation.index()
+                    );
+                    continue;
+                }
+            };
+            if times.is_empty() {
+                continue;
+            }
+
+            channels.push(AnimationChannel {
+                node: channel.target().node().index(),
+                property,
+                interpolation: match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                },
+                times,
+                values,
+            });
+        }
+
+        Self {
+            name: animation.name().map(|x| x.to_string()),
+            duration: channels
+                .iter()
+                .filter_map(|x| x.times.last().copied())
+                .fold(0.0, f32::max),
+            channels,
+        }
+    }
+}
+
+// Plays a model's animation clips on its skeleton. `update` is meant to be
+// called every frame, after which `joint_matrices` gives each skin's pose for
+// the vertex shader
+pub struct AnimationPlayer {
+    skeleton: Skeleton,
+    clips: Vec<AnimationClip>,
+    clip: Option<usize>,
+    time: f32,
+    speed: f32,
+    looping: bool,
+    paused: bool,
+    // Model space transform of each node as of the last update
+    globals: Vec<Mat4>,
+}
+
+impl AnimationPlayer {
+    // Starts playing the first clip, if there is one, on a loop
+    pub fn new(skeleton: Skeleton, clips: Vec<AnimationClip>) -> Self {
+        let mut player = Self {
+            skeleton,
+            clip: (!clips.is_empty()).then_some(0),
+            clips,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            paused: false,
+            globals: vec![],
+        };
+        player._pose();
+        player
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    pub fn clip(&self) -> Option<usize> {
+        self.clip
+    }
+
+    // Starts the clip from the beginning. None shows the rest pose
+    pub fn play(&mut self, clip: Option<usize>) {
+        assert!(clip.is_none_or(|x| x < self.clips.len()), "unknown clip");
+        self.clip = clip;
+        self.time = 0.0;
+        self._pose();
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+        self._pose();
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // Negative speeds play backwards
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    // Clips that don't loop hold their last pose
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    // Advances the clip by `delta` seconds and samples it
+    pub fn update(&mut self, delta: f32) {
+        let Some(clip) = self.clip else {
+            return;
+        };
+        if self.paused {
+            return;
+        }
+
+        let duration = self.clips[clip].duration;
+        self.time += delta * self.speed;
+        self.time = if self.looping && duration > 0.0 {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+        self._pose();
+    }
+
+    // The skin's joint matrices, which move vertices from the bind pose to
+    // the current pose in model space
+    pub fn joint_matrices(&self, skin: usize) -> Vec<Mat4> {
+        let skin = &self.skeleton.skins[skin];
+        skin.joints
+            .iter()
+            .zip(&skin.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| self.globals[*joint] * *inverse_bind)
+            .collect()
+    }
+
+    fn _pose(&mut self) {
+        let mut local = self
+            .skeleton
+            .nodes
+            .iter()
+            .map(|x| (x.translation, x.rotation, x.scale))
+            .collect::<Vec<_>>();
+
+        if let Some(clip) = self.clip {
+            for channel in &self.clips[clip].channels {
+                let value = channel.sample(self.time);
+                let (translation, rotation, scale) = &mut local[channel.node];
+                match channel.property {
+                    AnimationProperty::Translation => *translation = value.truncate(),
+                    AnimationProperty::Rotation => *rotation = Quat::from_vec4(value),
+                    AnimationProperty::Scale => *scale = value.truncate(),
+                }
+            }
+        }
+
+        self.skeleton._global_transforms(&local, &mut self.globals);
+    }
+}
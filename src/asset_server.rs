@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+use crate::jobs::{JobHandle, JobSystem};
+use crate::mesh_optimizer::MeshImportSettings;
+use crate::model::ModelData;
+use crate::skybox::EquirectImage;
+use crate::texture_cache::ColorSpace;
+use crate::texture_file::{load_image_file, TextureData};
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// An asset whose CPU side work is done and which is ready to upload
+pub enum LoadedAsset {
+    Model(PathBuf, ModelData),
+    Environment(EquirectImage),
+    // A new version of a file watched with `AssetServer::watch_texture`
+    Texture(PathBuf, ColorSpace, TextureData),
+}
+
+// What to load, kept around so watched assets can be loaded again
+#[derive(Clone)]
+enum AssetRequest {
+    Model(PathBuf, MeshImportSettings),
+    Environment(PathBuf),
+    Texture(PathBuf, ColorSpace),
+}
+
+impl AssetRequest {
+    fn _path(&self) -> &Path {
+        match self {
+            AssetRequest::Model(path, _)
+            | AssetRequest::Environment(path)
+            | AssetRequest::Texture(path, _) => path,
+        }
+    }
+
+    // Whether both replace the same asset, so only the newest one matters.
+    // There is one environment map
+    fn _same_asset(&self, other: &AssetRequest) -> bool {
+        match (self, other) {
+            (AssetRequest::Model(a, _), AssetRequest::Model(b, _)) => a == b,
+            (AssetRequest::Environment(_), AssetRequest::Environment(_)) => true,
+            (AssetRequest::Texture(a, x), AssetRequest::Texture(b, y)) => a == b && x == y,
+            _ => false,
+        }
+    }
+
+    fn _load(self) -> Result<LoadedAsset, String> {
+        let asset = match self {
+            AssetRequest::Model(path, settings) => {
+                let data = ModelData::load(&path, &settings)?;
+                LoadedAsset::Model(path, data)
+            }
+            AssetRequest::Environment(path) => LoadedAsset::Environment(EquirectImage::load(path)?),
+            AssetRequest::Texture(path, color_space) => {
+                let data = load_image_file(&path, color_space)?;
+                LoadedAsset::Texture(path, color_space, data)
+            }
+        };
+        Ok(asset)
+    }
+}
+
+struct PendingAsset {
+    request: AssetRequest,
+    job: JobHandle<Result<LoadedAsset, String>>,
+    // Only changes how a failure is reported, the previous version is kept
+    // either way
+    reload: bool,
+}
+
+struct WatchedAsset {
+    request: AssetRequest,
+    // Every file the asset was loaded from, with its modification time then
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl WatchedAsset {
+    fn new(request: AssetRequest, files: Vec<PathBuf>) -> Self {
+        let files = files
+            .into_iter()
+            .map(|x| {
+                let modified = AssetServer::_modified(&x);
+                (x, modified)
+            })
+            .collect();
+        Self { request, files }
+    }
+}
+
+// Reads, decodes and processes assets on the job system's workers, so loads
+// don't stall the render thread. Finished assets are handed back by `poll`,
+// and the caller uploads them with the next frame, showing placeholders in
+// the meantime. Loaded assets are watched, and are loaded again when any
+// of their files change
+pub struct AssetServer {
+    jobs: JobSystem,
+    pending: Vec<PendingAsset>,
+    watched: Vec<WatchedAsset>,
+    last_watch: Instant,
+}
+
+impl AssetServer {
+    // A worker count of 0 uses one worker per available core but one
+    pub fn new(worker_count: usize) -> Self {
+        Self {
+            jobs: JobSystem::new(worker_count),
+            pending: vec![],
+            watched: vec![],
+            last_watch: Instant::now(),
+        }
+    }
+
+    pub fn load_model(&mut self, path: impl Into<PathBuf>, settings: MeshImportSettings) {
+        self._load(AssetRequest::Model(path.into(), settings), false);
+    }
+
+    pub fn load_environment(&mut self, path: impl Into<PathBuf>) {
+        self._load(AssetRequest::Environment(path.into()), false);
+    }
+
+    // Watches a texture file the caller already loaded, e.g. with
+    // `TextureCache::load`, and returns a `LoadedAsset::Texture` whenever it
+    // changes
+    pub fn watch_texture(&mut self, path: impl Into<PathBuf>, color_space: ColorSpace) {
+        let path = path.into();
+        self._watch(WatchedAsset::new(
+            AssetRequest::Texture(path.clone(), color_space),
+            vec![path],
+        ));
+    }
+
+    // Loads that haven't been returned by `poll` yet, including reloads
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Takes the assets that finished loading since the last call, in the
+    // order they were started, and starts reloading watched assets whose
+    // files changed. Loads that fail are logged and skipped, which leaves the
+    // previous version or the placeholder in place. The file is still watched,
+    // since files are often changed again while being saved
+    pub fn poll(&mut self) -> Vec<LoadedAsset> {
+        let mut loaded = vec![];
+        let mut watched = vec![];
+        self.pending.retain(|x| {
+            match x.job.poll() {
+                Some(Ok(asset)) => {
+                    let files = match &asset {
+                        LoadedAsset::Model(_, data) => data.sources().to_vec(),
+                        _ => vec![x.request._path().to_path_buf()],
+                    };
+                    watched.push(WatchedAsset::new(x.request.clone(), files));
+                    loaded.push(asset);
+                }
+                Some(Err(error)) => {
+                    if x.reload {
+                        warn!("{}, keeping the previous version", error);
+                    } else {
+                        warn!("{}", error);
+                        let files = vec![x.request._path().to_path_buf()];
+                        watched.push(WatchedAsset::new(x.request.clone(), files));
+                    }
+                }
+                None => return true,
+            }
+            false
+        });
+        for x in watched {
+            self._watch(x);
+        }
+
+        if self.last_watch.elapsed() >= WATCH_INTERVAL {
+            self.last_watch = Instant::now();
+            self._reload_changed();
+        }
+        loaded
+    }
+
+    fn _load(&mut self, request: AssetRequest, reload: bool) {
+        // A reload of what this replaces would otherwise win if it finished
+        // later
+        self.pending
+            .retain(|x| !(x.reload && x.request._same_asset(&request)));
+
+        let job = {
+            let request = request.clone();
+            self.jobs.spawn(move || request._load())
+        };
+        self.pending.push(PendingAsset {
+            request,
+            job,
+            reload,
+        });
+    }
+
+    fn _watch(&mut self, asset: WatchedAsset) {
+        self.watched
+            .retain(|x| !x.request._same_asset(&asset.request));
+        self.watched.push(asset);
+    }
+
+    fn _reload_changed(&mut self) {
+        let mut reloads = vec![];
+        for asset in &mut self.watched {
+            // Skipped while the asset is still loading, it's watched again
+            // with the new modification times once that's done
+            if self
+                .pending
+                .iter()
+                .any(|x| x.request._same_asset(&asset.request))
+            {
+                continue;
+            }
+
+            let mut changed = false;
+            for (path, modified) in &mut asset.files {
+                let current = AssetServer::_modified(path);
+                if current != *modified {
+                    *modified = current;
+                    changed = true;
+                }
+            }
+            if changed {
+                info!("reloading {}", asset.request._path().display());
+                reloads.push(asset.request.clone());
+            }
+        }
+
+        for request in reloads {
+            self._load(request, true);
+        }
+    }
+
+    fn _modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|x| x.modified()).ok()
+    }
+}
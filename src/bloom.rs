@@ -0,0 +1,375 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    additive_blend_attachment, rendering_attachment, CommandBuffer, DescriptorPool, DescriptorSet,
+    DescriptorSetLayout, DescriptorWriter, Device, GraphicsPipeline, HotReload, Image, ImageConfig,
+    ImageHandle, ImageUsage, ImageView, MemoryPriority, PipelineLayout, RenderGraph,
+    RenderingConfig, Sampler, ShaderId, ShaderKind, ShaderModule, ShaderRegistry,
+};
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Levels below a few pixels only add blur that isn't visible
+const MAX_LEVELS: usize = 6;
+
+// Matches `Constants` in bloom_downsample.glsl and bloom_upsample.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomConstants {
+    src_texel_size: [f32; 2],
+    threshold: f32,
+    knee: f32,
+    prefilter: u32,
+}
+
+struct BloomFrame {
+    // One per downsample and upsample pass
+    descriptor_sets: Vec<DescriptorSet>,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// One downsample or upsample pass, filtering `src` into `dst`
+struct BloomFilter<'a> {
+    set: &'a DescriptorSet,
+    pipeline: &'a Arc<GraphicsPipeline>,
+    src: Arc<ImageView>,
+    dst: Arc<ImageView>,
+    load_op: vk::AttachmentLoadOp,
+    // Applies the threshold, done by the first downsample
+    prefilter: bool,
+}
+
+// Extracts the parts of the draw image above a brightness threshold and blurs
+// them by downsampling through a chain of half resolution images and then
+// upsampling back up, adding each level onto the next larger one. The result
+// ends up in the first level, which the tonemap pass adds to the image
+pub struct BloomPass {
+    shader_ids: Vec<ShaderId>,
+    pipeline_layout: Arc<PipelineLayout>,
+    // Downsample and upsample
+    pipelines: (Arc<GraphicsPipeline>, Arc<GraphicsPipeline>),
+    sampler: Arc<Sampler>,
+    frames: Vec<BloomFrame>,
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+}
+
+impl BloomPass {
+    pub fn new(
+        device: &Arc<Device>,
+        shader_registry: &mut ShaderRegistry,
+        vertex_shader_path: &str,
+        downsample_shader_path: &str,
+        upsample_shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(vertex_shader_path, ShaderKind::Vertex, "main"),
+            shader_registry.load(downsample_shader_path, ShaderKind::Fragment, "main"),
+            shader_registry.load(upsample_shader_path, ShaderKind::Fragment, "main"),
+        ];
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let image_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[image_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<BloomConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipelines = BloomPass::_create_pipelines(
+            device,
+            &shader_registry.modules(&shader_ids),
+            &pipeline_layout,
+        );
+
+        let sets_per_frame = 2 * MAX_LEVELS - 1;
+        let set_count = (sets_per_frame * frames_in_flight) as u32;
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            set_count,
+            &[(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, set_count)],
+        );
+        let frames = (0..frames_in_flight)
+            .map(|_| BloomFrame {
+                descriptor_sets: descriptor_pool
+                    .allocate(&vec![&*set_layout; sets_per_frame])
+                    .into_vec(),
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        Self {
+            shader_ids,
+            pipeline_layout,
+            pipelines,
+            sampler: Sampler::clamped(device.clone()),
+            frames,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.05,
+        }
+    }
+
+    fn _create_pipelines(
+        device: &Arc<Device>,
+        shader_modules: &[Arc<ShaderModule>],
+        pipeline_layout: &PipelineLayout,
+    ) -> (Arc<GraphicsPipeline>, Arc<GraphicsPipeline>) {
+        let downsample_pipeline = GraphicsPipeline::builder()
+            .shader_modules(&[shader_modules[0].clone(), shader_modules[1].clone()])
+            .cull_mode(vk::CullModeFlags::NONE)
+            .color_formats(&[FORMAT])
+            .build(device.clone(), pipeline_layout);
+
+        let upsample_pipeline = GraphicsPipeline::builder()
+            .shader_modules(&[shader_modules[0].clone(), shader_modules[2].clone()])
+            .cull_mode(vk::CullModeFlags::NONE)
+            .color_attachment(FORMAT, additive_blend_attachment())
+            .build(device.clone(), pipeline_layout);
+
+        (downsample_pipeline, upsample_pipeline)
+    }
+
+    // The chain of images for a draw image of `extent`, each half the size of
+    // the previous one starting at half resolution. Like the draw images these
+    // are per frame and have to be recreated when the extent changes
+    pub fn create_images(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Vec<Arc<Image>> {
+        let smallest_side = extent.width.min(extent.height).max(1);
+        let level_count = (smallest_side.ilog2() as usize)
+            .saturating_sub(2)
+            .clamp(1, MAX_LEVELS);
+
+        (0..level_count)
+            .map(|level| {
+                Image::new(
+                    device.clone(),
+                    allocator.clone(),
+                    ImageConfig::new(
+                        FORMAT,
+                        vk::Extent3D {
+                            width: (extent.width >> (level + 1)).max(1),
+                            height: (extent.height >> (level + 1)).max(1),
+                            depth: 1,
+                        },
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    )
+                    .priority(MemoryPriority::High),
+                )
+            })
+            .collect()
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    // Brightness above which colors start to bloom
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    // How much of the bloom is added to the image, 0 disables it
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    // Adds the downsample and upsample passes reading `input` to the graph.
+    // `images` are the frame's images from `create_images`. Returns the
+    // first level, which holds the result
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        input: ImageHandle,
+        input_view: Arc<ImageView>,
+        images: &'a [Arc<Image>],
+    ) -> ImageHandle {
+        let frame = &self.frames[frame_index];
+        frame.views.borrow_mut().clear();
+
+        let levels = images
+            .iter()
+            .map(|image| {
+                graph.import_image(
+                    image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags2::NONE,
+                )
+            })
+            .collect::<Vec<_>>();
+        let views = images
+            .iter()
+            .map(|image| image.get_default_view(vk::ImageAspectFlags::COLOR))
+            .collect::<Vec<_>>();
+
+        let mut sets = frame.descriptor_sets.iter();
+        let (downsample_pipeline, upsample_pipeline) = &self.pipelines;
+
+        // The first downsample also applies the threshold
+        let mut src = (input, input_view);
+        for level in 0..images.len() {
+            let set = sets.next().unwrap();
+            let (src_handle, src_view) = src;
+            let dst_view = views[level].clone();
+            graph
+                .add_pass("bloom downsample")
+                .read_image(src_handle, ImageUsage::Sampled)
+                .write_image(levels[level], ImageUsage::ColorAttachment)
+                .record(move |cmd| {
+                    self._record_filter(
+                        cmd,
+                        frame,
+                        BloomFilter {
+                            set,
+                            pipeline: downsample_pipeline,
+                            src: src_view,
+                            dst: dst_view,
+                            load_op: vk::AttachmentLoadOp::DONT_CARE,
+                            prefilter: level == 0,
+                        },
+                    );
+                });
+            src = (levels[level], views[level].clone());
+        }
+
+        for level in (1..images.len()).rev() {
+            let set = sets.next().unwrap();
+            let src_view = views[level].clone();
+            let dst_view = views[level - 1].clone();
+            graph
+                .add_pass("bloom upsample")
+                .read_image(levels[level], ImageUsage::Sampled)
+                .write_image(levels[level - 1], ImageUsage::ColorAttachment)
+                .record(move |cmd| {
+                    self._record_filter(
+                        cmd,
+                        frame,
+                        BloomFilter {
+                            set,
+                            pipeline: upsample_pipeline,
+                            src: src_view,
+                            dst: dst_view,
+                            load_op: vk::AttachmentLoadOp::LOAD,
+                            prefilter: false,
+                        },
+                    );
+                });
+        }
+
+        levels[0]
+    }
+
+    fn _record_filter(&self, cmd_buf: &CommandBuffer, frame: &BloomFrame, filter: BloomFilter) {
+        let BloomFilter {
+            set,
+            pipeline,
+            src,
+            dst,
+            load_op,
+            prefilter,
+        } = filter;
+
+        {
+            let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+            writer.write_image(
+                set,
+                &self.sampler,
+                &src,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.flush();
+        }
+
+        let src_extent = src.image().extent();
+        let dst_extent = dst.image().extent();
+        let extent = vk::Extent2D {
+            width: dst_extent.width,
+            height: dst_extent.height,
+        };
+
+        let color_attachment = rendering_attachment(
+            &dst,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue::default(),
+        );
+
+        let constants = BloomConstants {
+            src_texel_size: [
+                1.0 / src_extent.width as f32,
+                1.0 / src_extent.height as f32,
+            ],
+            threshold: self.threshold,
+            knee: self.knee,
+            prefilter: prefilter as u32,
+        };
+
+        cmd_buf
+            .begin_rendering(RenderingConfig::new(extent).color_attachments(&[color_attachment]));
+        cmd_buf.bind_pipeline(pipeline.as_ref());
+        cmd_buf.set_full_viewport_scissor(extent);
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &self.pipeline_layout,
+            0,
+            &[set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        cmd_buf.draw(3, 1, 0, 0);
+        cmd_buf.end_rendering();
+
+        frame.views.borrow_mut().extend([src, dst]);
+    }
+}
+
+impl HotReload for BloomPass {
+    type Pipelines = (Arc<GraphicsPipeline>, Arc<GraphicsPipeline>);
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Self::Pipelines {
+        BloomPass::_create_pipelines(
+            self.pipeline_layout.device(),
+            &shader_registry.modules(&self.shader_ids),
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Self::Pipelines {
+        &mut self.pipelines
+    }
+}
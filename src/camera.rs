@@ -0,0 +1,209 @@
+use crate::input::InputValue;
+use crate::projection::{self, DepthDirection};
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+// Keeps the camera from flipping over when looking straight up or down
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Projection {
+    // `far` can be `f32::INFINITY` for a projection without a far plane
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    // `height` is the height of the view volume in world units
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    // Near and far plane distances
+    pub fn depth_range(&self) -> (f32, f32) {
+        match *self {
+            Projection::Perspective { near, far, .. } => (near, far),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        }
+    }
+}
+
+// World space is Z-up. Yaw is measured counter-clockwise from +X around Z and
+// pitch is measured up from the XY plane
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    pub projection: Projection,
+}
+
+impl Camera {
+    pub fn new(position: Vec3, yaw: f32, pitch: f32, projection: Projection) -> Self {
+        let mut camera = Self {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            projection,
+        };
+        camera.set_orientation(yaw, pitch);
+        camera
+    }
+
+    pub fn look_at(position: Vec3, target: Vec3, projection: Projection) -> Self {
+        let direction = (target - position).normalize();
+        let yaw = direction.y.atan2(direction.x);
+        let pitch = direction.z.clamp(-1.0, 1.0).asin();
+        Self::new(position, yaw, pitch, projection)
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw.rem_euclid(std::f32::consts::TAU);
+        self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn rotate(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.set_orientation(self.yaw + delta_yaw, self.pitch + delta_pitch);
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_yaw * cos_pitch, sin_yaw * cos_pitch, sin_pitch)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(Vec3::Z).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_to_rh(self.position, self.forward(), Vec3::Z)
+    }
+
+    // Y is flipped by the viewport, so this is a plain right-handed projection
+    pub fn projection_matrix(&self, aspect_ratio: f32, direction: DepthDirection) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                projection::perspective(fov_y, aspect_ratio, near, far, direction)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                projection::orthographic(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                    direction,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CameraAction {
+    // Digital composite, e.g. WASD
+    Move,
+    // Analog composite, e.g. the left stick
+    MoveStick,
+    Rise,
+    Fall,
+    // Relative mouse motion
+    Look,
+    // Analog composite, e.g. the right stick
+    LookStick,
+}
+
+// Free-flying camera controller. Input events for `CameraAction`s are fed in
+// as they arrive and the camera is moved once per frame by `update`
+pub struct FlyCameraController {
+    pub move_speed: f32,
+    // Radians per unit of mouse motion
+    pub mouse_sensitivity: f32,
+    // Radians per second at full stick deflection
+    pub stick_look_speed: f32,
+    pub invert_y: bool,
+    movement: Vec2,
+    stick_movement: Vec2,
+    rise: bool,
+    fall: bool,
+    look_delta: Vec2,
+    stick_look: Vec2,
+}
+
+impl FlyCameraController {
+    pub fn new(move_speed: f32, mouse_sensitivity: f32, stick_look_speed: f32) -> Self {
+        Self {
+            move_speed,
+            mouse_sensitivity,
+            stick_look_speed,
+            invert_y: false,
+            movement: Vec2::ZERO,
+            stick_movement: Vec2::ZERO,
+            rise: false,
+            fall: false,
+            look_delta: Vec2::ZERO,
+            stick_look: Vec2::ZERO,
+        }
+    }
+
+    pub fn handle_input(&mut self, action: CameraAction, value: InputValue) {
+        match (action, value) {
+            (CameraAction::Move, InputValue::Analog2d(x, y)) => {
+                self.movement = Vec2::new(x as f32, y as f32);
+            }
+            (CameraAction::MoveStick, InputValue::Analog2d(x, y)) => {
+                self.stick_movement = Vec2::new(x as f32, y as f32);
+            }
+            (CameraAction::Rise, InputValue::Digital(pressed)) => self.rise = pressed,
+            (CameraAction::Fall, InputValue::Digital(pressed)) => self.fall = pressed,
+            // Mouse motion is relative, so it's accumulated until the next
+            // update. Screen Y points down
+            (CameraAction::Look, InputValue::Analog2d(x, y)) => {
+                self.look_delta += Vec2::new(x as f32, -y as f32);
+            }
+            (CameraAction::LookStick, InputValue::Analog2d(x, y)) => {
+                self.stick_look = Vec2::new(x as f32, y as f32);
+            }
+            _ => {}
+        }
+    }
+
+    // Drops held movement and pending look motion, e.g. when the window loses
+    // focus and release events may never arrive
+    pub fn reset(&mut self) {
+        self.movement = Vec2::ZERO;
+        self.stick_movement = Vec2::ZERO;
+        self.rise = false;
+        self.fall = false;
+        self.look_delta = Vec2::ZERO;
+        self.stick_look = Vec2::ZERO;
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let look =
+            self.look_delta * self.mouse_sensitivity + self.stick_look * self.stick_look_speed * dt;
+        self.look_delta = Vec2::ZERO;
+
+        let pitch = if self.invert_y { -look.y } else { look.y };
+        camera.rotate(-look.x, pitch);
+
+        let movement = (self.movement + self.stick_movement).clamp_length_max(1.0);
+        let vertical = match (self.rise, self.fall) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+
+        let direction =
+            camera.forward() * movement.y + camera.right() * movement.x + Vec3::Z * vertical;
+        camera.position += direction * self.move_speed * dt;
+    }
+}
@@ -0,0 +1,212 @@
+use ash::vk;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::gpu::PresentModePreference;
+use crate::mesh_optimizer::MeshImportSettings;
+
+pub const CONFIG_PATH: &str = "vulka.toml";
+
+// Startup options. Loaded from a TOML file, where any missing field keeps its
+// default, and then overridden by command line flags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Initial window size in logical pixels
+    pub width: u32,
+    pub height: u32,
+    pub frames_in_flight: usize,
+    pub present_mode: PresentModePreference,
+    // Picks the best suitable GPU whose name contains this, ignoring case.
+    // `VULKA_GPU` overrides both this and the index
+    pub gpu_name: Option<String>,
+    // Index into the instance's physical devices, in enumeration order
+    pub gpu_index: Option<usize>,
+    pub validation: bool,
+    // 1, 2, 4, 8, ... Falls back to 1 if the GPU doesn't support it
+    pub msaa_samples: u32,
+    // A glTF model for the app to load, see `Renderer::load_model`
+    pub model_path: Option<PathBuf>,
+    // An equirectangular environment map to show instead of the gradient
+    pub environment_path: Option<PathBuf>,
+    // Objects farther than this from the camera aren't drawn. None draws
+    // everything in the frustum
+    pub cull_distance: Option<f32>,
+    // Reorder loaded meshes for the GPU, which needs the meshopt feature
+    pub optimize_meshes: bool,
+    // Use 16-bit indices for loaded meshes where they fit
+    pub small_indices: bool,
+    // Where the renderer's shaders are loaded from, relative to the working
+    // directory
+    pub shader_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            frames_in_flight: 2,
+            present_mode: PresentModePreference::default(),
+            gpu_name: None,
+            gpu_index: None,
+            validation: cfg!(debug_assertions),
+            msaa_samples: 1,
+            model_path: None,
+            environment_path: None,
+            cull_distance: None,
+            optimize_meshes: true,
+            small_indices: true,
+            shader_dir: PathBuf::from("./src/shaders"),
+        }
+    }
+}
+
+const USAGE: &str = "usage: vulka [options] [model] [environment]
+
+options:
+    --config <path>             read options from a TOML file (default vulka.toml)
+    --width <pixels>
+    --height <pixels>
+    --frames-in-flight <count>
+    --present-mode <mode>       vsync, adaptive, mailbox or immediate
+    --gpu <name>                use a GPU whose name contains <name>
+    --gpu-index <index>         use the GPU at <index>, VULKA_GPU overrides both
+    --validation, --no-validation
+    --msaa <samples>            1, 2, 4, 8, ...
+    --cull-distance <units>     don't draw objects farther than <units> from the camera
+    --optimize-meshes, --no-optimize-meshes
+    --small-indices, --no-small-indices
+    --shader-dir <path>         load shaders from <path> (default ./src/shaders)";
+
+impl Config {
+    // A missing file isn't an error, everything just keeps its default. A
+    // file that can't be read or parsed is skipped with a warning
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(error) => {
+                warn!("failed to read config {}: {}", path.display(), error);
+                return Self::default();
+            }
+        };
+        toml::from_str(&text).unwrap_or_else(|error| {
+            warn!("failed to parse config {}: {}", path.display(), error);
+            Self::default()
+        })
+    }
+
+    // Loads the file given by `--config`, or `CONFIG_PATH`, and applies the
+    // rest of the arguments on top. `args` doesn't include the program name
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let args: Vec<String> = args.into_iter().collect();
+        let path = args
+            .iter()
+            .position(|x| x == "--config")
+            .map(|i| match args.get(i + 1) {
+                Some(path) => path.as_str(),
+                None => Config::_usage_error("missing value for --config"),
+            })
+            .unwrap_or(CONFIG_PATH);
+        let mut config = Config::load(path);
+        config.apply_args(args);
+        config
+    }
+
+    // Flags override the matching field. The first positional argument is the
+    // model and the second the environment map. Exits with the usage on a
+    // malformed argument
+    pub fn apply_args(&mut self, args: impl IntoIterator<Item = String>) {
+        let mut args = args.into_iter();
+        let mut positional = 0;
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| Config::_usage_error(&format!("missing value for {}", arg)))
+            };
+            match arg.as_str() {
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "--config" => {
+                    // Already loaded by `from_args`
+                    value();
+                }
+                "--width" => self.width = Config::_parse(&arg, &value()),
+                "--height" => self.height = Config::_parse(&arg, &value()),
+                "--frames-in-flight" => self.frames_in_flight = Config::_parse(&arg, &value()),
+                "--present-mode" => {
+                    self.present_mode = match value().as_str() {
+                        "vsync" => PresentModePreference::Vsync,
+                        "adaptive" => PresentModePreference::Adaptive,
+                        "mailbox" => PresentModePreference::Mailbox,
+                        "immediate" => PresentModePreference::Immediate,
+                        x => Config::_usage_error(&format!("unknown present mode {}", x)),
+                    }
+                }
+                "--gpu" => self.gpu_name = Some(value()),
+                "--gpu-index" => self.gpu_index = Some(Config::_parse(&arg, &value())),
+                "--validation" => self.validation = true,
+                "--no-validation" => self.validation = false,
+                "--msaa" => self.msaa_samples = Config::_parse(&arg, &value()),
+                "--cull-distance" => self.cull_distance = Some(Config::_parse(&arg, &value())),
+                "--optimize-meshes" => self.optimize_meshes = true,
+                "--no-optimize-meshes" => self.optimize_meshes = false,
+                "--small-indices" => self.small_indices = true,
+                "--no-small-indices" => self.small_indices = false,
+                "--shader-dir" => self.shader_dir = PathBuf::from(value()),
+                x if x.starts_with('-') => {
+                    Config::_usage_error(&format!("unknown option {}", x));
+                }
+                _ => {
+                    match positional {
+                        0 => self.model_path = Some(PathBuf::from(arg)),
+                        1 => self.environment_path = Some(PathBuf::from(arg)),
+                        _ => Config::_usage_error(&format!("unexpected argument {}", arg)),
+                    }
+                    positional += 1;
+                }
+            }
+        }
+
+        if self.width == 0 || self.height == 0 {
+            Config::_usage_error("resolution must not be zero");
+        }
+        if self.frames_in_flight == 0 {
+            Config::_usage_error("frames in flight must not be zero");
+        }
+        if !self.msaa_samples.is_power_of_two() || self.msaa_samples > 64 {
+            Config::_usage_error(&format!("invalid MSAA sample count {}", self.msaa_samples));
+        }
+        if self.cull_distance.is_some_and(|x| x <= 0.0) {
+            Config::_usage_error("cull distance must be positive");
+        }
+    }
+
+    pub fn mesh_import_settings(&self) -> MeshImportSettings {
+        MeshImportSettings {
+            optimize: self.optimize_meshes,
+            small_indices: self.small_indices,
+        }
+    }
+
+    // The sample count bits have the same value as the count
+    pub fn msaa_sample_flags(&self) -> vk::SampleCountFlags {
+        vk::SampleCountFlags::from_raw(self.msaa_samples)
+    }
+
+    fn _parse<T: std::str::FromStr>(arg: &str, value: &str) -> T {
+        value.parse().unwrap_or_else(|_| {
+            Config::_usage_error(&format!("invalid value for {}: {}", arg, value))
+        })
+    }
+
+    fn _usage_error(message: &str) -> ! {
+        eprintln!("{}\n\n{}", message, USAGE);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,837 @@
+use ash::vk;
+use glam::{Mat3, Mat4, Vec3, Vec4};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::camera::Projection;
+use crate::gpu::{
+    Buffer, CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, DeviceFeature, HotReload, ImageView, MemoryPriority, PipelineLayout,
+    Sampler, ShaderId, ShaderKind, ShaderRegistry,
+};
+use crate::lod::LodSettings;
+use crate::projection::DepthDirection;
+use crate::render_world::{JointsHandle, RenderWorld, RenderableId};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Objects and draws each frame's buffers start out with room for
+const INITIAL_CAPACITY: usize = 64;
+
+// Matches `Object` in cull.glsl, vertex.glsl and the other vertex shaders
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CullObject {
+    transform: Mat4,
+    // Bounding sphere center in xyz and radius in w, in mesh space
+    bounds: Vec4,
+    // Index of the draw for the object's batch and level of detail
+    draw: u32,
+    // Nonzero if the CPU already culled the object, which leaves it to the
+    // unculled draws
+    cpu_culled: u32,
+    // Index of the object's first joint matrix in `joint_buffer`
+    joint_offset: u32,
+    _padding: u32,
+}
+
+// Where the camera is for the culling done on the CPU before the objects are
+// written, see `CullingPass::prepare`
+#[derive(Debug, Clone, Copy)]
+pub struct CullView {
+    pub clip_from_world: Mat4,
+    pub camera_position: Vec3,
+    pub projection: Projection,
+    // Objects whose bounding sphere is entirely farther than this are culled
+    pub max_distance: Option<f32>,
+    pub lod: LodSettings,
+}
+
+// Objects culled on the CPU in a frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuCullStats {
+    pub frustum_culled: u32,
+    pub distance_culled: u32,
+    // Batches without any objects left, whose draws are skipped entirely
+    pub culled_batches: u32,
+}
+
+#[repr(C)]
+struct CullUniform {
+    clip_from_world: Mat4,
+    planes: [Vec4; 6],
+    object_count: u32,
+    reversed_depth: u32,
+    _padding: [u32; 2],
+}
+
+// Matches `Constants` in cull.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullConstants {
+    // Nonzero to also test objects against the depth pyramid
+    occlusion: u32,
+}
+
+// Extracts the left, right, bottom, top, near and far planes from a clip
+// matrix with [0, 1] depth. A point is inside a plane when
+// `dot(plane.xyz, p) + plane.w >= 0`. With reversed depth the near and far
+// planes are swapped, and a missing far plane has every point inside it
+pub fn frustum_planes(clip_from_object: Mat4) -> [Vec4; 6] {
+    let row = |i| clip_from_object.row(i);
+    [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ]
+    .map(|x| {
+        let length = x.truncate().length();
+        if length > 0.0 {
+            x / length
+        } else {
+            Vec4::W
+        }
+    })
+}
+
+struct CullingFrame {
+    uniform_buffer: Buffer,
+    // Rewritten from the world every frame
+    object_buffer: Buffer,
+    // Indirect draws with zero instances, copied over the draws before
+    // culling
+    draw_template_buffer: Buffer,
+    draw_buffer: Buffer,
+    visible_buffer: Buffer,
+    // Draws of every object and their indices, laid out like the culled
+    // ones
+    all_draw_buffer: Buffer,
+    all_instance_buffer: Buffer,
+    // Joint matrices of skinned objects, starting with an identity for the
+    // ones without joints
+    joint_buffer: Buffer,
+    object_capacity: usize,
+    draw_capacity: usize,
+    joint_capacity: usize,
+    object_count: u32,
+    draw_count: u32,
+    // Each batch's first draw and number of draws, one per level of detail
+    batch_draws: Vec<(u32, u32)>,
+    // Whether each batch has any objects left after CPU culling
+    batch_visible: Vec<bool>,
+    cpu_cull_stats: CpuCullStats,
+    // The frame's pyramid, see `CullingPass::set_depth_pyramid`
+    depth_pyramid: Option<Arc<ImageView>>,
+    descriptor_set: DescriptorSet,
+}
+
+// Culls renderables by their bounding boxes and distance on the CPU, then
+// tests the rest's bounding spheres against the camera frustum in a compute
+// shader, and optionally against a depth pyramid of the depth prepass in a
+// second dispatch, see `record_occlusion`. Each of the world's batches gets one indexed indirect draw
+// whose instance count is the number of visible objects, and the visible
+// object indices are compacted into `visible_buffer` starting at the draw's
+// first instance
+pub struct CullingPass {
+    device: Arc<Device>,
+    allocator: Arc<vma::Allocator>,
+    shader_id: ShaderId,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+    // Draws a batch's levels with one call when the device can
+    multi_draw_indirect: bool,
+    // The level each renderable was last drawn at, by renderable index. Kept
+    // across frames for the LOD hysteresis
+    lod_levels: Vec<u8>,
+    frames: Vec<CullingFrame>,
+}
+
+impl CullingPass {
+    pub const DRAW_STRIDE: u32 = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        shader_registry: &mut ShaderRegistry,
+        shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_id = shader_registry.load(shader_path, ShaderKind::Compute, "main");
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let uniform_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let object_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let draw_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let visible_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let depth_pyramid_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[
+                    uniform_binding,
+                    object_binding,
+                    draw_binding,
+                    visible_binding,
+                    depth_pyramid_binding,
+                ],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<CullConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            shader_registry.get(shader_id),
+            &pipeline_layout,
+        );
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            frames_in_flight as u32,
+            &[
+                (vk::DescriptorType::UNIFORM_BUFFER, frames_in_flight as u32),
+                (
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    3 * frames_in_flight as u32,
+                ),
+                (
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    frames_in_flight as u32,
+                ),
+            ],
+        );
+        let sampler = Sampler::clamped(device.clone());
+        let descriptor_sets = descriptor_pool.allocate(&vec![&*set_layout; frames_in_flight]);
+
+        let frames = descriptor_sets
+            .into_vec()
+            .into_iter()
+            .map(|descriptor_set| {
+                let (object_buffer, visible_buffer, all_instance_buffer) =
+                    CullingPass::_create_object_buffers(device, allocator, INITIAL_CAPACITY);
+                let (draw_template_buffer, draw_buffer, all_draw_buffer) =
+                    CullingPass::_create_draw_buffers(device, allocator, INITIAL_CAPACITY);
+                let frame = CullingFrame {
+                    uniform_buffer: CullingPass::_create_host_buffer(
+                        device,
+                        allocator,
+                        size_of::<CullUniform>(),
+                        vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    ),
+                    object_buffer,
+                    draw_template_buffer,
+                    draw_buffer,
+                    visible_buffer,
+                    all_draw_buffer,
+                    all_instance_buffer,
+                    joint_buffer: CullingPass::_create_joint_buffer(
+                        device,
+                        allocator,
+                        INITIAL_CAPACITY,
+                    ),
+                    object_capacity: INITIAL_CAPACITY,
+                    draw_capacity: INITIAL_CAPACITY,
+                    joint_capacity: INITIAL_CAPACITY,
+                    object_count: 0,
+                    draw_count: 0,
+                    batch_draws: vec![],
+                    batch_visible: vec![],
+                    cpu_cull_stats: CpuCullStats::default(),
+                    depth_pyramid: None,
+                    descriptor_set,
+                };
+                CullingPass::_write_descriptor_set(device, &sampler, &frame);
+                frame
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            device: device.clone(),
+            allocator: allocator.clone(),
+            shader_id,
+            pipeline_layout,
+            pipeline,
+            sampler,
+            multi_draw_indirect: DeviceFeature::MultiDrawIndirect
+                .is_enabled(device.enabled_features()),
+            lod_levels: vec![],
+            frames,
+        }
+    }
+
+    fn _create_host_buffer(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> Buffer {
+        Buffer::new(
+            device.clone(),
+            allocator.clone(),
+            size,
+            usage,
+            vma::MemoryUsage::AutoPreferHost,
+            vma::AllocationCreateFlags::MAPPED
+                | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+            MemoryPriority::Normal,
+        )
+    }
+
+    fn _create_device_buffer(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> Buffer {
+        Buffer::new(
+            device.clone(),
+            allocator.clone(),
+            size,
+            usage,
+            vma::MemoryUsage::AutoPreferDevice,
+            vma::AllocationCreateFlags::empty(),
+            MemoryPriority::High,
+        )
+    }
+
+    // Returns the object, visible and unculled instance buffers. Objects are
+    // written grouped by batch, so every object is its own unculled instance
+    fn _create_object_buffers(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        capacity: usize,
+    ) -> (Buffer, Buffer, Buffer) {
+        let object_buffer = CullingPass::_create_host_buffer(
+            device,
+            allocator,
+            size_of::<CullObject>() * capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        let visible_buffer = CullingPass::_create_device_buffer(
+            device,
+            allocator,
+            size_of::<u32>() * capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        let all_instance_buffer = CullingPass::_create_host_buffer(
+            device,
+            allocator,
+            size_of::<u32>() * capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        all_instance_buffer.copy_nonoverlapping(&(0..capacity as u32).collect::<Vec<_>>());
+        (object_buffer, visible_buffer, all_instance_buffer)
+    }
+
+    fn _create_joint_buffer(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        capacity: usize,
+    ) -> Buffer {
+        CullingPass::_create_host_buffer(
+            device,
+            allocator,
+            size_of::<Mat4>() * capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+    }
+
+    // Returns the draw template, draw and unculled draw buffers
+    fn _create_draw_buffers(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        capacity: usize,
+    ) -> (Buffer, Buffer, Buffer) {
+        let size = CullingPass::DRAW_STRIDE as usize * capacity;
+        let draw_template_buffer = CullingPass::_create_host_buffer(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+        let draw_buffer = CullingPass::_create_device_buffer(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+        );
+        let all_draw_buffer = CullingPass::_create_host_buffer(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        (draw_template_buffer, draw_buffer, all_draw_buffer)
+    }
+
+    fn _write_descriptor_set(device: &Arc<Device>, sampler: &Sampler, frame: &CullingFrame) {
+        let set = &frame.descriptor_set;
+        let storage = vk::DescriptorType::STORAGE_BUFFER;
+        let mut writer = DescriptorWriter::new(device.clone());
+        writer
+            .write_buffer(
+                set,
+                &frame.uniform_buffer,
+                0,
+                size_of::<CullUniform>() as u64,
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+            )
+            .write_buffer(set, &frame.object_buffer, 0, vk::WHOLE_SIZE, 1, storage)
+            .write_buffer(set, &frame.draw_buffer, 0, vk::WHOLE_SIZE, 2, storage)
+            .write_buffer(set, &frame.visible_buffer, 0, vk::WHOLE_SIZE, 3, storage);
+        if let Some(depth_pyramid) = &frame.depth_pyramid {
+            writer.write_image(
+                set,
+                sampler,
+                depth_pyramid,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                4,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+        }
+        writer.flush();
+    }
+
+    // The frame's depth pyramid from `DepthPyramidPass::create_image`. Must
+    // be set after the frame's fence has been waited on, like `prepare`, and
+    // before `record_occlusion`
+    pub fn set_depth_pyramid(&mut self, frame_index: usize, depth_pyramid: Arc<ImageView>) {
+        let frame = &mut self.frames[frame_index];
+        frame.depth_pyramid = Some(depth_pyramid);
+        CullingPass::_write_descriptor_set(&self.device, &self.sampler, frame);
+    }
+
+    // Per object data read by the vertex shader
+    pub fn object_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].object_buffer
+    }
+
+    // Joint matrices read by the skinned vertex shaders, see
+    // `Object::jointOffset`
+    pub fn joint_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].joint_buffer
+    }
+
+    pub fn draw_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].draw_buffer
+    }
+
+    pub fn visible_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].visible_buffer
+    }
+
+    // Like `visible_buffer` but with every object
+    pub fn unculled_instance_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].all_instance_buffer
+    }
+
+    // False if CPU culling left none of the batch's objects, so its draw can
+    // be skipped
+    pub fn batch_visible(&self, frame_index: usize, batch: usize) -> bool {
+        self.frames[frame_index].batch_visible[batch]
+    }
+
+    pub fn cpu_cull_stats(&self, frame_index: usize) -> CpuCullStats {
+        self.frames[frame_index].cpu_cull_stats
+    }
+
+    // Draws the batch's visible objects from `draw_buffer`, with a draw for
+    // each level of detail. Expects the mesh's index buffer to be bound
+    pub fn draw_batch(&self, cmd_buf: &CommandBuffer, frame_index: usize, batch: usize) {
+        let frame = &self.frames[frame_index];
+        self._draw(cmd_buf, &frame.draw_buffer, frame.batch_draws[batch]);
+    }
+
+    // Like `draw_batch` but with every object, from `unculled_draw_buffer`
+    pub fn draw_batch_unculled(&self, cmd_buf: &CommandBuffer, frame_index: usize, batch: usize) {
+        let frame = &self.frames[frame_index];
+        self._draw(cmd_buf, &frame.all_draw_buffer, frame.batch_draws[batch]);
+    }
+
+    fn _draw(
+        &self,
+        cmd_buf: &CommandBuffer,
+        buffer: &Buffer,
+        (first_draw, draw_count): (u32, u32),
+    ) {
+        let offset =
+            |draw: u32| draw as vk::DeviceSize * CullingPass::DRAW_STRIDE as vk::DeviceSize;
+        if self.multi_draw_indirect {
+            cmd_buf.draw_indexed_indirect(
+                buffer,
+                offset(first_draw),
+                draw_count,
+                CullingPass::DRAW_STRIDE,
+            );
+        } else {
+            for draw in first_draw..first_draw + draw_count {
+                cmd_buf.draw_indexed_indirect(buffer, offset(draw), 1, CullingPass::DRAW_STRIDE);
+            }
+        }
+    }
+
+    // Tests the object's world space bounding box against the frustum planes
+    // and its bounding sphere against the distance
+    fn _cpu_cull(
+        view: &CullView,
+        planes: &[Vec4; 6],
+        transform: Mat4,
+        (min, max): (Vec3, Vec3),
+        (center, radius): (Vec3, f32),
+        stats: &mut CpuCullStats,
+    ) -> bool {
+        if let Some(max_distance) = view.max_distance {
+            let (scale, _, _) = transform.to_scale_rotation_translation();
+            let center = transform.transform_point3(center);
+            let distance = center.distance(view.camera_position) - radius * scale.max_element();
+            if distance > max_distance {
+                stats.distance_culled += 1;
+                return true;
+            }
+        }
+
+        // The box's half extents along the world axes
+        let center = transform.transform_point3((min + max) / 2.0);
+        let axes = Mat3::from_mat4(transform);
+        let axes = Mat3::from_cols(axes.x_axis.abs(), axes.y_axis.abs(), axes.z_axis.abs());
+        let extents = axes * ((max - min) / 2.0);
+        let outside = planes.iter().any(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w < -normal.abs().dot(extents)
+        });
+        if outside {
+            stats.frustum_culled += 1;
+        }
+        outside
+    }
+
+    // Bounding sphere around a skinned mesh in the pose given by its joints.
+    // Each vertex is a weighted average of its position moved by its joints,
+    // so it's inside the spheres around the bind pose moved by each joint
+    fn _skinned_bounds(joints: &[Mat4], (center, radius): (Vec3, f32)) -> (Vec3, f32) {
+        let spheres = joints.iter().map(|x| {
+            let (scale, _, _) = x.to_scale_rotation_translation();
+            (x.transform_point3(center), radius * scale.max_element())
+        });
+        let (min, max) = spheres.clone().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), (center, radius)| (min.min(center - radius), max.max(center + radius)),
+        );
+        let center = (min + max) / 2.0;
+        let radius = spheres
+            .map(|(x, radius)| x.distance(center) + radius)
+            .fold(0.0, f32::max);
+        (center, radius)
+    }
+
+    // Picks the renderable's level of detail by the fraction of the viewport
+    // height its bounding sphere covers
+    fn _select_lod(
+        lod_levels: &mut Vec<u8>,
+        view: &CullView,
+        id: RenderableId,
+        transform: Mat4,
+        (center, radius): (Vec3, f32),
+        lod_count: usize,
+    ) -> usize {
+        if id.index() >= lod_levels.len() {
+            lod_levels.resize(id.index() + 1, 0);
+        }
+
+        let (scale, _, _) = transform.to_scale_rotation_translation();
+        let radius = radius * scale.max_element();
+        let screen_size = match view.projection {
+            Projection::Perspective { fov_y, .. } => {
+                let distance = transform
+                    .transform_point3(center)
+                    .distance(view.camera_position);
+                radius / (distance.max(1e-4) * (fov_y / 2.0).tan())
+            }
+            Projection::Orthographic { height, .. } => 2.0 * radius / height,
+        };
+
+        let previous = lod_levels[id.index()] as usize;
+        let lod = view.lod.select(screen_size, previous, lod_count);
+        lod_levels[id.index()] = lod as u8;
+        lod
+    }
+
+    // Writes the frame's objects and draws from the world's batches, growing
+    // its buffers if they're too small. Objects outside the view's frustum or
+    // distance are flagged so the compute shader skips them, but are still
+    // written for the unculled draws. Must be called after the frame's fence
+    // has been waited on. Returns true if the buffers were recreated, in
+    // which case other descriptor sets using them for this frame have to be
+    // rewritten
+    pub fn prepare(&mut self, frame_index: usize, world: &RenderWorld, view: &CullView) -> bool {
+        let batches = world.batches();
+        let object_count = batches.iter().map(|x| x.renderables.len()).sum::<usize>();
+        let draw_count = batches
+            .iter()
+            .map(|x| world.mesh(x.mesh).lods().len())
+            .sum::<usize>();
+
+        let frame = &mut self.frames[frame_index];
+        let mut recreated = false;
+        if object_count > frame.object_capacity {
+            let capacity = object_count.next_power_of_two();
+            (
+                frame.object_buffer,
+                frame.visible_buffer,
+                frame.all_instance_buffer,
+            ) = CullingPass::_create_object_buffers(&self.device, &self.allocator, capacity);
+            frame.object_capacity = capacity;
+            recreated = true;
+        }
+        if draw_count > frame.draw_capacity {
+            let capacity = draw_count.next_power_of_two();
+            (
+                frame.draw_template_buffer,
+                frame.draw_buffer,
+                frame.all_draw_buffer,
+            ) = CullingPass::_create_draw_buffers(&self.device, &self.allocator, capacity);
+            frame.draw_capacity = capacity;
+            recreated = true;
+        }
+        if recreated {
+            CullingPass::_write_descriptor_set(&self.device, &self.sampler, frame);
+        }
+
+        // Each batch gets a draw per level of detail, and each draw's objects
+        // are packed from its first instance. Culled objects still get a
+        // level for the unculled draws
+        let planes = frustum_planes(view.clip_from_world);
+        let mut stats = CpuCullStats::default();
+        let mut objects = Vec::with_capacity(object_count);
+        let mut draws = Vec::with_capacity(draw_count);
+        let mut all_draws = Vec::with_capacity(draw_count);
+        let mut joints = vec![Mat4::IDENTITY];
+        let mut joint_offsets = HashMap::<JointsHandle, u32>::new();
+        frame.batch_draws.clear();
+        frame.batch_visible.clear();
+        for batch in batches {
+            let mesh = world.mesh(batch.mesh);
+            let (center, radius) = mesh.bounding_sphere();
+            let lods = mesh.lods();
+            let first_draw = draws.len();
+
+            let mut levels = vec![vec![]; lods.len()];
+            let mut visible = false;
+            for id in &batch.renderables {
+                let renderable = world.renderable(*id).expect("batch renderable was removed");
+                let transform = world.transform(renderable.transform);
+
+                // Skinned objects are bounded in their current pose, and
+                // objects sharing joints share their matrices
+                let (bounding_box, bounds, joint_offset) = match renderable
+                    .joints
+                    .filter(|_| mesh.is_skinned())
+                {
+                    Some(handle) => {
+                        let offset = *joint_offsets.entry(handle).or_insert_with(|| {
+                            joints.extend_from_slice(world.joints(handle));
+                            (joints.len() - world.joints(handle).len()) as u32
+                        });
+                        let (center, radius) =
+                            CullingPass::_skinned_bounds(world.joints(handle), (center, radius));
+                        let extent = Vec3::splat(radius);
+                        ((center - extent, center + extent), (center, radius), offset)
+                    }
+                    None => (mesh.bounding_box(), (center, radius), 0),
+                };
+
+                let culled = CullingPass::_cpu_cull(
+                    view,
+                    &planes,
+                    transform,
+                    bounding_box,
+                    bounds,
+                    &mut stats,
+                );
+                visible |= !culled;
+                let lod = CullingPass::_select_lod(
+                    &mut self.lod_levels,
+                    view,
+                    *id,
+                    transform,
+                    bounds,
+                    lods.len(),
+                );
+                levels[lod].push(CullObject {
+                    transform,
+                    bounds: bounds.0.extend(bounds.1),
+                    draw: (first_draw + lod) as u32,
+                    cpu_culled: culled as u32,
+                    joint_offset,
+                    _padding: 0,
+                });
+            }
+
+            for (lod, level) in lods.iter().zip(levels) {
+                let draw = vk::DrawIndexedIndirectCommand {
+                    index_count: lod.index_count,
+                    instance_count: 0,
+                    first_index: lod.first_index,
+                    vertex_offset: 0,
+                    first_instance: objects.len() as u32,
+                };
+                draws.push(draw);
+                all_draws.push(vk::DrawIndexedIndirectCommand {
+                    instance_count: level.len() as u32,
+                    ..draw
+                });
+                objects.extend(level);
+            }
+
+            frame
+                .batch_draws
+                .push((first_draw as u32, lods.len() as u32));
+            frame.batch_visible.push(visible);
+            if !visible {
+                stats.culled_batches += 1;
+            }
+        }
+
+        if joints.len() > frame.joint_capacity {
+            let capacity = joints.len().next_power_of_two();
+            frame.joint_buffer =
+                CullingPass::_create_joint_buffer(&self.device, &self.allocator, capacity);
+            frame.joint_capacity = capacity;
+            recreated = true;
+        }
+
+        frame.object_buffer.copy_nonoverlapping(&objects);
+        frame.joint_buffer.copy_nonoverlapping(&joints);
+        frame.draw_template_buffer.copy_nonoverlapping(&draws);
+        frame.all_draw_buffer.copy_nonoverlapping(&all_draws);
+        frame.object_count = objects.len() as u32;
+        frame.draw_count = draws.len() as u32;
+        frame.cpu_cull_stats = stats;
+
+        recreated
+    }
+
+    // Must be recorded outside of rendering, after `prepare`. The draw and
+    // visible buffers are written by a compute shader, so the caller has to
+    // synchronize them with the draws, e.g. by declaring them as render graph
+    // pass writes
+    pub fn record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame_index: usize,
+        clip_from_world: Mat4,
+        depth_direction: DepthDirection,
+    ) {
+        let frame = &self.frames[frame_index];
+
+        frame.uniform_buffer.copy_nonoverlapping(&[CullUniform {
+            clip_from_world,
+            planes: frustum_planes(clip_from_world),
+            object_count: frame.object_count,
+            reversed_depth: (depth_direction == DepthDirection::Reversed) as u32,
+            _padding: [0; 2],
+        }]);
+
+        self._dispatch(cmd_buf, frame, false);
+    }
+
+    // Culls the frame's objects again after `record`, this time also against
+    // the depth pyramid, which has to be in SHADER_READ_ONLY_OPTIMAL. The
+    // draws `record` wrote are overwritten, so whatever read them, like the
+    // depth prepass, has to be done with them
+    pub fn record_occlusion(&self, cmd_buf: &CommandBuffer, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        assert!(
+            frame.depth_pyramid.is_some(),
+            "occlusion culling without a depth pyramid"
+        );
+        self._dispatch(cmd_buf, frame, true);
+    }
+
+    fn _dispatch(&self, cmd_buf: &CommandBuffer, frame: &CullingFrame, occlusion: bool) {
+        if frame.draw_count == 0 {
+            return;
+        }
+
+        cmd_buf.copy_buffer(
+            &frame.draw_template_buffer,
+            &frame.draw_buffer,
+            &[vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: frame.draw_count as vk::DeviceSize
+                    * CullingPass::DRAW_STRIDE as vk::DeviceSize,
+            }],
+        );
+
+        cmd_buf.memory_barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        );
+
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[&frame.descriptor_set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &CullConstants {
+                occlusion: occlusion as u32,
+            },
+        );
+        cmd_buf.dispatch(frame.object_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+impl HotReload for CullingPass {
+    type Pipelines = Arc<ComputePipeline>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Arc<ComputePipeline> {
+        ComputePipeline::new(
+            self.pipeline_layout.device().clone(),
+            shader_registry.get(self.shader_id),
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Arc<ComputePipeline> {
+        &mut self.pipeline
+    }
+}
@@ -0,0 +1,295 @@
+use ash::vk;
+use glam::{Mat4, Vec3, Vec4};
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::gpu::{
+    rendering_attachment, Buffer, CommandBuffer, Device, GraphicsPipeline, HotReload, ImageHandle,
+    ImageUsage, ImageView, MemoryPriority, PipelineLayout, RenderGraph, RenderingConfig, ShaderId,
+    ShaderKind, ShaderRegistry, Unorm8x4, VertexLayout,
+};
+use crate::vertex_field;
+
+// Vertices past this are dropped for the frame
+const MAX_VERTICES: usize = 65536;
+
+// Segments per circle of a sphere
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DebugVertex {
+    pub position: Vec3,
+    pub color: Unorm8x4,
+}
+
+impl DebugVertex {
+    pub fn layout() -> VertexLayout {
+        VertexLayout::builder()
+            .binding::<DebugVertex>(vk::VertexInputRate::VERTEX)
+            .attribute(vertex_field!(DebugVertex, position))
+            .attribute(vertex_field!(DebugVertex, color))
+            .build()
+    }
+}
+
+// Immediate mode lines in world space. Shapes are added every frame they
+// should be shown and cleared once the frame is recorded. Colors are in
+// [0, 1]
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: Vec4) {
+        let color = Unorm8x4::new(color);
+        self.vertices.push(DebugVertex { position: a, color });
+        self.vertices.push(DebugVertex { position: b, color });
+    }
+
+    // Axis aligned box between two corners
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec4) {
+        let corners = [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+        ];
+        self._box(&corners, color);
+    }
+
+    // A circle around each axis
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        for (u, v) in [(Vec3::X, Vec3::Y), (Vec3::Y, Vec3::Z), (Vec3::Z, Vec3::X)] {
+            let point = |i: usize| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    // The volume `view_proj` maps into clip space, with [0, 1] depth. Works
+    // for perspective and orthographic projections
+    pub fn frustum(&mut self, view_proj: Mat4, color: Vec4) {
+        let inverse = view_proj.inverse();
+        let corners = [
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(1.0, -1.0, 1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(-1.0, 1.0, 1.0),
+        ]
+        .map(|x| inverse.project_point3(x));
+        self._box(&corners, color);
+    }
+
+    // The transform's X, Y and Z axes in red, green and blue, `size` long
+    pub fn axis(&mut self, transform: Mat4, size: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [
+            (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ] {
+            let end = transform.transform_point3(axis * size);
+            self.line(origin, end, color);
+        }
+    }
+
+    // Corners are the near face then the far face, each wound the same way
+    fn _box(&mut self, corners: &[Vec3; 8], color: Vec4) {
+        for i in 0..4 {
+            let j = (i + 1) % 4;
+            self.line(corners[i], corners[j], color);
+            self.line(corners[i + 4], corners[j + 4], color);
+            self.line(corners[i], corners[i + 4], color);
+        }
+    }
+}
+
+// Draws a frame's `DebugDraw` lines over the draw image after the main pass.
+// There's no depth test, so lines show through geometry
+pub struct DebugDrawPass {
+    shader_ids: Vec<ShaderId>,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    // Host visible, one for each frame in flight
+    vertex_buffers: Vec<Buffer>,
+}
+
+impl DebugDrawPass {
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        shader_registry: &mut ShaderRegistry,
+        vertex_shader_path: &str,
+        fragment_shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(vertex_shader_path, ShaderKind::Vertex, "main"),
+            shader_registry.load(fragment_shader_path, ShaderKind::Fragment, "main"),
+        ];
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<Mat4>(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let pipeline_layout = device.get_pipeline_layout(&[], &push_constant_ranges);
+
+        let pipeline =
+            DebugDrawPass::_create_pipeline(device, shader_registry, &shader_ids, &pipeline_layout);
+
+        let vertex_buffers = (0..frames_in_flight)
+            .map(|_| {
+                Buffer::new(
+                    device.clone(),
+                    allocator.clone(),
+                    MAX_VERTICES * size_of::<DebugVertex>(),
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vma::MemoryUsage::AutoPreferHost,
+                    vma::AllocationCreateFlags::MAPPED
+                        | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+                    MemoryPriority::Normal,
+                )
+            })
+            .collect();
+
+        Self {
+            shader_ids,
+            pipeline_layout,
+            pipeline,
+            vertex_buffers,
+        }
+    }
+
+    fn _create_pipeline(
+        device: &Arc<Device>,
+        shader_registry: &ShaderRegistry,
+        shader_ids: &[ShaderId],
+        pipeline_layout: &PipelineLayout,
+    ) -> Arc<GraphicsPipeline> {
+        GraphicsPipeline::builder()
+            .shader_modules(&shader_registry.modules(shader_ids))
+            .vertex_layout(&DebugVertex::layout())
+            .topology(vk::PrimitiveTopology::LINE_LIST)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .color_formats(&[vk::Format::R16G16B16A16_SFLOAT])
+            .build(device.clone(), pipeline_layout)
+    }
+
+    // Copies the lines into the frame's vertex buffer and adds the pass
+    // drawing them into `draw`. Nothing is added if there aren't any lines
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        debug_draw: &DebugDraw,
+        (draw, draw_view): (ImageHandle, Arc<ImageView>),
+        extent: vk::Extent2D,
+        view_proj: Mat4,
+    ) {
+        if debug_draw.is_empty() {
+            return;
+        }
+
+        // The frame's fence was waited on, so the buffer isn't in use. Whole
+        // lines are kept when truncating
+        let vertices = debug_draw.vertices();
+        let vertices = &vertices[..vertices.len().min(MAX_VERTICES) & !1];
+        let vertex_buffer = &self.vertex_buffers[frame_index];
+        vertex_buffer.copy_nonoverlapping(vertices);
+        let vertex_count = vertices.len() as u32;
+
+        graph
+            .add_pass("debug_draw")
+            .write_image(draw, ImageUsage::ColorAttachment)
+            .record(move |cmd| {
+                self._record(
+                    cmd,
+                    vertex_buffer,
+                    vertex_count,
+                    draw_view,
+                    extent,
+                    view_proj,
+                )
+            });
+    }
+
+    fn _record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        vertex_buffer: &Buffer,
+        vertex_count: u32,
+        draw_view: Arc<ImageView>,
+        extent: vk::Extent2D,
+        view_proj: Mat4,
+    ) {
+        let color_attachment = rendering_attachment(
+            &draw_view,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::LOAD,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue::default(),
+        );
+
+        cmd_buf
+            .begin_rendering(RenderingConfig::new(extent).color_attachments(&[color_attachment]));
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+
+        // Flipped like the main pass, so the same projection applies
+        cmd_buf.set_full_viewport_scissor_flipped(extent);
+        cmd_buf.bind_vertex_buffers(0, &[(vertex_buffer, 0)]);
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            &view_proj,
+        );
+        cmd_buf.draw(vertex_count, 1, 0, 0);
+        cmd_buf.end_rendering();
+    }
+}
+
+impl HotReload for DebugDrawPass {
+    type Pipelines = Arc<GraphicsPipeline>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Arc<GraphicsPipeline> {
+        DebugDrawPass::_create_pipeline(
+            self.pipeline_layout.device(),
+            shader_registry,
+            &self.shader_ids,
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Arc<GraphicsPipeline> {
+        &mut self.pipeline
+    }
+}
@@ -0,0 +1,305 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, HotReload, Image, ImageConfig, ImageHandle, ImageUsage, ImageView,
+    MemoryPriority, PipelineLayout, RenderGraph, Sampler, ShaderId, ShaderKind, ShaderRegistry,
+};
+use crate::projection::DepthDirection;
+
+const FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+// Matches `local_size_x` and `local_size_y` in depth_pyramid.glsl and
+// depth_pyramid_msaa.glsl
+const WORKGROUP_SIZE: u32 = 8;
+
+// Enough for a 16384 pixel wide draw image
+const MAX_LEVELS: usize = 15;
+
+// Matches `Constants` in depth_pyramid.glsl and depth_pyramid_msaa.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PyramidConstants {
+    reversed_depth: u32,
+}
+
+struct DepthPyramidFrame {
+    // One per level
+    descriptor_sets: Vec<DescriptorSet>,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// Builds a mip chain from the depth prepass where each texel holds the
+// farthest depth under it, for the culling shader's occlusion test. The first
+// level is the draw image's size rounded down to powers of two, and each
+// level after it halves it down to a single texel
+pub struct DepthPyramidPass {
+    shader_ids: Vec<ShaderId>,
+    pipeline_layout: Arc<PipelineLayout>,
+    // Reduces a single sampled image, and the multisampled depth image into
+    // the first level
+    pipelines: (Arc<ComputePipeline>, Arc<ComputePipeline>),
+    sampler: Arc<Sampler>,
+    frames: Vec<DepthPyramidFrame>,
+}
+
+impl DepthPyramidPass {
+    pub fn new(
+        device: &Arc<Device>,
+        shader_registry: &mut ShaderRegistry,
+        shader_path: &str,
+        msaa_shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(shader_path, ShaderKind::Compute, "main"),
+            shader_registry.load(msaa_shader_path, ShaderKind::Compute, "main"),
+        ];
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let output_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_IMAGE)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let input_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[output_binding, input_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<PyramidConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipelines = DepthPyramidPass::_create_pipelines(
+            device,
+            shader_registry,
+            &shader_ids,
+            &pipeline_layout,
+        );
+
+        let set_count = (MAX_LEVELS * frames_in_flight) as u32;
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            set_count,
+            &[
+                (vk::DescriptorType::STORAGE_IMAGE, set_count),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, set_count),
+            ],
+        );
+        let frames = (0..frames_in_flight)
+            .map(|_| DepthPyramidFrame {
+                descriptor_sets: descriptor_pool
+                    .allocate(&[&*set_layout; MAX_LEVELS])
+                    .into_vec(),
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        Self {
+            shader_ids,
+            pipeline_layout,
+            pipelines,
+            sampler: Sampler::clamped(device.clone()),
+            frames,
+        }
+    }
+
+    fn _create_pipelines(
+        device: &Arc<Device>,
+        shader_registry: &ShaderRegistry,
+        shader_ids: &[ShaderId],
+        pipeline_layout: &PipelineLayout,
+    ) -> (Arc<ComputePipeline>, Arc<ComputePipeline>) {
+        (
+            ComputePipeline::new(
+                device.clone(),
+                shader_registry.get(shader_ids[0]),
+                pipeline_layout,
+            ),
+            ComputePipeline::new(
+                device.clone(),
+                shader_registry.get(shader_ids[1]),
+                pipeline_layout,
+            ),
+        )
+    }
+
+    // The pyramid for a draw image of `extent`. Like the draw images it's per
+    // frame and has to be recreated when the extent changes
+    pub fn create_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Arc<Image> {
+        let width = 1u32 << extent.width.max(1).ilog2();
+        let height = 1u32 << extent.height.max(1).ilog2();
+        let level_count = width.max(height).ilog2() + 1;
+
+        Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(
+                FORMAT,
+                vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            )
+            .mip_levels(level_count.min(MAX_LEVELS as u32))
+            .priority(MemoryPriority::High),
+        )
+    }
+
+    // Adds the pass building `image`, the frame's pyramid from `create_image`,
+    // from `depth` to the graph. Returns the pyramid, which is left in GENERAL
+    // for the pass reading it to transition
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        depth: (ImageHandle, Arc<ImageView>),
+        image: &'a Arc<Image>,
+        depth_direction: DepthDirection,
+    ) -> ImageHandle {
+        let frame = &self.frames[frame_index];
+        frame.views.borrow_mut().clear();
+
+        let pyramid = graph.import_image(
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags2::NONE,
+        );
+        let constants = PyramidConstants {
+            reversed_depth: (depth_direction == DepthDirection::Reversed) as u32,
+        };
+
+        let (depth, depth_view) = depth;
+        graph
+            .add_pass("depth pyramid")
+            .read_image(depth, ImageUsage::Sampled)
+            .write_image(pyramid, ImageUsage::Storage)
+            .record(move |cmd| self._record(cmd, frame, depth_view, image, &constants));
+
+        pyramid
+    }
+
+    // Each level reads the one before it, so the dispatches are separated by
+    // barriers. The levels all stay in GENERAL
+    fn _record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame: &DepthPyramidFrame,
+        depth_view: Arc<ImageView>,
+        image: &Arc<Image>,
+        constants: &PyramidConstants,
+    ) {
+        let device = self.pipeline_layout.device();
+        let mut views = frame.views.borrow_mut();
+
+        let mut input = (depth_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        for level in 0..image.mip_levels() {
+            let set = &frame.descriptor_sets[level as usize];
+            let output = image.get_mip_view(vk::ImageAspectFlags::COLOR, level);
+
+            {
+                let mut writer = DescriptorWriter::new(device.clone());
+                writer
+                    .write_images(
+                        set,
+                        0,
+                        0,
+                        vk::DescriptorType::STORAGE_IMAGE,
+                        None,
+                        &[(&output, vk::ImageLayout::GENERAL)],
+                    )
+                    .write_image(
+                        set,
+                        &self.sampler,
+                        &input.0,
+                        input.1,
+                        1,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    );
+                writer.flush();
+            }
+
+            let multisampled = input.0.image().samples() != vk::SampleCountFlags::TYPE_1;
+            let pipeline = if multisampled {
+                &self.pipelines.1
+            } else {
+                &self.pipelines.0
+            };
+
+            let extent = vk::Extent2D {
+                width: (image.extent().width >> level).max(1),
+                height: (image.extent().height >> level).max(1),
+            };
+            cmd_buf.bind_pipeline(pipeline.as_ref());
+            cmd_buf.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                &self.pipeline_layout,
+                0,
+                &[set],
+            );
+            cmd_buf.push_constants(
+                &self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                constants,
+            );
+            cmd_buf.dispatch(
+                extent.width.div_ceil(WORKGROUP_SIZE),
+                extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+
+            if level + 1 < image.mip_levels() {
+                cmd_buf.memory_barrier(
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_SAMPLED_READ,
+                );
+            }
+
+            let (previous, _) = std::mem::replace(&mut input, (output, vk::ImageLayout::GENERAL));
+            views.push(previous);
+        }
+        views.push(input.0);
+    }
+}
+
+impl HotReload for DepthPyramidPass {
+    type Pipelines = (Arc<ComputePipeline>, Arc<ComputePipeline>);
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Self::Pipelines {
+        DepthPyramidPass::_create_pipelines(
+            self.pipeline_layout.device(),
+            shader_registry,
+            &self.shader_ids,
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Self::Pipelines {
+        &mut self.pipelines
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::gpu::PipelineStatistics;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PassTime {
+    pub name: &'static str,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    // Time since the previous frame started
+    pub cpu_frame_time: Duration,
+    // Time spent recording the frame's commands
+    pub cpu_record_time: Duration,
+    // From the first pass starting to the last one finishing
+    pub gpu_frame_time: Option<Duration>,
+    // Timestamps are only read back once a frame's fence has signaled, so GPU
+    // times lag behind the rest by the number of frames in flight. Empty when
+    // the device doesn't support timestamps
+    pub gpu_passes: Vec<PassTime>,
+    // Covers the whole frame, with the same latency as the GPU times. None
+    // without the pipelineStatisticsQuery feature
+    pub pipeline_statistics: Option<PipelineStatistics>,
+    // Batches skipped by occlusion culling
+    pub occluded_batches: u32,
+    // Objects culled on the CPU before the GPU's frustum culling, and the
+    // batches left without any objects, whose draws are skipped
+    pub cpu_frustum_culled: u32,
+    pub cpu_distance_culled: u32,
+    pub cpu_culled_batches: u32,
+    pub draw_calls: u32,
+    pub dispatches: u32,
+}
+
+impl FrameStats {
+    pub fn fps(&self) -> f32 {
+        let seconds = self.cpu_frame_time.as_secs_f32();
+        if seconds > 0.0 {
+            1.0 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    // One line summary, short enough for a window title
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{:.0} fps | cpu {:.2} ms (record {:.2} ms)",
+            self.fps(),
+            _millis(self.cpu_frame_time),
+            _millis(self.cpu_record_time),
+        );
+        if let Some(gpu_frame_time) = self.gpu_frame_time {
+            write!(summary, " | gpu {:.2} ms", _millis(gpu_frame_time)).unwrap();
+        }
+        write!(
+            summary,
+            " | {} draws, {} dispatches",
+            self.draw_calls, self.dispatches
+        )
+        .unwrap();
+        summary
+    }
+
+    // The summary followed by a line for each pass
+    pub fn report(&self) -> String {
+        let mut report = self.summary();
+        for pass in &self.gpu_passes {
+            write!(
+                report,
+                "\n  {:<20} {:>7.3} ms",
+                pass.name,
+                _millis(pass.time)
+            )
+            .unwrap();
+        }
+        if let Some(stats) = &self.pipeline_statistics {
+            write!(report, "\n  {:#?}", stats).unwrap();
+        }
+        if self.occluded_batches > 0 {
+            write!(report, "\n  {} batches occluded", self.occluded_batches).unwrap();
+        }
+        if self.cpu_frustum_culled + self.cpu_distance_culled > 0 {
+            write!(
+                report,
+                "\n  cpu culled {} outside the frustum, {} by distance, {} batches skipped",
+                self.cpu_frustum_culled, self.cpu_distance_culled, self.cpu_culled_batches
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+fn _millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
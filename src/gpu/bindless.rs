@@ -0,0 +1,123 @@
+use super::{DescriptorPool, DescriptorSet, DescriptorSetLayout, Device, ImageView, Sampler};
+use ash::vk;
+use std::sync::Arc;
+
+// A single large array of combined image samplers that stays bound for the
+// whole frame. Textures are inserted into free slots and referenced from
+// shaders by their u32 slot index, so materials don't need their own sets
+pub struct BindlessTextureTable {
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    descriptor_set: DescriptorSet,
+    capacity: u32,
+    // Keeps what's written to the set alive while its slot is in use
+    slots: Vec<Option<(Arc<Sampler>, Arc<ImageView>)>>,
+    free_slots: Vec<u32>,
+}
+
+impl BindlessTextureTable {
+    pub fn new(device: Arc<Device>, capacity: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        assert!(
+            device.supports_bindless(),
+            "device doesn't support bindless descriptors"
+        );
+
+        let descriptor_set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let textures_binding = builder
+                .binding()
+                .descriptor(capacity, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(stage_flags)
+                .flags(
+                    vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                        | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                        | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                        | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+                );
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+                &[textures_binding],
+            )
+        };
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+            1,
+            &[(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, capacity)],
+        );
+
+        let descriptor_set = descriptor_pool
+            .allocate_variable(&[&descriptor_set_layout], &[capacity])
+            .into_vec()
+            .pop()
+            .unwrap();
+
+        Self {
+            descriptor_set_layout,
+            descriptor_set,
+            capacity,
+            slots: vec![],
+            free_slots: vec![],
+        }
+    }
+
+    pub fn descriptor_set_layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.descriptor_set_layout
+    }
+
+    pub fn descriptor_set(&self) -> &DescriptorSet {
+        &self.descriptor_set
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Writes the texture into a free slot and returns the slot index. Slots
+    // can be written while the set is bound by frames in flight
+    pub fn insert(&mut self, sampler: &Arc<Sampler>, image_view: &Arc<ImageView>) -> u32 {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                let slot: u32 = self.slots.len().try_into().unwrap();
+                assert!(slot < self.capacity, "bindless texture table is full");
+                self.slots.push(None);
+                slot
+            }
+        };
+
+        self.descriptor_set.write_image(
+            sampler,
+            image_view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            0,
+            slot,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+
+        self.slots[slot as usize] = Some((sampler.clone(), image_view.clone()));
+        slot
+    }
+
+    // Frees the slot and returns its sampler and view. Frames in flight may
+    // still sample them, so they should be kept alive until those complete
+    // and the slot shouldn't be referenced by anything recorded afterwards
+    pub fn remove(&mut self, slot: u32) -> (Arc<Sampler>, Arc<ImageView>) {
+        let texture = self.slots[slot as usize]
+            .take()
+            .expect("bindless texture slot is already free");
+        self.free_slots.push(slot);
+        texture
+    }
+}
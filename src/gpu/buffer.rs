@@ -1,6 +1,6 @@
-use super::{Device, HasRawAshHandle, HasRawVkHandle};
+use super::{Device, HasRawAshHandle, HasRawVkHandle, MemoryPriority};
 use ash::vk;
-use std::{ffi::c_void, mem::size_of, sync::Arc};
+use std::{ffi::c_void, sync::Arc};
 use vma::Alloc;
 
 pub struct Buffer {
@@ -19,7 +19,17 @@ impl Buffer {
         buffer_usage: vk::BufferUsageFlags,
         memory_usage: vma::MemoryUsage,
         allocation_flags: vma::AllocationCreateFlags,
+        priority: MemoryPriority,
     ) -> Self {
+        // The allocator adds the matching DEVICE_ADDRESS memory allocate flag,
+        // as long as it was created with BUFFER_DEVICE_ADDRESS
+        if buffer_usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            assert!(
+                device.supports_buffer_device_address(),
+                "buffer device addresses aren't supported"
+            );
+        }
+
         let vk_buffer_info = vk::BufferCreateInfo {
             s_type: vk::StructureType::BUFFER_CREATE_INFO,
             p_next: std::ptr::null(),
@@ -38,7 +48,7 @@ impl Buffer {
             preferred_flags: vk::MemoryPropertyFlags::empty(),
             memory_type_bits: 0,
             user_data: 0,
-            priority: 0.0,
+            priority: priority.value(),
         };
 
         let (vk_buffer, vma_allocation) = unsafe {
@@ -71,17 +81,31 @@ impl Buffer {
                 .get_buffer_device_address(&vk_addr_info)
         };
 
-        DeviceAddress::new(&self, vk_device_address)
+        DeviceAddress::new(self, vk_device_address)
     }
 
-    pub fn copy_nonoverlapping<T>(&self, src: &[T]) -> () {
+    pub fn copy_nonoverlapping<T>(&self, src: &[T]) {
         unsafe {
-            let size = size_of::<T>() * src.len();
+            let size = size_of_val(src);
             let dst = self.vma_allocation_info.mapped_data;
 
             std::ptr::copy_nonoverlapping(src.as_ptr() as *const c_void, dst, size);
         }
     }
+
+    // Like `copy_nonoverlapping` but starting `offset` bytes into the buffer
+    pub fn copy_nonoverlapping_at<T>(&self, offset: usize, src: &[T]) {
+        let size = size_of_val(src);
+        assert!(
+            offset + size <= self.vma_allocation_info.size as usize,
+            "copy out of buffer bounds"
+        );
+
+        unsafe {
+            let dst = (self.vma_allocation_info.mapped_data as *mut u8).add(offset);
+            std::ptr::copy_nonoverlapping(src.as_ptr() as *const u8, dst, size);
+        }
+    }
 }
 
 impl HasRawVkHandle<vk::Buffer> for Buffer {
@@ -100,14 +124,15 @@ impl Drop for Buffer {
 }
 
 pub struct DeviceAddress<'t> {
-    buffer: &'t Buffer,
+    // Ties the address' lifetime to the buffer
+    _buffer: &'t Buffer,
     vk_device_address: vk::DeviceAddress,
 }
 
 impl DeviceAddress<'_> {
     pub fn new<'t>(buffer: &'t Buffer, vk_device_address: vk::DeviceAddress) -> DeviceAddress<'t> {
         DeviceAddress::<'t> {
-            buffer,
+            _buffer: buffer,
             vk_device_address,
         }
     }
@@ -0,0 +1,219 @@
+use super::{Buffer, Device, MemoryPriority, UploadQueue};
+use ash::vk;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+
+// Suballocates vertex and index data out of a few large device local buffers
+// instead of creating a buffer per mesh. Freed slices go back on a per-block
+// free list and are reused by later allocations
+pub struct BufferArena {
+    state: Rc<RefCell<ArenaState>>,
+}
+
+struct ArenaState {
+    device: Arc<Device>,
+    allocator: Arc<vma::Allocator>,
+    usage: vk::BufferUsageFlags,
+    block_size: u64,
+    alignment: u64,
+    blocks: Vec<ArenaBlock>,
+}
+
+struct ArenaBlock {
+    buffer: Rc<Buffer>,
+    size: u64,
+    // (offset, size) of each free range, sorted by offset and never adjacent
+    free_ranges: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BufferArenaStats {
+    pub block_count: usize,
+    pub capacity: u64,
+    pub used: u64,
+    pub free_range_count: usize,
+    pub largest_free_range: u64,
+}
+
+impl BufferArenaStats {
+    // 0 when all free space is in one range, approaching 1 as it gets split
+    // into many small ones
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.capacity - self.used;
+        if free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_range as f32 / free as f32
+        }
+    }
+}
+
+impl BufferArena {
+    // `usage` gets TRANSFER_DST added so slices can be uploaded to. Blocks
+    // are `block_size` bytes unless an allocation needs a bigger one
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<vma::Allocator>,
+        usage: vk::BufferUsageFlags,
+        block_size: usize,
+    ) -> Self {
+        // Covers index and vertex offsets, storage buffers need more on some
+        // devices
+        let mut alignment = 16;
+        if usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER) {
+            let limits = device.physical_device().device_limits();
+            alignment = alignment.max(limits.min_storage_buffer_offset_alignment);
+        }
+
+        Self {
+            state: Rc::new(RefCell::new(ArenaState {
+                device,
+                allocator,
+                usage: usage | vk::BufferUsageFlags::TRANSFER_DST,
+                block_size: block_size as u64,
+                alignment,
+                blocks: vec![],
+            })),
+        }
+    }
+
+    pub fn allocate(&self, size: usize) -> BufferSlice {
+        let mut state = self.state.borrow_mut();
+        let size = (size.max(1) as u64).next_multiple_of(state.alignment);
+
+        // First fit in the existing blocks, then a new block
+        let found = state
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, block)| block.allocate(size).map(|offset| (index, offset)));
+
+        let (block_index, offset) = match found {
+            Some(found) => found,
+            None => {
+                let block_size = state.block_size.max(size);
+                let buffer = Buffer::new(
+                    state.device.clone(),
+                    state.allocator.clone(),
+                    block_size as usize,
+                    state.usage,
+                    vma::MemoryUsage::AutoPreferDevice,
+                    vma::AllocationCreateFlags::empty(),
+                    MemoryPriority::Normal,
+                );
+                let mut block = ArenaBlock {
+                    buffer: Rc::new(buffer),
+                    size: block_size,
+                    free_ranges: vec![(0, block_size)],
+                };
+                let offset = block.allocate(size).unwrap();
+                state.blocks.push(block);
+                (state.blocks.len() - 1, offset)
+            }
+        };
+
+        BufferSlice {
+            arena: Rc::downgrade(&self.state),
+            buffer: state.blocks[block_index].buffer.clone(),
+            block_index,
+            offset,
+            size,
+        }
+    }
+
+    // Copies `data` into a new slice with the next frame's uploads
+    pub fn upload<T: Copy>(&self, uploads: &UploadQueue, data: &[T]) -> BufferSlice {
+        let slice = self.allocate(size_of_val(data));
+        uploads.copy_to_buffer(slice.buffer.clone(), slice.offset, data);
+        slice
+    }
+
+    pub fn stats(&self) -> BufferArenaStats {
+        let state = self.state.borrow();
+        let mut stats = BufferArenaStats {
+            block_count: state.blocks.len(),
+            ..Default::default()
+        };
+
+        for block in &state.blocks {
+            stats.capacity += block.size;
+            stats.used += block.size;
+            for (_, size) in &block.free_ranges {
+                stats.used -= size;
+                stats.free_range_count += 1;
+                stats.largest_free_range = stats.largest_free_range.max(*size);
+            }
+        }
+
+        stats
+    }
+}
+
+impl ArenaBlock {
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let index = self.free_ranges.iter().position(|(_, x)| *x >= size)?;
+        let (offset, free_size) = self.free_ranges[index];
+        if free_size == size {
+            self.free_ranges.remove(index);
+        } else {
+            self.free_ranges[index] = (offset + size, free_size - size);
+        }
+        Some(offset)
+    }
+
+    // Merges the range back with its free neighbours
+    fn free(&mut self, offset: u64, size: u64) {
+        let index = self.free_ranges.partition_point(|(x, _)| *x < offset);
+        self.free_ranges.insert(index, (offset, size));
+
+        if index + 1 < self.free_ranges.len() {
+            let (next_offset, next_size) = self.free_ranges[index + 1];
+            if offset + size == next_offset {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+
+        if index > 0 {
+            let (prev_offset, prev_size) = self.free_ranges[index - 1];
+            if prev_offset + prev_size == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+    }
+}
+
+// Part of one of a `BufferArena`'s buffers. The range is returned to the arena
+// when dropped, so like a `Buffer` it has to outlive any GPU work using it
+pub struct BufferSlice {
+    arena: Weak<RefCell<ArenaState>>,
+    buffer: Rc<Buffer>,
+    block_index: usize,
+    offset: u64,
+    size: u64,
+}
+
+impl BufferSlice {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    // Rounded up to the arena's alignment
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+impl Drop for BufferSlice {
+    fn drop(&mut self) {
+        if let Some(arena) = self.arena.upgrade() {
+            arena.borrow_mut().blocks[self.block_index].free(self.offset, self.size);
+        }
+    }
+}
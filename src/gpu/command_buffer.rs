@@ -1,12 +1,79 @@
 use super::{
-    Buffer, DescriptorSet, Device, Framebuffer, Image, Pipeline, PipelineLayout, QueueFamily,
-    RenderPass,
+    Buffer, CommandTrace, DescriptorSet, Device, Framebuffer, Image, ImageView, Pipeline,
+    PipelineLayout, QueryPool, QueueFamily, RenderPass, TracedCommand,
 };
 use super::{HasRawAshHandle, HasRawVkHandle};
-use ash::vk;
-use std::rc::Rc;
+use ash::vk::{self, Handle};
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
 
+// What `CommandBuffer::begin_rendering` renders into. Starts out as a single
+// layer covering `extent` without any attachments
+pub struct RenderingConfig<'a> {
+    flags: vk::RenderingFlags,
+    render_area: vk::Rect2D,
+    layer_count: u32,
+    view_mask: u32,
+    color_attachments: &'a [vk::RenderingAttachmentInfo],
+    depth_attachment: Option<vk::RenderingAttachmentInfo>,
+    stencil_attachment: Option<vk::RenderingAttachmentInfo>,
+}
+
+impl<'a> RenderingConfig<'a> {
+    pub fn new(extent: vk::Extent2D) -> Self {
+        Self {
+            flags: vk::RenderingFlags::empty(),
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            layer_count: 1,
+            view_mask: 0,
+            color_attachments: &[],
+            depth_attachment: None,
+            stencil_attachment: None,
+        }
+    }
+
+    pub fn flags(mut self, flags: vk::RenderingFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn render_area(mut self, render_area: vk::Rect2D) -> Self {
+        self.render_area = render_area;
+        self
+    }
+
+    pub fn layer_count(mut self, layer_count: u32) -> Self {
+        self.layer_count = layer_count;
+        self
+    }
+
+    pub fn view_mask(mut self, view_mask: u32) -> Self {
+        self.view_mask = view_mask;
+        self
+    }
+
+    pub fn color_attachments(
+        mut self,
+        color_attachments: &'a [vk::RenderingAttachmentInfo],
+    ) -> Self {
+        self.color_attachments = color_attachments;
+        self
+    }
+
+    pub fn depth_attachment(mut self, depth_attachment: vk::RenderingAttachmentInfo) -> Self {
+        self.depth_attachment = Some(depth_attachment);
+        self
+    }
+
+    pub fn stencil_attachment(mut self, stencil_attachment: vk::RenderingAttachmentInfo) -> Self {
+        self.stencil_attachment = Some(stencil_attachment);
+        self
+    }
+}
+
 pub struct CommandPool {
     device: Arc<Device>,
     vk_command_pool: vk::CommandPool,
@@ -17,7 +84,7 @@ impl CommandPool {
         device: Arc<Device>,
         queue_family: &QueueFamily,
         flags: vk::CommandPoolCreateFlags,
-    ) -> Rc<Self> {
+    ) -> Arc<Self> {
         let create_info = vk::CommandPoolCreateInfo {
             s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
             p_next: std::ptr::null(),
@@ -32,8 +99,8 @@ impl CommandPool {
                 .expect("failed to create command buffer pool")
         };
 
-        Rc::new(Self {
-            device: device,
+        Arc::new(Self {
+            device,
             vk_command_pool,
         })
     }
@@ -42,11 +109,15 @@ impl CommandPool {
         &self.device
     }
 
+    /// # Safety
+    ///
+    /// The handle must not be used after the pool is dropped, or to reset it
+    /// while its command buffers are in use
     pub unsafe fn handle(&self) -> vk::CommandPool {
         self.vk_command_pool
     }
 
-    pub fn allocate_one(self: &Rc<CommandPool>, level: vk::CommandBufferLevel) -> CommandBuffer {
+    pub fn allocate_one(self: &Arc<CommandPool>, level: vk::CommandBufferLevel) -> CommandBuffer {
         let allocate_info = vk::CommandBufferAllocateInfo {
             s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
             p_next: std::ptr::null(),
@@ -64,6 +135,16 @@ impl CommandPool {
 
         CommandBuffer::new(self.clone(), vk_command_buffer)
     }
+
+    // Returns every buffer allocated from the pool to the initial state
+    pub fn reset(&self) {
+        unsafe {
+            self.device
+                .get_ash_handle()
+                .reset_command_pool(self.vk_command_pool, vk::CommandPoolResetFlags::empty())
+                .expect("failed to reset command pool");
+        }
+    }
 }
 
 impl HasRawVkHandle<vk::CommandPool> for CommandPool {
@@ -82,28 +163,86 @@ impl Drop for CommandPool {
     }
 }
 
+// A secondary command buffer recorded on another thread, with the stats and
+// trace to fold into the primary that executes it
+pub struct SecondaryRecording {
+    pub command_buffer: vk::CommandBuffer,
+    pub draw_count: u32,
+    pub dispatch_count: u32,
+    pub trace: Option<CommandTrace>,
+}
+
+// Command buffers and their pools are `Send` but not `Sync`: Vulkan requires a
+// pool and everything allocated from it to be used by one thread at a time
 pub struct CommandBuffer {
-    pool: Rc<CommandPool>,
+    pool: Arc<CommandPool>,
     vk_command_buffer: vk::CommandBuffer,
+    trace: RefCell<Option<CommandTrace>>,
+    // Counted since the last `begin`, for frame stats
+    draw_count: Cell<u32>,
+    dispatch_count: Cell<u32>,
 }
 
 impl CommandBuffer {
-    pub fn new(pool: Rc<CommandPool>, vk_command_buffer: vk::CommandBuffer) -> Self {
+    pub fn new(pool: Arc<CommandPool>, vk_command_buffer: vk::CommandBuffer) -> Self {
         Self {
             pool,
             vk_command_buffer,
+            trace: RefCell::new(None),
+            draw_count: Cell::new(0),
+            dispatch_count: Cell::new(0),
         }
     }
 
-    pub fn pool(&self) -> &Rc<CommandPool> {
+    // Starts recording the structure of the commands that follow into a
+    // `CommandTrace`, for debug dumps
+    pub fn begin_trace(&self) {
+        *self.trace.borrow_mut() = Some(CommandTrace::new());
+    }
+
+    pub fn end_trace(&self) -> Option<CommandTrace> {
+        self.trace.borrow_mut().take()
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.borrow().is_some()
+    }
+
+    pub fn trace_name<H: Handle>(&self, handle: H, name: &str) {
+        self._trace(|trace| trace.set_name(handle.as_raw(), name));
+    }
+
+    fn _trace(&self, f: impl FnOnce(&mut CommandTrace)) {
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            f(trace);
+        }
+    }
+
+    pub fn pool(&self) -> &Arc<CommandPool> {
         &self.pool
     }
 
+    /// # Safety
+    ///
+    /// The handle must not be used after the command buffer is dropped, and
+    /// commands recorded through it bypass the command trace
     pub unsafe fn handle(&self) -> vk::CommandBuffer {
         self.vk_command_buffer
     }
 
-    pub fn begin(&self, flags: vk::CommandBufferUsageFlags) -> () {
+    // Draw calls recorded since `begin`. Indirect draws count each command
+    pub fn draw_count(&self) -> u32 {
+        self.draw_count.get()
+    }
+
+    pub fn dispatch_count(&self) -> u32 {
+        self.dispatch_count.get()
+    }
+
+    pub fn begin(&self, flags: vk::CommandBufferUsageFlags) {
+        self.draw_count.set(0);
+        self.dispatch_count.set(0);
+
         unsafe {
             self.pool
                 .device
@@ -121,12 +260,65 @@ impl CommandBuffer {
         }
     }
 
+    // Secondaries don't continue a render pass of the primary, so they begin
+    // their own dynamic rendering if they draw. Queries active on the primary
+    // aren't inherited
+    pub fn begin_secondary(&self, flags: vk::CommandBufferUsageFlags) {
+        self.draw_count.set(0);
+        self.dispatch_count.set(0);
+
+        let inheritance_info = vk::CommandBufferInheritanceInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_INHERITANCE_INFO,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.pool
+                .device
+                .get_ash_handle()
+                .begin_command_buffer(
+                    self.vk_command_buffer,
+                    &vk::CommandBufferBeginInfo {
+                        s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+                        p_next: std::ptr::null(),
+                        flags,
+                        p_inheritance_info: &inheritance_info,
+                    },
+                )
+                .expect("failed to begin secondary command buffer recording");
+        }
+    }
+
+    // The secondary has to stay alive until the primary finishes executing
+    pub fn execute_secondary(&self, recording: SecondaryRecording) {
+        self.draw_count
+            .set(self.draw_count.get() + recording.draw_count);
+        self.dispatch_count
+            .set(self.dispatch_count.get() + recording.dispatch_count);
+        if let Some(trace) = recording.trace {
+            self._trace(|x| x.append(trace));
+        }
+
+        unsafe {
+            self.pool
+                .device
+                .get_ash_handle()
+                .cmd_execute_commands(self.vk_command_buffer, &[recording.command_buffer]);
+        }
+    }
+
     pub fn clear_color_image(
         &self,
         image: &Image,
         clear_value: vk::ClearColorValue,
         clear_range: &[vk::ImageSubresourceRange],
-    ) -> () {
+    ) {
+        self._trace(|trace| {
+            trace.push(TracedCommand::ClearColor {
+                image: unsafe { image.get_vk_handle() }.as_raw(),
+            })
+        });
+
         unsafe {
             self.pool.device.get_ash_handle().cmd_clear_color_image(
                 self.vk_command_buffer,
@@ -145,7 +337,7 @@ impl CommandBuffer {
         render_area: vk::Rect2D,
         clear_values: Option<&[vk::ClearValue]>,
         contents: vk::SubpassContents,
-    ) -> () {
+    ) {
         unsafe {
             let mut info = vk::RenderPassBeginInfo {
                 s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
@@ -170,16 +362,16 @@ impl CommandBuffer {
         }
     }
 
-    pub fn begin_rendering(
-        &self,
-        flags: vk::RenderingFlags,
-        render_area: vk::Rect2D,
-        layer_count: u32,
-        view_mask: u32,
-        color_attachments: Option<&[vk::RenderingAttachmentInfo]>,
-        depth_attachment: Option<vk::RenderingAttachmentInfo>,
-        stencil_attachment: Option<vk::RenderingAttachmentInfo>,
-    ) -> () {
+    pub fn begin_rendering(&self, config: RenderingConfig) {
+        let RenderingConfig {
+            flags,
+            render_area,
+            layer_count,
+            view_mask,
+            color_attachments,
+            depth_attachment,
+            stencil_attachment,
+        } = config;
         let mut info = vk::RenderingInfo {
             s_type: vk::StructureType::RENDERING_INFO,
             p_next: std::ptr::null(),
@@ -193,41 +385,74 @@ impl CommandBuffer {
             p_stencil_attachment: std::ptr::null(),
         };
 
+        self._trace(|trace| {
+            trace.push(TracedCommand::Rendering {
+                color: color_attachments
+                    .iter()
+                    .flat_map(|x| [x.image_view, x.resolve_image_view])
+                    .filter(|x| *x != vk::ImageView::null())
+                    .map(|x| x.as_raw())
+                    .collect(),
+                depth: depth_attachment.map(|x| x.image_view.as_raw()),
+                stencil: stencil_attachment.map(|x| x.image_view.as_raw()),
+                draws: 0,
+            })
+        });
+
         unsafe {
-            if let Some(color) = color_attachments {
-                info.color_attachment_count = color.len().try_into().unwrap();
-                info.p_color_attachments = color.as_ptr();
+            if !color_attachments.is_empty() {
+                info.color_attachment_count = color_attachments.len().try_into().unwrap();
+                info.p_color_attachments = color_attachments.as_ptr();
             }
 
-            if let Some(depth) = depth_attachment {
-                info.p_depth_attachment = &depth;
+            // Borrow the attachments so they outlive the call
+            if let Some(depth) = &depth_attachment {
+                info.p_depth_attachment = depth;
             }
 
-            if let Some(stencil) = stencil_attachment {
-                info.p_stencil_attachment = &stencil;
+            if let Some(stencil) = &stencil_attachment {
+                info.p_stencil_attachment = stencil;
             }
 
             self.pool
                 .device
-                .get_ash_handle()
+                .vulkan13()
                 .cmd_begin_rendering(self.vk_command_buffer, &info);
         }
     }
 
-    pub fn bind_pipeline<T>(&self, pipeline: &T) -> ()
+    pub fn bind_pipeline<T>(&self, pipeline: &T)
     where
         T: Pipeline + HasRawVkHandle<vk::Pipeline>,
     {
         unsafe {
             self.pool.device.get_ash_handle().cmd_bind_pipeline(
                 self.vk_command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.bind_point(),
                 pipeline.get_vk_handle(),
             );
         }
     }
 
-    pub fn set_viewport(&self, first_viewport: u32, viewports: &[vk::Viewport]) -> () {
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        self._trace(|trace| {
+            trace.push(TracedCommand::Dispatch {
+                groups: [group_count_x, group_count_y, group_count_z],
+            })
+        });
+        self.dispatch_count.set(self.dispatch_count.get() + 1);
+
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_dispatch(
+                self.vk_command_buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            );
+        }
+    }
+
+    pub fn set_viewport(&self, first_viewport: u32, viewports: &[vk::Viewport]) {
         unsafe {
             self.pool.device.get_ash_handle().cmd_set_viewport(
                 self.vk_command_buffer,
@@ -237,7 +462,7 @@ impl CommandBuffer {
         }
     }
 
-    pub fn set_scissor(&self, first_scissor: u32, scissors: &[vk::Rect2D]) -> () {
+    pub fn set_scissor(&self, first_scissor: u32, scissors: &[vk::Rect2D]) {
         unsafe {
             self.pool.device.get_ash_handle().cmd_set_scissor(
                 self.vk_command_buffer,
@@ -247,12 +472,101 @@ impl CommandBuffer {
         }
     }
 
+    // Extended dynamic state. The pipeline needs the matching
+    // `vk::DynamicState`, and before 1.3 check
+    // `Vulkan13Dispatch::supports_extended_dynamic_state` first
+    pub fn set_cull_mode(&self, cull_mode: vk::CullModeFlags) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_cull_mode(self.vk_command_buffer, cull_mode)
+        }
+    }
+
+    pub fn set_front_face(&self, front_face: vk::FrontFace) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_front_face(self.vk_command_buffer, front_face)
+        }
+    }
+
+    pub fn set_primitive_topology(&self, primitive_topology: vk::PrimitiveTopology) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_primitive_topology(self.vk_command_buffer, primitive_topology)
+        }
+    }
+
+    pub fn set_depth_test_enable(&self, enable: bool) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_depth_test_enable(self.vk_command_buffer, enable)
+        }
+    }
+
+    pub fn set_depth_write_enable(&self, enable: bool) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_depth_write_enable(self.vk_command_buffer, enable)
+        }
+    }
+
+    pub fn set_depth_compare_op(&self, compare_op: vk::CompareOp) {
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
+                .cmd_set_depth_compare_op(self.vk_command_buffer, compare_op)
+        }
+    }
+
+    // Sets viewport 0 and scissor 0 to cover the whole of `extent`
+    pub fn set_full_viewport_scissor(&self, extent: vk::Extent2D) {
+        self._set_full_viewport_scissor(extent, false);
+    }
+
+    // Same as `set_full_viewport_scissor` but with a negative viewport height,
+    // so that +Y points up in clip space like in OpenGL and glam's projection
+    // matrices can be used without flipping them
+    pub fn set_full_viewport_scissor_flipped(&self, extent: vk::Extent2D) {
+        self._set_full_viewport_scissor(extent, true);
+    }
+
+    fn _set_full_viewport_scissor(&self, extent: vk::Extent2D, flip_y: bool) {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: if flip_y { height } else { 0.0 },
+            width,
+            height: if flip_y { -height } else { height },
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+
+        self.set_viewport(0, &[viewport]);
+        self.set_scissor(0, &[scissor]);
+    }
+
     pub fn bind_index_buffer(
         &self,
         buffer: &Buffer,
         offset: vk::DeviceSize,
         index_type: vk::IndexType,
-    ) -> () {
+    ) {
         unsafe {
             self.pool.device.get_ash_handle().cmd_bind_index_buffer(
                 self.vk_command_buffer,
@@ -264,7 +578,7 @@ impl CommandBuffer {
     }
 
     // TODO: u64's should be vk::DeviceSize
-    pub fn bind_vertex_buffers(&self, first_binding: u32, buffers: &[(&Buffer, u64)]) -> () {
+    pub fn bind_vertex_buffers(&self, first_binding: u32, buffers: &[(&Buffer, u64)]) {
         unsafe {
             let vk_buffer: Vec<_> = buffers.iter().map(|x| x.0.get_vk_handle()).collect();
             let vk_offsets: Vec<_> = buffers.iter().map(|x| x.1).collect();
@@ -301,13 +615,38 @@ impl CommandBuffer {
         }
     }
 
+    pub fn push_constants<T: bytemuck::Pod>(
+        &self,
+        layout: &PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        constants: &T,
+    ) {
+        assert!(
+            offset.is_multiple_of(4),
+            "push constant offset must be a multiple of 4"
+        );
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_push_constants(
+                self.vk_command_buffer,
+                layout.get_vk_handle(),
+                stage_flags,
+                offset,
+                bytemuck::bytes_of(constants),
+            );
+        }
+    }
+
     pub fn draw(
         &self,
         vertex_count: u32,
         instance_count: u32,
         first_vertex: u32,
         first_instance: u32,
-    ) -> () {
+    ) {
+        self._trace(|trace| trace.record_draw());
+        self.draw_count.set(self.draw_count.get() + 1);
+
         unsafe {
             self.pool.device.get_ash_handle().cmd_draw(
                 self.vk_command_buffer,
@@ -326,7 +665,10 @@ impl CommandBuffer {
         first_index: u32,
         vertex_offset: i32,
         first_instance: u32,
-    ) -> () {
+    ) {
+        self._trace(|trace| trace.record_draw());
+        self.draw_count.set(self.draw_count.get() + 1);
+
         unsafe {
             self.pool.device.get_ash_handle().cmd_draw_indexed(
                 self.vk_command_buffer,
@@ -339,7 +681,29 @@ impl CommandBuffer {
         }
     }
 
-    pub fn end_render_pass(&self) -> () {
+    // Reads `draw_count` VkDrawIndexedIndirectCommands from `buffer`
+    pub fn draw_indexed_indirect(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        self._trace(|trace| trace.record_draw());
+        self.draw_count.set(self.draw_count.get() + draw_count);
+
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_draw_indexed_indirect(
+                self.vk_command_buffer,
+                buffer.get_vk_handle(),
+                offset,
+                draw_count,
+                stride,
+            );
+        }
+    }
+
+    pub fn end_render_pass(&self) {
         unsafe {
             self.pool
                 .device
@@ -348,16 +712,16 @@ impl CommandBuffer {
         }
     }
 
-    pub fn end_rendering(&self) -> () {
+    pub fn end_rendering(&self) {
         unsafe {
             self.pool
                 .device
-                .get_ash_handle()
+                .vulkan13()
                 .cmd_end_rendering(self.vk_command_buffer);
         }
     }
 
-    pub fn end(&self) -> () {
+    pub fn end(&self) {
         unsafe {
             self.pool
                 .device
@@ -372,12 +736,20 @@ impl CommandBuffer {
         image: &Image,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
-    ) -> () {
+    ) {
         let aspect_mask = match new_layout {
             vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => vk::ImageAspectFlags::DEPTH,
             _ => vk::ImageAspectFlags::COLOR,
         };
 
+        self._trace(|trace| {
+            trace.push(TracedCommand::Barrier {
+                image: unsafe { image.get_vk_handle() }.as_raw(),
+                old_layout,
+                new_layout,
+            })
+        });
+
         unsafe {
             let image_barrier = vk::ImageMemoryBarrier2 {
                 s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2_KHR,
@@ -415,21 +787,97 @@ impl CommandBuffer {
 
             self.pool
                 .device
-                .get_ash_handle()
+                .vulkan13()
                 .cmd_pipeline_barrier2(self.vk_command_buffer, &dep_info)
         }
     }
 
-    pub fn blit_image(&self, blit_image_info: &vk::BlitImageInfo2) -> () {
+    // Records any number of barriers with one call
+    pub fn pipeline_barrier(
+        &self,
+        memory_barriers: &[vk::MemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        self._trace(|trace| {
+            for barrier in memory_barriers {
+                trace.push(TracedCommand::MemoryBarrier {
+                    src_stage: barrier.src_stage_mask,
+                    dst_stage: barrier.dst_stage_mask,
+                })
+            }
+            for barrier in image_barriers {
+                trace.push(TracedCommand::Barrier {
+                    image: barrier.image.as_raw(),
+                    old_layout: barrier.old_layout,
+                    new_layout: barrier.new_layout,
+                })
+            }
+        });
+
+        let dep_info = vk::DependencyInfo {
+            s_type: vk::StructureType::DEPENDENCY_INFO,
+            p_next: std::ptr::null(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barrier_count: memory_barriers.len() as u32,
+            p_memory_barriers: memory_barriers.as_ptr(),
+            buffer_memory_barrier_count: 0,
+            p_buffer_memory_barriers: std::ptr::null(),
+            image_memory_barrier_count: image_barriers.len() as u32,
+            p_image_memory_barriers: image_barriers.as_ptr(),
+        };
+
         unsafe {
             self.pool
                 .device
-                .get_ash_handle()
+                .vulkan13()
+                .cmd_pipeline_barrier2(self.vk_command_buffer, &dep_info)
+        }
+    }
+
+    // For buffer hazards, which unlike images don't need layout transitions
+    pub fn memory_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags2,
+        src_access_mask: vk::AccessFlags2,
+        dst_stage_mask: vk::PipelineStageFlags2,
+        dst_access_mask: vk::AccessFlags2,
+    ) {
+        let memory_barrier = vk::MemoryBarrier2 {
+            s_type: vk::StructureType::MEMORY_BARRIER_2,
+            p_next: std::ptr::null(),
+            src_stage_mask,
+            src_access_mask,
+            dst_stage_mask,
+            dst_access_mask,
+        };
+
+        self.pipeline_barrier(&[memory_barrier], &[]);
+    }
+
+    pub fn blit_image(&self, blit_image_info: &vk::BlitImageInfo2) {
+        self._trace(|trace| {
+            trace.push(TracedCommand::Blit {
+                src: blit_image_info.src_image.as_raw(),
+                dst: blit_image_info.dst_image.as_raw(),
+            })
+        });
+
+        unsafe {
+            self.pool
+                .device
+                .vulkan13()
                 .cmd_blit_image2(self.vk_command_buffer, blit_image_info)
         }
     }
 
-    pub fn copy_buffer(&self, src: &Buffer, dst: &Buffer, regions: &[vk::BufferCopy]) -> () {
+    pub fn copy_buffer(&self, src: &Buffer, dst: &Buffer, regions: &[vk::BufferCopy]) {
+        self._trace(|trace| unsafe {
+            trace.push(TracedCommand::CopyBuffer {
+                src: src.get_vk_handle().as_raw(),
+                dst: dst.get_vk_handle().as_raw(),
+            })
+        });
+
         unsafe {
             self.pool.device.get_ash_handle().cmd_copy_buffer(
                 self.vk_command_buffer,
@@ -440,7 +888,7 @@ impl CommandBuffer {
         }
     }
 
-    pub fn copy_buffer_to_image(&self, src: &Buffer, dst: &Image) -> () {
+    pub fn copy_buffer_to_image(&self, src: &Buffer, dst: &Image) {
         let extent = dst.extent();
 
         let region = vk::BufferImageCopy {
@@ -461,18 +909,87 @@ impl CommandBuffer {
             },
         };
 
+        self.copy_buffer_to_image_regions(src, dst, &[region]);
+    }
+
+    // `dst` must be in TRANSFER_DST_OPTIMAL
+    pub fn copy_buffer_to_image_regions(
+        &self,
+        src: &Buffer,
+        dst: &Image,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        self._trace(|trace| unsafe {
+            trace.push(TracedCommand::CopyBufferToImage {
+                src: src.get_vk_handle().as_raw(),
+                dst: dst.get_vk_handle().as_raw(),
+            })
+        });
+
         unsafe {
             self.pool.device.get_ash_handle().cmd_copy_buffer_to_image(
                 self.vk_command_buffer,
                 src.get_vk_handle(),
                 dst.get_vk_handle(),
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[region],
+                regions,
             )
         }
     }
 
-    pub fn reset(&self) -> () {
+    // Queries have to be reset before they're written again
+    pub fn reset_query_pool(&self, query_pool: &QueryPool, first_query: u32, count: u32) {
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_reset_query_pool(
+                self.vk_command_buffer,
+                query_pool.get_vk_handle(),
+                first_query,
+                count,
+            );
+        }
+    }
+
+    // Occlusion and pipeline statistics queries count the work recorded
+    // between `begin_query` and `end_query`
+    pub fn begin_query(&self, query_pool: &QueryPool, query: u32, flags: vk::QueryControlFlags) {
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_begin_query(
+                self.vk_command_buffer,
+                query_pool.get_vk_handle(),
+                query,
+                flags,
+            );
+        }
+    }
+
+    pub fn end_query(&self, query_pool: &QueryPool, query: u32) {
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_end_query(
+                self.vk_command_buffer,
+                query_pool.get_vk_handle(),
+                query,
+            );
+        }
+    }
+
+    // Writes the time once all previous commands have reached `stage`
+    pub fn write_timestamp(
+        &self,
+        stage: vk::PipelineStageFlags,
+        query_pool: &QueryPool,
+        query: u32,
+    ) {
+        unsafe {
+            self.pool.device.get_ash_handle().cmd_write_timestamp(
+                self.vk_command_buffer,
+                stage,
+                query_pool.get_vk_handle(),
+                query,
+            );
+        }
+    }
+
+    pub fn reset(&self) {
         unsafe {
             self.pool
                 .device
@@ -483,6 +1000,49 @@ impl CommandBuffer {
     }
 }
 
+// A color or depth attachment for `begin_rendering`. The view has to outlive
+// the commands using it
+pub fn rendering_attachment(
+    view: &ImageView,
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+) -> vk::RenderingAttachmentInfo {
+    unsafe {
+        vk::RenderingAttachmentInfo {
+            s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
+            p_next: std::ptr::null(),
+            image_view: view.get_vk_handle(),
+            image_layout: layout,
+            resolve_mode: vk::ResolveModeFlags::NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op,
+            store_op,
+            clear_value,
+        }
+    }
+}
+
+// Renders the attachment into the multisampled view instead and resolves it
+// into the attachment's view when rendering ends
+pub fn resolved_attachment(
+    attachment: vk::RenderingAttachmentInfo,
+    msaa_view: &ImageView,
+    mode: vk::ResolveModeFlags,
+) -> vk::RenderingAttachmentInfo {
+    unsafe {
+        vk::RenderingAttachmentInfo {
+            image_view: msaa_view.get_vk_handle(),
+            resolve_mode: mode,
+            resolve_image_view: attachment.image_view,
+            resolve_image_layout: attachment.image_layout,
+            ..attachment
+        }
+    }
+}
+
 impl HasRawVkHandle<vk::CommandBuffer> for CommandBuffer {
     unsafe fn get_vk_handle(&self) -> vk::CommandBuffer {
         self.vk_command_buffer
@@ -0,0 +1,300 @@
+use ash::vk;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone)]
+pub enum TracedCommand {
+    Barrier {
+        image: u64,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    },
+    // Global memory barrier, e.g. between a compute pass and indirect draws
+    MemoryBarrier {
+        src_stage: vk::PipelineStageFlags2,
+        dst_stage: vk::PipelineStageFlags2,
+    },
+    Rendering {
+        color: Vec<u64>,
+        depth: Option<u64>,
+        stencil: Option<u64>,
+        draws: u32,
+    },
+    ClearColor {
+        image: u64,
+    },
+    Blit {
+        src: u64,
+        dst: u64,
+    },
+    CopyBuffer {
+        src: u64,
+        dst: u64,
+    },
+    CopyBufferToImage {
+        src: u64,
+        dst: u64,
+    },
+    // Resources accessed through descriptors aren't tracked
+    Dispatch {
+        groups: [u32; 3],
+    },
+}
+
+impl TracedCommand {
+    fn _label(&self) -> &'static str {
+        match self {
+            TracedCommand::Barrier { .. } => "barrier",
+            TracedCommand::MemoryBarrier { .. } => "memory_barrier",
+            TracedCommand::Rendering { .. } => "rendering",
+            TracedCommand::ClearColor { .. } => "clear_color",
+            TracedCommand::Blit { .. } => "blit",
+            TracedCommand::CopyBuffer { .. } => "copy_buffer",
+            TracedCommand::CopyBufferToImage { .. } => "copy_buffer_to_image",
+            TracedCommand::Dispatch { .. } => "dispatch",
+        }
+    }
+
+    // Resources read and written by the command, as raw handles
+    fn _usages(&self) -> (Vec<u64>, Vec<u64>) {
+        match self {
+            TracedCommand::Barrier { image, .. } => (vec![*image], vec![*image]),
+            TracedCommand::Rendering {
+                color,
+                depth,
+                stencil,
+                ..
+            } => {
+                let mut writes = color.clone();
+                writes.extend(depth);
+                writes.extend(stencil);
+                (vec![], writes)
+            }
+            TracedCommand::ClearColor { image } => (vec![], vec![*image]),
+            TracedCommand::Blit { src, dst }
+            | TracedCommand::CopyBuffer { src, dst }
+            | TracedCommand::CopyBufferToImage { src, dst } => (vec![*src], vec![*dst]),
+            TracedCommand::MemoryBarrier { .. } | TracedCommand::Dispatch { .. } => {
+                (vec![], vec![])
+            }
+        }
+    }
+}
+
+// Records the structure of a command buffer as it's recorded, so the passes,
+// resource usages and barriers of a frame can be inspected afterwards
+#[derive(Debug, Clone, Default)]
+pub struct CommandTrace {
+    commands: Vec<TracedCommand>,
+    names: HashMap<u64, String>,
+}
+
+impl CommandTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn commands(&self) -> &[TracedCommand] {
+        &self.commands
+    }
+
+    // Names a raw handle in the dump. Handles that share a name, like an image
+    // and its view, are shown as a single resource
+    pub fn set_name(&mut self, handle: u64, name: &str) {
+        self.names.insert(handle, String::from(name));
+    }
+
+    pub fn push(&mut self, command: TracedCommand) {
+        self.commands.push(command);
+    }
+
+    // Adds the commands and names of a secondary command buffer's trace
+    pub fn append(&mut self, other: CommandTrace) {
+        self.commands.extend(other.commands);
+        self.names.extend(other.names);
+    }
+
+    pub fn record_draw(&mut self) {
+        if let Some(TracedCommand::Rendering { draws, .. }) = self.commands.last_mut() {
+            *draws += 1;
+        }
+    }
+
+    pub fn resource_name(&self, handle: u64) -> String {
+        match self.names.get(&handle) {
+            Some(name) => name.clone(),
+            None => format!("0x{:x}", handle),
+        }
+    }
+
+    // A barrier is redundant if it doesn't change the layout, or if the image
+    // isn't used between it and the next barrier on the same image
+    pub fn redundant_barriers(&self) -> Vec<usize> {
+        let mut redundant = vec![];
+        let mut pending: HashMap<String, usize> = HashMap::new();
+        for (i, command) in self.commands.iter().enumerate() {
+            match command {
+                TracedCommand::Barrier {
+                    image,
+                    old_layout,
+                    new_layout,
+                } => {
+                    let name = self.resource_name(*image);
+                    if old_layout == new_layout {
+                        redundant.push(i);
+                    }
+                    if let Some(previous) = pending.insert(name, i) {
+                        if !redundant.contains(&previous) {
+                            redundant.push(previous);
+                        }
+                    }
+                }
+                command => {
+                    let (reads, writes) = command._usages();
+                    for handle in reads.iter().chain(writes.iter()) {
+                        pending.remove(&self.resource_name(*handle));
+                    }
+                }
+            }
+        }
+        redundant.sort();
+        redundant
+    }
+
+    pub fn to_json(&self) -> String {
+        let redundant = self.redundant_barriers();
+        let name = |x: &u64| self.resource_name(*x);
+
+        let commands = self
+            .commands
+            .iter()
+            .enumerate()
+            .map(|(i, command)| {
+                let mut fields = match command {
+                    TracedCommand::Barrier {
+                        image,
+                        old_layout,
+                        new_layout,
+                    } => json!({
+                        "image": name(image),
+                        "old_layout": format!("{:?}", old_layout),
+                        "new_layout": format!("{:?}", new_layout),
+                        "redundant": redundant.contains(&i),
+                    }),
+                    TracedCommand::MemoryBarrier {
+                        src_stage,
+                        dst_stage,
+                    } => json!({
+                        "src_stage": format!("{:?}", src_stage),
+                        "dst_stage": format!("{:?}", dst_stage),
+                    }),
+                    TracedCommand::Rendering {
+                        color,
+                        depth,
+                        stencil,
+                        draws,
+                    } => json!({
+                        "color": color.iter().map(name).collect::<Vec<_>>(),
+                        "depth": depth.as_ref().map(name),
+                        "stencil": stencil.as_ref().map(name),
+                        "draws": draws,
+                    }),
+                    TracedCommand::ClearColor { image } => json!({ "image": name(image) }),
+                    TracedCommand::Blit { src, dst }
+                    | TracedCommand::CopyBuffer { src, dst }
+                    | TracedCommand::CopyBufferToImage { src, dst } => {
+                        json!({ "src": name(src), "dst": name(dst) })
+                    }
+                    TracedCommand::Dispatch { groups } => json!({ "groups": groups }),
+                };
+                fields["index"] = json!(i);
+                fields["op"] = json!(command._label());
+                fields
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = serde_json::to_string_pretty(&json!({ "commands": commands })).unwrap();
+        out.push('\n');
+        out
+    }
+
+    // Graphviz graph with commands in recording order and edges to and from
+    // the resources they read and write. Redundant barriers are drawn in red
+    pub fn to_dot(&self) -> String {
+        let redundant = self.redundant_barriers();
+        let mut out = String::from("digraph frame {\n  rankdir=LR;\n");
+        let mut resources: Vec<String> = vec![];
+
+        for (i, command) in self.commands.iter().enumerate() {
+            let label = match command {
+                TracedCommand::Barrier {
+                    old_layout,
+                    new_layout,
+                    ..
+                } => format!("barrier\\n{:?} -> {:?}", old_layout, new_layout),
+                TracedCommand::MemoryBarrier {
+                    src_stage,
+                    dst_stage,
+                } => format!("memory_barrier\\n{:?} -> {:?}", src_stage, dst_stage),
+                TracedCommand::Rendering { draws, .. } => format!("rendering\\n{} draws", draws),
+                TracedCommand::Dispatch { groups } => {
+                    format!("dispatch\\n{}x{}x{}", groups[0], groups[1], groups[2])
+                }
+                command => String::from(command._label()),
+            };
+            let (shape, color) = match command {
+                TracedCommand::Barrier { .. } if redundant.contains(&i) => ("diamond", "red"),
+                TracedCommand::Barrier { .. } | TracedCommand::MemoryBarrier { .. } => {
+                    ("diamond", "black")
+                }
+                _ => ("box", "black"),
+            };
+            writeln!(
+                out,
+                "  cmd{} [label=\"{}: {}\", shape={}, color={}];",
+                i, i, label, shape, color
+            )
+            .unwrap();
+            if i > 0 {
+                writeln!(out, "  cmd{} -> cmd{} [style=dotted];", i - 1, i).unwrap();
+            }
+
+            let (reads, writes) = command._usages();
+            for (handles, is_write) in [(reads, false), (writes, true)] {
+                for handle in handles {
+                    let name = self.resource_name(handle);
+                    let index = match resources.iter().position(|x| *x == name) {
+                        Some(index) => index,
+                        None => {
+                            resources.push(name);
+                            resources.len() - 1
+                        }
+                    };
+                    if is_write {
+                        writeln!(out, "  cmd{} -> res{};", i, index).unwrap();
+                    } else {
+                        writeln!(out, "  res{} -> cmd{};", index, i).unwrap();
+                    }
+                }
+            }
+        }
+
+        for (i, name) in resources.iter().enumerate() {
+            writeln!(
+                out,
+                "  res{} [label={}, shape=ellipse];",
+                i,
+                _json_string(name)
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// Quoted and escaped, which is also valid in DOT
+fn _json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
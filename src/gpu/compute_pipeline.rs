@@ -0,0 +1,62 @@
+use super::ShaderModule;
+use super::{Device, HasRawAshHandle, HasRawVkHandle, Pipeline, PipelineLayout, ShaderKind};
+use ash::vk;
+use std::sync::Arc;
+
+pub struct ComputePipeline {
+    device: Arc<Device>,
+    vk_pipeline: vk::Pipeline,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: Arc<Device>,
+        shader_module: &ShaderModule,
+        pipeline_layout: &PipelineLayout,
+    ) -> Arc<ComputePipeline> {
+        assert!(matches!(shader_module.kind(), ShaderKind::Compute));
+
+        let create_info = unsafe {
+            vk::ComputePipelineCreateInfo::builder()
+                .stage(shader_module.pipeline_shader_stage_create_info())
+                .layout(pipeline_layout.get_vk_handle())
+                .build()
+        };
+
+        let vk_pipeline = unsafe {
+            let pipelines = device
+                .get_ash_handle()
+                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+                .map_err(|(_, result)| result)
+                .expect("failed to create compute pipeline");
+            pipelines[0]
+        };
+
+        Arc::new(ComputePipeline {
+            device,
+            vk_pipeline,
+        })
+    }
+}
+
+impl Pipeline for ComputePipeline {
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::COMPUTE
+    }
+}
+
+impl HasRawVkHandle<vk::Pipeline> for ComputePipeline {
+    unsafe fn get_vk_handle(&self) -> vk::Pipeline {
+        self.vk_pipeline
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .get_ash_handle()
+                .destroy_pipeline(self.vk_pipeline, None);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::any::Any;
+
+// Holds on to resources that may still be used by submitted frames until
+// those frames are known to have completed. Resources are tagged with the
+// number of frames submitted when they were retired, and are dropped once
+// that many frames have finished on the GPU
+pub struct DeletionQueue {
+    entries: Vec<(u64, Box<dyn Any>)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push<T: 'static>(&mut self, submitted_frames: u64, resource: T) {
+        self.entries.push((submitted_frames, Box::new(resource)));
+    }
+
+    // Drops every resource retired before `completed_frames` frames had been
+    // submitted
+    pub fn collect(&mut self, completed_frames: u64) {
+        self.entries.retain(|(x, _)| *x > completed_frames);
+    }
+
+    // Drops everything, only safe once the device is idle
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for DeletionQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn collect_drops_once_frames_complete() {
+        let resource = Rc::new(());
+        let mut queue = DeletionQueue::new();
+        queue.push(2, resource.clone());
+
+        queue.collect(1);
+        assert_eq!(Rc::strong_count(&resource), 2);
+        queue.collect(2);
+        assert_eq!(Rc::strong_count(&resource), 1);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let mut queue = DeletionQueue::new();
+        queue.push(0, 1u32);
+        queue.push(5, 2u32);
+        queue.clear();
+        assert!(queue.is_empty());
+    }
+}
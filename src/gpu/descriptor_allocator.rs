@@ -0,0 +1,107 @@
+use super::{DescriptorPool, DescriptorSet, DescriptorSetLayout, Device, DroppedDescriptorSets};
+use ash::vk;
+use std::sync::Arc;
+
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+// Allocates sets of any layout without knowing how many will be needed up
+// front. When the current pool runs out a new one is created, each holding
+// twice as many sets as the last. Pool sizes are given as the number of
+// descriptors of each type per set
+pub struct DescriptorAllocator {
+    device: Arc<Device>,
+    flags: vk::DescriptorPoolCreateFlags,
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    ready_pools: Vec<Arc<DescriptorPool>>,
+    full_pools: Vec<Arc<DescriptorPool>>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(
+        device: Arc<Device>,
+        flags: vk::DescriptorPoolCreateFlags,
+        initial_sets: u32,
+        ratios: &[(vk::DescriptorType, f32)],
+    ) -> Self {
+        Self {
+            device,
+            flags,
+            ratios: ratios.to_vec(),
+            sets_per_pool: initial_sets.max(1),
+            ready_pools: vec![],
+            full_pools: vec![],
+        }
+    }
+
+    pub fn pool_count(&self) -> usize {
+        self.ready_pools.len() + self.full_pools.len()
+    }
+
+    pub fn allocate(&mut self, set_layout: &DescriptorSetLayout) -> DescriptorSet {
+        loop {
+            let (pool, is_new) = self._get_pool();
+
+            match pool.try_allocate(&[set_layout]) {
+                Ok(sets) => {
+                    self.ready_pools.push(pool);
+                    return sets.into_vec().pop().unwrap();
+                }
+                // Sets that don't fit in an empty pool never will
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                    if !is_new =>
+                {
+                    self.full_pools.push(pool);
+                }
+                Err(error) => panic!("failed to allocate descriptor set: {}", error),
+            }
+        }
+    }
+
+    // Returns every set to its pool. Sets allocated before the reset must not
+    // be used afterwards
+    pub fn reset(&mut self) {
+        for pool in self.ready_pools.iter().chain(&self.full_pools) {
+            pool.reset();
+        }
+        self.ready_pools.append(&mut self.full_pools);
+    }
+
+    // Sets dropped from any of the pools since the last call, see
+    // `DescriptorPool::take_dropped_sets`
+    pub fn take_dropped_sets(&self) -> Vec<DroppedDescriptorSets> {
+        self.ready_pools
+            .iter()
+            .chain(&self.full_pools)
+            .map(|x| x.take_dropped_sets())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    // Takes the most recently used pool that isn't full, or creates one.
+    // Returns whether the pool was just created
+    fn _get_pool(&mut self) -> (Arc<DescriptorPool>, bool) {
+        // Full pools that had sets freed since may have room again
+        let (freed, full) = std::mem::take(&mut self.full_pools)
+            .into_iter()
+            .partition::<Vec<_>, _>(|x| x.take_freed());
+        self.full_pools = full;
+        self.ready_pools.extend(freed);
+
+        if let Some(pool) = self.ready_pools.pop() {
+            return (pool, false);
+        }
+
+        let max_sets = self.sets_per_pool;
+        self.sets_per_pool = (self.sets_per_pool * 2).min(MAX_SETS_PER_POOL);
+
+        let pool_sizes = self
+            .ratios
+            .iter()
+            .map(|(ty, ratio)| (*ty, ((ratio * max_sets as f32).ceil() as u32).max(1)))
+            .collect::<Vec<_>>();
+
+        let pool = DescriptorPool::new(self.device.clone(), self.flags, max_sets, &pool_sizes);
+        (pool, true)
+    }
+}
@@ -1,10 +1,22 @@
-use super::{Buffer, Device, HasRawAshHandle, HasRawVkHandle, Image, ImageView, Sampler};
+use super::{Buffer, Device, HasRawAshHandle, HasRawVkHandle, ImageView, Sampler};
 use ash::vk;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::{cell::OnceCell, collections::HashMap, sync::Arc};
 
 pub struct DescriptorPool {
     device: Arc<Device>,
     vk_descriptor_pool: vk::DescriptorPool,
+    flags: vk::DescriptorPoolCreateFlags,
+    // Incremented on every reset so sets allocated before it know they were
+    // already returned to the pool
+    generation: AtomicU64,
+    // Sets dropped since the last `take_dropped_sets`, which aren't freed yet
+    // since frames in flight may still be using them
+    dropped_sets: Mutex<Vec<vk::DescriptorSet>>,
+    // Whether sets were freed since the last `take_freed`
+    freed: AtomicBool,
 }
 
 impl DescriptorPool {
@@ -13,7 +25,7 @@ impl DescriptorPool {
         flags: vk::DescriptorPoolCreateFlags,
         max_sets: u32,
         set_types: &[(vk::DescriptorType, u32)],
-    ) -> Self {
+    ) -> Arc<Self> {
         let vk_pool_sizes = set_types
             .iter()
             .map(|(ty, descriptor_count)| vk::DescriptorPoolSize {
@@ -38,34 +50,132 @@ impl DescriptorPool {
                 .expect("failed to create descriptor pool")
         };
 
-        Self {
+        Arc::new(Self {
             device,
             vk_descriptor_pool,
+            flags,
+            generation: AtomicU64::new(0),
+            dropped_sets: Mutex::new(vec![]),
+            freed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn flags(&self) -> vk::DescriptorPoolCreateFlags {
+        self.flags
+    }
+
+    // Sets can only be freed individually if the pool was created with
+    // FREE_DESCRIPTOR_SET
+    pub fn can_free_sets(&self) -> bool {
+        self.flags
+            .contains(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+    }
+
+    // Returns every set allocated from the pool back to it. Sets allocated
+    // before the reset must not be used afterwards
+    pub fn reset(&self) {
+        unsafe {
+            self.device
+                .get_ash_handle()
+                .reset_descriptor_pool(
+                    self.vk_descriptor_pool,
+                    vk::DescriptorPoolResetFlags::empty(),
+                )
+                .expect("failed to reset descriptor pool");
         }
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.dropped_sets.lock().unwrap().clear();
     }
 
-    pub fn allocate(&self, set_layouts: &[&DescriptorSetLayout]) -> Box<[DescriptorSet]> {
+    // Whether any sets were freed back to the pool since the last call, e.g.
+    // to find out if a pool that ran out has room again
+    pub fn take_freed(&self) -> bool {
+        self.freed.swap(false, Ordering::AcqRel)
+    }
+
+    // Sets dropped since the last call, which are freed when the result is
+    // dropped. Meant to be handed to `FrameRing::defer_delete` once a frame,
+    // so they're only freed after the frames using them have completed
+    pub fn take_dropped_sets(self: &Arc<DescriptorPool>) -> DroppedDescriptorSets {
+        DroppedDescriptorSets {
+            pool: self.clone(),
+            sets: std::mem::take(&mut *self.dropped_sets.lock().unwrap()),
+            generation: self.generation.load(Ordering::Acquire),
+        }
+    }
+
+    pub fn allocate(
+        self: &Arc<DescriptorPool>,
+        set_layouts: &[&DescriptorSetLayout],
+    ) -> Box<[DescriptorSet]> {
+        self._allocate(set_layouts, None)
+            .expect("failed to allocate descriptor sets")
+    }
+
+    // Like `allocate`, but returns the error instead of panicking when the
+    // pool is out of memory or fragmented
+    pub fn try_allocate(
+        self: &Arc<DescriptorPool>,
+        set_layouts: &[&DescriptorSetLayout],
+    ) -> Result<Box<[DescriptorSet]>, vk::Result> {
+        self._allocate(set_layouts, None)
+    }
+
+    // Allocates sets whose layouts end in a VARIABLE_DESCRIPTOR_COUNT binding,
+    // with the actual size of that binding given per set
+    pub fn allocate_variable(
+        self: &Arc<DescriptorPool>,
+        set_layouts: &[&DescriptorSetLayout],
+        descriptor_counts: &[u32],
+    ) -> Box<[DescriptorSet]> {
+        assert_eq!(set_layouts.len(), descriptor_counts.len());
+        self._allocate(set_layouts, Some(descriptor_counts))
+            .expect("failed to allocate descriptor sets")
+    }
+
+    fn _allocate(
+        self: &Arc<DescriptorPool>,
+        set_layouts: &[&DescriptorSetLayout],
+        descriptor_counts: Option<&[u32]>,
+    ) -> Result<Box<[DescriptorSet]>, vk::Result> {
         unsafe {
             let vk_set_layouts = set_layouts
                 .iter()
                 .map(|x| x.get_vk_handle())
                 .collect::<Box<_>>();
 
+            let variable_count_info = descriptor_counts.map(|counts| {
+                vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                    .descriptor_counts(counts)
+                    .build()
+            });
+
             let info = vk::DescriptorSetAllocateInfo {
                 s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
-                p_next: std::ptr::null(),
+                p_next: match &variable_count_info {
+                    Some(x) => x as *const _ as *const std::ffi::c_void,
+                    None => std::ptr::null(),
+                },
                 descriptor_pool: self.vk_descriptor_pool,
                 descriptor_set_count: vk_set_layouts.len().try_into().unwrap(),
                 p_set_layouts: vk_set_layouts.as_ptr(),
             };
 
-            self.device
+            let generation = self.generation.load(Ordering::Acquire);
+
+            let vk_descriptor_sets = self
+                .device
                 .get_ash_handle()
-                .allocate_descriptor_sets(&info)
-                .expect("failed to allocate descriptor sets")
+                .allocate_descriptor_sets(&info)?;
+
+            Ok(vk_descriptor_sets
                 .into_iter()
-                .map(|x| DescriptorSet::new(self.device.clone(), x))
-                .collect()
+                .map(|x| DescriptorSet::new(self.clone(), x, generation))
+                .collect())
         }
     }
 }
@@ -87,18 +197,33 @@ impl Drop for DescriptorPool {
 }
 
 pub struct DescriptorSet {
-    device: Arc<Device>,
+    pool: Arc<DescriptorPool>,
     vk_descriptor_set: vk::DescriptorSet,
+    generation: u64,
 }
 
 impl DescriptorSet {
-    pub fn new(device: Arc<Device>, vk_descriptor_set: vk::DescriptorSet) -> Self {
+    pub fn new(
+        pool: Arc<DescriptorPool>,
+        vk_descriptor_set: vk::DescriptorSet,
+        generation: u64,
+    ) -> Self {
         Self {
-            device,
+            pool,
             vk_descriptor_set,
+            generation,
         }
     }
 
+    pub fn pool(&self) -> &Arc<DescriptorPool> {
+        &self.pool
+    }
+
+    // False once the pool has been reset, after which the set must not be used
+    pub fn is_valid(&self) -> bool {
+        self.generation == self.pool.generation.load(Ordering::Acquire)
+    }
+
     pub fn write_buffer(
         &self,
         buffer: &Buffer,
@@ -108,30 +233,9 @@ impl DescriptorSet {
         element: u32,
         ty: vk::DescriptorType,
     ) {
-        unsafe {
-            let buffer_info = vk::DescriptorBufferInfo {
-                buffer: buffer.get_vk_handle(),
-                offset,
-                range,
-            };
-
-            let write = vk::WriteDescriptorSet {
-                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
-                p_next: std::ptr::null(),
-                dst_set: self.get_vk_handle(),
-                dst_binding: binding,
-                dst_array_element: element,
-                descriptor_count: 1,
-                descriptor_type: ty,
-                p_image_info: std::ptr::null(),
-                p_buffer_info: &buffer_info,
-                p_texel_buffer_view: std::ptr::null(),
-            };
-
-            self.device
-                .get_ash_handle()
-                .update_descriptor_sets(&[write], &[]);
-        }
+        let mut writer = DescriptorWriter::new(self.pool.device.clone());
+        writer.write_buffers(self, binding, element, ty, &[(buffer, offset, range)]);
+        writer.flush();
     }
 
     pub fn write_image(
@@ -143,43 +247,344 @@ impl DescriptorSet {
         element: u32,
         ty: vk::DescriptorType,
     ) {
+        let mut writer = DescriptorWriter::new(self.pool.device.clone());
+        writer.write_images(
+            self,
+            binding,
+            element,
+            ty,
+            Some(sampler),
+            &[(image_view, image_layout)],
+        );
+        writer.flush();
+    }
+}
+
+impl HasRawVkHandle<vk::DescriptorSet> for DescriptorSet {
+    unsafe fn get_vk_handle(&self) -> vk::DescriptorSet {
+        self.vk_descriptor_set
+    }
+}
+
+// Sets from pools without FREE_DESCRIPTOR_SET are only returned to the pool
+// when it's reset or destroyed. The others are freed later, see
+// `DescriptorPool::take_dropped_sets`
+impl Drop for DescriptorSet {
+    fn drop(&mut self) {
+        if self.pool.can_free_sets() && self.is_valid() {
+            self.pool
+                .dropped_sets
+                .lock()
+                .unwrap()
+                .push(self.vk_descriptor_set);
+        }
+    }
+}
+
+// Dropped sets waiting to be freed, see `DescriptorPool::take_dropped_sets`
+pub struct DroppedDescriptorSets {
+    pool: Arc<DescriptorPool>,
+    sets: Vec<vk::DescriptorSet>,
+    generation: u64,
+}
+
+impl DroppedDescriptorSets {
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+// Sets the pool was reset since are already back in it
+impl Drop for DroppedDescriptorSets {
+    fn drop(&mut self) {
+        let pool = &self.pool;
+        if self.sets.is_empty() || self.generation != pool.generation.load(Ordering::Acquire) {
+            return;
+        }
         unsafe {
-            let image_info = vk::DescriptorImageInfo {
-                sampler: sampler.get_vk_handle(),
-                image_view: image_view.get_vk_handle(),
-                image_layout,
-            };
+            pool.device
+                .get_ash_handle()
+                .free_descriptor_sets(pool.vk_descriptor_pool, &self.sets)
+                .expect("failed to free descriptor sets");
+        }
+        pool.freed.store(true, Ordering::Release);
+    }
+}
 
-            let write = vk::WriteDescriptorSet {
-                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
-                p_next: std::ptr::null(),
-                dst_set: self.get_vk_handle(),
-                dst_binding: binding,
-                dst_array_element: element,
-                descriptor_count: 1,
-                descriptor_type: ty,
-                p_image_info: &image_info,
-                p_buffer_info: std::ptr::null(),
-                p_texel_buffer_view: std::ptr::null(),
-            };
+enum DescriptorInfos {
+    Buffer(std::ops::Range<usize>),
+    Image(std::ops::Range<usize>),
+    TexelBuffer(std::ops::Range<usize>),
+}
+
+struct PendingWrite {
+    dst_set: vk::DescriptorSet,
+    binding: u32,
+    first_element: u32,
+    ty: vk::DescriptorType,
+    infos: DescriptorInfos,
+}
 
+// Accumulates descriptor writes and copies, across any number of sets, and
+// applies them with a single `update_descriptor_sets` call on `flush`. Array
+// bindings can be written a range of elements at a time. Everything written
+// is borrowed until the writer is flushed or dropped
+pub struct DescriptorWriter<'a> {
+    device: Arc<Device>,
+    buffer_infos: Vec<vk::DescriptorBufferInfo>,
+    image_infos: Vec<vk::DescriptorImageInfo>,
+    texel_buffer_views: Vec<vk::BufferView>,
+    writes: Vec<PendingWrite>,
+    copies: Vec<vk::CopyDescriptorSet>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> DescriptorWriter<'a> {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            buffer_infos: vec![],
+            image_infos: vec![],
+            texel_buffer_views: vec![],
+            writes: vec![],
+            copies: vec![],
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.copies.is_empty()
+    }
+
+    // Writes the first array element of `binding`
+    pub fn write_buffer(
+        &mut self,
+        set: &'a DescriptorSet,
+        buffer: &'a Buffer,
+        offset: u64,
+        range: u64,
+        binding: u32,
+        ty: vk::DescriptorType,
+    ) -> &mut Self {
+        self.write_buffers(set, binding, 0, ty, &[(buffer, offset, range)])
+    }
+
+    // Writes consecutive array elements starting at `first_element`. Each
+    // buffer is given with its offset and range
+    pub fn write_buffers(
+        &mut self,
+        set: &'a DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        ty: vk::DescriptorType,
+        buffers: &[(&'a Buffer, u64, u64)],
+    ) -> &mut Self {
+        let start = self.buffer_infos.len();
+        for (buffer, offset, range) in buffers {
+            self.buffer_infos.push(vk::DescriptorBufferInfo {
+                buffer: unsafe { buffer.get_vk_handle() },
+                offset: *offset,
+                range: *range,
+            });
+        }
+        self._push_write(
+            set,
+            binding,
+            first_element,
+            ty,
+            DescriptorInfos::Buffer(start..self.buffer_infos.len()),
+        )
+    }
+
+    // Writes the first array element of `binding`
+    pub fn write_image(
+        &mut self,
+        set: &'a DescriptorSet,
+        sampler: &'a Sampler,
+        image_view: &'a Arc<ImageView>,
+        image_layout: vk::ImageLayout,
+        binding: u32,
+        ty: vk::DescriptorType,
+    ) -> &mut Self {
+        self.write_images(
+            set,
+            binding,
+            0,
+            ty,
+            Some(sampler),
+            &[(image_view, image_layout)],
+        )
+    }
+
+    // Writes consecutive array elements starting at `first_element`. The
+    // sampler is only used by sampler and combined image sampler descriptors
+    pub fn write_images(
+        &mut self,
+        set: &'a DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        ty: vk::DescriptorType,
+        sampler: Option<&'a Sampler>,
+        image_views: &[(&'a Arc<ImageView>, vk::ImageLayout)],
+    ) -> &mut Self {
+        let sampler = match sampler {
+            Some(sampler) => unsafe { sampler.get_vk_handle() },
+            None => vk::Sampler::null(),
+        };
+        let start = self.image_infos.len();
+        for (image_view, image_layout) in image_views {
+            self.image_infos.push(vk::DescriptorImageInfo {
+                sampler,
+                image_view: unsafe { image_view.get_vk_handle() },
+                image_layout: *image_layout,
+            });
+        }
+        self._push_write(
+            set,
+            binding,
+            first_element,
+            ty,
+            DescriptorInfos::Image(start..self.image_infos.len()),
+        )
+    }
+
+    // For uniform and storage texel buffer descriptors
+    pub fn write_texel_buffers(
+        &mut self,
+        set: &'a DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        ty: vk::DescriptorType,
+        buffer_views: &[vk::BufferView],
+    ) -> &mut Self {
+        let start = self.texel_buffer_views.len();
+        self.texel_buffer_views.extend_from_slice(buffer_views);
+        self._push_write(
+            set,
+            binding,
+            first_element,
+            ty,
+            DescriptorInfos::TexelBuffer(start..self.texel_buffer_views.len()),
+        )
+    }
+
+    // Copies `count` consecutive descriptors between sets, each given with its
+    // binding and first array element. Copies are applied after all of the
+    // writes
+    pub fn copy(
+        &mut self,
+        (src_set, src_binding, src_element): (&'a DescriptorSet, u32, u32),
+        (dst_set, dst_binding, dst_element): (&'a DescriptorSet, u32, u32),
+        count: u32,
+    ) -> &mut Self {
+        self.copies.push(vk::CopyDescriptorSet {
+            s_type: vk::StructureType::COPY_DESCRIPTOR_SET,
+            p_next: std::ptr::null(),
+            src_set: unsafe { src_set.get_vk_handle() },
+            src_binding,
+            src_array_element: src_element,
+            dst_set: unsafe { dst_set.get_vk_handle() },
+            dst_binding,
+            dst_array_element: dst_element,
+            descriptor_count: count,
+        });
+        self
+    }
+
+    fn _push_write(
+        &mut self,
+        set: &'a DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        ty: vk::DescriptorType,
+        infos: DescriptorInfos,
+    ) -> &mut Self {
+        self.writes.push(PendingWrite {
+            dst_set: unsafe { set.get_vk_handle() },
+            binding,
+            first_element,
+            ty,
+            infos,
+        });
+        self
+    }
+
+    // Applies every pending write and copy, leaving the writer empty
+    pub fn flush(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+
+        // Info arrays are only pointed into once they've stopped growing
+        let writes = self
+            .writes
+            .iter()
+            .map(|write| {
+                let mut vk_write = vk::WriteDescriptorSet {
+                    s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                    p_next: std::ptr::null(),
+                    dst_set: write.dst_set,
+                    dst_binding: write.binding,
+                    dst_array_element: write.first_element,
+                    descriptor_count: 0,
+                    descriptor_type: write.ty,
+                    p_image_info: std::ptr::null(),
+                    p_buffer_info: std::ptr::null(),
+                    p_texel_buffer_view: std::ptr::null(),
+                };
+                let range = match &write.infos {
+                    DescriptorInfos::Buffer(range) => {
+                        vk_write.p_buffer_info = self.buffer_infos[range.clone()].as_ptr();
+                        range
+                    }
+                    DescriptorInfos::Image(range) => {
+                        vk_write.p_image_info = self.image_infos[range.clone()].as_ptr();
+                        range
+                    }
+                    DescriptorInfos::TexelBuffer(range) => {
+                        vk_write.p_texel_buffer_view =
+                            self.texel_buffer_views[range.clone()].as_ptr();
+                        range
+                    }
+                };
+                vk_write.descriptor_count = range.len().try_into().unwrap();
+                vk_write
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
             self.device
                 .get_ash_handle()
-                .update_descriptor_sets(&[write], &[]);
+                .update_descriptor_sets(&writes, &self.copies);
         }
-        //
+
+        self.buffer_infos.clear();
+        self.image_infos.clear();
+        self.texel_buffer_views.clear();
+        self.writes.clear();
+        self.copies.clear();
     }
 }
 
-impl HasRawVkHandle<vk::DescriptorSet> for DescriptorSet {
-    unsafe fn get_vk_handle(&self) -> vk::DescriptorSet {
-        self.vk_descriptor_set
-    }
+// Describes a set layout by its definition rather than its handle. Identically
+// defined set layouts are compatible, so two layouts with the same key can be
+// used interchangeably
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DescriptorSetLayoutKey {
+    flags: vk::DescriptorSetLayoutCreateFlags,
+    bindings: Vec<(
+        u32,
+        vk::DescriptorType,
+        u32,
+        vk::ShaderStageFlags,
+        vk::DescriptorBindingFlags,
+    )>,
 }
 
 pub struct DescriptorSetLayout {
     device: Arc<Device>,
     vk_descriptor_set_layout: vk::DescriptorSetLayout,
+    key: DescriptorSetLayoutKey,
 }
 
 impl DescriptorSetLayout {
@@ -190,12 +595,18 @@ impl DescriptorSetLayout {
     pub fn new(
         device: Arc<Device>,
         vk_descriptor_set_layout: vk::DescriptorSetLayout,
+        key: DescriptorSetLayoutKey,
     ) -> Arc<Self> {
         Arc::new(Self {
             device,
             vk_descriptor_set_layout,
+            key,
         })
     }
+
+    pub fn key(&self) -> &DescriptorSetLayoutKey {
+        &self.key
+    }
 }
 
 impl HasRawVkHandle<vk::DescriptorSetLayout> for DescriptorSetLayout {
@@ -261,9 +672,25 @@ impl DescriptorSetLayoutBuilder {
             .map(|x| x.get_descriptor_set_layout_binding(&layout_binding_indices))
             .collect::<Vec<_>>();
 
+        // Only the last binding of a layout can have a variable count
+        let vk_binding_flags = bindings.iter().map(|x| x.binding_flags).collect::<Vec<_>>();
+        for flags in vk_binding_flags.iter().rev().skip(1) {
+            assert!(!flags.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT));
+        }
+
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&vk_binding_flags)
+            .build();
+
+        let has_binding_flags = vk_binding_flags.iter().any(|x| !x.is_empty());
+
         let info = vk::DescriptorSetLayoutCreateInfo {
             s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
-            p_next: std::ptr::null(),
+            p_next: if has_binding_flags {
+                &binding_flags_info as *const _ as *const std::ffi::c_void
+            } else {
+                std::ptr::null()
+            },
             flags,
             binding_count: vk_bindings.len().try_into().unwrap(),
             p_bindings: vk_bindings.as_ptr(),
@@ -276,7 +703,30 @@ impl DescriptorSetLayoutBuilder {
                 .expect("failed to create descriptor set layout")
         };
 
-        DescriptorSetLayout::new(device, vk_descriptor_set_layout)
+        let key = DescriptorSetLayoutKey {
+            flags,
+            bindings: vk_bindings
+                .iter()
+                .zip(&vk_binding_flags)
+                .map(|(x, binding_flags)| {
+                    (
+                        x.binding,
+                        x.descriptor_type,
+                        x.descriptor_count,
+                        x.stage_flags,
+                        *binding_flags,
+                    )
+                })
+                .collect(),
+        };
+
+        DescriptorSetLayout::new(device, vk_descriptor_set_layout, key)
+    }
+}
+
+impl Default for DescriptorSetLayoutBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -285,6 +735,7 @@ pub struct DescriptorSetLayoutBindingBuilder {
     parent_id: usize,
     count_and_type: Option<(u32, vk::DescriptorType)>,
     shader_stage: Option<vk::ShaderStageFlags>,
+    binding_flags: vk::DescriptorBindingFlags,
 }
 
 impl DescriptorSetLayoutBindingBuilder {
@@ -294,6 +745,7 @@ impl DescriptorSetLayoutBindingBuilder {
             parent_id,
             count_and_type: None,
             shader_stage: None,
+            binding_flags: vk::DescriptorBindingFlags::empty(),
         }
     }
 
@@ -315,6 +767,17 @@ impl DescriptorSetLayoutBindingBuilder {
         self
     }
 
+    // With VARIABLE_DESCRIPTOR_COUNT the count passed to `descriptor` is the
+    // upper bound, and the real count is chosen when sets are allocated
+    pub fn flags(mut self, binding_flags: vk::DescriptorBindingFlags) -> Self {
+        self.binding_flags = binding_flags;
+        self
+    }
+
+    pub fn binding_flags(&self) -> vk::DescriptorBindingFlags {
+        self.binding_flags
+    }
+
     pub fn get_descriptor_set_layout_binding(
         &self,
         layout_binding_indices: &HashMap<usize, u32>,
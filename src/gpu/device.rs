@@ -1,14 +1,29 @@
-use super::{Fence, HasRawAshHandle, HasRawVkHandle, PhysicalDevice, Queue, Swapchain};
+use super::{
+    CrashReport, DescriptorSetLayout, DeviceFaultInfo, DeviceFeaturesRequest, FeatureChain, Fence,
+    HasRawAshHandle, HasRawVkHandle, PhysicalDevice, PipelineLayout, PipelineLayoutKey, Queue,
+    SurfaceFormat, Swapchain, Vulkan13Dispatch, MAX_CHECKPOINTS,
+};
+use ash::prelude::VkResult;
 use ash::vk;
-use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::CStr;
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use tracing::error;
 
 pub struct Device {
     gpu_phy_device: Arc<PhysicalDevice>,
-    vk_phy_device: vk::PhysicalDevice,
     ash_device: ash::Device,
     queue_families: Vec<QueueFamily>,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    enabled_extensions: HashSet<Vec<u8>>,
+    bindless_supported: bool,
+    buffer_device_address_supported: bool,
+    vulkan13: Vulkan13Dispatch,
+    device_fault_fn: Option<vk::ExtDeviceFaultFn>,
+    checkpoints: Mutex<VecDeque<String>>,
+    // Weak so that layouts are still destroyed once nothing uses them, and
+    // because layouts hold a reference to the device
+    pipeline_layouts: Mutex<HashMap<PipelineLayoutKey, Weak<PipelineLayout>>>,
 }
 
 impl Device {
@@ -16,7 +31,10 @@ impl Device {
         gpu_phy_device: Arc<PhysicalDevice>,
         vk_phy_device: vk::PhysicalDevice,
         queue_family_indices: &[u32],
-        enabled_extensions: &[&[u8]],
+        required_extensions: &[&[u8]],
+        optional_extensions: &[&[u8]],
+        features: &DeviceFeaturesRequest,
+        mut feature_chain: FeatureChain,
     ) -> Arc<Device> {
         // Get the filtered list of queue families
         let queue_family_properties = gpu_phy_device.get_queue_family_properties();
@@ -32,57 +50,297 @@ impl Device {
             queue_create_infos.push(unsafe { queue_family.get_device_queue_create_info() });
         }
 
-        let enabled_extensions_nul = enabled_extensions.iter().map(|x| x).collect::<Vec<_>>();
+        // Optional extensions are dropped if the device doesn't support them
+        let supported_extensions = gpu_phy_device.extension_name_hashset();
+        let api_version = gpu_phy_device.api_version();
+        let enabled_extensions = required_extensions
+            .iter()
+            .chain(Vulkan13Dispatch::required_extensions(api_version))
+            .chain(
+                optional_extensions
+                    .iter()
+                    .chain(Vulkan13Dispatch::optional_extensions(api_version))
+                    .filter(|x| supported_extensions.contains(*x)),
+            )
+            .collect::<Vec<_>>();
+
+        let enabled_extensions_nul = enabled_extensions.iter().collect::<Vec<_>>();
 
         let enabled_extensions_ptrs = enabled_extensions_nul
             .iter()
             .map(|x| CStr::from_bytes_with_nul(x).unwrap().as_ptr())
             .collect::<Vec<_>>();
 
-        let enabled_features = vk::PhysicalDeviceFeatures {
-            sampler_anisotropy: vk::TRUE,
-            ..Default::default()
+        // Optional features are only enabled when supported, check
+        // `enabled_features` before relying on them
+        let enabled_features = features.resolve(&gpu_phy_device.device_features());
+
+        // Enable whatever descriptor indexing the device supports, needed for
+        // variable sized and partially bound descriptor arrays
+        let supported_indexing = gpu_phy_device.descriptor_indexing_features();
+        let bindless_supported = [
+            supported_indexing.runtime_descriptor_array,
+            supported_indexing.descriptor_binding_partially_bound,
+            supported_indexing.descriptor_binding_variable_descriptor_count,
+            supported_indexing.shader_sampled_image_array_non_uniform_indexing,
+            supported_indexing.descriptor_binding_sampled_image_update_after_bind,
+            supported_indexing.descriptor_binding_update_unused_while_pending,
+        ]
+        .iter()
+        .all(|x| *x == vk::TRUE);
+
+        // For vertex pulling and other shader access through device addresses
+        let buffer_device_address_supported = gpu_phy_device
+            .buffer_device_address_features()
+            .buffer_device_address
+            == vk::TRUE;
+
+        // The features the device needs are merged into the caller's chain.
+        // If it has a core struct they're set there, since the extension
+        // structs it replaces can't be chained alongside it
+        if let Some(x) = feature_chain.get_mut::<vk::PhysicalDeviceVulkan12Features>() {
+            x.runtime_descriptor_array |= supported_indexing.runtime_descriptor_array;
+            x.descriptor_binding_partially_bound |=
+                supported_indexing.descriptor_binding_partially_bound;
+            x.descriptor_binding_variable_descriptor_count |=
+                supported_indexing.descriptor_binding_variable_descriptor_count;
+            x.shader_sampled_image_array_non_uniform_indexing |=
+                supported_indexing.shader_sampled_image_array_non_uniform_indexing;
+            x.descriptor_binding_sampled_image_update_after_bind |=
+                supported_indexing.descriptor_binding_sampled_image_update_after_bind;
+            x.descriptor_binding_update_unused_while_pending |=
+                supported_indexing.descriptor_binding_update_unused_while_pending;
+            x.timeline_semaphore = vk::TRUE;
+            if buffer_device_address_supported {
+                x.buffer_device_address = vk::TRUE;
+            }
+        } else {
+            let x = feature_chain.entry::<vk::PhysicalDeviceDescriptorIndexingFeatures>();
+            x.runtime_descriptor_array |= supported_indexing.runtime_descriptor_array;
+            x.descriptor_binding_partially_bound |=
+                supported_indexing.descriptor_binding_partially_bound;
+            x.descriptor_binding_variable_descriptor_count |=
+                supported_indexing.descriptor_binding_variable_descriptor_count;
+            x.shader_sampled_image_array_non_uniform_indexing |=
+                supported_indexing.shader_sampled_image_array_non_uniform_indexing;
+            x.descriptor_binding_sampled_image_update_after_bind |=
+                supported_indexing.descriptor_binding_sampled_image_update_after_bind;
+            x.descriptor_binding_update_unused_while_pending |=
+                supported_indexing.descriptor_binding_update_unused_while_pending;
+            // Core since 1.2
+            feature_chain
+                .entry::<vk::PhysicalDeviceTimelineSemaphoreFeatures>()
+                .timeline_semaphore = vk::TRUE;
+            if buffer_device_address_supported {
+                feature_chain
+                    .entry::<vk::PhysicalDeviceBufferDeviceAddressFeatures>()
+                    .buffer_device_address = vk::TRUE;
+            }
+        }
+
+        // Before 1.3 these come from the extensions `Vulkan13Dispatch`
+        // requires
+        if Vulkan13Dispatch::is_core(api_version) {
+            // The caller may have chained the extension structs, which can't
+            // go alongside the core one. Their only features are enabled on
+            // it below, so they're dropped
+            feature_chain.remove::<vk::PhysicalDeviceDynamicRenderingFeatures>();
+            feature_chain.remove::<vk::PhysicalDeviceSynchronization2Features>();
+            let x = feature_chain.entry::<vk::PhysicalDeviceVulkan13Features>();
+            x.dynamic_rendering = vk::TRUE;
+            x.synchronization2 = vk::TRUE;
+        } else {
+            feature_chain
+                .entry::<vk::PhysicalDeviceDynamicRenderingFeatures>()
+                .dynamic_rendering = vk::TRUE;
+            feature_chain
+                .entry::<vk::PhysicalDeviceSynchronization2Features>()
+                .synchronization2 = vk::TRUE;
+        }
+
+        let is_enabled = |name: &std::ffi::CStr| {
+            enabled_extensions
+                .iter()
+                .any(|x| **x == name.to_bytes_with_nul())
         };
+        let device_fault_enabled = is_enabled(vk::ExtDeviceFaultFn::name());
+        let memory_priority_enabled = is_enabled(vk::ExtMemoryPriorityFn::name());
+        let pageable_memory_enabled = is_enabled(vk::ExtPageableDeviceLocalMemoryFn::name());
+        let extended_dynamic_state_enabled = is_enabled(vk::ExtExtendedDynamicStateFn::name());
 
-        let mut dynamic_rendering_feature = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
-            .dynamic_rendering(true)
-            .build();
+        if extended_dynamic_state_enabled {
+            feature_chain
+                .entry::<vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT>()
+                .extended_dynamic_state = vk::TRUE;
+        }
 
-        let mut syncronization2_feature = vk::PhysicalDeviceSynchronization2Features::builder()
-            .synchronization2(true)
-            .build();
+        if device_fault_enabled {
+            feature_chain
+                .entry::<vk::PhysicalDeviceFaultFeaturesEXT>()
+                .device_fault = vk::TRUE;
+        }
+
+        if memory_priority_enabled {
+            feature_chain
+                .entry::<vk::PhysicalDeviceMemoryPriorityFeaturesEXT>()
+                .memory_priority = vk::TRUE;
+        }
 
-        let device_create_info = vk::DeviceCreateInfo::builder()
-            .push_next(&mut dynamic_rendering_feature)
-            .push_next(&mut syncronization2_feature)
+        // Pageable memory depends on memory priority
+        if memory_priority_enabled && pageable_memory_enabled {
+            feature_chain
+                .entry::<vk::PhysicalDevicePageableDeviceLocalMemoryFeaturesEXT>()
+                .pageable_device_local_memory = vk::TRUE;
+        }
+
+        let mut device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(enabled_extensions_ptrs.as_slice())
             .enabled_features(&enabled_features)
             .build();
+        device_create_info.p_next = unsafe { feature_chain.link() };
 
-        let ash_device = unsafe {
+        let (ash_device, vulkan13, device_fault_fn) = unsafe {
             let ash_instance = gpu_phy_device.instance().get_ash_handle();
-            ash_instance
+            let ash_device = ash_instance
                 .create_device(vk_phy_device, &device_create_info, None)
-                .expect("failed to create device")
+                .expect("failed to create device");
+            let vulkan13 = Vulkan13Dispatch::new(
+                ash_instance,
+                &ash_device,
+                api_version,
+                extended_dynamic_state_enabled,
+            );
+            let device_fault_fn = device_fault_enabled.then(|| {
+                vk::ExtDeviceFaultFn::load(|name| {
+                    std::mem::transmute(
+                        ash_instance.get_device_proc_addr(ash_device.handle(), name.as_ptr()),
+                    )
+                })
+            });
+            (ash_device, vulkan13, device_fault_fn)
         };
 
         Arc::new_cyclic(|arc| Device {
             gpu_phy_device,
-            vk_phy_device,
             ash_device,
             queue_families: queue_family_configs
                 .drain(..)
                 .map(|x| QueueFamily::new(arc, x))
                 .collect(),
+            enabled_features,
+            enabled_extensions: enabled_extensions.iter().map(|x| Vec::from(**x)).collect(),
+            bindless_supported,
+            buffer_device_address_supported,
+            vulkan13,
+            device_fault_fn,
+            checkpoints: Mutex::new(VecDeque::new()),
+            pipeline_layouts: Mutex::new(HashMap::new()),
         })
     }
 
+    // Returns a shared pipeline layout for the given set layouts and push
+    // constant ranges, creating it if no compatible layout is alive
+    pub fn get_pipeline_layout(
+        self: &Arc<Device>,
+        descriptor_set_layouts: &[Arc<DescriptorSetLayout>],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Arc<PipelineLayout> {
+        let key = PipelineLayoutKey::new(descriptor_set_layouts, push_constant_ranges);
+        let mut pipeline_layouts = self.pipeline_layouts.lock().unwrap();
+
+        if let Some(layout) = pipeline_layouts.get(&key).and_then(Weak::upgrade) {
+            return layout;
+        }
+
+        pipeline_layouts.retain(|_, x| x.strong_count() > 0);
+
+        let layout =
+            PipelineLayout::new(self.clone(), descriptor_set_layouts, push_constant_ranges);
+        pipeline_layouts.insert(key, Arc::downgrade(&layout));
+        layout
+    }
+
     pub fn physical_device(&self) -> &Arc<PhysicalDevice> {
         &self.gpu_phy_device
     }
 
-    pub fn queue_families<'t>(self: &'t Arc<Device>) -> &'t Vec<QueueFamily> {
+    pub fn enabled_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
+    pub fn api_version(&self) -> u32 {
+        self.gpu_phy_device.api_version()
+    }
+
+    pub fn vulkan13(&self) -> &Vulkan13Dispatch {
+        &self.vulkan13
+    }
+
+    // Records a label for the work that is about to be submitted. The most
+    // recent ones are included in the crash report if the device is lost
+    pub fn push_checkpoint(&self, label: impl Into<String>) {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        if checkpoints.len() == MAX_CHECKPOINTS {
+            checkpoints.pop_front();
+        }
+        checkpoints.push_back(label.into());
+    }
+
+    pub fn crash_report(&self) -> CrashReport {
+        let fault = self
+            .device_fault_fn
+            .as_ref()
+            .and_then(|x| unsafe { DeviceFaultInfo::query(x, self.ash_device.handle()) });
+
+        CrashReport {
+            device_name: String::from(self.gpu_phy_device.device_name()),
+            fault,
+            checkpoints: self.checkpoints.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+
+    // Writes a crash report and panics. Called when a command returns
+    // ERROR_DEVICE_LOST
+    pub fn report_device_lost(&self) -> ! {
+        let report = self.crash_report();
+        error!("{}", report.to_text());
+        match report.write() {
+            Ok(path) => panic!("device lost, crash report written to {}", path),
+            Err(error) => panic!("device lost, failed to write crash report: {}", error),
+        }
+    }
+
+    // Like `expect` but writes a crash report if the device was lost
+    pub fn check<T>(&self, result: VkResult<T>, msg: &str) -> T {
+        match result {
+            Ok(x) => x,
+            Err(vk::Result::ERROR_DEVICE_LOST) => self.report_device_lost(),
+            Err(error) => panic!("{}: {:?}", msg, error),
+        }
+    }
+
+    // Whether the descriptor indexing features needed by
+    // `BindlessTextureTable` were enabled
+    pub fn supports_bindless(&self) -> bool {
+        self.bindless_supported
+    }
+
+    // Whether buffers can be created with SHADER_DEVICE_ADDRESS usage
+    pub fn supports_buffer_device_address(&self) -> bool {
+        self.buffer_device_address_supported
+    }
+
+    // Extension names include the nul terminator, like when they're requested
+    pub fn is_extension_enabled(&self, name: &[u8]) -> bool {
+        self.enabled_extensions.contains(name)
+    }
+
+    pub fn enabled_extensions(&self) -> impl Iterator<Item = &[u8]> {
+        self.enabled_extensions.iter().map(Vec::as_slice)
+    }
+
+    pub fn queue_families(self: &Arc<Device>) -> &Vec<QueueFamily> {
         &self.queue_families
     }
 
@@ -109,8 +367,7 @@ impl Device {
     pub fn get_swapchain(
         self: Arc<Device>,
         min_image_count: u32,
-        image_format: vk::Format,
-        image_color_space: vk::ColorSpaceKHR,
+        surface_format: SurfaceFormat,
         image_extent: vk::Extent2D,
         image_usage: vk::ImageUsageFlags,
         present_mode: vk::PresentModeKHR,
@@ -119,8 +376,7 @@ impl Device {
         Swapchain::new(
             self.clone(),
             min_image_count,
-            image_format,
-            image_color_space,
+            surface_format,
             image_extent,
             image_usage,
             present_mode,
@@ -128,7 +384,7 @@ impl Device {
         )
     }
 
-    pub fn wait_idle(&self) -> () {
+    pub fn wait_idle(&self) {
         unsafe {
             self.ash_device
                 .device_wait_idle()
@@ -136,16 +392,19 @@ impl Device {
         }
     }
 
-    pub fn wait_for_fences(&self, fences: &[&Fence], wait_all: bool, timeout: Option<u64>) -> () {
+    pub fn wait_for_fences(&self, fences: &[&Fence], wait_all: bool, timeout: Option<u64>) {
         unsafe {
             let vk_fences: Vec<_> = fences.iter().map(|x| x.get_vk_handle()).collect();
-            self.ash_device
-                .wait_for_fences(vk_fences.as_slice(), wait_all, timeout.unwrap_or(u64::MAX))
-                .expect("failed to wait for fences")
+            let result = self.ash_device.wait_for_fences(
+                vk_fences.as_slice(),
+                wait_all,
+                timeout.unwrap_or(u64::MAX),
+            );
+            self.check(result, "failed to wait for fences")
         }
     }
 
-    pub fn reset_fences(&self, fences: &[&Fence]) -> () {
+    pub fn reset_fences(&self, fences: &[&Fence]) {
         unsafe {
             let vk_fences: Vec<_> = fences.iter().map(|x| x.get_vk_handle()).collect();
             self.ash_device
@@ -184,7 +443,10 @@ impl QueueFamilyConfig {
         }
     }
 
-    // Unsafe because `p_queue_priorities` can outlive self
+    /// # Safety
+    ///
+    /// `p_queue_priorities` points into the config, so the returned struct
+    /// must not be used after the config is modified or dropped
     pub unsafe fn get_device_queue_create_info(&self) -> vk::DeviceQueueCreateInfo {
         vk::DeviceQueueCreateInfo {
             s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
@@ -200,14 +462,14 @@ impl QueueFamilyConfig {
 pub struct QueueFamily {
     device: Weak<Device>,
     config: QueueFamilyConfig,
-    queues: Vec<OnceCell<Arc<Queue>>>,
+    queues: Vec<OnceLock<Arc<Queue>>>,
 }
 
 impl QueueFamily {
     pub fn new(device: &Weak<Device>, config: QueueFamilyConfig) -> QueueFamily {
-        let mut queues: Vec<OnceCell<Arc<Queue>>> = vec![];
+        let mut queues: Vec<OnceLock<Arc<Queue>>> = vec![];
         queues.resize_with(config.properties.queue_count.try_into().unwrap(), || {
-            OnceCell::new()
+            OnceLock::new()
         });
         QueueFamily {
             device: device.clone(),
@@ -0,0 +1,151 @@
+use ash::vk;
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+// Number of checkpoints kept for crash reports
+pub const MAX_CHECKPOINTS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct DeviceFaultAddress {
+    pub address_type: vk::DeviceFaultAddressTypeEXT,
+    pub address: vk::DeviceAddress,
+    pub precision: vk::DeviceSize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceFaultVendorInfo {
+    pub description: String,
+    pub fault_code: u64,
+    pub fault_data: u64,
+}
+
+// Fault information reported by VK_EXT_device_fault after a device loss
+#[derive(Debug, Clone)]
+pub struct DeviceFaultInfo {
+    pub description: String,
+    pub addresses: Vec<DeviceFaultAddress>,
+    pub vendor_infos: Vec<DeviceFaultVendorInfo>,
+}
+
+impl DeviceFaultInfo {
+    /// Asks the driver what caused the device loss. None if it can't say
+    ///
+    /// # Safety
+    ///
+    /// `fault_fn` must have been loaded for `device`, which must have been
+    /// created with VK_EXT_device_fault and its `deviceFault` feature enabled.
+    /// The device must not be destroyed during the call
+    pub unsafe fn query(fault_fn: &vk::ExtDeviceFaultFn, device: vk::Device) -> Option<Self> {
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        let result =
+            (fault_fn.get_device_fault_info_ext)(device, &mut counts, std::ptr::null_mut());
+        if result != vk::Result::SUCCESS {
+            return None;
+        }
+
+        // Vendor binary data isn't requested, it's only useful to vendor tools
+        counts.vendor_binary_size = 0;
+
+        let mut addresses =
+            vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+        let mut vendor_infos =
+            vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+
+        let mut info = vk::DeviceFaultInfoEXT {
+            p_address_infos: addresses.as_mut_ptr(),
+            p_vendor_infos: vendor_infos.as_mut_ptr(),
+            ..Default::default()
+        };
+
+        let result = (fault_fn.get_device_fault_info_ext)(device, &mut counts, &mut info);
+        if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+            return None;
+        }
+
+        addresses.truncate(counts.address_info_count as usize);
+        vendor_infos.truncate(counts.vendor_info_count as usize);
+
+        Some(Self {
+            description: _c_chars_to_string(&info.description),
+            addresses: addresses
+                .iter()
+                .map(|x| DeviceFaultAddress {
+                    address_type: x.address_type,
+                    address: x.reported_address,
+                    precision: x.address_precision,
+                })
+                .collect(),
+            vendor_infos: vendor_infos
+                .iter()
+                .map(|x| DeviceFaultVendorInfo {
+                    description: _c_chars_to_string(&x.description),
+                    fault_code: x.vendor_fault_code,
+                    fault_data: x.vendor_fault_data,
+                })
+                .collect(),
+        })
+    }
+}
+
+// Everything known about a device loss, written out before panicking so
+// there's something to look at after the fact
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub device_name: String,
+    pub fault: Option<DeviceFaultInfo>,
+    pub checkpoints: Vec<String>,
+}
+
+impl CrashReport {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "device lost on {}", self.device_name).unwrap();
+
+        match &self.fault {
+            None => writeln!(out, "\nno fault info (VK_EXT_device_fault unavailable)").unwrap(),
+            Some(fault) => {
+                writeln!(out, "\nfault: {}", fault.description).unwrap();
+                for x in &fault.addresses {
+                    writeln!(
+                        out,
+                        "  address {:?} 0x{:x} (precision 0x{:x})",
+                        x.address_type, x.address, x.precision
+                    )
+                    .unwrap();
+                }
+                for x in &fault.vendor_infos {
+                    writeln!(
+                        out,
+                        "  vendor {} (code 0x{:x}, data 0x{:x})",
+                        x.description, x.fault_code, x.fault_data
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        writeln!(out, "\nlast checkpoints, oldest first:").unwrap();
+        for x in &self.checkpoints {
+            writeln!(out, "  {}", x).unwrap();
+        }
+        out
+    }
+
+    // Writes the report to a timestamped file in the working directory and
+    // returns its path
+    pub fn write(&self) -> std::io::Result<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let path = format!("device-lost-{}.txt", timestamp);
+        std::fs::write(&path, self.to_text())?;
+        Ok(path)
+    }
+}
+
+fn _c_chars_to_string(chars: &[std::ffi::c_char]) -> String {
+    unsafe { CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,119 @@
+use ash::vk;
+
+// Core features that can be asked for when creating a device. Anything not
+// requested stays disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceFeature {
+    GeometryShader,
+    TessellationShader,
+    SamplerAnisotropy,
+    PipelineStatisticsQuery,
+    OcclusionQueryPrecise,
+    FillModeNonSolid,
+    WideLines,
+    LargePoints,
+    DepthClamp,
+    DepthBiasClamp,
+    IndependentBlend,
+    SampleRateShading,
+    MultiDrawIndirect,
+    DrawIndirectFirstInstance,
+    ShaderInt64,
+    ShaderFloat64,
+    TextureCompressionBc,
+}
+
+impl DeviceFeature {
+    fn _field(self, features: &mut vk::PhysicalDeviceFeatures) -> &mut vk::Bool32 {
+        match self {
+            DeviceFeature::GeometryShader => &mut features.geometry_shader,
+            DeviceFeature::TessellationShader => &mut features.tessellation_shader,
+            DeviceFeature::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            DeviceFeature::PipelineStatisticsQuery => &mut features.pipeline_statistics_query,
+            DeviceFeature::OcclusionQueryPrecise => &mut features.occlusion_query_precise,
+            DeviceFeature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            DeviceFeature::WideLines => &mut features.wide_lines,
+            DeviceFeature::LargePoints => &mut features.large_points,
+            DeviceFeature::DepthClamp => &mut features.depth_clamp,
+            DeviceFeature::DepthBiasClamp => &mut features.depth_bias_clamp,
+            DeviceFeature::IndependentBlend => &mut features.independent_blend,
+            DeviceFeature::SampleRateShading => &mut features.sample_rate_shading,
+            DeviceFeature::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            DeviceFeature::DrawIndirectFirstInstance => &mut features.draw_indirect_first_instance,
+            DeviceFeature::ShaderInt64 => &mut features.shader_int64,
+            DeviceFeature::ShaderFloat64 => &mut features.shader_float64,
+            DeviceFeature::TextureCompressionBc => &mut features.texture_compression_bc,
+        }
+    }
+
+    pub fn is_enabled(self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let mut features = *features;
+        *self._field(&mut features) == vk::TRUE
+    }
+
+    pub fn enable(self, features: &mut vk::PhysicalDeviceFeatures) {
+        *self._field(features) = vk::TRUE;
+    }
+}
+
+// The features to enable on a device. Required ones have to be supported or
+// device creation panics, optional ones are enabled only if they are, so
+// check `Device::enabled_features` before relying on them
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFeaturesRequest {
+    required: Vec<DeviceFeature>,
+    optional: Vec<DeviceFeature>,
+}
+
+impl DeviceFeaturesRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require(mut self, feature: DeviceFeature) -> Self {
+        self.required.push(feature);
+        self
+    }
+
+    pub fn request(mut self, feature: DeviceFeature) -> Self {
+        self.optional.push(feature);
+        self
+    }
+
+    pub fn required(&self) -> &[DeviceFeature] {
+        &self.required
+    }
+
+    pub fn optional(&self) -> &[DeviceFeature] {
+        &self.optional
+    }
+
+    // Required features that `supported` lacks
+    pub fn missing(&self, supported: &vk::PhysicalDeviceFeatures) -> Vec<DeviceFeature> {
+        self.required
+            .iter()
+            .copied()
+            .filter(|x| !x.is_enabled(supported))
+            .collect()
+    }
+
+    // The features to pass to device creation. Panics if a required feature
+    // isn't supported
+    pub fn resolve(&self, supported: &vk::PhysicalDeviceFeatures) -> vk::PhysicalDeviceFeatures {
+        let missing = self.missing(supported);
+        assert!(
+            missing.is_empty(),
+            "unsupported required device features: {:?}",
+            missing
+        );
+
+        let mut enabled = vk::PhysicalDeviceFeatures::default();
+        for feature in &self.required {
+            feature.enable(&mut enabled);
+        }
+        for feature in self.optional.iter().filter(|x| x.is_enabled(supported)) {
+            feature.enable(&mut enabled);
+        }
+        enabled
+    }
+}
@@ -0,0 +1,207 @@
+use super::{DeviceFeature, DeviceFeaturesRequest, PhysicalDevice, Vulkan13Dispatch};
+use ash::vk;
+use std::ffi::CStr;
+use std::sync::Arc;
+
+// Overrides the selector's index and name. A number picks the adapter at that
+// index, anything else is matched against adapter names
+pub const GPU_ENV_VAR: &str = "VULKA_GPU";
+
+// What a physical device offers, checked against the renderer's requirements
+#[derive(Clone)]
+pub struct AdapterInfo {
+    pub index: usize,
+    pub physical_device: Arc<PhysicalDevice>,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    // Total size of the device local heaps in bytes
+    pub device_memory: u64,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    pub supports_surface: bool,
+    // Required queue flags no queue family has
+    pub missing_queue_flags: vk::QueueFlags,
+    // Required extensions the device doesn't support, including the ones
+    // needed for 1.3 features on older devices
+    pub missing_extensions: Vec<String>,
+    // Required features the device doesn't support
+    pub missing_features: Vec<DeviceFeature>,
+}
+
+impl AdapterInfo {
+    pub fn new(
+        index: usize,
+        physical_device: Arc<PhysicalDevice>,
+        required_queue_flags: vk::QueueFlags,
+        required_extensions: &[&[u8]],
+        features: &DeviceFeaturesRequest,
+    ) -> Self {
+        let queue_families = physical_device.get_queue_family_properties();
+        let supports_surface = (0..queue_families.len())
+            .any(|i| physical_device.supports_surface(i.try_into().unwrap()));
+        let available_queue_flags = queue_families
+            .iter()
+            .fold(vk::QueueFlags::empty(), |flags, x| flags | x.queue_flags);
+
+        let extensions_hashset = physical_device.extension_name_hashset();
+        let missing_extensions = required_extensions
+            .iter()
+            .chain(Vulkan13Dispatch::required_extensions(
+                physical_device.api_version(),
+            ))
+            .filter(|x| !extensions_hashset.contains(*x))
+            .map(|x| {
+                CStr::from_bytes_with_nul(x)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+
+        let memory_properties = physical_device.get_memory_properties();
+        let device_memory = memory_properties.memory_heaps
+            [..memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|x| x.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|x| x.size)
+            .sum();
+
+        Self {
+            index,
+            name: physical_device.device_name().to_string(),
+            device_type: physical_device.device_type(),
+            api_version: physical_device.api_version(),
+            device_memory,
+            queue_families,
+            supports_surface,
+            missing_queue_flags: required_queue_flags & !available_queue_flags,
+            missing_extensions,
+            missing_features: features.missing(&physical_device.device_features()),
+            physical_device,
+        }
+    }
+
+    pub fn is_suitable(&self) -> bool {
+        self.supports_surface
+            && self.missing_queue_flags.is_empty()
+            && self.missing_extensions.is_empty()
+            && self.missing_features.is_empty()
+    }
+
+    // Case insensitive substring match
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.name.to_lowercase().contains(&name.to_lowercase())
+    }
+}
+
+impl std::fmt::Display for AdapterInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {} ({:?}, Vulkan {}.{}.{}, {} MiB, {} queue families)",
+            self.index,
+            self.name,
+            self.device_type,
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version),
+            self.device_memory / (1024 * 1024),
+            self.queue_families.len(),
+        )?;
+        if !self.supports_surface {
+            write!(f, ", no surface support")?;
+        }
+        if !self.missing_queue_flags.is_empty() {
+            write!(f, ", missing queues {:?}", self.missing_queue_flags)?;
+        }
+        if !self.missing_extensions.is_empty() {
+            write!(f, ", missing {}", self.missing_extensions.join(", "))?;
+        }
+        if !self.missing_features.is_empty() {
+            write!(f, ", missing features {:?}", self.missing_features)?;
+        }
+        Ok(())
+    }
+}
+
+// None rejects the adapter
+type AdapterScore = Box<dyn Fn(&AdapterInfo) -> Option<i64>>;
+
+// Picks an adapter out of the suitable ones. An index or name override
+// narrows the choice down first, then the highest score wins, with ties going
+// to the lowest index
+pub struct DeviceSelector {
+    index: Option<usize>,
+    name: Option<String>,
+    score: AdapterScore,
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        Self {
+            index: None,
+            name: None,
+            score: Box::new(DeviceSelector::default_score),
+        }
+    }
+}
+
+impl DeviceSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Prefers discrete over integrated GPUs, then more device memory
+    pub fn default_score(adapter: &AdapterInfo) -> Option<i64> {
+        let type_score = match adapter.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 2,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+            _ => 0,
+        };
+        let memory_mib = (adapter.device_memory / (1024 * 1024)) as i64;
+        Some((type_score << 40) + memory_mib)
+    }
+
+    pub fn index(mut self, index: Option<usize>) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn score(mut self, score: impl Fn(&AdapterInfo) -> Option<i64> + 'static) -> Self {
+        self.score = Box::new(score);
+        self
+    }
+
+    // Replaces the index and name overrides with `GPU_ENV_VAR` if it's set
+    pub fn env_override(mut self) -> Self {
+        if let Ok(value) = std::env::var(GPU_ENV_VAR) {
+            match value.parse() {
+                Ok(index) => {
+                    self.index = Some(index);
+                    self.name = None;
+                }
+                Err(_) => {
+                    self.index = None;
+                    self.name = Some(value);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn select<'a>(&self, adapters: &'a [AdapterInfo]) -> Option<&'a AdapterInfo> {
+        adapters
+            .iter()
+            .filter(|x| x.is_suitable())
+            .filter(|x| self.index.is_none_or(|index| index == x.index))
+            .filter(|x| self.name.as_ref().is_none_or(|name| x.matches_name(name)))
+            .filter_map(|x| (self.score)(x).map(|score| (x, score)))
+            .min_by_key(|(x, score)| (std::cmp::Reverse(*score), x.index))
+            .map(|(x, _)| x)
+    }
+}
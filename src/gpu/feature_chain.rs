@@ -0,0 +1,166 @@
+use super::{HasRawAshHandle, HasRawVkHandle, PhysicalDevice};
+use ash::vk;
+use std::any::Any;
+use std::ffi::c_void;
+
+// Feature structs to chain onto `DeviceCreateInfo`, at most one of each type.
+// The Vulkan 1.1, 1.2 and 1.3 structs replace the extension structs they were
+// promoted from, and the spec doesn't allow chaining both, e.g.
+// `PhysicalDeviceVulkan12Features` together with
+// `PhysicalDeviceDescriptorIndexingFeatures`. Extension structs also need
+// their extension enabled on the device
+#[derive(Default)]
+pub struct FeatureChain {
+    // Each one is a Vulkan struct that starts with `s_type` and `p_next`, see
+    // `push`
+    structs: Vec<Box<dyn Any>>,
+}
+
+impl FeatureChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vulkan11(self, features: vk::PhysicalDeviceVulkan11Features) -> Self {
+        self.push(features)
+    }
+
+    pub fn vulkan12(self, features: vk::PhysicalDeviceVulkan12Features) -> Self {
+        self.push(features)
+    }
+
+    pub fn vulkan13(self, features: vk::PhysicalDeviceVulkan13Features) -> Self {
+        self.push(features)
+    }
+
+    // Replaces the struct of the same type if there is one. `p_next` is
+    // overwritten when the chain is linked
+    pub fn push<T: vk::ExtendsDeviceCreateInfo + 'static>(mut self, features: T) -> Self {
+        match self.get_mut::<T>() {
+            Some(x) => *x = features,
+            None => self.structs.push(Box::new(features)),
+        }
+        self
+    }
+
+    pub fn contains<T: vk::ExtendsDeviceCreateInfo + 'static>(&self) -> bool {
+        self.get::<T>().is_some()
+    }
+
+    pub fn get<T: vk::ExtendsDeviceCreateInfo + 'static>(&self) -> Option<&T> {
+        self.structs.iter().find_map(|x| x.downcast_ref())
+    }
+
+    pub fn get_mut<T: vk::ExtendsDeviceCreateInfo + 'static>(&mut self) -> Option<&mut T> {
+        self.structs.iter_mut().find_map(|x| x.downcast_mut())
+    }
+
+    pub fn remove<T: vk::ExtendsDeviceCreateInfo + 'static>(&mut self) -> Option<T> {
+        let index = self.structs.iter().position(|x| x.is::<T>())?;
+        self.structs.remove(index).downcast().ok().map(|x| *x)
+    }
+
+    // The struct of type `T`, which is added with its defaults if missing
+    pub fn entry<T: vk::ExtendsDeviceCreateInfo + Default + 'static>(&mut self) -> &mut T {
+        if !self.contains::<T>() {
+            self.structs.push(Box::new(T::default()));
+        }
+        self.get_mut().unwrap()
+    }
+
+    /// Points each struct's `p_next` at the next one and returns the head, or
+    /// null if the chain is empty
+    ///
+    /// # Safety
+    ///
+    /// The returned pointer and the `p_next` pointers behind it point into
+    /// the chain's boxed structs. They must not be dereferenced, or handed to
+    /// Vulkan, after the chain is modified or dropped. Structs that were in
+    /// the chain before are left pointing at the old links
+    pub unsafe fn link(&mut self) -> *mut c_void {
+        let mut next = std::ptr::null_mut();
+        for x in self.structs.iter_mut().rev() {
+            let base = x.as_mut() as *mut dyn Any as *mut vk::BaseOutStructure;
+            (*base).p_next = next;
+            next = base;
+        }
+        next as *mut c_void
+    }
+
+    // Overwrites every struct with what the physical device supports, e.g.
+    // to check a chain before creating a device with it. Only valid if every
+    // struct is a feature struct
+    pub fn query_supported(&mut self, physical_device: &PhysicalDevice) {
+        unsafe {
+            let mut features2 = vk::PhysicalDeviceFeatures2 {
+                p_next: self.link(),
+                ..Default::default()
+            };
+            physical_device
+                .instance()
+                .get_ash_handle()
+                .get_physical_device_features2(physical_device.get_vk_handle(), &mut features2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_replaces_struct_of_the_same_type() {
+        let chain = FeatureChain::new()
+            .vulkan12(vk::PhysicalDeviceVulkan12Features {
+                timeline_semaphore: vk::TRUE,
+                ..Default::default()
+            })
+            .vulkan12(vk::PhysicalDeviceVulkan12Features {
+                buffer_device_address: vk::TRUE,
+                ..Default::default()
+            });
+
+        let features = chain.get::<vk::PhysicalDeviceVulkan12Features>().unwrap();
+        assert_eq!(features.timeline_semaphore, vk::FALSE);
+        assert_eq!(features.buffer_device_address, vk::TRUE);
+        assert!(!chain.contains::<vk::PhysicalDeviceVulkan13Features>());
+    }
+
+    #[test]
+    fn entry_adds_defaults_and_remove_takes_it_out() {
+        let mut chain = FeatureChain::new();
+        chain
+            .entry::<vk::PhysicalDeviceVulkan13Features>()
+            .dynamic_rendering = vk::TRUE;
+        assert!(chain.contains::<vk::PhysicalDeviceVulkan13Features>());
+
+        let features = chain
+            .remove::<vk::PhysicalDeviceVulkan13Features>()
+            .unwrap();
+        assert_eq!(features.dynamic_rendering, vk::TRUE);
+        assert!(!chain.contains::<vk::PhysicalDeviceVulkan13Features>());
+        assert!(chain
+            .remove::<vk::PhysicalDeviceVulkan13Features>()
+            .is_none());
+    }
+
+    #[test]
+    fn link_chains_structs_in_order() {
+        let mut chain = FeatureChain::new();
+        assert!(unsafe { chain.link() }.is_null());
+
+        let mut chain = chain
+            .vulkan11(vk::PhysicalDeviceVulkan11Features::default())
+            .vulkan12(vk::PhysicalDeviceVulkan12Features::default());
+        let head = unsafe { chain.link() };
+
+        let vulkan11 = chain.get::<vk::PhysicalDeviceVulkan11Features>().unwrap();
+        let vulkan12 = chain.get::<vk::PhysicalDeviceVulkan12Features>().unwrap();
+        assert_eq!(head as *const c_void, vulkan11 as *const _ as *const c_void);
+        assert_eq!(
+            vulkan11.p_next as *const c_void,
+            vulkan12 as *const _ as *const c_void
+        );
+        assert!(vulkan12.p_next.is_null());
+    }
+}
@@ -0,0 +1,124 @@
+use super::DeletionQueue;
+
+// Per-frame resources for each frame in flight, cycled through in order.
+// Resources retired while recording are held until every frame that could
+// still be using them has completed
+pub struct FrameRing<T> {
+    frames: Vec<T>,
+    current: usize,
+    frame_count: u64,
+    deletion_queue: DeletionQueue,
+}
+
+impl<T> FrameRing<T> {
+    pub fn new(frames: Vec<T>) -> Self {
+        assert!(!frames.is_empty(), "frame ring needs at least one frame");
+        Self {
+            frames,
+            current: 0,
+            frame_count: 0,
+            deletion_queue: DeletionQueue::new(),
+        }
+    }
+
+    // Number of frames in flight
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Index of the current frame in the ring
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    // Number of frames submitted so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn current(&self) -> &T {
+        &self.frames[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut T {
+        &mut self.frames[self.current]
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        &self.frames[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.frames[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.frames.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.frames.iter_mut()
+    }
+
+    // Moves on to the next frame once the current one has been submitted
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.frames.len();
+        self.frame_count += 1;
+    }
+
+    // Holds on to `resource` until the frames submitted so far have completed
+    pub fn defer_delete<R: 'static>(&mut self, resource: R) {
+        self.deletion_queue.push(self.frame_count, resource);
+    }
+
+    // Drops deferred resources that are no longer in use. Must be called
+    // after waiting on the current frame's fence, at which point every frame
+    // submitted before the previous use of this slot has completed
+    pub fn collect(&mut self) {
+        let frames_in_flight = self.frames.len() as u64;
+        let completed_frames = (self.frame_count + 1).saturating_sub(frames_in_flight);
+        self.deletion_queue.collect(completed_frames);
+    }
+
+    // Drops every deferred resource, only safe once the device is idle
+    pub fn clear_deferred(&mut self) {
+        self.deletion_queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn collect_waits_for_every_frame_in_flight() {
+        let resource = Rc::new(());
+        let mut ring = FrameRing::new(vec![(), ()]);
+
+        // Frame 0 was submitted and may still be using it
+        ring.advance();
+        ring.defer_delete(resource.clone());
+        ring.collect();
+        assert_eq!(Rc::strong_count(&resource), 2);
+
+        // Frame 0 has completed once its slot comes around again
+        ring.advance();
+        ring.collect();
+        assert_eq!(Rc::strong_count(&resource), 1);
+    }
+
+    #[test]
+    fn advance_wraps_around() {
+        let mut ring = FrameRing::new(vec![0, 1, 2]);
+        for expected in [1, 2, 0, 1] {
+            ring.advance();
+            assert_eq!(*ring.current(), expected);
+        }
+        assert_eq!(ring.frame_count(), 4);
+    }
+}
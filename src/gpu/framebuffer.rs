@@ -5,7 +5,8 @@ use std::sync::Arc;
 pub struct Framebuffer {
     render_pass: Arc<RenderPass>,
     vk_framebuffer: vk::Framebuffer,
-    image_views: Box<[Arc<ImageView>]>,
+    // Kept alive for as long as the framebuffer refers to them
+    _image_views: Box<[Arc<ImageView>]>,
 }
 
 impl Framebuffer {
@@ -46,7 +47,7 @@ impl Framebuffer {
         Arc::new(Framebuffer {
             render_pass: render_pass.clone(),
             vk_framebuffer,
-            image_views: image_views.into(),
+            _image_views: image_views.into(),
         })
     }
 
@@ -1,4 +1,4 @@
-use super::{Device, HasRawAshHandle, HasRawVkHandle, PipelineLayout, ShaderModule};
+use super::{Device, HasRawAshHandle, HasRawVkHandle, PipelineLayout, ShaderModule, VertexLayout};
 use ash::vk;
 use std::sync::Arc;
 
@@ -12,25 +12,268 @@ pub trait Pipeline {
 }
 
 impl GraphicsPipeline {
-    pub fn new(
+    pub fn builder() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder::new()
+    }
+}
+
+// Opaque color output, which is what attachments default to
+pub fn opaque_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::FALSE,
+        src_color_blend_factor: vk::BlendFactor::ONE,
+        dst_color_blend_factor: vk::BlendFactor::ZERO,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ONE,
+        dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+    }
+}
+
+// Straight alpha blending, `src * src_alpha + dst * (1 - src_alpha)`
+pub fn alpha_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::TRUE,
+        src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ONE,
+        dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+    }
+}
+
+// Adds onto what's already in the attachment, `src + dst`
+pub fn additive_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::TRUE,
+        src_color_blend_factor: vk::BlendFactor::ONE,
+        dst_color_blend_factor: vk::BlendFactor::ONE,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ONE,
+        dst_alpha_blend_factor: vk::BlendFactor::ONE,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+    }
+}
+
+// Defaults to filled, back-face culled, counter-clockwise triangle lists with
+// no depth testing and one sample. Viewport and scissor are always dynamic
+pub struct GraphicsPipelineBuilder {
+    shader_modules: Vec<Arc<ShaderModule>>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    dynamic_states: Vec<vk::DynamicState>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    line_width: f32,
+    // Constant factor, clamp and slope factor
+    depth_bias: Option<(f32, f32, f32)>,
+    samples: vk::SampleCountFlags,
+    alpha_to_coverage: bool,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    // Stage, constant id and value
+    specialization_constants: Vec<(vk::ShaderStageFlags, u32, Vec<u8>)>,
+    color_formats: Vec<vk::Format>,
+    depth_format: vk::Format,
+    stencil_format: vk::Format,
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new() -> Self {
+        Self {
+            shader_modules: vec![],
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_bias: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            alpha_to_coverage: false,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::LESS,
+            blend_attachments: vec![],
+            specialization_constants: vec![],
+            color_formats: vec![],
+            depth_format: vk::Format::UNDEFINED,
+            stencil_format: vk::Format::UNDEFINED,
+        }
+    }
+
+    pub fn shader_modules(mut self, shader_modules: &[Arc<ShaderModule>]) -> Self {
+        self.shader_modules = shader_modules.to_vec();
+        self
+    }
+
+    pub fn vertex_layout(mut self, layout: &VertexLayout) -> Self {
+        self.vertex_bindings = layout.bindings().to_vec();
+        self.vertex_attributes = layout.attributes().to_vec();
+        self
+    }
+
+    // Adds to the viewport and scissor states, which are always dynamic
+    pub fn dynamic_state(mut self, state: vk::DynamicState) -> Self {
+        if !self.dynamic_states.contains(&state) {
+            self.dynamic_states.push(state);
+        }
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn primitive_restart(mut self, enable: bool) -> Self {
+        self.primitive_restart = enable;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant_factor: f32, clamp: f32, slope_factor: f32) -> Self {
+        self.depth_bias = Some((constant_factor, clamp, slope_factor));
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn alpha_to_coverage(mut self, enable: bool) -> Self {
+        self.alpha_to_coverage = enable;
+        self
+    }
+
+    pub fn depth_test(mut self, enable: bool) -> Self {
+        self.depth_test = enable;
+        self
+    }
+
+    pub fn depth_write(mut self, enable: bool) -> Self {
+        self.depth_write = enable;
+        self
+    }
+
+    pub fn depth_compare_op(mut self, compare_op: vk::CompareOp) -> Self {
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    // One per color attachment, in order. Attachments without one are opaque
+    pub fn blend_attachment(mut self, state: vk::PipelineColorBlendAttachmentState) -> Self {
+        self.blend_attachments.push(state);
+        self
+    }
+
+    // Adds a color attachment after the others, with its own blend state.
+    // Earlier attachments without one are opaque
+    pub fn color_attachment(
+        mut self,
+        format: vk::Format,
+        blend: vk::PipelineColorBlendAttachmentState,
+    ) -> Self {
+        self.blend_attachments
+            .resize(self.color_formats.len(), opaque_blend_attachment());
+        self.color_formats.push(format);
+        self.blend_attachments.push(blend);
+        self
+    }
+
+    // Like `color_attachment` for each of them, in order. These are the
+    // fragment shader's outputs, matching the attachments the pipeline is
+    // rendered into
+    pub fn color_attachments(
+        mut self,
+        attachments: &[(vk::Format, vk::PipelineColorBlendAttachmentState)],
+    ) -> Self {
+        for (format, blend) in attachments {
+            self = self.color_attachment(*format, *blend);
+        }
+        self
+    }
+
+    // Sets `layout(constant_id = N)` in the shader of the given stage
+    pub fn specialization_constant<T: bytemuck::Pod>(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        constant_id: u32,
+        value: T,
+    ) -> Self {
+        self.specialization_constants
+            .retain(|(x, id, _)| !(*x == stage && *id == constant_id));
+        self.specialization_constants.push((
+            stage,
+            constant_id,
+            bytemuck::bytes_of(&value).to_vec(),
+        ));
+        self
+    }
+
+    // Replaces the color attachments' formats, see `color_attachment` to set
+    // them along with their blend states
+    pub fn color_formats(mut self, formats: &[vk::Format]) -> Self {
+        self.color_formats = formats.to_vec();
+        self
+    }
+
+    pub fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    pub fn stencil_format(mut self, format: vk::Format) -> Self {
+        self.stencil_format = format;
+        self
+    }
+
+    pub fn build(
+        &self,
         device: Arc<Device>,
-        shader_modules: &[Arc<ShaderModule>],
-        vertex_bindings: Option<&[vk::VertexInputBindingDescription]>,
-        vertex_attributes: Option<&[vk::VertexInputAttributeDescription]>,
-        dynamic_states: &[vk::DynamicState],
-        topology: vk::PrimitiveTopology,
-        primitive_restart: bool,
-        _viewports: Option<&[vk::Viewport]>,
-        _scissors: Option<&[vk::Rect2D]>,
         pipeline_layout: &PipelineLayout,
-        color_attachment_formats: &[vk::Format],
-        depth_attachment_format: vk::Format,
-        stencil_attachment_format: vk::Format,
     ) -> Arc<GraphicsPipeline> {
+        assert!(
+            self.blend_attachments.len() <= self.color_formats.len(),
+            "more blend attachments than color attachments"
+        );
+
         let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
-            .color_attachment_formats(color_attachment_formats)
-            .depth_attachment_format(depth_attachment_format)
-            .stencil_attachment_format(stencil_attachment_format)
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format)
+            .stencil_attachment_format(self.stencil_format)
             .build();
 
         let mut create_info = unsafe {
@@ -40,40 +283,65 @@ impl GraphicsPipeline {
                 .build()
         };
 
-        // ~~~~
+        // Specialization info for each stage, which the stage create infos
+        // point into
+        let mut specializations = vec![];
+        for shader_module in &self.shader_modules {
+            let stage = shader_module.pipeline_shader_stage_create_info().stage;
+            let mut map_entries = vec![];
+            let mut data = vec![];
+            for (_, constant_id, value) in self
+                .specialization_constants
+                .iter()
+                .filter(|(x, _, _)| *x == stage)
+            {
+                map_entries.push(vk::SpecializationMapEntry {
+                    constant_id: *constant_id,
+                    offset: data.len().try_into().unwrap(),
+                    size: value.len(),
+                });
+                data.extend_from_slice(value);
+            }
+            specializations.push((map_entries, data));
+        }
+
+        let specialization_infos = specializations
+            .iter()
+            .map(|(map_entries, data)| vk::SpecializationInfo {
+                map_entry_count: map_entries.len().try_into().unwrap(),
+                p_map_entries: map_entries.as_ptr(),
+                data_size: data.len(),
+                p_data: data.as_ptr() as *const _,
+            })
+            .collect::<Vec<_>>();
 
         let mut shader_stage_create_infos = vec![];
-        for shader_module in shader_modules {
-            shader_stage_create_infos.push(*shader_module.pipeline_shader_stage_create_info());
+        for (shader_module, specialization_info) in
+            self.shader_modules.iter().zip(&specialization_infos)
+        {
+            let mut stage_create_info = shader_module.pipeline_shader_stage_create_info();
+            if specialization_info.map_entry_count > 0 {
+                stage_create_info.p_specialization_info = specialization_info;
+            }
+            shader_stage_create_infos.push(stage_create_info);
         }
         create_info.stage_count = shader_stage_create_infos.len().try_into().unwrap();
         create_info.p_stages = shader_stage_create_infos.as_ptr();
 
-        let mut _vertex_input_state_create_info = None;
-        if !dynamic_states.contains(&vk::DynamicState::VERTEX_INPUT_EXT) {
-            let mut handle = Box::new(vk::PipelineVertexInputStateCreateInfo {
-                s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-                p_next: std::ptr::null(),
-                flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-                vertex_binding_description_count: 0,
-                p_vertex_binding_descriptions: std::ptr::null(),
-                vertex_attribute_description_count: 0,
-                p_vertex_attribute_descriptions: std::ptr::null(),
-            });
-
-            if let Some(bindings) = vertex_bindings {
-                handle.vertex_binding_description_count = bindings.len().try_into().unwrap();
-                handle.p_vertex_binding_descriptions = bindings.as_ptr();
-            }
-
-            if let Some(attributes) = vertex_attributes {
-                handle.vertex_attribute_description_count = attributes.len().try_into().unwrap();
-                handle.p_vertex_attribute_descriptions = attributes.as_ptr();
-            }
-
-            let ptr = &*handle as *const _;
-            _vertex_input_state_create_info = Some(handle);
-            create_info.p_vertex_input_state = ptr;
+        let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            vertex_binding_description_count: self.vertex_bindings.len().try_into().unwrap(),
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+            vertex_attribute_description_count: self.vertex_attributes.len().try_into().unwrap(),
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+        };
+        if !self
+            .dynamic_states
+            .contains(&vk::DynamicState::VERTEX_INPUT_EXT)
+        {
+            create_info.p_vertex_input_state = &vertex_input_state_create_info;
         }
 
         // TODO: Dynamic state
@@ -81,8 +349,8 @@ impl GraphicsPipeline {
             s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
-            topology,
-            primitive_restart_enable: if primitive_restart {
+            topology: self.topology,
+            primitive_restart_enable: if self.primitive_restart {
                 vk::TRUE
             } else {
                 vk::FALSE
@@ -99,118 +367,108 @@ impl GraphicsPipeline {
         };
         create_info.p_tessellation_state = &tessellation_state_create_info;
 
-        // TODO: Really need to do some more complex logix to handle all the
-        // edge cases here...
-        //
-        // TODO: Assuming that viewport/scissor is *always* provided at draw time
-        let v_count = dynamic_states.contains(&vk::DynamicState::VIEWPORT_WITH_COUNT);
-        let s_count = dynamic_states.contains(&vk::DynamicState::SCISSOR_WITH_COUNT);
-        let mut _viewport_state_create_info = None;
+        // Viewport and scissor are always provided at draw time
+        let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::PipelineViewportStateCreateFlags::empty(),
+            viewport_count: 1,
+            p_viewports: std::ptr::null(),
+            scissor_count: 1,
+            p_scissors: std::ptr::null(),
+        };
+        let v_count = self
+            .dynamic_states
+            .contains(&vk::DynamicState::VIEWPORT_WITH_COUNT);
+        let s_count = self
+            .dynamic_states
+            .contains(&vk::DynamicState::SCISSOR_WITH_COUNT);
         if !(v_count && s_count) {
-            let handle = Box::new(vk::PipelineViewportStateCreateInfo {
-                s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
-                p_next: std::ptr::null(),
-                flags: vk::PipelineViewportStateCreateFlags::empty(),
-                viewport_count: 1,
-                p_viewports: std::ptr::null(),
-                scissor_count: 1,
-                p_scissors: std::ptr::null(),
-            });
-            let ptr = &*handle as *const _;
-            _viewport_state_create_info = Some(handle);
-            create_info.p_viewport_state = ptr;
+            create_info.p_viewport_state = &viewport_state_create_info;
         }
 
-        // if let Some(viewports) = viewports {
-        //     viewport_state_create_info.viewport_count = viewports.len().try_into().unwrap();
-        //     viewport_state_create_info.p_viewports = viewports.as_ptr();
-        // }
-
-        // if let Some(scissors) = scissors {
-        //     viewport_state_create_info.scissor_count = scissors.len().try_into().unwrap();
-        //     viewport_state_create_info.p_scissors = scissors.as_ptr();
-        // }
+        let (
+            depth_bias_enable,
+            depth_bias_constant_factor,
+            depth_bias_clamp,
+            depth_bias_slope_factor,
+        ) = match self.depth_bias {
+            Some((constant_factor, clamp, slope_factor)) => {
+                (vk::TRUE, constant_factor, clamp, slope_factor)
+            }
+            None => (vk::FALSE, 0.0, 0.0, 0.0),
+        };
 
-        // TODO: Hardcoded for now
         let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineRasterizationStateCreateFlags::empty(),
             depth_clamp_enable: vk::FALSE,
             rasterizer_discard_enable: vk::FALSE,
-            polygon_mode: vk::PolygonMode::FILL,
-            cull_mode: vk::CullModeFlags::BACK,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            depth_bias_enable: vk::FALSE,
-            depth_bias_constant_factor: 0.0,
-            depth_bias_clamp: 0.0,
-            depth_bias_slope_factor: 0.0,
-            line_width: 1.0,
+            polygon_mode: self.polygon_mode,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            depth_bias_enable,
+            depth_bias_constant_factor,
+            depth_bias_clamp,
+            depth_bias_slope_factor,
+            line_width: self.line_width,
         };
         create_info.p_rasterization_state = &rasterization_state_create_info;
 
-        // TODO: Hardcoded for now
         let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineMultisampleStateCreateFlags::empty(),
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            rasterization_samples: self.samples,
             sample_shading_enable: vk::FALSE,
             min_sample_shading: 1.0,
             p_sample_mask: std::ptr::null(),
-            alpha_to_coverage_enable: vk::FALSE,
+            alpha_to_coverage_enable: if self.alpha_to_coverage {
+                vk::TRUE
+            } else {
+                vk::FALSE
+            },
             alpha_to_one_enable: vk::FALSE,
         };
         create_info.p_multisample_state = &multisample_state_create_info;
 
-        // TODO: Hardcoded null for depth/stencil for now
-
-        // XXX
-        // TODO: Depends on number of attachements
-        // assert!(render_pass.attachment_count() == 1);
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
-            blend_enable: vk::FALSE,
-            src_color_blend_factor: vk::BlendFactor::ONE,
-            dst_color_blend_factor: vk::BlendFactor::ZERO,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ONE,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::R
-                | vk::ColorComponentFlags::G
-                | vk::ColorComponentFlags::B
-                | vk::ColorComponentFlags::A,
-        };
+        let depth_stencil_state_create_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare_op)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .build();
+        create_info.p_depth_stencil_state = &depth_stencil_state_create_info;
+
+        let mut blend_attachments = self.blend_attachments.clone();
+        blend_attachments.resize(self.color_formats.len(), opaque_blend_attachment());
 
-        // XXX
         let color_blend_state_create_info = vk::PipelineColorBlendStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineColorBlendStateCreateFlags::empty(),
             logic_op_enable: vk::FALSE,
             logic_op: vk::LogicOp::COPY,
-            attachment_count: 1,
-            p_attachments: &color_blend_attachment,
+            attachment_count: blend_attachments.len().try_into().unwrap(),
+            p_attachments: blend_attachments.as_ptr(),
             blend_constants: [0.0, 0.0, 0.0, 0.0],
         };
         create_info.p_color_blend_state = &color_blend_state_create_info;
 
-        // XXX
-        assert!(dynamic_states.contains(&vk::DynamicState::VIEWPORT));
-        assert!(dynamic_states.contains(&vk::DynamicState::SCISSOR));
         let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineDynamicStateCreateFlags::empty(),
-            dynamic_state_count: dynamic_states.len().try_into().unwrap(),
-            p_dynamic_states: dynamic_states.as_ptr(),
+            dynamic_state_count: self.dynamic_states.len().try_into().unwrap(),
+            p_dynamic_states: self.dynamic_states.as_ptr(),
         };
         create_info.p_dynamic_state = &dynamic_state_create_info;
 
         let create_infos = [create_info];
 
-        // TODO: Vulkan clearly wants us to be creating pipelines in batches.
-        // Need a builder / loader pattern for that
+        // TODO: Vulkan clearly wants us to be creating pipelines in batches
         let vk_pipeline = unsafe {
             let pipelines = device
                 .get_ash_handle()
@@ -226,6 +484,12 @@ impl GraphicsPipeline {
     }
 }
 
+impl Default for GraphicsPipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Pipeline for GraphicsPipeline {
     fn bind_point(&self) -> vk::PipelineBindPoint {
         vk::PipelineBindPoint::GRAPHICS
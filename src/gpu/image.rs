@@ -1,4 +1,4 @@
-use super::{Device, HasRawVkHandle, ImageView};
+use super::{Device, HasRawVkHandle, ImageView, MemoryPriority};
 use ash::vk;
 use std::sync::Arc;
 use vma::Alloc;
@@ -9,35 +9,139 @@ pub struct Image {
     image_type: vk::ImageType,
     format: vk::Format,
     extent: vk::Extent3D,
+    mip_levels: u32,
+    array_layers: u32,
+    samples: vk::SampleCountFlags,
+    flags: vk::ImageCreateFlags,
     allocated: Option<AllocatedImage>,
 }
 
 pub struct AllocatedImage {
     allocator: Arc<vma::Allocator>,
     vma_allocation: vma::Allocation,
-    vma_allocation_info: vma::AllocationInfo,
+}
+
+// What `Image::new` creates. Starts out as a single 2D level in device local
+// memory, which is what most images are
+pub struct ImageConfig {
+    flags: vk::ImageCreateFlags,
+    image_type: vk::ImageType,
+    format: vk::Format,
+    extent: vk::Extent3D,
+    mip_levels: u32,
+    array_layers: u32,
+    samples: vk::SampleCountFlags,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    memory_usage: vma::MemoryUsage,
+    allocation_flags: vma::AllocationCreateFlags,
+    required_flags: vk::MemoryPropertyFlags,
+    priority: MemoryPriority,
+}
+
+impl ImageConfig {
+    pub fn new(format: vk::Format, extent: vk::Extent3D, usage: vk::ImageUsageFlags) -> Self {
+        Self {
+            flags: vk::ImageCreateFlags::empty(),
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            memory_usage: vma::MemoryUsage::AutoPreferDevice,
+            allocation_flags: vma::AllocationCreateFlags::empty(),
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            priority: MemoryPriority::Normal,
+        }
+    }
+
+    pub fn flags(mut self, flags: vk::ImageCreateFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn image_type(mut self, image_type: vk::ImageType) -> Self {
+        self.image_type = image_type;
+        self
+    }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn tiling(mut self, tiling: vk::ImageTiling) -> Self {
+        self.tiling = tiling;
+        self
+    }
+
+    pub fn memory_usage(mut self, memory_usage: vma::MemoryUsage) -> Self {
+        self.memory_usage = memory_usage;
+        self
+    }
+
+    pub fn allocation_flags(mut self, allocation_flags: vma::AllocationCreateFlags) -> Self {
+        self.allocation_flags = allocation_flags;
+        self
+    }
+
+    pub fn required_flags(mut self, required_flags: vk::MemoryPropertyFlags) -> Self {
+        self.required_flags = required_flags;
+        self
+    }
+
+    pub fn priority(mut self, priority: MemoryPriority) -> Self {
+        self.priority = priority;
+        self
+    }
 }
 
 impl Image {
     pub fn new(
         device: Arc<Device>,
         allocator: Arc<vma::Allocator>,
-        image_type: vk::ImageType,
-        format: vk::Format,
-        extent: vk::Extent3D,
-        mip_levels: u32,
-        array_layers: u32,
-        samples: vk::SampleCountFlags,
-        tiling: vk::ImageTiling,
-        image_usage: vk::ImageUsageFlags,
-        memory_usage: vma::MemoryUsage,
-        allocation_flags: vma::AllocationCreateFlags,
-        required_flags: vk::MemoryPropertyFlags,
+        config: ImageConfig,
     ) -> Arc<Self> {
+        let ImageConfig {
+            flags,
+            image_type,
+            format,
+            extent,
+            mip_levels,
+            array_layers,
+            samples,
+            tiling,
+            usage,
+            memory_usage,
+            allocation_flags,
+            required_flags,
+            priority,
+        } = config;
+
+        if flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) {
+            assert!(
+                array_layers.is_multiple_of(6) && extent.width == extent.height,
+                "cube compatible images need square layers in multiples of six"
+            );
+        }
+
         let vk_image_info = vk::ImageCreateInfo {
             s_type: vk::StructureType::IMAGE_CREATE_INFO,
             p_next: std::ptr::null(),
-            flags: vk::ImageCreateFlags::empty(),
+            flags,
             image_type,
             format,
             extent,
@@ -45,7 +149,7 @@ impl Image {
             array_layers,
             samples,
             tiling,
-            usage: image_usage,
+            usage,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             queue_family_index_count: 0,
             p_queue_family_indices: std::ptr::null(),
@@ -59,7 +163,7 @@ impl Image {
             preferred_flags: vk::MemoryPropertyFlags::empty(),
             memory_type_bits: 0,
             user_data: 0,
-            priority: 0.0,
+            priority: priority.value(),
         };
 
         let (vk_image, vma_allocation) = unsafe {
@@ -68,22 +172,50 @@ impl Image {
                 .expect("failed to create and allocate image")
         };
 
-        let vma_allocation_info = allocator.get_allocation_info(&vma_allocation);
-
         Arc::new(Self {
             device,
             vk_image,
             image_type,
             format,
             extent,
+            mip_levels,
+            array_layers,
+            samples,
+            flags,
             allocated: Some(AllocatedImage {
                 allocator,
                 vma_allocation,
-                vma_allocation_info,
             }),
         })
     }
 
+    // A device local cubemap with six square layers, which the default view
+    // sees as a cube
+    pub fn new_cube(
+        device: Arc<Device>,
+        allocator: Arc<vma::Allocator>,
+        format: vk::Format,
+        size: u32,
+        mip_levels: u32,
+        usage: vk::ImageUsageFlags,
+        priority: MemoryPriority,
+    ) -> Arc<Self> {
+        let extent = vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        };
+        Image::new(
+            device,
+            allocator,
+            ImageConfig::new(format, extent, usage)
+                .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+                .mip_levels(mip_levels)
+                .array_layers(6)
+                .priority(priority),
+        )
+    }
+
     pub fn image_type(&self) -> &vk::ImageType {
         &self.image_type
     }
@@ -96,6 +228,26 @@ impl Image {
         &self.extent
     }
 
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    pub fn flags(&self) -> vk::ImageCreateFlags {
+        self.flags
+    }
+
+    pub fn is_cube(&self) -> bool {
+        self.flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+    }
+
     // Create an image that is owned by a swapchain
     pub fn from_swapchain(
         device: Arc<Device>,
@@ -110,6 +262,10 @@ impl Image {
             image_type,
             format,
             extent,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            flags: vk::ImageCreateFlags::empty(),
             allocated: None,
         })
     }
@@ -118,11 +274,14 @@ impl Image {
         &self.device
     }
 
+    // Cube compatible images are viewed as a cube over their first six
+    // layers, and everything else as the first layer
     pub fn get_default_view(self: &Arc<Self>, aspect_mask: vk::ImageAspectFlags) -> Arc<ImageView> {
-        let view_type = match self.image_type {
-            vk::ImageType::TYPE_1D => vk::ImageViewType::TYPE_1D,
-            vk::ImageType::TYPE_2D => vk::ImageViewType::TYPE_2D,
-            vk::ImageType::TYPE_3D => vk::ImageViewType::TYPE_3D,
+        let (view_type, layer_count) = match self.image_type {
+            vk::ImageType::TYPE_1D => (vk::ImageViewType::TYPE_1D, 1),
+            vk::ImageType::TYPE_2D if self.is_cube() => (vk::ImageViewType::CUBE, 6),
+            vk::ImageType::TYPE_2D => (vk::ImageViewType::TYPE_2D, 1),
+            vk::ImageType::TYPE_3D => (vk::ImageViewType::TYPE_3D, 1),
             _ => unreachable!(),
         };
 
@@ -133,12 +292,54 @@ impl Image {
             vk::ImageSubresourceRange {
                 aspect_mask,
                 base_mip_level: 0,
+                level_count: self.mip_levels,
+                base_array_layer: 0,
+                layer_count,
+            },
+        )
+    }
+
+    // A single mip level of a 2D image, e.g. for writing to it as a storage
+    // image
+    pub fn get_mip_view(
+        self: &Arc<Self>,
+        aspect_mask: vk::ImageAspectFlags,
+        mip_level: u32,
+    ) -> Arc<ImageView> {
+        ImageView::new(
+            self.clone(),
+            vk::ImageViewType::TYPE_2D,
+            self.format,
+            vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: mip_level,
                 level_count: 1,
                 base_array_layer: 0,
                 layer_count: 1,
             },
         )
     }
+
+    // Every layer of the image as a 2D array, e.g. for writing to the faces of
+    // a cubemap from a compute shader
+    pub fn get_array_view(
+        self: &Arc<Self>,
+        aspect_mask: vk::ImageAspectFlags,
+        mip_level: u32,
+    ) -> Arc<ImageView> {
+        ImageView::new(
+            self.clone(),
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            self.format,
+            vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: mip_level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: self.array_layers,
+            },
+        )
+    }
 }
 
 impl HasRawVkHandle<vk::Image> for Image {
@@ -5,6 +5,8 @@ use std::sync::Arc;
 pub struct ImageView {
     image: Arc<Image>,
     vk_image_view: vk::ImageView,
+    view_type: vk::ImageViewType,
+    subresource_range: vk::ImageSubresourceRange,
 }
 
 impl ImageView {
@@ -36,8 +38,29 @@ impl ImageView {
         Arc::new(Self {
             image,
             vk_image_view,
+            view_type,
+            subresource_range,
         })
     }
+
+    pub fn image(&self) -> &Arc<Image> {
+        &self.image
+    }
+
+    pub fn view_type(&self) -> vk::ImageViewType {
+        self.view_type
+    }
+
+    pub fn subresource_range(&self) -> &vk::ImageSubresourceRange {
+        &self.subresource_range
+    }
+
+    pub fn is_cube(&self) -> bool {
+        matches!(
+            self.view_type,
+            vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY
+        )
+    }
 }
 
 impl HasRawVkHandle<vk::ImageView> for ImageView {
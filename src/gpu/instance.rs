@@ -1,38 +1,37 @@
-use super::{HasRawAshHandle, HasRawVkHandle, PhysicalDevice};
+use super::{AdapterInfo, DeviceFeaturesRequest, HasRawAshHandle, HasRawVkHandle, PhysicalDevice};
 use ash::vk;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use std::cell::OnceCell;
 use std::ffi::CStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 pub struct Instance {
-    ash_entry: ash::Entry,
+    // Holds the loaded Vulkan library, which has to outlive the instance
+    _ash_entry: ash::Entry,
     ash_instance: ash::Instance,
     surface: Surface,
-    vk_physical_devices: OnceCell<Vec<vk::PhysicalDevice>>,
+    vk_physical_devices: OnceLock<Vec<vk::PhysicalDevice>>,
 }
 
 impl Instance {
-    pub fn new(window: &Arc<impl HasRawDisplayHandle + HasRawWindowHandle>) -> Arc<Instance> {
+    pub fn new(
+        window: &Arc<impl HasRawDisplayHandle + HasRawWindowHandle>,
+        validation: bool,
+    ) -> Arc<Instance> {
         unsafe {
             let app_info = vk::ApplicationInfo {
                 s_type: vk::StructureType::APPLICATION_INFO,
                 p_next: std::ptr::null(),
-                p_application_name: CStr::from_bytes_with_nul(b"vulka\0").unwrap().as_ptr(),
+                p_application_name: c"vulka".as_ptr(),
                 application_version: 0,
-                p_engine_name: CStr::from_bytes_with_nul(b"no engine\0").unwrap().as_ptr(),
+                p_engine_name: c"no engine".as_ptr(),
                 engine_version: 0,
                 api_version: vk::make_api_version(0, 1, 3, 268),
             };
 
             let mut enabled_layer_names = vec![];
 
-            if cfg!(debug_assertions) {
-                enabled_layer_names.push(
-                    CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0")
-                        .unwrap()
-                        .as_ptr(),
-                );
+            if validation {
+                enabled_layer_names.push(c"VK_LAYER_KHRONOS_validation".as_ptr());
 
                 // enabled_layer_names.push(
                 //     CStr::from_bytes_with_nul(b"VK_LAYER_LUNARG_api_dump\0")
@@ -44,9 +43,25 @@ impl Instance {
             let raw_display_handle = window.raw_display_handle();
             let raw_window_handle = window.raw_window_handle();
 
+            let ash_entry = ash::Entry::load().expect("failed to initialize ash");
+
             // Get the necessary extensions for the window surface
-            let surface_extensions = ash_window::enumerate_required_extensions(raw_display_handle)
-                .expect("failed to get windowing extensions");
+            let mut enabled_extension_names =
+                ash_window::enumerate_required_extensions(raw_display_handle)
+                    .expect("failed to get windowing extensions")
+                    .to_vec();
+
+            // Surfaces only report HDR color spaces when this is enabled
+            let supported_extensions = ash_entry
+                .enumerate_instance_extension_properties(None)
+                .expect("failed to get instance extensions");
+            let swapchain_colorspace = vk::ExtSwapchainColorspaceFn::name();
+            let supports_swapchain_colorspace = supported_extensions
+                .iter()
+                .any(|x| CStr::from_ptr(x.extension_name.as_ptr()) == swapchain_colorspace);
+            if supports_swapchain_colorspace {
+                enabled_extension_names.push(swapchain_colorspace.as_ptr());
+            }
 
             let create_info = vk::InstanceCreateInfo {
                 s_type: vk::StructureType::INSTANCE_CREATE_INFO,
@@ -55,12 +70,10 @@ impl Instance {
                 p_application_info: &app_info,
                 enabled_layer_count: enabled_layer_names.len().try_into().unwrap(),
                 pp_enabled_layer_names: enabled_layer_names.as_ptr(),
-                enabled_extension_count: surface_extensions.len().try_into().unwrap(),
-                pp_enabled_extension_names: surface_extensions.as_ptr(),
+                enabled_extension_count: enabled_extension_names.len().try_into().unwrap(),
+                pp_enabled_extension_names: enabled_extension_names.as_ptr(),
             };
 
-            let ash_entry = ash::Entry::load().expect("failed to initialize ash");
-
             let ash_instance = ash_entry
                 .create_instance(&create_info, None)
                 .expect("failed to create instance");
@@ -78,10 +91,10 @@ impl Instance {
             let ash_surface_fn = ash::extensions::khr::Surface::new(&ash_entry, &ash_instance);
 
             Arc::new(Instance {
-                ash_entry,
+                _ash_entry: ash_entry,
                 ash_instance,
                 surface: Surface::new(vk_surface, ash_surface_fn),
-                vk_physical_devices: OnceCell::new(),
+                vk_physical_devices: OnceLock::new(),
             })
         }
     }
@@ -101,6 +114,23 @@ impl Instance {
             .map(|vk_phy_device| PhysicalDevice::new(*vk_phy_device, self.clone()))
             .collect()
     }
+
+    // Every physical device in enumeration order, with what it's missing from
+    // the given requirements. Pass the result to a `DeviceSelector`
+    pub fn enumerate_adapters(
+        self: &Arc<Instance>,
+        required_queue_flags: vk::QueueFlags,
+        required_extensions: &[&[u8]],
+        features: &DeviceFeaturesRequest,
+    ) -> Vec<AdapterInfo> {
+        self.get_physical_devices()
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| {
+                AdapterInfo::new(i, x, required_queue_flags, required_extensions, features)
+            })
+            .collect()
+    }
 }
 
 impl HasRawAshHandle<ash::Instance> for Instance {
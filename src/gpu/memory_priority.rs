@@ -0,0 +1,25 @@
+// Hint for which allocations the driver should keep resident in device local
+// memory under pressure. Only has an effect when VK_EXT_memory_priority is
+// enabled, and with VK_EXT_pageable_device_local_memory also lets the driver
+// page out low priority allocations instead of failing
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MemoryPriority {
+    // Staging and streamed resources that are cheap to bring back
+    Low,
+    #[default]
+    Normal,
+    // Render targets and anything else touched every frame
+    High,
+    Custom(f32),
+}
+
+impl MemoryPriority {
+    pub fn value(&self) -> f32 {
+        match self {
+            MemoryPriority::Low => 0.0,
+            MemoryPriority::Normal => 0.5,
+            MemoryPriority::High => 1.0,
+            MemoryPriority::Custom(x) => x.clamp(0.0, 1.0),
+        }
+    }
+}
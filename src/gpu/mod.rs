@@ -1,37 +1,81 @@
+mod bindless;
 mod buffer;
+mod buffer_arena;
 mod command_buffer;
+mod command_trace;
+mod compute_pipeline;
+mod deletion_queue;
+mod descriptor_allocator;
 mod descriptor_set;
 mod device;
+mod device_fault;
+mod device_features;
+mod device_selector;
+mod feature_chain;
+mod frame_ring;
 mod framebuffer;
 mod graphics_pipeline;
 mod image;
 mod image_view;
 mod instance;
+mod memory_priority;
 mod physical_device;
 mod pipeline_layout;
+mod present_mode;
+mod query_pool;
 mod queue;
 mod raw_handle;
+mod render_graph;
 mod render_pass;
 mod sampler;
 mod shader_module;
+mod shader_registry;
+mod staging_arena;
+mod surface_format;
 mod swapchain;
 mod sync;
+mod thread_command_pools;
+mod upload;
+mod vertex_format;
+mod vulkan13;
 
+pub use bindless::*;
 pub use buffer::*;
+pub use buffer_arena::*;
 pub use command_buffer::*;
+pub use command_trace::*;
+pub use compute_pipeline::*;
+pub use deletion_queue::*;
+pub use descriptor_allocator::*;
 pub use descriptor_set::*;
 pub use device::*;
+pub use device_fault::*;
+pub use device_features::*;
+pub use device_selector::*;
+pub use feature_chain::*;
+pub use frame_ring::*;
 pub use framebuffer::*;
 pub use graphics_pipeline::*;
 pub use image::*;
 pub use image_view::*;
 pub use instance::*;
+pub use memory_priority::*;
 pub use physical_device::*;
 pub use pipeline_layout::*;
+pub use present_mode::*;
+pub use query_pool::*;
 pub use queue::*;
 pub use raw_handle::*;
+pub use render_graph::*;
 pub use render_pass::*;
 pub use sampler::*;
 pub use shader_module::*;
+pub use shader_registry::*;
+pub use staging_arena::*;
+pub use surface_format::*;
 pub use swapchain::*;
 pub use sync::*;
+pub use thread_command_pools::*;
+pub use upload::*;
+pub use vertex_format::*;
+pub use vulkan13::*;
@@ -1,9 +1,10 @@
-use super::{Device, HasRawAshHandle, HasRawVkHandle, Instance};
+use super::{
+    Device, DeviceFeaturesRequest, FeatureChain, HasRawAshHandle, HasRawVkHandle, Instance,
+};
 use ash::vk;
-use std::cell::OnceCell;
 use std::collections::HashSet;
 use std::ffi::CStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Clone)]
 pub struct PhysicalDevice {
@@ -11,9 +12,9 @@ pub struct PhysicalDevice {
     vk_phy_device: vk::PhysicalDevice,
     // TODO: Factor these out into separate structs so they don't take up memory
     // for entire lifetime of GpuPhysicalDevice?
-    properties: OnceCell<vk::PhysicalDeviceProperties>,
-    extension_properties: OnceCell<Vec<vk::ExtensionProperties>>,
-    extension_names: OnceCell<Vec<Vec<u8>>>,
+    properties: OnceLock<vk::PhysicalDeviceProperties>,
+    extension_properties: OnceLock<Vec<vk::ExtensionProperties>>,
+    extension_names: OnceLock<Vec<Vec<u8>>>,
 }
 
 impl PhysicalDevice {
@@ -22,11 +23,11 @@ impl PhysicalDevice {
         gpu_instance: Arc<Instance>,
     ) -> Arc<PhysicalDevice> {
         Arc::new(PhysicalDevice {
-            gpu_instance: gpu_instance,
+            gpu_instance,
             vk_phy_device,
-            properties: OnceCell::new(),
-            extension_properties: OnceCell::new(),
-            extension_names: OnceCell::new(),
+            properties: OnceLock::new(),
+            extension_properties: OnceLock::new(),
+            extension_names: OnceLock::new(),
         })
     }
 
@@ -37,13 +38,19 @@ impl PhysicalDevice {
     pub fn get_device(
         self: &Arc<PhysicalDevice>,
         queue_family_indices: &[u32],
-        enabled_extensions: &[&[u8]],
+        required_extensions: &[&[u8]],
+        optional_extensions: &[&[u8]],
+        features: &DeviceFeaturesRequest,
+        feature_chain: FeatureChain,
     ) -> Arc<Device> {
         Device::new(
             self.clone(),
             self.vk_phy_device,
             queue_family_indices,
-            enabled_extensions,
+            required_extensions,
+            optional_extensions,
+            features,
+            feature_chain,
         )
     }
 
@@ -68,6 +75,10 @@ impl PhysicalDevice {
         self._get_physical_device_properties().device_id
     }
 
+    pub fn api_version(&self) -> u32 {
+        self._get_physical_device_properties().api_version
+    }
+
     pub fn device_name(&self) -> &str {
         get_str_from_chars(&self._get_physical_device_properties().device_name)
     }
@@ -84,6 +95,66 @@ impl PhysicalDevice {
         }
     }
 
+    pub fn descriptor_indexing_features(&self) -> vk::PhysicalDeviceDescriptorIndexingFeatures {
+        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut descriptor_indexing)
+            .build();
+        unsafe {
+            self.gpu_instance
+                .get_ash_handle()
+                .get_physical_device_features2(self.vk_phy_device, &mut features2);
+        }
+        descriptor_indexing.p_next = std::ptr::null_mut();
+        descriptor_indexing
+    }
+
+    pub fn buffer_device_address_features(&self) -> vk::PhysicalDeviceBufferDeviceAddressFeatures {
+        let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut buffer_device_address)
+            .build();
+        unsafe {
+            self.gpu_instance
+                .get_ash_handle()
+                .get_physical_device_features2(self.vk_phy_device, &mut features2);
+        }
+        buffer_device_address.p_next = std::ptr::null_mut();
+        buffer_device_address
+    }
+
+    pub fn format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        unsafe {
+            self.gpu_instance
+                .get_ash_handle()
+                .get_physical_device_format_properties(self.vk_phy_device, format)
+        }
+    }
+
+    pub fn supports_vertex_format(&self, format: vk::Format) -> bool {
+        self.format_properties(format)
+            .buffer_features
+            .contains(vk::FormatFeatureFlags::VERTEX_BUFFER)
+    }
+
+    // Whether images of `format` can be uploaded to and sampled with optimal
+    // tiling, e.g. for block compressed textures
+    pub fn supports_sampled_format(&self, format: vk::Format) -> bool {
+        self.format_properties(format)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST)
+    }
+
+    // Sample counts usable for rendering with both color and depth attachments
+    pub fn supported_sample_counts(&self) -> vk::SampleCountFlags {
+        let limits = self.device_limits();
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+    }
+
+    pub fn supports_sample_count(&self, samples: vk::SampleCountFlags) -> bool {
+        self.supported_sample_counts().contains(samples)
+    }
+
     pub fn device_type(&self) -> vk::PhysicalDeviceType {
         self._get_physical_device_properties().device_type
     }
@@ -207,7 +278,3 @@ fn get_str_from_chars(chars: &[i8]) -> &str {
     let cstr = CStr::from_bytes_until_nul(bytes).unwrap();
     cstr.to_str().unwrap()
 }
-
-fn get_string_from_chars(chars: &[i8]) -> String {
-    String::from(get_str_from_chars(chars))
-}
@@ -1,14 +1,43 @@
-use super::{DescriptorSetLayout, Device, HasRawAshHandle, HasRawVkHandle};
+use super::{DescriptorSetLayout, DescriptorSetLayoutKey, Device, HasRawAshHandle, HasRawVkHandle};
 use ash::vk;
 use std::sync::Arc;
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineLayoutKey {
+    set_layouts: Vec<DescriptorSetLayoutKey>,
+    push_constant_ranges: Vec<(vk::ShaderStageFlags, u32, u32)>,
+}
+
+impl PipelineLayoutKey {
+    pub fn new(
+        descriptor_set_layouts: &[Arc<DescriptorSetLayout>],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Self {
+        Self {
+            set_layouts: descriptor_set_layouts
+                .iter()
+                .map(|x| x.key().clone())
+                .collect(),
+            push_constant_ranges: push_constant_ranges
+                .iter()
+                .map(|x| (x.stage_flags, x.offset, x.size))
+                .collect(),
+        }
+    }
+}
+
 pub struct PipelineLayout {
     device: Arc<Device>,
     descriptor_set_layouts: Box<[Arc<DescriptorSetLayout>]>,
     vk_pipeline_layout: vk::PipelineLayout,
+    key: PipelineLayoutKey,
 }
 
 impl PipelineLayout {
+    pub fn push_constant_ranges() -> PushConstantRangesBuilder {
+        PushConstantRangesBuilder::new()
+    }
+
     pub fn new(
         device: Arc<Device>,
         descriptor_set_layouts: &[Arc<DescriptorSetLayout>],
@@ -26,7 +55,7 @@ impl PipelineLayout {
 
         let vk_pipeline_layout = unsafe {
             let vk_set_layouts;
-            if descriptor_set_layouts.len() > 0 {
+            if !descriptor_set_layouts.is_empty() {
                 vk_set_layouts = descriptor_set_layouts
                     .iter()
                     .map(|x| x.get_vk_handle())
@@ -36,7 +65,7 @@ impl PipelineLayout {
                 info.p_set_layouts = vk_set_layouts.as_ptr();
             }
 
-            if push_constant_ranges.len() > 0 {
+            if !push_constant_ranges.is_empty() {
                 info.push_constant_range_count = push_constant_ranges.len().try_into().unwrap();
                 info.p_push_constant_ranges = push_constant_ranges.as_ptr();
             }
@@ -51,12 +80,31 @@ impl PipelineLayout {
             device,
             descriptor_set_layouts: descriptor_set_layouts.into(),
             vk_pipeline_layout,
+            key: PipelineLayoutKey::new(descriptor_set_layouts, push_constant_ranges),
         })
     }
 
     pub fn device(&self) -> &Arc<Device> {
         &self.device
     }
+
+    pub fn descriptor_set_layouts(&self) -> &[Arc<DescriptorSetLayout>] {
+        &self.descriptor_set_layouts
+    }
+
+    pub fn key(&self) -> &PipelineLayoutKey {
+        &self.key
+    }
+
+    // Descriptor sets bound at `set` and below stay valid when switching
+    // between pipelines whose layouts are compatible up to `set`, so they
+    // don't need to be rebound
+    pub fn is_compatible_for_set(&self, other: &PipelineLayout, set: usize) -> bool {
+        self.key.push_constant_ranges == other.key.push_constant_ranges
+            && set < self.key.set_layouts.len()
+            && set < other.key.set_layouts.len()
+            && self.key.set_layouts[..=set] == other.key.set_layouts[..=set]
+    }
 }
 
 impl HasRawVkHandle<vk::PipelineLayout> for PipelineLayout {
@@ -74,3 +122,49 @@ impl Drop for PipelineLayout {
         }
     }
 }
+
+// Lays out push constant ranges back to back, sized from the structs that are
+// pushed with `CommandBuffer::push_constants`
+pub struct PushConstantRangesBuilder {
+    ranges: Vec<vk::PushConstantRange>,
+    offset: u32,
+}
+
+impl PushConstantRangesBuilder {
+    pub fn new() -> Self {
+        Self {
+            ranges: vec![],
+            offset: 0,
+        }
+    }
+
+    // Offset the next range will be placed at, to pass to `push_constants`
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn range<T: bytemuck::Pod>(mut self, stage_flags: vk::ShaderStageFlags) -> Self {
+        let size: u32 = std::mem::size_of::<T>().try_into().unwrap();
+        assert!(
+            size.is_multiple_of(4),
+            "push constant size must be a multiple of 4"
+        );
+        self.ranges.push(vk::PushConstantRange {
+            stage_flags,
+            offset: self.offset,
+            size,
+        });
+        self.offset += size;
+        self
+    }
+
+    pub fn build(self) -> Vec<vk::PushConstantRange> {
+        self.ranges
+    }
+}
+
+impl Default for PushConstantRangesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,40 @@
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+// FIFO is the only present mode every surface has to support, so each
+// preference falls back to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentModePreference {
+    // Waits for vertical blank, capping the framerate to the refresh rate
+    Vsync,
+    // Like vsync, but presents immediately when a frame is late instead of
+    // waiting for the next blank, which can tear
+    #[default]
+    Adaptive,
+    // Uncapped without tearing. The newest frame replaces any queued one
+    Mailbox,
+    // Uncapped and can tear
+    Immediate,
+}
+
+impl PresentModePreference {
+    fn fallbacks(&self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentModePreference::Vsync => &[vk::PresentModeKHR::FIFO],
+            PresentModePreference::Adaptive => &[vk::PresentModeKHR::FIFO_RELAXED],
+            PresentModePreference::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+            PresentModePreference::Immediate => {
+                &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX]
+            }
+        }
+    }
+
+    pub fn choose(&self, present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        self.fallbacks()
+            .iter()
+            .find(|x| present_modes.contains(x))
+            .copied()
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+}
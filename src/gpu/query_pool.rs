@@ -0,0 +1,280 @@
+use super::{CommandBuffer, Device, HasRawAshHandle, HasRawVkHandle};
+use ash::vk;
+use std::sync::Arc;
+
+pub struct QueryPool {
+    device: Arc<Device>,
+    vk_query_pool: vk::QueryPool,
+    query_type: vk::QueryType,
+    pipeline_statistics: vk::QueryPipelineStatisticFlags,
+    count: u32,
+}
+
+impl QueryPool {
+    // `pipeline_statistics` is only used by PIPELINE_STATISTICS queries
+    pub fn new(
+        device: Arc<Device>,
+        query_type: vk::QueryType,
+        pipeline_statistics: vk::QueryPipelineStatisticFlags,
+        count: u32,
+    ) -> Self {
+        let create_info = vk::QueryPoolCreateInfo {
+            s_type: vk::StructureType::QUERY_POOL_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::QueryPoolCreateFlags::empty(),
+            query_type,
+            query_count: count,
+            pipeline_statistics,
+        };
+
+        let vk_query_pool = unsafe {
+            device
+                .get_ash_handle()
+                .create_query_pool(&create_info, None)
+                .expect("failed to create query pool")
+        };
+
+        Self {
+            device,
+            vk_query_pool,
+            query_type,
+            pipeline_statistics,
+            count,
+        }
+    }
+
+    pub fn timestamps(device: Arc<Device>, count: u32) -> Self {
+        QueryPool::new(
+            device,
+            vk::QueryType::TIMESTAMP,
+            vk::QueryPipelineStatisticFlags::empty(),
+            count,
+        )
+    }
+
+    // Each query counts the samples that passed the depth and stencil tests
+    pub fn occlusion(device: Arc<Device>, count: u32) -> Self {
+        QueryPool::new(
+            device,
+            vk::QueryType::OCCLUSION,
+            vk::QueryPipelineStatisticFlags::empty(),
+            count,
+        )
+    }
+
+    // Needs the pipelineStatisticsQuery feature
+    pub fn pipeline_statistics(
+        device: Arc<Device>,
+        statistics: vk::QueryPipelineStatisticFlags,
+        count: u32,
+    ) -> Self {
+        assert!(
+            QueryPool::supports_pipeline_statistics(&device),
+            "pipeline statistics queries aren't enabled"
+        );
+        QueryPool::new(
+            device,
+            vk::QueryType::PIPELINE_STATISTICS,
+            statistics,
+            count,
+        )
+    }
+
+    // Timestamps can be written from any graphics or compute queue when this
+    // is set. Otherwise it depends on the queue family's timestamp_valid_bits
+    pub fn supports_timestamps(device: &Device) -> bool {
+        let limits = device.physical_device().device_limits();
+        limits.timestamp_compute_and_graphics == vk::TRUE
+    }
+
+    pub fn supports_pipeline_statistics(device: &Device) -> bool {
+        device.enabled_features().pipeline_statistics_query == vk::TRUE
+    }
+
+    // Without this, occlusion queries begun with PRECISE may only report
+    // whether any sample passed
+    pub fn supports_precise_occlusion(device: &Device) -> bool {
+        device.enabled_features().occlusion_query_precise == vk::TRUE
+    }
+
+    // Nanoseconds per timestamp tick
+    pub fn timestamp_period(device: &Device) -> f64 {
+        device.physical_device().device_limits().timestamp_period as f64
+    }
+
+    pub fn query_type(&self) -> vk::QueryType {
+        self.query_type
+    }
+
+    pub fn pipeline_statistics_flags(&self) -> vk::QueryPipelineStatisticFlags {
+        self.pipeline_statistics
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Pipeline statistics queries write one value for each enabled statistic,
+    // in the order of their flag bits. Everything else writes one
+    pub fn values_per_query(&self) -> u32 {
+        match self.query_type {
+            vk::QueryType::PIPELINE_STATISTICS => self.pipeline_statistics.as_raw().count_ones(),
+            _ => 1,
+        }
+    }
+
+    // Reads back the 64-bit results of `count` queries starting at
+    // `first_query` without waiting. Returns None if any of them aren't
+    // available yet, which is also the case for queries that were reset but
+    // never written
+    pub fn get_results(&self, first_query: u32, count: u32) -> Option<Vec<u64>> {
+        self._get_results(first_query, count, vk::QueryResultFlags::TYPE_64)
+    }
+
+    // Like `get_results`, but blocks until the queries are available. The
+    // queries must have been submitted, or this never returns
+    pub fn wait_results(&self, first_query: u32, count: u32) -> Vec<u64> {
+        self._get_results(
+            first_query,
+            count,
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+        )
+        .unwrap()
+    }
+
+    fn _get_results(
+        &self,
+        first_query: u32,
+        count: u32,
+        flags: vk::QueryResultFlags,
+    ) -> Option<Vec<u64>> {
+        assert!(first_query + count <= self.count, "query out of range");
+
+        // ash's wrapper assumes one value per query, so this goes through the
+        // function pointer to set the stride for pipeline statistics
+        let values_per_query = self.values_per_query() as usize;
+        let mut results = vec![0_u64; count as usize * values_per_query];
+        let stride = (values_per_query * std::mem::size_of::<u64>()) as vk::DeviceSize;
+        let result = unsafe {
+            let device = self.device.get_ash_handle();
+            (device.fp_v1_0().get_query_pool_results)(
+                device.handle(),
+                self.vk_query_pool,
+                first_query,
+                count,
+                results.len() * std::mem::size_of::<u64>(),
+                results.as_mut_ptr().cast(),
+                stride,
+                flags,
+            )
+        };
+
+        match result {
+            vk::Result::SUCCESS => Some(results),
+            vk::Result::NOT_READY => None,
+            result => panic!("failed to get query pool results: {:?}", result),
+        }
+    }
+}
+
+impl HasRawVkHandle<vk::QueryPool> for QueryPool {
+    unsafe fn get_vk_handle(&self) -> vk::QueryPool {
+        self.vk_query_pool
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .get_ash_handle()
+                .destroy_query_pool(self.vk_query_pool, None);
+        }
+    }
+}
+
+// Counters for everything the pipeline statistics query can report. Counts
+// are only guaranteed to be within a factor of the work done, e.g. vertex
+// shader invocations may include cache misses or be skipped on hits
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    pub const FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+            | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+    );
+
+    // `values` are in the order of the flag bits in `FLAGS`
+    fn _from_values(values: &[u64]) -> Self {
+        Self {
+            input_assembly_vertices: values[0],
+            input_assembly_primitives: values[1],
+            vertex_shader_invocations: values[2],
+            clipping_invocations: values[3],
+            clipping_primitives: values[4],
+            fragment_shader_invocations: values[5],
+            compute_shader_invocations: values[6],
+        }
+    }
+}
+
+// A pool of pipeline statistics queries that decodes into
+// `PipelineStatistics`. Each query covers the work recorded between its
+// `begin` and `end`, which can't span render pass instances
+pub struct StatsQuery {
+    query_pool: QueryPool,
+}
+
+impl StatsQuery {
+    pub fn new(device: Arc<Device>, count: u32) -> Self {
+        Self {
+            query_pool: QueryPool::pipeline_statistics(device, PipelineStatistics::FLAGS, count),
+        }
+    }
+
+    pub fn query_pool(&self) -> &QueryPool {
+        &self.query_pool
+    }
+
+    pub fn count(&self) -> u32 {
+        self.query_pool.count()
+    }
+
+    // Must be recorded outside of a render pass, before the queries are begun
+    pub fn reset(&self, cmd_buf: &CommandBuffer) {
+        cmd_buf.reset_query_pool(&self.query_pool, 0, self.query_pool.count());
+    }
+
+    pub fn begin(&self, cmd_buf: &CommandBuffer, query: u32) {
+        cmd_buf.begin_query(&self.query_pool, query, vk::QueryControlFlags::empty());
+    }
+
+    pub fn end(&self, cmd_buf: &CommandBuffer, query: u32) {
+        cmd_buf.end_query(&self.query_pool, query);
+    }
+
+    // None until the submission that ended the query has completed
+    pub fn results(&self, query: u32) -> Option<PipelineStatistics> {
+        self.query_pool
+            .get_results(query, 1)
+            .map(|x| PipelineStatistics::_from_values(&x))
+    }
+
+    pub fn wait_results(&self, query: u32) -> PipelineStatistics {
+        PipelineStatistics::_from_values(&self.query_pool.wait_results(query, 1))
+    }
+}
@@ -1,4 +1,4 @@
-use super::{CommandBuffer, Device, Fence, QueueFamily, Semaphore, Swapchain};
+use super::{CommandBuffer, Device, Fence, QueueFamily, Semaphore, Swapchain, TimelineValue};
 use super::{HasRawAshHandle, HasRawVkHandle};
 use ash::vk;
 use std::sync::Arc;
@@ -41,10 +41,12 @@ impl Queue {
     pub fn submit(
         &self,
         wait: Option<&[(&Semaphore, vk::PipelineStageFlags2)]>,
+        timeline_wait: &[TimelineValue],
         command_buffers: &[&CommandBuffer],
         signal: Option<&[(&Semaphore, vk::PipelineStageFlags2)]>,
+        timeline_signal: &[TimelineValue],
         fence: Option<&Fence>,
-    ) -> () {
+    ) {
         // TODO: This feels like it could be improved. Too much unnecessary
         // copying and `queue_submit` works on batches so the API should
         // probably be batch-oriented
@@ -55,25 +57,29 @@ impl Queue {
         let mut signal_semaphore_infos: Vec<vk::SemaphoreSubmitInfo> = vec![];
 
         unsafe {
-            if let Some(wait) = wait {
-                wait_semaphore_infos.reserve(wait.len());
-
-                for x in wait {
-                    let info = vk::SemaphoreSubmitInfo::builder()
-                        .semaphore(x.0.get_vk_handle())
-                        .value(1)
-                        .stage_mask(x.1)
-                        .device_index(0)
-                        .build();
+            // The value is ignored for binary semaphores
+            for x in wait.unwrap_or(&[]) {
+                let info = vk::SemaphoreSubmitInfo::builder()
+                    .semaphore(x.0.get_vk_handle())
+                    .value(1)
+                    .stage_mask(x.1)
+                    .device_index(0)
+                    .build();
+
+                wait_semaphore_infos.push(info);
+            }
 
-                    wait_semaphore_infos.push(info);
-                }
+            for x in timeline_wait {
+                wait_semaphore_infos.push(Queue::_timeline_submit_info(x));
+            }
 
-                submit_info.wait_semaphore_info_count = wait.len().try_into().unwrap();
+            if !wait_semaphore_infos.is_empty() {
+                submit_info.wait_semaphore_info_count =
+                    wait_semaphore_infos.len().try_into().unwrap();
                 submit_info.p_wait_semaphore_infos = wait_semaphore_infos.as_ptr();
             }
 
-            if command_buffers.len() > 0 {
+            if !command_buffers.is_empty() {
                 command_buffer_infos.reserve(command_buffers.len());
 
                 for x in command_buffers {
@@ -89,21 +95,24 @@ impl Queue {
                 submit_info.p_command_buffer_infos = command_buffer_infos.as_ptr();
             }
 
-            if let Some(signal) = signal {
-                signal_semaphore_infos.reserve(signal.len());
+            for x in signal.unwrap_or(&[]) {
+                let info = vk::SemaphoreSubmitInfo::builder()
+                    .semaphore(x.0.get_vk_handle())
+                    .value(1)
+                    .stage_mask(x.1)
+                    .device_index(0)
+                    .build();
 
-                for x in signal {
-                    let info = vk::SemaphoreSubmitInfo::builder()
-                        .semaphore(x.0.get_vk_handle())
-                        .value(1)
-                        .stage_mask(x.1)
-                        .device_index(0)
-                        .build();
+                signal_semaphore_infos.push(info);
+            }
 
-                    signal_semaphore_infos.push(info);
-                }
+            for x in timeline_signal {
+                signal_semaphore_infos.push(Queue::_timeline_submit_info(x));
+            }
 
-                submit_info.signal_semaphore_info_count = signal.len().try_into().unwrap();
+            if !signal_semaphore_infos.is_empty() {
+                submit_info.signal_semaphore_info_count =
+                    signal_semaphore_infos.len().try_into().unwrap();
                 submit_info.p_signal_semaphore_infos = signal_semaphore_infos.as_ptr();
             }
 
@@ -111,58 +120,76 @@ impl Queue {
                 .map(|x| x.get_vk_handle())
                 .unwrap_or(vk::Fence::null());
 
-            self.device
-                .get_ash_handle()
-                .queue_submit2(self.get_vk_handle(), &[submit_info], submit_fence)
-                // .queue_submit(self.vk_queue, submit_infos, submit_fence)
-                .expect("failed to submit to queue");
+            let result = self.device.vulkan13().queue_submit2(
+                self.get_vk_handle(),
+                &[submit_info],
+                submit_fence,
+            );
+            // .queue_submit(self.vk_queue, submit_infos, submit_fence)
+            self.device.check(result, "failed to submit to queue");
         }
     }
 
+    unsafe fn _timeline_submit_info(x: &TimelineValue) -> vk::SemaphoreSubmitInfo {
+        vk::SemaphoreSubmitInfo::builder()
+            .semaphore(x.semaphore.get_vk_handle())
+            .value(x.value)
+            .stage_mask(x.stage_mask)
+            .device_index(0)
+            .build()
+    }
+
+    // Presents an image to each swapchain with a single call, e.g. one per
+    // window, and returns a result per swapchain in the same order. `Ok` says
+    // whether that swapchain is suboptimal
     pub fn submit_present(
         &self,
         wait: &[&Semaphore],
-        swapchain: &Swapchain,
-        image_index: u32,
-    ) -> Result<bool, vk::Result> {
-        let mut info = vk::PresentInfoKHR {
+        presents: &[(&Swapchain, u32)],
+    ) -> Vec<Result<bool, vk::Result>> {
+        assert!(!presents.is_empty(), "nothing to present");
+
+        let vk_wait_semaphores: Vec<vk::Semaphore> =
+            wait.iter().map(|x| unsafe { x.get_vk_handle() }).collect();
+        let vk_swapchains: Vec<vk::SwapchainKHR> = presents
+            .iter()
+            .map(|x| unsafe { x.0.get_vk_handle() })
+            .collect();
+        let image_indices: Vec<u32> = presents.iter().map(|x| x.1).collect();
+        let mut results = vec![vk::Result::SUCCESS; presents.len()];
+
+        let info = vk::PresentInfoKHR {
             s_type: vk::StructureType::PRESENT_INFO_KHR,
             p_next: std::ptr::null(),
-            wait_semaphore_count: 0,
-            p_wait_semaphores: std::ptr::null(),
-            swapchain_count: 1,
-            p_swapchains: std::ptr::null(),
-            p_image_indices: std::ptr::null(),
-            ..Default::default()
+            wait_semaphore_count: vk_wait_semaphores.len().try_into().unwrap(),
+            p_wait_semaphores: vk_wait_semaphores.as_ptr(),
+            swapchain_count: vk_swapchains.len().try_into().unwrap(),
+            p_swapchains: vk_swapchains.as_ptr(),
+            p_image_indices: image_indices.as_ptr(),
+            p_results: results.as_mut_ptr(),
         };
 
-        let mut vk_wait_semaphores: Vec<vk::Semaphore>;
-        let vk_swapchains: [vk::SwapchainKHR; 1];
-
-        unsafe {
-            if wait.len() > 0 {
-                vk_wait_semaphores = vec![];
-                vk_wait_semaphores.reserve(wait.len());
-
-                for x in wait {
-                    vk_wait_semaphores.push(x.get_vk_handle());
-                }
-
-                info.wait_semaphore_count = wait.len().try_into().unwrap();
-                info.p_wait_semaphores = vk_wait_semaphores.as_ptr();
-            }
-
-            vk_swapchains = [swapchain.get_vk_handle()];
-            info.p_swapchains = vk_swapchains.as_ptr();
-            info.p_image_indices = &image_index;
-
-            swapchain
+        // Every swapchain belongs to the same device, so any of their loaders
+        // will do
+        let result = unsafe {
+            presents[0]
+                .0
                 .get_ash_handle()
                 .queue_present(self.vk_queue, &info)
-        }
+        };
+
+        results
+            .into_iter()
+            .map(|x| match (x, result) {
+                (vk::Result::SUCCESS, Err(error)) => Err(error),
+                (vk::Result::SUCCESS, _) => Ok(false),
+                (vk::Result::SUBOPTIMAL_KHR, _) => Ok(true),
+                (error, _) => Err(error),
+            })
+            .collect()
     }
 
-    pub fn wait_idle(&self) -> () {
+    pub fn wait_idle(&self) {
         unsafe {
             self.device
                 .get_ash_handle()
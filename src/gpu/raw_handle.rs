@@ -1,7 +1,14 @@
 pub trait HasRawAshHandle<T> {
-    unsafe fn get_ash_handle<'t>(&'t self) -> &'t T;
+    /// # Safety
+    ///
+    /// The handle must not be used to destroy the object, or after the
+    /// object is dropped
+    unsafe fn get_ash_handle(&self) -> &T;
 }
 
 pub trait HasRawVkHandle<T> {
+    /// # Safety
+    ///
+    /// Same as `HasRawAshHandle::get_ash_handle`
     unsafe fn get_vk_handle(&self) -> T;
 }
@@ -0,0 +1,555 @@
+use super::{
+    Buffer, CommandBuffer, HasRawVkHandle, Image, QueryPool, SecondaryRecording, ThreadCommandPools,
+};
+use ash::vk;
+
+// How a pass uses an image, which determines its layout and the stages and
+// accesses barriers have to cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageUsage {
+    ColorAttachment,
+    DepthAttachment,
+    // Sampled from fragment or compute shaders
+    Sampled,
+    // Storage image in fragment or compute shaders
+    Storage,
+    TransferSrc,
+    TransferDst,
+    // For `CommandBuffer::clear_color_image`, which expects GENERAL
+    Clear,
+    Present,
+}
+
+impl ImageUsage {
+    fn layout(&self) -> vk::ImageLayout {
+        match self {
+            ImageUsage::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageUsage::DepthAttachment => vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            ImageUsage::Sampled => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageUsage::Storage | ImageUsage::Clear => vk::ImageLayout::GENERAL,
+            ImageUsage::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageUsage::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageUsage::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    fn stage_access(&self, write: bool) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        let shaders =
+            vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER;
+        match (self, write) {
+            (ImageUsage::ColorAttachment, false) => (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ,
+            ),
+            (ImageUsage::ColorAttachment, true) => (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+            (ImageUsage::DepthAttachment, false) => (
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ,
+            ),
+            (ImageUsage::DepthAttachment, true) => (
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            (ImageUsage::Sampled, _) => (shaders, vk::AccessFlags2::SHADER_SAMPLED_READ),
+            (ImageUsage::Storage, false) => (shaders, vk::AccessFlags2::SHADER_STORAGE_READ),
+            (ImageUsage::Storage, true) => (
+                shaders,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            ),
+            (ImageUsage::TransferSrc, _) => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+            ),
+            (ImageUsage::TransferDst | ImageUsage::Clear, _) => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            // Presentation is synchronized with semaphores
+            (ImageUsage::Present, _) => (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            ImageUsage::Sampled | ImageUsage::TransferSrc | ImageUsage::Present
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+    Vertex,
+    Index,
+    Indirect,
+    // Uniform buffer in any shader stage
+    Uniform,
+    // Storage buffer in any shader stage
+    Storage,
+    TransferSrc,
+    TransferDst,
+}
+
+impl BufferUsage {
+    fn stage_access(&self, write: bool) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        let shaders = vk::PipelineStageFlags2::VERTEX_SHADER
+            | vk::PipelineStageFlags2::FRAGMENT_SHADER
+            | vk::PipelineStageFlags2::COMPUTE_SHADER;
+        match (self, write) {
+            (BufferUsage::Vertex, _) => (
+                vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+                vk::AccessFlags2::VERTEX_ATTRIBUTE_READ,
+            ),
+            (BufferUsage::Index, _) => (
+                vk::PipelineStageFlags2::INDEX_INPUT,
+                vk::AccessFlags2::INDEX_READ,
+            ),
+            (BufferUsage::Indirect, _) => (
+                vk::PipelineStageFlags2::DRAW_INDIRECT,
+                vk::AccessFlags2::INDIRECT_COMMAND_READ,
+            ),
+            (BufferUsage::Uniform, _) => (shaders, vk::AccessFlags2::UNIFORM_READ),
+            (BufferUsage::Storage, false) => (shaders, vk::AccessFlags2::SHADER_STORAGE_READ),
+            (BufferUsage::Storage, true) => (
+                shaders,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            ),
+            (BufferUsage::TransferSrc, _) => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+            ),
+            (BufferUsage::TransferDst, _) => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHandle(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferHandle(usize);
+
+// Synchronization state of a resource while the graph is recorded. Reads
+// since the last write are tracked so that repeated reads don't get barriers
+// and the next write waits for all of them
+struct ResourceState {
+    layout: vk::ImageLayout,
+    write_stage: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    read_stage: vk::PipelineStageFlags2,
+    read_access: vk::AccessFlags2,
+}
+
+impl ResourceState {
+    fn new(layout: vk::ImageLayout, stage: vk::PipelineStageFlags2) -> Self {
+        Self {
+            layout,
+            write_stage: stage,
+            write_access: vk::AccessFlags2::NONE,
+            read_stage: vk::PipelineStageFlags2::NONE,
+            read_access: vk::AccessFlags2::NONE,
+        }
+    }
+
+    // Returns the source and destination scopes of the barrier needed before
+    // the access, if any, and updates the state to after it
+    fn access(
+        &mut self,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+        write: bool,
+    ) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+        // Layout transitions are writes as far as hazards are concerned
+        if write || layout != self.layout {
+            let src = (self.write_stage | self.read_stage, self.write_access);
+            self.layout = layout;
+            self.write_stage = stage;
+            self.write_access = if write {
+                access
+            } else {
+                vk::AccessFlags2::NONE
+            };
+            self.read_stage = if write {
+                vk::PipelineStageFlags2::NONE
+            } else {
+                stage
+            };
+            self.read_access = if write {
+                vk::AccessFlags2::NONE
+            } else {
+                access
+            };
+            return Some(src);
+        }
+
+        // Reads after a write wait on it, unless an earlier read already did
+        // for the same stages and accesses
+        if self.read_stage.contains(stage) && self.read_access.contains(access) {
+            return None;
+        }
+        self.read_stage |= stage;
+        self.read_access |= access;
+        if self.write_access.is_empty() && self.write_stage.is_empty() {
+            None
+        } else {
+            Some((self.write_stage, self.write_access))
+        }
+    }
+}
+
+struct GraphImage<'a> {
+    image: &'a Image,
+    state: ResourceState,
+}
+
+struct GraphBuffer<'a> {
+    _buffer: &'a Buffer,
+    state: ResourceState,
+}
+
+type ParallelRecord<'a> = Box<dyn FnOnce(&CommandBuffer) + Send + 'a>;
+
+enum PassRecord<'a> {
+    Inline(Box<dyn FnOnce(&CommandBuffer) + 'a>),
+    // Recorded into a secondary command buffer on a worker thread
+    Parallel(ParallelRecord<'a>),
+}
+
+struct GraphPass<'a> {
+    name: &'static str,
+    images: Vec<(ImageHandle, ImageUsage, bool)>,
+    buffers: Vec<(BufferHandle, BufferUsage, bool)>,
+    record: PassRecord<'a>,
+}
+
+// Passes declare the images and buffers they read and write, and the graph
+// records them in order with the layout transitions and barriers between
+// them. Passes run in the order they're added
+pub struct RenderGraph<'a> {
+    images: Vec<GraphImage<'a>>,
+    buffers: Vec<GraphBuffer<'a>>,
+    passes: Vec<GraphPass<'a>>,
+    thread_pools: Option<&'a ThreadCommandPools>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            images: vec![],
+            buffers: vec![],
+            passes: vec![],
+            thread_pools: None,
+        }
+    }
+
+    // Parallel passes are recorded concurrently with these pools' workers.
+    // Without them they're recorded on the calling thread like other passes
+    pub fn set_thread_pools(&mut self, thread_pools: &'a ThreadCommandPools) {
+        self.thread_pools = Some(thread_pools);
+    }
+
+    // `stage` is what earlier work on the image is synchronized with, e.g. the
+    // stage a swapchain image's acquire semaphore is waited on. Images whose
+    // contents are discarded are imported as UNDEFINED
+    pub fn import_image(
+        &mut self,
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags2,
+    ) -> ImageHandle {
+        self.images.push(GraphImage {
+            image,
+            state: ResourceState::new(layout, stage),
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    // Buffers are assumed to be synchronized with any work outside the graph
+    pub fn import_buffer(&mut self, buffer: &'a Buffer) -> BufferHandle {
+        self.buffers.push(GraphBuffer {
+            _buffer: buffer,
+            state: ResourceState::new(vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags2::NONE),
+        });
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: &'static str) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: GraphPass {
+                name,
+                images: vec![],
+                buffers: vec![],
+                record: PassRecord::Inline(Box::new(|_| {})),
+            },
+        }
+    }
+
+    pub fn execute(self, cmd_buf: &CommandBuffer) {
+        self._execute(cmd_buf, None);
+    }
+
+    // Like `execute`, but writes a timestamp before and after each pass, to
+    // queries 2i and 2i + 1 of `query_pool`. Returns the pass names in order
+    pub fn execute_timed(
+        self,
+        cmd_buf: &CommandBuffer,
+        query_pool: &QueryPool,
+    ) -> Vec<&'static str> {
+        self._execute(cmd_buf, Some(query_pool))
+    }
+
+    fn _execute(
+        mut self,
+        cmd_buf: &CommandBuffer,
+        query_pool: Option<&QueryPool>,
+    ) -> Vec<&'static str> {
+        let query_count = 2 * self.passes.len() as u32;
+        if let Some(query_pool) = query_pool {
+            assert!(
+                query_count <= query_pool.count(),
+                "query pool too small for {} passes",
+                self.passes.len()
+            );
+            cmd_buf.reset_query_pool(query_pool, 0, query_count);
+        }
+
+        // Barriers only depend on the order of the passes, so they're all
+        // computed before anything is recorded
+        let passes = std::mem::take(&mut self.passes);
+        let barriers: Vec<_> = passes.iter().map(|x| self._barriers(x)).collect();
+
+        let mut names = vec![];
+        let mut inline = vec![];
+        let mut parallel = vec![];
+        for (i, pass) in passes.into_iter().enumerate() {
+            names.push(pass.name);
+            match (pass.record, self.thread_pools) {
+                (PassRecord::Parallel(record), Some(_)) => {
+                    inline.push(None);
+                    parallel.push((i, record));
+                }
+                (PassRecord::Parallel(record), None) => inline.push(Some(record as Box<_>)),
+                (PassRecord::Inline(record), _) => inline.push(Some(record)),
+            }
+        }
+
+        let mut secondaries: Vec<Option<SecondaryRecording>> = names.iter().map(|_| None).collect();
+        if let Some(thread_pools) = self.thread_pools {
+            for (i, recording) in _record_parallel(thread_pools, parallel, cmd_buf.is_tracing()) {
+                secondaries[i] = Some(recording);
+            }
+        }
+
+        let passes = barriers.into_iter().zip(inline).zip(secondaries);
+        for (i, (((memory_barriers, image_barriers), inline), secondary)) in passes.enumerate() {
+            if !memory_barriers.is_empty() || !image_barriers.is_empty() {
+                cmd_buf.pipeline_barrier(&memory_barriers, &image_barriers);
+            }
+
+            let query = 2 * i as u32;
+            if let Some(query_pool) = query_pool {
+                cmd_buf.write_timestamp(vk::PipelineStageFlags::TOP_OF_PIPE, query_pool, query);
+            }
+
+            if let Some(record) = inline {
+                record(cmd_buf);
+            }
+            if let Some(secondary) = secondary {
+                cmd_buf.execute_secondary(secondary);
+            }
+
+            if let Some(query_pool) = query_pool {
+                cmd_buf.write_timestamp(
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    query + 1,
+                );
+            }
+        }
+        names
+    }
+
+    // Returns the barriers needed before the pass and updates the resource
+    // states to after it
+    fn _barriers(
+        &mut self,
+        pass: &GraphPass,
+    ) -> (Vec<vk::MemoryBarrier2>, Vec<vk::ImageMemoryBarrier2>) {
+        let mut memory_barriers = vec![];
+        let mut image_barriers = vec![];
+
+        for (handle, usage, write) in &pass.images {
+            let image = &mut self.images[handle.0];
+            let old_layout = image.state.layout;
+            let new_layout = usage.layout();
+            let (dst_stage, dst_access) = usage.stage_access(*write);
+
+            if let Some((src_stage, src_access)) = image
+                .state
+                .access(new_layout, dst_stage, dst_access, *write)
+            {
+                image_barriers.push(vk::ImageMemoryBarrier2 {
+                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
+                    src_stage_mask: src_stage,
+                    src_access_mask: src_access,
+                    dst_stage_mask: dst_stage,
+                    dst_access_mask: dst_access,
+                    old_layout,
+                    new_layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: unsafe { image.image.get_vk_handle() },
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask: _aspect_mask(*image.image.format()),
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    },
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Buffer barriers are merged into one global memory barrier
+        let mut buffer_barrier = vk::MemoryBarrier2::default();
+        for (handle, usage, write) in &pass.buffers {
+            let (dst_stage, dst_access) = usage.stage_access(*write);
+            if let Some((src_stage, src_access)) = self.buffers[handle.0].state.access(
+                vk::ImageLayout::UNDEFINED,
+                dst_stage,
+                dst_access,
+                *write,
+            ) {
+                buffer_barrier.src_stage_mask |= src_stage;
+                buffer_barrier.src_access_mask |= src_access;
+                buffer_barrier.dst_stage_mask |= dst_stage;
+                buffer_barrier.dst_access_mask |= dst_access;
+            }
+        }
+        if !buffer_barrier.src_stage_mask.is_empty() {
+            memory_barriers.push(buffer_barrier);
+        }
+
+        (memory_barriers, image_barriers)
+    }
+}
+
+impl Default for RenderGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Spreads the passes over the pools' workers, one thread each. Returns each
+// pass' index with its recording
+fn _record_parallel<'a>(
+    thread_pools: &ThreadCommandPools,
+    passes: Vec<(usize, ParallelRecord<'a>)>,
+    trace: bool,
+) -> Vec<(usize, SecondaryRecording)> {
+    let worker_count = thread_pools.worker_count().min(passes.len());
+    if worker_count == 0 {
+        return vec![];
+    }
+
+    let mut batches: Vec<Vec<_>> = (0..worker_count).map(|_| vec![]).collect();
+    for (i, pass) in passes.into_iter().enumerate() {
+        batches[i % worker_count].push(pass);
+    }
+
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = batches
+            .into_iter()
+            .enumerate()
+            .map(|(worker, batch)| {
+                scope.spawn(move || {
+                    batch
+                        .into_iter()
+                        .map(|(i, record)| (i, thread_pools.record(worker, trace, record)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .flat_map(|x| x.join().expect("pass recording thread panicked"))
+            .collect()
+    })
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: GraphPass<'a>,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    pub fn read_image(mut self, image: ImageHandle, usage: ImageUsage) -> Self {
+        self.pass.images.push((image, usage, false));
+        self
+    }
+
+    pub fn write_image(mut self, image: ImageHandle, usage: ImageUsage) -> Self {
+        assert!(
+            !usage.is_read_only(),
+            "pass {} writes an image as {:?}",
+            self.pass.name,
+            usage
+        );
+        self.pass.images.push((image, usage, true));
+        self
+    }
+
+    pub fn read_buffer(mut self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+        self.pass.buffers.push((buffer, usage, false));
+        self
+    }
+
+    pub fn write_buffer(mut self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+        self.pass.buffers.push((buffer, usage, true));
+        self
+    }
+
+    // Adds the pass to the graph. `record` is called when the graph is
+    // executed, after the pass' barriers
+    pub fn record(mut self, record: impl FnOnce(&CommandBuffer) + 'a) {
+        self.pass.record = PassRecord::Inline(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+
+    // Like `record`, but the pass is recorded into a secondary command buffer
+    // on a worker thread, concurrently with the graph's other parallel passes,
+    // and executed by the primary in order. Parallel passes are recorded
+    // before the inline ones, begin their own rendering, and can't rely on
+    // state or queries from the primary
+    pub fn record_parallel(mut self, record: impl FnOnce(&CommandBuffer) + Send + 'a) {
+        self.pass.record = PassRecord::Parallel(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+}
+
+fn _aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
@@ -98,8 +98,8 @@ impl RenderPassBuilder {
     pub fn build(self, config: RenderPassConfig) -> Arc<RenderPass> {
         // Collect attachments
         let mut attachment_indices: HashMap<usize, u32> = HashMap::new();
-        let mut attachment_descriptions: Vec<vk::AttachmentDescription> = vec![];
-        attachment_descriptions.reserve(config.attachments.len());
+        let mut attachment_descriptions: Vec<vk::AttachmentDescription> =
+            Vec::with_capacity(config.attachments.len());
         for (index, attachment) in config.attachments.iter().enumerate() {
             assert!(attachment.parent_id() == self.id());
             let res = attachment_indices.insert(attachment.id(), index.try_into().unwrap());
@@ -111,8 +111,8 @@ impl RenderPassBuilder {
 
         // Collect the subpass builders
         let mut subpass_indices: HashMap<usize, u32> = HashMap::new();
-        let mut subpass_descriptions: Vec<vk::SubpassDescription> = vec![];
-        subpass_descriptions.reserve(config.subpasses.len());
+        let mut subpass_descriptions: Vec<vk::SubpassDescription> =
+            Vec::with_capacity(config.subpasses.len());
         for (index, subpass) in config.subpasses.iter().enumerate() {
             assert!(subpass.parent_id() == self.id());
             let res = subpass_indices.insert(subpass.id(), index.try_into().unwrap());
@@ -151,18 +151,18 @@ impl RenderPassBuilder {
             p_dependencies: std::ptr::null(),
         };
 
-        if attachment_descriptions.len() > 0 {
+        if !attachment_descriptions.is_empty() {
             render_pass_create_info.attachment_count =
                 attachment_descriptions.len().try_into().unwrap();
             render_pass_create_info.p_attachments = attachment_descriptions.as_ptr();
         }
 
-        if subpass_descriptions.len() > 0 {
+        if !subpass_descriptions.is_empty() {
             render_pass_create_info.subpass_count = subpass_descriptions.len().try_into().unwrap();
             render_pass_create_info.p_subpasses = subpass_descriptions.as_ptr();
         }
 
-        if vk_dependencies.len() > 0 {
+        if !vk_dependencies.is_empty() {
             render_pass_create_info.dependency_count = vk_dependencies.len().try_into().unwrap();
             render_pass_create_info.p_dependencies = vk_dependencies.as_ptr();
         }
@@ -183,6 +183,12 @@ impl RenderPassBuilder {
     }
 }
 
+impl Default for RenderPassBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct AttachmentBuilder {
     id: usize,
     parent_id: usize,
@@ -267,11 +273,11 @@ pub struct SubpassBuilder {
 
 struct SubpassDescriptionState {
     subpass_description: vk::SubpassDescription,
-    input_attachments: Box<Vec<vk::AttachmentReference>>,
-    color_attachments: Box<Vec<vk::AttachmentReference>>,
-    resolve_attachments: Box<Vec<vk::AttachmentReference>>,
+    input_attachments: Vec<vk::AttachmentReference>,
+    color_attachments: Vec<vk::AttachmentReference>,
+    resolve_attachments: Vec<vk::AttachmentReference>,
     depth_stencil_attachment: Box<vk::AttachmentReference>,
-    preserve_attachments: Box<Vec<u32>>,
+    preserve_attachments: Vec<u32>,
 }
 
 impl SubpassBuilder {
@@ -337,6 +343,10 @@ impl SubpassBuilder {
         self
     }
 
+    /// # Safety
+    ///
+    /// The description's attachment pointers point into the builder, so it
+    /// must not be used after the builder is dropped
     pub unsafe fn get_subpass_description(
         &self,
         attachment_indices: &HashMap<usize, u32>,
@@ -355,19 +365,14 @@ impl SubpassBuilder {
                     preserve_attachment_count: 0,
                     p_preserve_attachments: std::ptr::null(),
                 },
-                input_attachments: Box::new(vec![]),
-                color_attachments: Box::new(vec![]),
-                resolve_attachments: Box::new(vec![]),
+                input_attachments: Vec::with_capacity(self.input.len()),
+                color_attachments: Vec::with_capacity(self.color.len()),
+                resolve_attachments: Vec::with_capacity(self.resolve.len()),
                 depth_stencil_attachment: Box::new(vk::AttachmentReference::default()),
-                preserve_attachments: Box::new(vec![]),
+                preserve_attachments: Vec::with_capacity(self.preserve.len()),
             };
 
-            state.input_attachments.reserve(self.input.len());
-            state.color_attachments.reserve(self.color.len());
-            state.resolve_attachments.reserve(self.resolve.len());
-            state.preserve_attachments.reserve(self.preserve.len());
-
-            if self.input.len() > 0 {
+            if !self.input.is_empty() {
                 for (id, layout) in &self.input {
                     state.input_attachments.push(vk::AttachmentReference {
                         attachment: *attachment_indices.get(id).unwrap(),
@@ -379,7 +384,7 @@ impl SubpassBuilder {
                 state.subpass_description.p_input_attachments = state.input_attachments.as_ptr();
             }
 
-            if self.color.len() > 0 {
+            if !self.color.is_empty() {
                 for (id, layout) in &self.color {
                     state.color_attachments.push(vk::AttachmentReference {
                         attachment: *attachment_indices.get(id).unwrap(),
@@ -391,7 +396,7 @@ impl SubpassBuilder {
                 state.subpass_description.p_color_attachments = state.color_attachments.as_ptr();
             }
 
-            if self.resolve.len() > 0 {
+            if !self.resolve.is_empty() {
                 if self.resolve.len() != self.color.len() {
                     panic!(
                     "number of subpass resolve attachments must equal number of color attachments"
@@ -415,7 +420,7 @@ impl SubpassBuilder {
                     state.depth_stencil_attachment.as_ref();
             }
 
-            if self.preserve.len() > 0 {
+            if !self.preserve.is_empty() {
                 for id in &self.preserve {
                     state
                         .preserve_attachments
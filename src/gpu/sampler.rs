@@ -9,9 +9,28 @@ pub struct Sampler {
 
 impl Sampler {
     pub fn new(device: Arc<Device>) -> Arc<Self> {
+        Sampler::with_address_mode(device, vk::SamplerAddressMode::REPEAT)
+    }
+
+    // For sampling render targets, where wrapping around would bleed the
+    // opposite edge in
+    pub fn clamped(device: Arc<Device>) -> Arc<Self> {
+        Sampler::with_address_mode(device, vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+
+    pub fn with_address_mode(
+        device: Arc<Device>,
+        address_mode: vk::SamplerAddressMode,
+    ) -> Arc<Self> {
         let physical_device = device.physical_device();
 
-        let max_anisotropy = physical_device.device_limits().max_sampler_anisotropy;
+        // Fall back to plain linear filtering on devices without anisotropy
+        let anisotropy_enable = device.enabled_features().sampler_anisotropy;
+        let max_anisotropy = if anisotropy_enable == vk::TRUE {
+            physical_device.device_limits().max_sampler_anisotropy
+        } else {
+            1.0
+        };
 
         let create_info = vk::SamplerCreateInfo {
             s_type: vk::StructureType::SAMPLER_CREATE_INFO,
@@ -20,24 +39,55 @@ impl Sampler {
             mag_filter: vk::Filter::LINEAR,
             min_filter: vk::Filter::LINEAR,
             mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-            address_mode_u: vk::SamplerAddressMode::REPEAT,
-            address_mode_v: vk::SamplerAddressMode::REPEAT,
-            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
             mip_lod_bias: 0.0,
-            anisotropy_enable: vk::TRUE,
+            anisotropy_enable,
             max_anisotropy,
             compare_enable: vk::FALSE,
             compare_op: vk::CompareOp::ALWAYS,
             min_lod: 0.0,
-            max_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
             border_color: vk::BorderColor::INT_OPAQUE_BLACK,
             unnormalized_coordinates: vk::FALSE,
         };
 
+        Sampler::from_create_info(device, &create_info)
+    }
+
+    // For shadow maps sampled with `sampler2DShadow`. Lookups outside of the
+    // map compare against a depth of 1, so they're lit
+    pub fn comparison(device: Arc<Device>, compare_op: vk::CompareOp) -> Arc<Self> {
+        let create_info = vk::SamplerCreateInfo {
+            s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::SamplerCreateFlags::empty(),
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            mip_lod_bias: 0.0,
+            anisotropy_enable: vk::FALSE,
+            max_anisotropy: 1.0,
+            compare_enable: vk::TRUE,
+            compare_op,
+            min_lod: 0.0,
+            max_lod: 0.0,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            unnormalized_coordinates: vk::FALSE,
+        };
+
+        Sampler::from_create_info(device, &create_info)
+    }
+
+    pub fn from_create_info(device: Arc<Device>, create_info: &vk::SamplerCreateInfo) -> Arc<Self> {
         let vk_sampler = unsafe {
             device
                 .get_ash_handle()
-                .create_sampler(&create_info, None)
+                .create_sampler(create_info, None)
                 .unwrap()
         };
 
@@ -1,12 +1,16 @@
 use super::{Device, HasRawAshHandle, HasRawVkHandle};
 use ash::vk;
+#[cfg(feature = "shaderc")]
 use shaderc::CompileOptions;
-use std::{cell::OnceCell, ffi::CString, sync::Arc};
+use std::{ffi::CString, sync::Arc};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ShaderKind {
     Vertex,
+    // Needs `DeviceFeature::GeometryShader`
+    Geometry,
     Fragment,
+    Compute,
 }
 
 pub struct ShaderModule {
@@ -14,11 +18,12 @@ pub struct ShaderModule {
     vk_shader_module: vk::ShaderModule,
     kind: ShaderKind,
     entry_point: &'static str,
-    entry_point_cstr: OnceCell<CString>,
-    pipeline_shader_stage_create_info: OnceCell<vk::PipelineShaderStageCreateInfo>,
+    // What the stage create info's `p_name` points at
+    entry_point_cstr: CString,
 }
 
 impl ShaderModule {
+    #[cfg(feature = "shaderc")]
     pub fn new(
         device: Arc<Device>,
         compiler: &shaderc::Compiler,
@@ -28,23 +33,69 @@ impl ShaderModule {
         entry_point: &'static str,
         options: Option<&CompileOptions>,
     ) -> Arc<ShaderModule> {
+        ShaderModule::try_new(
+            device,
+            compiler,
+            source,
+            kind,
+            file_name,
+            entry_point,
+            options,
+        )
+        .expect("failed to compile shader")
+    }
+
+    // Like `new` but returns compile errors instead of panicking, for shaders
+    // that are edited while running
+    #[cfg(feature = "shaderc")]
+    pub fn try_new(
+        device: Arc<Device>,
+        compiler: &shaderc::Compiler,
+        source: &str,
+        kind: ShaderKind,
+        file_name: &str,
+        entry_point: &'static str,
+        options: Option<&CompileOptions>,
+    ) -> Result<Arc<ShaderModule>, shaderc::Error> {
         let shaderc_kind = match kind {
             ShaderKind::Vertex => shaderc::ShaderKind::Vertex,
+            ShaderKind::Geometry => shaderc::ShaderKind::Geometry,
             ShaderKind::Fragment => shaderc::ShaderKind::Fragment,
+            ShaderKind::Compute => shaderc::ShaderKind::Compute,
         };
 
-        let artifact = compiler
-            .compile_into_spirv(source, shaderc_kind, file_name, entry_point, options)
-            .expect("failed to compile shader");
+        let artifact =
+            compiler.compile_into_spirv(source, shaderc_kind, file_name, entry_point, options)?;
+
+        Ok(ShaderModule::from_spirv(
+            device,
+            artifact.as_binary(),
+            kind,
+            entry_point,
+        ))
+    }
 
-        let bytes = artifact.as_binary_u8();
+    // Creates a module from precompiled SPIR-V. Use `read_spirv` to get the
+    // words from a .spv file or bytes embedded with `include_bytes!`, which
+    // aren't guaranteed to be aligned
+    pub fn from_spirv(
+        device: Arc<Device>,
+        code: &[u32],
+        kind: ShaderKind,
+        entry_point: &'static str,
+    ) -> Arc<ShaderModule> {
+        assert!(
+            !matches!(kind, ShaderKind::Geometry)
+                || device.enabled_features().geometry_shader == vk::TRUE,
+            "geometry shaders need DeviceFeature::GeometryShader"
+        );
 
         let create_info = vk::ShaderModuleCreateInfo {
             s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::ShaderModuleCreateFlags::empty(),
-            code_size: bytes.len(),
-            p_code: bytes.as_ptr() as *const u32,
+            code_size: std::mem::size_of_val(code),
+            p_code: code.as_ptr(),
         };
 
         let vk_shader_module = unsafe {
@@ -59,8 +110,7 @@ impl ShaderModule {
             vk_shader_module,
             kind,
             entry_point,
-            entry_point_cstr: OnceCell::new(),
-            pipeline_shader_stage_create_info: OnceCell::new(),
+            entry_point_cstr: CString::new(entry_point).unwrap(),
         })
     }
 
@@ -76,30 +126,34 @@ impl ShaderModule {
         self.entry_point
     }
 
-    pub fn pipeline_shader_stage_create_info(&self) -> &vk::PipelineShaderStageCreateInfo {
-        self.pipeline_shader_stage_create_info.get_or_init(|| {
-            let entry_point_cstr = self
-                .entry_point_cstr
-                .get_or_init(|| CString::new(self.entry_point()).unwrap());
-
-            let stage = match self.kind {
-                ShaderKind::Vertex => vk::ShaderStageFlags::VERTEX,
-                ShaderKind::Fragment => vk::ShaderStageFlags::FRAGMENT,
-            };
-
-            vk::PipelineShaderStageCreateInfo {
-                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-                p_next: std::ptr::null(),
-                flags: vk::PipelineShaderStageCreateFlags::empty(),
-                stage,
-                module: self.vk_shader_module,
-                p_name: entry_point_cstr.as_ptr(),
-                p_specialization_info: std::ptr::null(),
-            }
-        })
+    // Built on each call rather than cached so the module stays Send and
+    // Sync. It points into the module, which has to outlive it
+    pub fn pipeline_shader_stage_create_info(&self) -> vk::PipelineShaderStageCreateInfo {
+        let stage = match self.kind {
+            ShaderKind::Vertex => vk::ShaderStageFlags::VERTEX,
+            ShaderKind::Geometry => vk::ShaderStageFlags::GEOMETRY,
+            ShaderKind::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ShaderKind::Compute => vk::ShaderStageFlags::COMPUTE,
+        };
+
+        vk::PipelineShaderStageCreateInfo {
+            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::PipelineShaderStageCreateFlags::empty(),
+            stage,
+            module: self.vk_shader_module,
+            p_name: self.entry_point_cstr.as_ptr(),
+            p_specialization_info: std::ptr::null(),
+        }
     }
 }
 
+// Decodes SPIR-V words from bytes, checking the magic number and fixing up
+// the byte order if needed
+pub fn read_spirv(bytes: &[u8]) -> std::io::Result<Vec<u32>> {
+    ash::util::read_spv(&mut std::io::Cursor::new(bytes))
+}
+
 impl HasRawVkHandle<vk::ShaderModule> for ShaderModule {
     unsafe fn get_vk_handle(&self) -> vk::ShaderModule {
         self.vk_shader_module
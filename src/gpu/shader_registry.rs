@@ -0,0 +1,189 @@
+use super::{read_spirv, Device, ShaderKind, ShaderModule};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+// How often source files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderId(usize);
+
+struct ShaderEntry {
+    path: PathBuf,
+    kind: ShaderKind,
+    entry_point: &'static str,
+    modified: Option<SystemTime>,
+    module: Arc<ShaderModule>,
+}
+
+// Loads shaders from disk and reloads them when their files change. Files
+// ending in .spv are loaded as precompiled SPIR-V and anything else is
+// compiled as GLSL, which needs the shaderc feature. Files are polled for
+// modification times rather than watched, since there are only a handful of
+// them
+pub struct ShaderRegistry {
+    device: Arc<Device>,
+    #[cfg(feature = "shaderc")]
+    compiler: shaderc::Compiler,
+    shaders: Vec<ShaderEntry>,
+    last_poll: Instant,
+}
+
+impl ShaderRegistry {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            #[cfg(feature = "shaderc")]
+            compiler: shaderc::Compiler::new().expect("failed to create shader compiler"),
+            shaders: vec![],
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Panics if the shader fails to compile, since there's nothing to fall
+    // back to yet
+    pub fn load(
+        &mut self,
+        path: impl Into<PathBuf>,
+        kind: ShaderKind,
+        entry_point: &'static str,
+    ) -> ShaderId {
+        let path = path.into();
+        let modified = ShaderRegistry::_modified(&path);
+        let module = self
+            ._compile(&path, kind, entry_point)
+            .unwrap_or_else(|error| panic!("failed to load shader: {}", error));
+
+        self.shaders.push(ShaderEntry {
+            path,
+            kind,
+            entry_point,
+            modified,
+            module,
+        });
+        ShaderId(self.shaders.len() - 1)
+    }
+
+    pub fn get(&self, id: ShaderId) -> &Arc<ShaderModule> {
+        &self.shaders[id.0].module
+    }
+
+    pub fn modules(&self, ids: &[ShaderId]) -> Vec<Arc<ShaderModule>> {
+        ids.iter().map(|x| self.get(*x).clone()).collect()
+    }
+
+    // Recompiles shaders whose files changed since they were last loaded and
+    // returns true if any module was replaced. Shaders that fail to compile
+    // keep their previous module and the error is printed
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut reloaded = false;
+        for i in 0..self.shaders.len() {
+            let entry = &self.shaders[i];
+            let modified = ShaderRegistry::_modified(&entry.path);
+            if modified == entry.modified {
+                continue;
+            }
+
+            let result = self._compile(&entry.path, entry.kind, entry.entry_point);
+            let entry = &mut self.shaders[i];
+            entry.modified = modified;
+
+            match result {
+                Ok(module) => {
+                    info!("reloaded shader {}", entry.path.display());
+                    entry.module = module;
+                    reloaded = true;
+                }
+                Err(error) => {
+                    warn!(
+                        "failed to reload shader {}: {}",
+                        entry.path.display(),
+                        error
+                    )
+                }
+            }
+        }
+        reloaded
+    }
+
+    fn _modified(path: &PathBuf) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|x| x.modified()).ok()
+    }
+
+    fn _compile(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        entry_point: &'static str,
+    ) -> Result<Arc<ShaderModule>, String> {
+        if path.extension().is_some_and(|x| x == "spv") {
+            let bytes = std::fs::read(path).map_err(|x| x.to_string())?;
+            let code = read_spirv(&bytes).map_err(|x| x.to_string())?;
+            return Ok(ShaderModule::from_spirv(
+                self.device.clone(),
+                &code,
+                kind,
+                entry_point,
+            ));
+        }
+        self._compile_glsl(path, kind, entry_point)
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn _compile_glsl(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        entry_point: &'static str,
+    ) -> Result<Arc<ShaderModule>, String> {
+        let source = std::fs::read_to_string(path).map_err(|x| x.to_string())?;
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        ShaderModule::try_new(
+            self.device.clone(),
+            &self.compiler,
+            &source,
+            kind,
+            &file_name,
+            entry_point,
+            None,
+        )
+        .map_err(|x| x.to_string())
+    }
+
+    #[cfg(not(feature = "shaderc"))]
+    fn _compile_glsl(
+        &self,
+        path: &Path,
+        _kind: ShaderKind,
+        _entry_point: &'static str,
+    ) -> Result<Arc<ShaderModule>, String> {
+        Err(format!(
+            "{} isn't SPIR-V and GLSL needs the shaderc feature",
+            path.display()
+        ))
+    }
+}
+
+// A pass with pipelines built from registry shaders, so they can be rebuilt
+// when the shaders are reloaded
+pub trait HotReload {
+    type Pipelines;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Self::Pipelines;
+
+    fn pipelines_mut(&mut self) -> &mut Self::Pipelines;
+
+    // Rebuilds the pipelines from the current shaders and returns the old
+    // ones, which frames in flight may still be using
+    fn recreate_pipelines(&mut self, shader_registry: &ShaderRegistry) -> Self::Pipelines {
+        let pipelines = self.create_pipelines(shader_registry);
+        std::mem::replace(self.pipelines_mut(), pipelines)
+    }
+}
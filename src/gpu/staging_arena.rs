@@ -0,0 +1,169 @@
+use super::{Buffer, Device, MemoryPriority};
+use ash::vk;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Persistently mapped ring buffer for staging uploads. Allocations made while
+// recording a frame stay valid until that frame's fence has been waited on,
+// so many small uploads share one buffer and nothing has to wait for the queue
+// to go idle
+pub struct StagingArena {
+    buffer: Rc<Buffer>,
+    capacity: u64,
+    alignment: u64,
+    // Both count bytes allocated since creation, so offsets into the buffer
+    // are taken modulo the capacity
+    head: Cell<u64>,
+    tail: Cell<u64>,
+    // Where `head` was when each frame in flight was last submitted
+    frame_ends: Vec<Cell<Option<u64>>>,
+    current_frame: Cell<Option<usize>>,
+}
+
+impl StagingArena {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<vma::Allocator>,
+        capacity: usize,
+        frames_in_flight: usize,
+    ) -> Self {
+        // 16 bytes covers the texel block size of every format we upload
+        let limits = device.physical_device().device_limits();
+        let alignment = limits.optimal_buffer_copy_offset_alignment.max(16);
+        let capacity = (capacity as u64).next_multiple_of(alignment);
+
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            capacity as usize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vma::MemoryUsage::AutoPreferHost,
+            vma::AllocationCreateFlags::MAPPED
+                | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+            MemoryPriority::Low,
+        );
+
+        Self {
+            buffer: Rc::new(buffer),
+            capacity,
+            alignment,
+            head: Cell::new(0),
+            tail: Cell::new(0),
+            frame_ends: (0..frames_in_flight).map(|_| Cell::new(None)).collect(),
+            current_frame: Cell::new(None),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    // Bytes that can still be allocated, ignoring any lost to wrapping around
+    pub fn available(&self) -> usize {
+        (self.capacity - (self.head.get() - self.tail.get())) as usize
+    }
+
+    // Must be called after waiting on the fence of frame `index`, which frees
+    // everything allocated up to its last submission
+    pub fn begin_frame(&self, index: usize) {
+        assert!(
+            self.current_frame.get().is_none(),
+            "staging arena frame already in progress"
+        );
+        if let Some(end) = self.frame_ends[index].take() {
+            self.tail.set(self.tail.get().max(end));
+        }
+        self.current_frame.set(Some(index));
+    }
+
+    // Must be called once the current frame has been submitted with its
+    // fence. Allocations made outside of a frame are freed with the next one
+    pub fn end_frame(&self) {
+        let index = self
+            .current_frame
+            .take()
+            .expect("no staging arena frame in progress");
+        self.frame_ends[index].set(Some(self.head.get()));
+    }
+
+    // Returns None when the frames in flight are still using too much of the
+    // arena for `size` bytes to fit
+    pub fn try_allocate(&self, size: usize) -> Option<StagingSlice> {
+        let size = size as u64;
+        if size > self.capacity {
+            return None;
+        }
+
+        // Allocations never straddle the end of the buffer, so skip ahead to
+        // the start if there isn't room left before it
+        let mut start = self.head.get().next_multiple_of(self.alignment);
+        let offset = start % self.capacity;
+        if offset + size > self.capacity {
+            start += self.capacity - offset;
+        }
+
+        let end = start + size;
+        if end - self.tail.get() > self.capacity {
+            return None;
+        }
+        self.head.set(end);
+
+        Some(StagingSlice {
+            buffer: self.buffer.clone(),
+            offset: (start % self.capacity) as usize,
+            size: size as usize,
+        })
+    }
+
+    pub fn allocate(&self, size: usize) -> StagingSlice {
+        self.try_allocate(size)
+            .expect("staging arena is out of space")
+    }
+
+    // Allocates a slice for `data` and copies it in
+    pub fn push<T: Copy>(&self, data: &[T]) -> StagingSlice {
+        let slice = self.allocate(size_of_val(data));
+        slice.write(data);
+        slice
+    }
+}
+
+// Part of a `StagingArena`. Only valid until the frame it was allocated in has
+// completed
+pub struct StagingSlice {
+    buffer: Rc<Buffer>,
+    offset: usize,
+    size: usize,
+}
+
+impl StagingSlice {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn write<T: Copy>(&self, data: &[T]) {
+        assert!(
+            size_of_val(data) <= self.size,
+            "data doesn't fit in the staging slice"
+        );
+        self.buffer.copy_nonoverlapping_at(self.offset, data);
+    }
+
+    // Region for copying the whole slice with `CommandBuffer::copy_buffer`
+    pub fn buffer_copy(&self, dst_offset: u64) -> vk::BufferCopy {
+        vk::BufferCopy {
+            src_offset: self.offset as u64,
+            dst_offset,
+            size: self.size as u64,
+        }
+    }
+}
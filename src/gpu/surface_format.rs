@@ -0,0 +1,99 @@
+use ash::vk;
+
+// What kind of output to ask the surface for. HDR color spaces need
+// VK_EXT_swapchain_colorspace and a display that supports them, so anything
+// that isn't available falls back to the next best option and then to SDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceFormatPreference {
+    // 8-bit sRGB, encoded by the swapchain format
+    #[default]
+    Sdr,
+    // 10-bit BT.2020 with the ST.2084 (PQ) transfer function, which has to be
+    // applied by whatever writes the swapchain image
+    Hdr10,
+    // 16-bit float linear BT.709, where 1.0 is SDR white and brighter values
+    // go above it
+    ScRgb,
+}
+
+impl SurfaceFormatPreference {
+    fn fallbacks(&self) -> &'static [SurfaceFormatPreference] {
+        use SurfaceFormatPreference::*;
+        match self {
+            Sdr => &[Sdr],
+            Hdr10 => &[Hdr10, ScRgb, Sdr],
+            ScRgb => &[ScRgb, Hdr10, Sdr],
+        }
+    }
+
+    pub fn matches(&self, format: &vk::SurfaceFormatKHR) -> bool {
+        match self {
+            SurfaceFormatPreference::Sdr => {
+                format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                    && matches!(
+                        format.format,
+                        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
+                    )
+            }
+            SurfaceFormatPreference::Hdr10 => {
+                format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT
+                    && matches!(
+                        format.format,
+                        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32
+                    )
+            }
+            SurfaceFormatPreference::ScRgb => {
+                format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+                    && format.format == vk::Format::R16G16B16A16_SFLOAT
+            }
+        }
+    }
+
+    // Picks the first of `formats` matching this preference or its fallbacks,
+    // and otherwise the first format the surface reported
+    pub fn choose(&self, formats: &[vk::SurfaceFormatKHR]) -> SurfaceFormat {
+        let format = self
+            .fallbacks()
+            .iter()
+            .find_map(|preference| formats.iter().find(|x| preference.matches(x)))
+            .or(formats.first())
+            .expect("surface has no formats");
+
+        SurfaceFormat {
+            format: format.format,
+            color_space: format.color_space,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SurfaceFormat {
+    pub format: vk::Format,
+    pub color_space: vk::ColorSpaceKHR,
+}
+
+impl SurfaceFormat {
+    // Which kind of output this is. Formats none of the preferences match are
+    // treated as SDR
+    pub fn preference(&self) -> SurfaceFormatPreference {
+        match self.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => SurfaceFormatPreference::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => SurfaceFormatPreference::ScRgb,
+            _ => SurfaceFormatPreference::Sdr,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.preference() != SurfaceFormatPreference::Sdr
+    }
+
+    // Whether the hardware applies the sRGB transfer function on write
+    pub fn is_srgb(&self) -> bool {
+        matches!(
+            self.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+}
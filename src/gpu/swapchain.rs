@@ -1,4 +1,4 @@
-use super::{Device, Fence, HasRawAshHandle, HasRawVkHandle, Image, Semaphore};
+use super::{Device, Fence, HasRawAshHandle, HasRawVkHandle, Image, Semaphore, SurfaceFormat};
 use ash::vk;
 use std::sync::Arc;
 
@@ -7,6 +7,7 @@ pub struct Swapchain {
     vk_swapchain: vk::SwapchainKHR,
     ash_swapchain_fn: ash::extensions::khr::Swapchain,
     format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
     extent: vk::Extent2D,
     images: Box<[Arc<Image>]>,
 }
@@ -15,8 +16,7 @@ impl Swapchain {
     pub fn new(
         device: Arc<Device>,
         min_image_count: u32,
-        image_format: vk::Format,
-        image_color_space: vk::ColorSpaceKHR,
+        surface_format: SurfaceFormat,
         image_extent: vk::Extent2D,
         image_usage: vk::ImageUsageFlags,
         present_mode: vk::PresentModeKHR,
@@ -43,8 +43,8 @@ impl Swapchain {
                 flags: vk::SwapchainCreateFlagsKHR::empty(),
                 surface: gpu_instance.get_surface().get_vk_handle(),
                 min_image_count,
-                image_format,
-                image_color_space,
+                image_format: surface_format.format,
+                image_color_space: surface_format.color_space,
                 image_extent,
                 image_array_layers: 1,
                 image_usage,
@@ -62,7 +62,7 @@ impl Swapchain {
         let ash_swapchain_fn = unsafe {
             let ash_instance = gpu_instance.get_ash_handle();
             let ash_device = device.get_ash_handle();
-            ash::extensions::khr::Swapchain::new(&ash_instance, &ash_device)
+            ash::extensions::khr::Swapchain::new(ash_instance, ash_device)
         };
 
         let vk_swapchain = unsafe {
@@ -81,7 +81,7 @@ impl Swapchain {
                         device.clone(),
                         vk_image,
                         vk::ImageType::TYPE_2D,
-                        image_format,
+                        surface_format.format,
                         vk::Extent3D {
                             width: image_extent.width,
                             height: image_extent.height,
@@ -96,7 +96,8 @@ impl Swapchain {
             device,
             vk_swapchain,
             ash_swapchain_fn,
-            format: image_format,
+            format: surface_format.format,
+            color_space: surface_format.color_space,
             extent: image_extent,
             images,
         }
@@ -110,11 +111,22 @@ impl Swapchain {
         &self.format
     }
 
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.color_space
+    }
+
+    pub fn surface_format(&self) -> SurfaceFormat {
+        SurfaceFormat {
+            format: self.format,
+            color_space: self.color_space,
+        }
+    }
+
     pub fn extent(&self) -> &vk::Extent2D {
         &self.extent
     }
 
-    pub fn images(&self) -> &Box<[Arc<Image>]> {
+    pub fn images(&self) -> &[Arc<Image>] {
         &self.images
     }
 
@@ -38,6 +38,139 @@ impl Drop for Semaphore {
     }
 }
 
+// Timeline semaphores hold a 64-bit counter that only increases. Waits
+// complete once the counter reaches the waited value, which lets the device
+// and host order work without fences or one semaphore per submit
+pub struct TimelineSemaphore {
+    device: Arc<Device>,
+    vk_semaphore: vk::Semaphore,
+}
+
+// A wait or signal of a timeline semaphore at a value, passed to
+// `Queue::submit`
+#[derive(Clone, Copy)]
+pub struct TimelineValue<'a> {
+    pub semaphore: &'a TimelineSemaphore,
+    pub value: u64,
+    pub stage_mask: vk::PipelineStageFlags2,
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: Arc<Device>, initial_value: u64) -> Self {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value)
+            .build();
+
+        let create_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut type_info)
+            .build();
+
+        let vk_semaphore = unsafe {
+            device
+                .get_ash_handle()
+                .create_semaphore(&create_info, None)
+                .expect("failed to create timeline semaphore")
+        };
+        Self {
+            device,
+            vk_semaphore,
+        }
+    }
+
+    // Signal the semaphore to `value` once the submitted work reaches
+    // `stage_mask`
+    pub fn signal_value(
+        &self,
+        value: u64,
+        stage_mask: vk::PipelineStageFlags2,
+    ) -> TimelineValue<'_> {
+        TimelineValue {
+            semaphore: self,
+            value,
+            stage_mask,
+        }
+    }
+
+    // Block `stage_mask` of the submitted work until the semaphore reaches
+    // `value`
+    pub fn wait_value(&self, value: u64, stage_mask: vk::PipelineStageFlags2) -> TimelineValue<'_> {
+        TimelineValue {
+            semaphore: self,
+            value,
+            stage_mask,
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        unsafe {
+            let result = self
+                .device
+                .get_ash_handle()
+                .get_semaphore_counter_value(self.vk_semaphore);
+            self.device
+                .check(result, "failed to get timeline semaphore value")
+        }
+    }
+
+    // Waits on the host until the semaphore reaches `value`. Returns false if
+    // the timeout (in nanoseconds) expired first
+    pub fn wait(&self, value: u64, timeout: Option<u64>) -> bool {
+        let semaphores = [self.vk_semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+
+        unsafe {
+            let result = self
+                .device
+                .get_ash_handle()
+                .wait_semaphores(&wait_info, timeout.unwrap_or(u64::MAX));
+            match result {
+                Err(vk::Result::TIMEOUT) => false,
+                result => {
+                    self.device
+                        .check(result, "failed to wait for timeline semaphore");
+                    true
+                }
+            }
+        }
+    }
+
+    // Sets the counter from the host. `value` must be greater than the
+    // current value and any pending signal values
+    pub fn signal(&self, value: u64) {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.vk_semaphore)
+            .value(value)
+            .build();
+
+        unsafe {
+            let result = self.device.get_ash_handle().signal_semaphore(&signal_info);
+            self.device
+                .check(result, "failed to signal timeline semaphore")
+        }
+    }
+}
+
+impl HasRawVkHandle<vk::Semaphore> for TimelineSemaphore {
+    unsafe fn get_vk_handle(&self) -> vk::Semaphore {
+        self.vk_semaphore
+    }
+}
+
+impl Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .get_ash_handle()
+                .destroy_semaphore(self.vk_semaphore, None);
+        }
+    }
+}
+
 pub struct Fence {
     device: Arc<Device>,
     vk_fence: vk::Fence,
@@ -51,10 +184,7 @@ impl Fence {
                 .create_fence(&vk::FenceCreateInfo::default(), None)
                 .expect("failed to create fence")
         };
-        Self {
-            device,
-            vk_fence,
-        }
+        Self { device, vk_fence }
     }
 
     pub fn signaled(device: Arc<Device>) -> Self {
@@ -70,10 +200,7 @@ impl Fence {
                 )
                 .expect("failed to create fence")
         };
-        Self {
-            device,
-            vk_fence,
-        }
+        Self { device, vk_fence }
     }
 }
 
@@ -0,0 +1,94 @@
+use super::{CommandBuffer, CommandPool, Device, QueueFamily, SecondaryRecording};
+use ash::vk;
+use std::sync::{Arc, Mutex};
+
+struct WorkerPool {
+    pool: Arc<CommandPool>,
+    // Allocated as needed and reused after `reset`
+    buffers: Vec<CommandBuffer>,
+    used: usize,
+}
+
+// Command pools for recording secondary command buffers on several threads at
+// once. Each worker records with its own pool, since a pool can't be used by
+// two threads at the same time. Recorded buffers are reused once the pools are
+// reset, so there should be one set per frame in flight, reset after the
+// frame's fence has signaled
+pub struct ThreadCommandPools {
+    workers: Vec<Mutex<WorkerPool>>,
+}
+
+impl ThreadCommandPools {
+    // A worker count of 0 uses one worker per available core
+    pub fn new(device: Arc<Device>, queue_family: &QueueFamily, worker_count: usize) -> Self {
+        let worker_count = match worker_count {
+            0 => std::thread::available_parallelism().map_or(1, |x| x.get()),
+            n => n,
+        };
+
+        let workers = (0..worker_count)
+            .map(|_| {
+                Mutex::new(WorkerPool {
+                    pool: CommandPool::new(
+                        device.clone(),
+                        queue_family,
+                        vk::CommandPoolCreateFlags::TRANSIENT,
+                    ),
+                    buffers: vec![],
+                    used: 0,
+                })
+            })
+            .collect();
+
+        Self { workers }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Records a one-time secondary command buffer with worker `worker`'s pool.
+    // Blocks while another thread is recording with the same worker. `trace`
+    // records a `CommandTrace` alongside, for when the primary is tracing
+    pub fn record(
+        &self,
+        worker: usize,
+        trace: bool,
+        record: impl FnOnce(&CommandBuffer),
+    ) -> SecondaryRecording {
+        let mut worker = self.workers[worker]
+            .lock()
+            .expect("command pool worker panicked");
+        let worker = &mut *worker;
+
+        if worker.used == worker.buffers.len() {
+            let cmd_buf = worker.pool.allocate_one(vk::CommandBufferLevel::SECONDARY);
+            worker.buffers.push(cmd_buf);
+        }
+        let cmd_buf = &worker.buffers[worker.used];
+        worker.used += 1;
+
+        cmd_buf.begin_secondary(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        if trace {
+            cmd_buf.begin_trace();
+        }
+        record(cmd_buf);
+        cmd_buf.end();
+
+        SecondaryRecording {
+            command_buffer: unsafe { cmd_buf.handle() },
+            draw_count: cmd_buf.draw_count(),
+            dispatch_count: cmd_buf.dispatch_count(),
+            trace: cmd_buf.end_trace(),
+        }
+    }
+
+    // Every buffer recorded since the last reset must have finished executing
+    pub fn reset(&self) {
+        for worker in &self.workers {
+            let mut worker = worker.lock().expect("command pool worker panicked");
+            worker.pool.reset();
+            worker.used = 0;
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use super::{
+    Buffer, CommandBuffer, Device, Image, ImageConfig, MemoryPriority, StagingArena, StagingSlice,
+};
+use ash::vk;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+
+type UploadCommand = Box<dyn Fn(&CommandBuffer)>;
+
+// Where data waiting to be copied to the GPU is kept. Uploads too big for the
+// staging arena, or made while it's full, get a buffer of their own
+pub enum Staged {
+    Arena(StagingSlice),
+    Buffer(Buffer),
+}
+
+impl Staged {
+    pub fn buffer(&self) -> &Buffer {
+        match self {
+            Staged::Arena(slice) => slice.buffer(),
+            Staged::Buffer(buffer) => buffer,
+        }
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        match self {
+            Staged::Arena(slice) => slice.offset() as vk::DeviceSize,
+            Staged::Buffer(_) => 0,
+        }
+    }
+}
+
+// Copies data to the GPU without waiting for the queue. Data is staged right
+// away, and the copies are recorded at the start of the next frame's command
+// buffer, ahead of anything that could use them. So destinations are created
+// and handed out immediately, and are ready by the time they're drawn with.
+// Commands hold on to what they use until their frame has completed
+pub struct UploadQueue {
+    device: Arc<Device>,
+    allocator: Arc<vma::Allocator>,
+    staging_arena: StagingArena,
+    pending: RefCell<Vec<UploadCommand>>,
+    // What each frame in flight recorded, dropped once it has completed
+    recorded: Vec<RefCell<Vec<UploadCommand>>>,
+    current_frame: Cell<Option<usize>>,
+}
+
+impl UploadQueue {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<vma::Allocator>,
+        staging_capacity: usize,
+        frames_in_flight: usize,
+    ) -> Self {
+        let staging_arena = StagingArena::new(
+            device.clone(),
+            allocator.clone(),
+            staging_capacity,
+            frames_in_flight,
+        );
+
+        Self {
+            device,
+            allocator,
+            staging_arena,
+            pending: RefCell::new(vec![]),
+            recorded: (0..frames_in_flight)
+                .map(|_| RefCell::new(vec![]))
+                .collect(),
+            current_frame: Cell::new(None),
+        }
+    }
+
+    // Must be called after waiting on the fence of frame `index`, which frees
+    // everything its last submission used
+    pub fn begin_frame(&self, index: usize) {
+        self.staging_arena.begin_frame(index);
+        self.recorded[index].borrow_mut().clear();
+        self.current_frame.set(Some(index));
+    }
+
+    // Must be called once the current frame has been submitted with its fence
+    pub fn end_frame(&self) {
+        self.staging_arena.end_frame();
+        self.current_frame.take();
+    }
+
+    // Records every pending upload into the current frame's command buffer,
+    // followed by a barrier that makes them visible to the rest of the frame
+    pub fn record(&self, cmds: &CommandBuffer) {
+        let index = self
+            .current_frame
+            .get()
+            .expect("uploads recorded outside of a frame");
+        let pending = std::mem::take(&mut *self.pending.borrow_mut());
+        if pending.is_empty() {
+            return;
+        }
+
+        for command in &pending {
+            command(cmds);
+        }
+        cmds.memory_barrier(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_WRITE,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        );
+        self.recorded[index].borrow_mut().extend(pending);
+    }
+
+    // Queues commands to record with the uploads, in order. Whatever the
+    // closure captures is kept until the frame it's recorded in has completed
+    pub fn push(&self, command: impl Fn(&CommandBuffer) + 'static) {
+        self.pending.borrow_mut().push(Box::new(command));
+    }
+
+    // Copies `data` into host visible staging memory
+    pub fn stage<T: Copy>(&self, data: &[T]) -> Staged {
+        let size = size_of_val(data);
+        if let Some(slice) = self.staging_arena.try_allocate(size) {
+            slice.write(data);
+            return Staged::Arena(slice);
+        }
+
+        let buffer = Buffer::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vma::MemoryUsage::AutoPreferHost,
+            vma::AllocationCreateFlags::MAPPED
+                | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+            MemoryPriority::Low,
+        );
+        buffer.copy_nonoverlapping(data);
+        Staged::Buffer(buffer)
+    }
+
+    // Copies `data` into `dst_offset` bytes into `buffer`
+    pub fn copy_to_buffer<T: Copy>(
+        &self,
+        buffer: impl AsRef<Buffer> + 'static,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) {
+        let staged = self.stage(data);
+        let size = size_of_val(data) as vk::DeviceSize;
+        self.push(move |cmds| {
+            cmds.copy_buffer(
+                staged.buffer(),
+                buffer.as_ref(),
+                &[vk::BufferCopy {
+                    src_offset: staged.offset(),
+                    dst_offset,
+                    size,
+                }],
+            );
+        });
+    }
+
+    // Copies `data` into a new device local buffer. Buffers keep their
+    // mapping info, which can't be shared between threads
+    pub fn upload_buffer<T: Copy>(&self, data: &[T], usage: vk::BufferUsageFlags) -> Rc<Buffer> {
+        let buffer = Rc::new(Buffer::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            size_of_val(data),
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vma::MemoryUsage::AutoPreferDevice,
+            vma::AllocationCreateFlags::empty(),
+            MemoryPriority::Normal,
+        ));
+        self.copy_to_buffer(buffer.clone(), 0, data);
+        buffer
+    }
+
+    // Creates a sampled 2D image from tightly packed pixels, which is in
+    // SHADER_READ_ONLY_OPTIMAL once the upload is recorded
+    pub fn upload_image(
+        &self,
+        pixels: &[u8],
+        format: vk::Format,
+        width: u32,
+        height: u32,
+    ) -> Arc<Image> {
+        self.upload_image_levels(&[pixels], format, width, height)
+    }
+
+    // Like `upload_image` but with one tightly packed buffer per mip level,
+    // starting with the full size image. Also works for block compressed formats
+    pub fn upload_image_levels(
+        &self,
+        levels: &[&[u8]],
+        format: vk::Format,
+        width: u32,
+        height: u32,
+    ) -> Arc<Image> {
+        assert!(!levels.is_empty(), "image needs at least one mip level");
+
+        // Copy offsets have to be a multiple of the texel block size, which is
+        // at most 16 bytes for the formats we upload
+        const LEVEL_ALIGNMENT: usize = 16;
+
+        let mut offsets = Vec::with_capacity(levels.len());
+        let mut staging_size = 0;
+        for level in levels {
+            offsets.push(staging_size);
+            staging_size += level.len().next_multiple_of(LEVEL_ALIGNMENT);
+        }
+
+        let mut staging_data = vec![0u8; staging_size];
+        for (level, offset) in levels.iter().zip(&offsets) {
+            staging_data[*offset..*offset + level.len()].copy_from_slice(level);
+        }
+        let staged = self.stage(&staging_data);
+
+        let image = Image::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            ImageConfig::new(
+                format,
+                vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            )
+            .mip_levels(levels.len() as u32),
+        );
+
+        let regions = offsets
+            .iter()
+            .enumerate()
+            .map(|(mip_level, offset)| vk::BufferImageCopy {
+                buffer_offset: staged.offset() + *offset as vk::DeviceSize,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: mip_level as u32,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: vk::Extent3D {
+                    width: (width >> mip_level).max(1),
+                    height: (height >> mip_level).max(1),
+                    depth: 1,
+                },
+            })
+            .collect::<Vec<_>>();
+
+        {
+            let image = image.clone();
+            self.push(move |cmds| {
+                cmds.transition_image(
+                    &image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                );
+                cmds.copy_buffer_to_image_regions(staged.buffer(), &image, &regions);
+                cmds.transition_image(
+                    &image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+            });
+        }
+
+        image
+    }
+}
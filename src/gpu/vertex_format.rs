@@ -0,0 +1,314 @@
+use ash::vk;
+use glam::{Vec2, Vec3, Vec4};
+use std::mem::size_of;
+
+// A type that can be used as a vertex attribute, with the format the vertex
+// input should read it as
+pub trait VertexAttribute {
+    const FORMAT: vk::Format;
+}
+
+impl VertexAttribute for f32 {
+    const FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+}
+
+impl VertexAttribute for Vec2 {
+    const FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+}
+
+impl VertexAttribute for Vec3 {
+    const FORMAT: vk::Format = vk::Format::R32G32B32_SFLOAT;
+}
+
+impl VertexAttribute for Vec4 {
+    const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+}
+
+// Read as a uvec4, e.g. joint indices
+impl VertexAttribute for [u16; 4] {
+    const FORMAT: vk::Format = vk::Format::R16G16B16A16_UINT;
+}
+
+pub fn vertex_attribute_description<T: VertexAttribute>(
+    binding: u32,
+    location: u32,
+    offset: usize,
+) -> vk::VertexInputAttributeDescription {
+    vk::VertexInputAttributeDescription {
+        binding,
+        location,
+        format: T::FORMAT,
+        offset: offset.try_into().unwrap(),
+    }
+}
+
+// A field of a vertex struct. The format is taken from the field's type, so
+// it can't get out of sync with the struct. Use `vertex_field!` to create one
+#[derive(Debug, Clone, Copy)]
+pub struct VertexField {
+    pub format: vk::Format,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl VertexField {
+    // `field` is only used to infer the field's type
+    pub fn new<V, F: VertexAttribute>(offset: usize, _field: fn(&V) -> &F) -> Self {
+        Self {
+            format: F::FORMAT,
+            offset,
+            size: size_of::<F>(),
+        }
+    }
+}
+
+// `vertex_field!(Vertex, position)` describes `Vertex::position`
+#[macro_export]
+macro_rules! vertex_field {
+    ($vertex:ty, $field:ident) => {
+        $crate::gpu::VertexField::new(::core::mem::offset_of!($vertex, $field), |x: &$vertex| {
+            &x.$field
+        })
+    };
+}
+
+// Vertex input bindings and attributes for a pipeline. Bindings are numbered
+// in the order they're added and attribute locations are assigned in order
+// across all bindings, matching the shader's `layout(location = N)`
+#[derive(Debug, Clone, Default)]
+pub struct VertexLayout {
+    bindings: Vec<vk::VertexInputBindingDescription>,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexLayout {
+    pub fn builder() -> VertexLayoutBuilder {
+        VertexLayoutBuilder {
+            layout: VertexLayout::default(),
+        }
+    }
+
+    pub fn bindings(&self) -> &[vk::VertexInputBindingDescription] {
+        &self.bindings
+    }
+
+    pub fn attributes(&self) -> &[vk::VertexInputAttributeDescription] {
+        &self.attributes
+    }
+}
+
+pub struct VertexLayoutBuilder {
+    layout: VertexLayout,
+}
+
+impl VertexLayoutBuilder {
+    // Starts a binding for a buffer of `V`s. Attributes added after this are
+    // read from it
+    pub fn binding<V>(mut self, input_rate: vk::VertexInputRate) -> Self {
+        let binding = self.layout.bindings.len().try_into().unwrap();
+        self.layout
+            .bindings
+            .push(vk::VertexInputBindingDescription {
+                binding,
+                stride: size_of::<V>().try_into().unwrap(),
+                input_rate,
+            });
+        self
+    }
+
+    pub fn attribute(mut self, field: VertexField) -> Self {
+        let binding = self
+            .layout
+            .bindings
+            .last()
+            .expect("vertex attribute added before any binding");
+        assert!(
+            field.offset + field.size <= binding.stride as usize,
+            "vertex attribute is outside of the binding's stride"
+        );
+
+        let location = self.layout.attributes.len().try_into().unwrap();
+        self.layout
+            .attributes
+            .push(vk::VertexInputAttributeDescription {
+                binding: binding.binding,
+                location,
+                format: field.format,
+                offset: field.offset.try_into().unwrap(),
+            });
+        self
+    }
+
+    pub fn build(self) -> VertexLayout {
+        self.layout
+    }
+}
+
+// Half-float pair, for UVs and positions that don't need full precision
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Half2([u16; 2]);
+
+impl Half2 {
+    pub fn new(value: Vec2) -> Self {
+        Self([f32_to_f16(value.x), f32_to_f16(value.y)])
+    }
+}
+
+impl VertexAttribute for Half2 {
+    const FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+}
+
+// Half-float position or color. The fourth component pads a three component
+// value, since three component 16-bit formats often aren't supported
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Half4([u16; 4]);
+
+impl Half4 {
+    pub fn new(value: Vec4) -> Self {
+        Self([
+            f32_to_f16(value.x),
+            f32_to_f16(value.y),
+            f32_to_f16(value.z),
+            f32_to_f16(value.w),
+        ])
+    }
+}
+
+impl VertexAttribute for Half4 {
+    const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+}
+
+// Normal or tangent packed into 10 bits per component, with the tangent
+// handedness in the 2-bit W. Vertex buffer support for this format is
+// optional, check `PhysicalDevice::supports_vertex_format` first
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PackedNormal(u32);
+
+impl PackedNormal {
+    pub fn new(value: Vec3) -> Self {
+        Self::with_w(value, 0.0)
+    }
+
+    pub fn with_w(value: Vec3, w: f32) -> Self {
+        let snorm = |x: f32, max: f32, bits: u32| {
+            ((x.clamp(-1.0, 1.0) * max).round() as i32 as u32) & ((1 << bits) - 1)
+        };
+        Self(
+            snorm(value.x, 511.0, 10)
+                | snorm(value.y, 511.0, 10) << 10
+                | snorm(value.z, 511.0, 10) << 20
+                | snorm(w, 1.0, 2) << 30,
+        )
+    }
+}
+
+impl VertexAttribute for PackedNormal {
+    const FORMAT: vk::Format = vk::Format::A2B10G10R10_SNORM_PACK32;
+}
+
+// Color or weights quantized to 8 bits per component
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Unorm8x4([u8; 4]);
+
+impl Unorm8x4 {
+    pub fn new(value: Vec4) -> Self {
+        let unorm = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Self([
+            unorm(value.x),
+            unorm(value.y),
+            unorm(value.z),
+            unorm(value.w),
+        ])
+    }
+}
+
+impl VertexAttribute for Unorm8x4 {
+    const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+}
+
+// Converts to IEEE half precision, rounding to nearest even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Infinity and NaN
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let round = |value: u32, shift: u32| {
+        let truncated = value >> shift;
+        let remainder = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && truncated & 1 == 1) {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+
+    if half_exponent <= 0 {
+        // Subnormal, or too small and flushed to zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let shift = (14 - half_exponent) as u32;
+        return sign | round(mantissa | 0x80_0000, shift) as u16;
+    }
+
+    // Rounding can carry into the exponent, which is still correct
+    sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f32_to_f16_normal() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    }
+
+    #[test]
+    fn f32_to_f16_rounds_to_nearest_even() {
+        assert_eq!(f32_to_f16(1.0 / 3.0), 0x3555);
+        // Halfway between 1.0 and the next half, which is odd
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        // Rounds up past the largest half
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+    }
+
+    #[test]
+    fn f32_to_f16_subnormal() {
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-15)), 0x0200);
+        assert_eq!(f32_to_f16(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_f16(-1e-10), 0x8000);
+    }
+
+    #[test]
+    fn f32_to_f16_special() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7fff, 0x7e00);
+    }
+}
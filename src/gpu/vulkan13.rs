@@ -0,0 +1,286 @@
+use ash::extensions::{ext, khr};
+use ash::prelude::VkResult;
+use ash::vk;
+
+// Extensions that provide the 1.3 core features used by the renderer on
+// Vulkan 1.2 devices
+const VULKAN13_FALLBACK_EXTENSIONS: &[&[u8]] = &[
+    b"VK_KHR_dynamic_rendering\0",
+    b"VK_KHR_synchronization2\0",
+    b"VK_KHR_copy_commands2\0",
+];
+
+// Enabled on Vulkan 1.2 devices if available. Core in 1.3
+const VULKAN13_OPTIONAL_FALLBACK_EXTENSIONS: &[&[u8]] = &[b"VK_EXT_extended_dynamic_state\0"];
+
+// Dynamic rendering, synchronization2, copy_commands2 and extended dynamic
+// state are core in Vulkan 1.3 and extensions before. Commands using them go
+// through here so they use the core entry points when available and the
+// extension ones otherwise. `Device::new` picks the variant. Both are boxed
+// since the function tables are large and differ a lot in size
+pub enum Vulkan13Dispatch {
+    Core(Box<ash::Device>),
+    Khr(Box<Vulkan13Extensions>),
+}
+
+pub struct Vulkan13Extensions {
+    dynamic_rendering: khr::DynamicRendering,
+    synchronization2: khr::Synchronization2,
+    copy_commands2: khr::CopyCommands2,
+    // None if the device doesn't support the extension
+    extended_dynamic_state: Option<ext::ExtendedDynamicState>,
+}
+
+impl Vulkan13Dispatch {
+    // `extended_dynamic_state` is whether the extension was enabled, which
+    // only matters before 1.3
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        api_version: u32,
+        extended_dynamic_state: bool,
+    ) -> Self {
+        if Self::is_core(api_version) {
+            Self::Core(Box::new(device.clone()))
+        } else {
+            Self::Khr(Box::new(Vulkan13Extensions {
+                dynamic_rendering: khr::DynamicRendering::new(instance, device),
+                synchronization2: khr::Synchronization2::new(instance, device),
+                copy_commands2: khr::CopyCommands2::new(instance, device),
+                extended_dynamic_state: extended_dynamic_state
+                    .then(|| ext::ExtendedDynamicState::new(instance, device)),
+            }))
+        }
+    }
+
+    pub fn is_core(api_version: u32) -> bool {
+        vk::api_version_major(api_version) > 1 || vk::api_version_minor(api_version) >= 3
+    }
+
+    // Device extensions that have to be enabled for a device with the given
+    // API version
+    pub fn required_extensions(api_version: u32) -> &'static [&'static [u8]] {
+        if Self::is_core(api_version) {
+            &[]
+        } else {
+            VULKAN13_FALLBACK_EXTENSIONS
+        }
+    }
+
+    // Device extensions to enable for a device with the given API version if
+    // it supports them
+    pub fn optional_extensions(api_version: u32) -> &'static [&'static [u8]] {
+        if Self::is_core(api_version) {
+            &[]
+        } else {
+            VULKAN13_OPTIONAL_FALLBACK_EXTENSIONS
+        }
+    }
+
+    // Whether the `cmd_set_*` commands for extended dynamic state can be used.
+    // They panic if not
+    pub fn supports_extended_dynamic_state(&self) -> bool {
+        match self {
+            Self::Core(_) => true,
+            Self::Khr(extensions) => extensions.extended_dynamic_state.is_some(),
+        }
+    }
+
+    fn _extended_dynamic_state(&self) -> &ext::ExtendedDynamicState {
+        match self {
+            Self::Khr(extensions) => extensions.extended_dynamic_state.as_ref(),
+            Self::Core(_) => None,
+        }
+        .expect("extended dynamic state is not supported")
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must belong to this device and be recording outside
+    /// of any render pass or dynamic rendering, and every view and pointer in
+    /// `rendering_info` must be valid for the duration of the call
+    pub unsafe fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_begin_rendering(command_buffer, rendering_info),
+            Self::Khr(extensions) => extensions
+                .dynamic_rendering
+                .cmd_begin_rendering(command_buffer, rendering_info),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must belong to this device and be inside dynamic
+    /// rendering started with `cmd_begin_rendering`
+    pub unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        match self {
+            Self::Core(device) => device.cmd_end_rendering(command_buffer),
+            Self::Khr(extensions) => extensions
+                .dynamic_rendering
+                .cmd_end_rendering(command_buffer),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must belong to this device and be recording, and the
+    /// barrier arrays `dependency_info` points at must outlive the call and
+    /// only name resources of this device
+    pub unsafe fn cmd_pipeline_barrier2(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        dependency_info: &vk::DependencyInfo,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_pipeline_barrier2(command_buffer, dependency_info),
+            Self::Khr(extensions) => extensions
+                .synchronization2
+                .cmd_pipeline_barrier2(command_buffer, dependency_info),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `queue` and `fence` must belong to this device, and the fence must be
+    /// null or unsignaled. The command buffers and semaphores in `submits`
+    /// have to stay alive until the submission completes
+    pub unsafe fn queue_submit2(
+        &self,
+        queue: vk::Queue,
+        submits: &[vk::SubmitInfo2],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        match self {
+            Self::Core(device) => device.queue_submit2(queue, submits, fence),
+            Self::Khr(extensions) => extensions
+                .synchronization2
+                .queue_submit2(queue, submits, fence),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must belong to this device and be recording outside
+    /// of rendering, and both images in `blit_image_info` must be in the
+    /// layouts it names
+    pub unsafe fn cmd_blit_image2(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        blit_image_info: &vk::BlitImageInfo2,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_blit_image2(command_buffer, blit_image_info),
+            Self::Khr(extensions) => extensions
+                .copy_commands2
+                .cmd_blit_image2(command_buffer, blit_image_info),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must belong to this device and be recording, and the
+    /// bound pipeline must have the state as dynamic. See
+    /// `supports_extended_dynamic_state`
+    pub unsafe fn cmd_set_cull_mode(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        cull_mode: vk::CullModeFlags,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_set_cull_mode(command_buffer, cull_mode),
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_cull_mode(command_buffer, cull_mode),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as `cmd_set_cull_mode`
+    pub unsafe fn cmd_set_front_face(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        front_face: vk::FrontFace,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_set_front_face(command_buffer, front_face),
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_front_face(command_buffer, front_face),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as `cmd_set_cull_mode`
+    pub unsafe fn cmd_set_primitive_topology(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        primitive_topology: vk::PrimitiveTopology,
+    ) {
+        match self {
+            Self::Core(device) => {
+                device.cmd_set_primitive_topology(command_buffer, primitive_topology)
+            }
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_primitive_topology(command_buffer, primitive_topology),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as `cmd_set_cull_mode`
+    pub unsafe fn cmd_set_depth_test_enable(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        depth_test_enable: bool,
+    ) {
+        match self {
+            Self::Core(device) => {
+                device.cmd_set_depth_test_enable(command_buffer, depth_test_enable)
+            }
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_depth_test_enable(command_buffer, depth_test_enable),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as `cmd_set_cull_mode`
+    pub unsafe fn cmd_set_depth_write_enable(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        depth_write_enable: bool,
+    ) {
+        match self {
+            Self::Core(device) => {
+                device.cmd_set_depth_write_enable(command_buffer, depth_write_enable)
+            }
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_depth_write_enable(command_buffer, depth_write_enable),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as `cmd_set_cull_mode`
+    pub unsafe fn cmd_set_depth_compare_op(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        depth_compare_op: vk::CompareOp,
+    ) {
+        match self {
+            Self::Core(device) => device.cmd_set_depth_compare_op(command_buffer, depth_compare_op),
+            Self::Khr(_) => self
+                ._extended_dynamic_state()
+                .cmd_set_depth_compare_op(command_buffer, depth_compare_op),
+        }
+    }
+}
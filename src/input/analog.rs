@@ -0,0 +1,72 @@
+#[derive(Debug, Clone, Copy)]
+pub enum ResponseCurve {
+    Linear,
+    Exponential(f64),
+    Custom(fn(f64) -> f64),
+}
+
+impl ResponseCurve {
+    // Maps a normalized magnitude in [0, 1] onto [0, 1]
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            ResponseCurve::Linear => x,
+            ResponseCurve::Exponential(exponent) => x.powf(*exponent),
+            ResponseCurve::Custom(f) => f(x).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnalogFilter {
+    pub dead_zone: f64,
+    pub saturation: f64,
+    pub curve: ResponseCurve,
+}
+
+impl Default for AnalogFilter {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.0,
+            saturation: 1.0,
+            curve: ResponseCurve::Linear,
+        }
+    }
+}
+
+impl AnalogFilter {
+    pub fn new(dead_zone: f64, saturation: f64, curve: ResponseCurve) -> Self {
+        assert!(0.0 <= dead_zone && dead_zone < saturation && saturation <= 1.0);
+        Self {
+            dead_zone,
+            saturation,
+            curve,
+        }
+    }
+
+    // Rescales a magnitude so the dead zone maps to 0 and the saturation
+    // point maps to 1, then applies the response curve
+    fn _shape(&self, magnitude: f64) -> f64 {
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+        let range = self.saturation - self.dead_zone;
+        let scaled = ((magnitude - self.dead_zone) / range).clamp(0.0, 1.0);
+        self.curve.apply(scaled)
+    }
+
+    pub fn apply(&self, x: f64) -> f64 {
+        self._shape(x.abs()).copysign(x)
+    }
+
+    // Applies the dead zone to the length of the vector instead of each
+    // component, which avoids the "cross" shaped dead zone of per-axis
+    // filtering on thumbsticks
+    pub fn apply_radial(&self, x: f64, y: f64) -> (f64, f64) {
+        let magnitude = x.hypot(y);
+        if magnitude == 0.0 {
+            return (0.0, 0.0);
+        }
+        let factor = self._shape(magnitude) / magnitude;
+        (x * factor, y * factor)
+    }
+}
@@ -0,0 +1,166 @@
+use super::{Composite, Control, DeviceId, InputContext, InputKind};
+use enumflags2::BitFlags;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::Path;
+use tracing::warn;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlBinding<C, Action> {
+    pub control: C,
+    pub action: Action,
+    // The input kinds the action receives, or every kind if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<Vec<InputKind>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "C: Control + Serialize, Action: Serialize",
+    deserialize = "C: Control + Deserialize<'de>, Action: Deserialize<'de>"
+))]
+pub struct CompositeProfileBinding<C: Control, Action> {
+    pub action: Action,
+    pub composite: Composite<C>,
+}
+
+// A context's control and composite bindings in a form that can be written
+// to and read from a file, so controls can be rebound without recompiling.
+// Device, chord, tap and hold bindings aren't included
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "C: Control + Serialize, Action: Serialize",
+    deserialize = "C: Control + Deserialize<'de>, Action: Deserialize<'de>"
+))]
+pub struct BindingProfile<C: Control, Action> {
+    #[serde(default)]
+    pub bindings: Vec<ControlBinding<C, Action>>,
+    #[serde(default)]
+    pub composites: Vec<CompositeProfileBinding<C, Action>>,
+}
+
+impl<C: Control, Action> Default for BindingProfile<C, Action> {
+    fn default() -> Self {
+        Self {
+            bindings: vec![],
+            composites: vec![],
+        }
+    }
+}
+
+impl<C, Action> BindingProfile<C, Action>
+where
+    C: Control + Debug,
+    Action: Copy + Clone + Eq + Hash + Debug,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, control: C, action: Action) -> &mut Self {
+        self.bind_masked(control, action, None)
+    }
+
+    pub fn bind_masked(
+        &mut self,
+        control: C,
+        action: Action,
+        mask: Option<BitFlags<InputKind>>,
+    ) -> &mut Self {
+        self.bindings.push(ControlBinding {
+            control,
+            action,
+            mask: mask.map(|x| x.iter().collect()),
+        });
+        self
+    }
+
+    pub fn bind_composite(&mut self, action: Action, composite: Composite<C>) -> &mut Self {
+        self.composites
+            .push(CompositeProfileBinding { action, composite });
+        self
+    }
+
+    // Bindings are sorted so that saving the same context twice gives the
+    // same file
+    pub fn from_context<DId: DeviceId>(context: &InputContext<DId, C, Action>) -> Self {
+        let mut bindings = context
+            .bindings()
+            .map(|(control, (action, mask))| ControlBinding {
+                control: *control,
+                action: *action,
+                mask: mask.map(|x| x.iter().collect()),
+            })
+            .collect::<Vec<_>>();
+        bindings.sort_by_cached_key(|x| format!("{:?} {:?}", x.action, x.control));
+
+        let composites = context
+            .composite_actions()
+            .iter()
+            .map(|x| CompositeProfileBinding {
+                action: x.action(),
+                composite: *x.composite(),
+            })
+            .collect();
+
+        Self {
+            bindings,
+            composites,
+        }
+    }
+
+    // Adds the bindings to `context`, replacing any existing binding for the
+    // same control
+    pub fn apply<DId: DeviceId>(&self, context: &mut InputContext<DId, C, Action>) {
+        for binding in &self.bindings {
+            let mask = binding
+                .mask
+                .as_ref()
+                .map(|x| x.iter().copied().collect::<BitFlags<InputKind>>());
+            context.set_action(binding.control, binding.action, mask);
+        }
+        for binding in &self.composites {
+            context.set_composite_action(binding.action, binding.composite);
+        }
+    }
+}
+
+impl<C, Action> BindingProfile<C, Action>
+where
+    C: Control + Serialize + DeserializeOwned,
+    Action: Serialize + DeserializeOwned,
+{
+    // None if the file doesn't exist or can't be read or parsed, so the
+    // caller can fall back to its default bindings. Bad files are warned about
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(error) => {
+                warn!(
+                    "failed to read binding profile {}: {}",
+                    path.display(),
+                    error
+                );
+                return None;
+            }
+        };
+        toml::from_str(&text)
+            .inspect_err(|error| {
+                warn!(
+                    "failed to parse binding profile {}: {}",
+                    path.display(),
+                    error
+                );
+            })
+            .ok()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let text = toml::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, text)
+    }
+}
@@ -0,0 +1,83 @@
+use super::Control;
+use enumflags2::{bitflags, BitFlags};
+use std::collections::HashSet;
+use winit::keyboard::ModifiersState;
+
+#[bitflags]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    pub fn from_winit(state: ModifiersState) -> BitFlags<Modifier> {
+        let mut modifiers = BitFlags::empty();
+        if state.shift_key() {
+            modifiers |= Modifier::Shift;
+        }
+        if state.control_key() {
+            modifiers |= Modifier::Ctrl;
+        }
+        if state.alt_key() {
+            modifiers |= Modifier::Alt;
+        }
+        if state.super_key() {
+            modifiers |= Modifier::Super;
+        }
+        modifiers
+    }
+}
+
+// The set of modifiers and controls that must be held for a chorded binding
+// to match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord<C: Control> {
+    modifiers: BitFlags<Modifier>,
+    controls: Vec<C>,
+}
+
+impl<C: Control> Chord<C> {
+    pub fn new() -> Self {
+        Self {
+            modifiers: BitFlags::empty(),
+            controls: vec![],
+        }
+    }
+
+    pub fn modifier(mut self, modifier: Modifier) -> Self {
+        self.modifiers |= modifier;
+        self
+    }
+
+    pub fn control(mut self, control: C) -> Self {
+        self.controls.push(control);
+        self
+    }
+
+    pub fn modifiers(&self) -> BitFlags<Modifier> {
+        self.modifiers
+    }
+
+    pub fn controls(&self) -> &[C] {
+        &self.controls
+    }
+
+    // Chords with more requirements take priority over chords with fewer
+    pub fn specificity(&self) -> usize {
+        self.modifiers.len() + self.controls.len()
+    }
+
+    pub fn is_held(&self, modifiers: BitFlags<Modifier>, held: &HashSet<C>) -> bool {
+        modifiers.contains(self.modifiers) && self.controls.iter().all(|x| held.contains(x))
+    }
+}
+
+impl<C: Control> Default for Chord<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct ClockState {
+    start_time: Instant,
+    frame_index: AtomicU64,
+    // Nanoseconds since `start_time` at which the current frame started
+    frame_started_at: AtomicU64,
+}
+
+// Clock shared by the input managers and the frame loop, so that event
+// timestamps can be compared across managers and against frame boundaries
+#[derive(Clone)]
+pub struct InputClock {
+    state: Arc<ClockState>,
+}
+
+impl InputClock {
+    pub fn new(start_time: Instant) -> Self {
+        Self {
+            state: Arc::new(ClockState {
+                start_time,
+                frame_index: AtomicU64::new(0),
+                frame_started_at: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn start_time(&self) -> Instant {
+        self.state.start_time
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.state.start_time.elapsed()
+    }
+
+    pub fn frame_index(&self) -> u64 {
+        self.state.frame_index.load(Ordering::Acquire)
+    }
+
+    pub fn frame_started_at(&self) -> Duration {
+        Duration::from_nanos(self.state.frame_started_at.load(Ordering::Acquire))
+    }
+
+    // Called by the frame loop at the start of every frame. Returns the index
+    // of the new frame
+    pub fn advance_frame(&self) -> u64 {
+        let now = u64::try_from(self.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.state.frame_started_at.store(now, Ordering::Release);
+        self.state.frame_index.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
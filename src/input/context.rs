@@ -0,0 +1,574 @@
+use super::{Chord, Control, DeviceId, InputKind, InputValue, Modifier};
+use enumflags2::BitFlags;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Write};
+use std::hash::Hash;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContextId(usize);
+
+impl ContextId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+// An action and the kinds of input it accepts from its control, any kind if
+// None
+pub type ActionBinding<Action> = (Action, Option<BitFlags<InputKind>>);
+
+type ChordBinding<C, Action> = (Chord<C>, ActionBinding<Action>);
+
+// Identifies a hold binding within its context, so progress on a held
+// control stays with the binding when others are added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HoldId(u64);
+
+// How much of the input a context hides from the contexts below it on the
+// stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsumePolicy {
+    // Every event is passed down, e.g. for an overlay that only adds a few
+    // shortcuts
+    None,
+    // Events for controls this context binds aren't passed down
+    #[default]
+    Bound,
+    // Nothing is passed down, e.g. for a console capturing all input
+    All,
+}
+
+pub struct InputContext<DId, C, Action>
+where
+    DId: DeviceId,
+    C: Control,
+    Action: Copy + Clone + Eq + Hash,
+{
+    id: ContextId,
+    name: String,
+    consume_policy: ConsumePolicy,
+    control_map: HashMap<C, ActionBinding<Action>>,
+    control_map_rev: HashMap<Action, C>,
+    device_map: HashMap<(DId, C), ActionBinding<Action>>,
+    wildcard_actions: Vec<ActionBinding<Action>>,
+    composite_actions: Vec<CompositeBinding<C, Action>>,
+    chord_map: HashMap<C, Vec<ChordBinding<C, Action>>>,
+    tap_map: HashMap<C, Vec<TapBinding<Action>>>,
+    hold_map: HashMap<C, Vec<HoldBinding<Action>>>,
+    next_hold_id: u64,
+    gesture_map: HashMap<C, GestureBinding<Action>>,
+}
+
+impl<DId, C, Action> InputContext<DId, C, Action>
+where
+    DId: DeviceId,
+    C: Control,
+    Action: Copy + Clone + Eq + Hash,
+{
+    pub fn new(index: usize, name: &str) -> Self {
+        Self {
+            id: ContextId(index),
+            name: String::from(name),
+            consume_policy: ConsumePolicy::default(),
+            control_map: HashMap::new(),
+            control_map_rev: HashMap::new(),
+            device_map: HashMap::new(),
+            wildcard_actions: vec![],
+            composite_actions: vec![],
+            chord_map: HashMap::new(),
+            tap_map: HashMap::new(),
+            hold_map: HashMap::new(),
+            next_hold_id: 0,
+            gesture_map: HashMap::new(),
+        }
+    }
+
+    pub fn id(&self) -> ContextId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn consume_policy(&self) -> ConsumePolicy {
+        self.consume_policy
+    }
+
+    pub fn set_consume_policy(&mut self, consume_policy: ConsumePolicy) {
+        self.consume_policy = consume_policy;
+    }
+
+    pub fn set_action(&mut self, control: C, action: Action, mask: Option<BitFlags<InputKind>>) {
+        self.control_map.insert(control, (action, mask));
+        self.control_map_rev.insert(action, control);
+    }
+
+    pub fn set_wildcard_action(&mut self, action: Action, mask: Option<BitFlags<InputKind>>) {
+        self.wildcard_actions.push((action, mask));
+    }
+
+    // Binds `control` on one specific device only. Device bindings take
+    // precedence over bindings for any device of the same kind
+    pub fn set_device_action(
+        &mut self,
+        device_id: DId,
+        control: C,
+        action: Action,
+        mask: Option<BitFlags<InputKind>>,
+    ) {
+        self.device_map.insert((device_id, control), (action, mask));
+    }
+
+    pub fn unbind_device(&mut self, device_id: DId, control: &C) -> Option<ActionBinding<Action>> {
+        self.device_map.remove(&(device_id, *control))
+    }
+
+    // Moves the bindings scoped to `from` over to `to`, e.g. for a gamepad
+    // that reconnected under a new id
+    pub fn remap_device(&mut self, from: DId, to: DId) {
+        let bindings: Vec<_> = self
+            .device_map
+            .extract_if(|(device_id, _), _| *device_id == from)
+            .collect();
+        for ((_, control), binding) in bindings {
+            self.device_map.insert((to, control), binding);
+        }
+    }
+
+    pub fn get_device_action(&self, device_id: DId, control: &C) -> Option<&ActionBinding<Action>> {
+        self.device_map.get(&(device_id, *control))
+    }
+
+    pub fn unbind(&mut self, control: &C) -> Option<ActionBinding<Action>> {
+        let removed = self.control_map.remove(control);
+        if let Some((action, _)) = &removed {
+            if self.control_map_rev.get(action) == Some(control) {
+                self.control_map_rev.remove(action);
+
+                // Keep the reverse lookup pointing at any other control that
+                // is still bound to the same action
+                let other = self
+                    .control_map
+                    .iter()
+                    .find(|(_, (x, _))| x == action)
+                    .map(|(x, _)| *x);
+
+                if let Some(other) = other {
+                    self.control_map_rev.insert(*action, other);
+                }
+            }
+        }
+        removed
+    }
+
+    // Binds `control` to `action` only while the chord is held. When several
+    // chords match, the most specific one wins, and the plain binding set with
+    // `set_action` is used when none of them match
+    pub fn set_chord_action(
+        &mut self,
+        control: C,
+        chord: Chord<C>,
+        action: Action,
+        mask: Option<BitFlags<InputKind>>,
+    ) {
+        let chords = self.chord_map.entry(control).or_default();
+        chords.retain(|(x, _)| *x != chord);
+        chords.push((chord, (action, mask)));
+        chords.sort_by_key(|(x, _)| std::cmp::Reverse(x.specificity()));
+    }
+
+    pub fn has_chords(&self, control: &C) -> bool {
+        self.chord_map.contains_key(control)
+    }
+
+    pub fn resolve_action(
+        &self,
+        control: &C,
+        modifiers: BitFlags<Modifier>,
+        held: &HashSet<C>,
+    ) -> Option<&ActionBinding<Action>> {
+        if let Some(chords) = self.chord_map.get(control) {
+            for (chord, binding) in chords {
+                if chord.is_held(modifiers, held) {
+                    return Some(binding);
+                }
+            }
+        }
+        self.control_map.get(control)
+    }
+
+    // Fires `action` when `control` is pressed `count` times in a row, with
+    // each press following the previous one within `window`
+    pub fn set_tap_action(&mut self, control: C, count: u32, window: Duration, action: Action) {
+        assert!(count > 0);
+        let taps = self.tap_map.entry(control).or_default();
+        taps.retain(|x| x.count != count);
+        taps.push(TapBinding {
+            count,
+            window,
+            action,
+        });
+    }
+
+    pub fn get_tap_actions(&self, control: &C) -> &[TapBinding<Action>] {
+        self.tap_map.get(control).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Fires `action` once `control` has been held for `threshold`, and then
+    // again every `repeat` interval for as long as it stays held
+    pub fn set_hold_action(
+        &mut self,
+        control: C,
+        threshold: Duration,
+        repeat: Option<Duration>,
+        action: Action,
+    ) {
+        assert!(repeat.is_none_or(|x| !x.is_zero()));
+        let id = HoldId(self.next_hold_id);
+        self.next_hold_id += 1;
+        let holds = self.hold_map.entry(control).or_default();
+        holds.retain(|x| x.action != action);
+        holds.push(HoldBinding {
+            id,
+            threshold,
+            repeat,
+            action,
+        });
+    }
+
+    pub fn get_hold_actions(&self, control: &C) -> &[HoldBinding<Action>] {
+        self.hold_map.get(control).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Reports taps, double taps and long presses of `control` to `action` as
+    // distinct gesture events. A press released within `tap_max` is a tap,
+    // and two taps with the second released within `double_tap_window` of the
+    // first are a double tap. Single taps are held back until the window
+    // passes, unless it's zero. Holding for `long_press` is a long press, and
+    // the release that follows isn't a tap
+    pub fn set_gesture_action(
+        &mut self,
+        control: C,
+        tap_max: Duration,
+        double_tap_window: Duration,
+        long_press: Duration,
+        action: Action,
+    ) {
+        assert!(tap_max < long_press);
+        self.gesture_map.insert(
+            control,
+            GestureBinding {
+                tap_max,
+                double_tap_window,
+                long_press,
+                action,
+            },
+        );
+    }
+
+    pub fn get_gesture_action(&self, control: &C) -> Option<&GestureBinding<Action>> {
+        self.gesture_map.get(control)
+    }
+
+    // Binds a virtual two-dimensional action that combines several controls
+    // into a single vector
+    pub fn set_composite_action(&mut self, action: Action, composite: Composite<C>) {
+        self.composite_actions.retain(|x| x.action != action);
+        self.composite_actions
+            .push(CompositeBinding::new(action, composite));
+    }
+
+    pub fn clear_action(&mut self, action: &Action) {
+        for chords in self.chord_map.values_mut() {
+            chords.retain(|(_, (x, _))| x != action);
+        }
+        self.chord_map.retain(|_, chords| !chords.is_empty());
+        for taps in self.tap_map.values_mut() {
+            taps.retain(|x| x.action != *action);
+        }
+        self.tap_map.retain(|_, taps| !taps.is_empty());
+        for holds in self.hold_map.values_mut() {
+            holds.retain(|x| x.action != *action);
+        }
+        self.hold_map.retain(|_, holds| !holds.is_empty());
+        self.gesture_map.retain(|_, x| x.action != *action);
+        self.control_map.retain(|_, (x, _)| x != action);
+        self.control_map_rev.remove(action);
+        self.device_map.retain(|_, (x, _)| x != action);
+        self.wildcard_actions.retain(|(x, _)| x != action);
+        self.composite_actions.retain(|x| x.action != *action);
+    }
+
+    // Whether any kind of binding in this context refers to `control`
+    pub fn is_bound(&self, control: &C) -> bool {
+        self.control_map.contains_key(control)
+            || self.chord_map.contains_key(control)
+            || self.tap_map.contains_key(control)
+            || self.hold_map.contains_key(control)
+            || self.gesture_map.contains_key(control)
+            || self.device_map.keys().any(|(_, x)| x == control)
+            || self.composite_actions.iter().any(|x| x.contains(control))
+    }
+
+    pub fn get_action(&self, control: &C) -> Option<&ActionBinding<Action>> {
+        self.control_map.get(control)
+    }
+
+    pub fn get_control(&self, action: &Action) -> Option<&C> {
+        self.control_map_rev.get(action)
+    }
+
+    pub fn wildcard_actions(&self) -> &[ActionBinding<Action>] {
+        &self.wildcard_actions
+    }
+
+    pub fn composite_actions(&self) -> &[CompositeBinding<C, Action>] {
+        &self.composite_actions
+    }
+
+    pub fn composite_actions_mut(&mut self) -> &mut [CompositeBinding<C, Action>] {
+        &mut self.composite_actions
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&C, &ActionBinding<Action>)> {
+        self.control_map.iter()
+    }
+
+    // Writes every binding in the context, annotated with the last value seen
+    // for its control
+    pub fn write_debug(&self, out: &mut impl Write, values: &HashMap<C, InputValue>) -> fmt::Result
+    where
+        DId: Debug,
+        C: Debug,
+        Action: Debug,
+    {
+        let value = |control: &C| match values.get(control) {
+            Some(value) => format!("{:?}", value),
+            None => String::from("-"),
+        };
+
+        for (control, (action, mask)) in &self.control_map {
+            writeln!(
+                out,
+                "  {:?} -> {:?} mask={:?} value={}",
+                control,
+                action,
+                mask,
+                value(control)
+            )?;
+        }
+        for ((device_id, control), (action, mask)) in &self.device_map {
+            writeln!(
+                out,
+                "  {:?} on {:?} -> {:?} mask={:?} value={}",
+                control,
+                device_id,
+                action,
+                mask,
+                value(control)
+            )?;
+        }
+        for (control, chords) in &self.chord_map {
+            for (chord, (action, mask)) in chords {
+                writeln!(
+                    out,
+                    "  {:?} with {:?} -> {:?} mask={:?} value={}",
+                    control,
+                    chord,
+                    action,
+                    mask,
+                    value(control)
+                )?;
+            }
+        }
+        for (control, taps) in &self.tap_map {
+            for tap in taps {
+                writeln!(
+                    out,
+                    "  {:?} x{} within {:?} -> {:?}",
+                    control, tap.count, tap.window, tap.action
+                )?;
+            }
+        }
+        for (control, holds) in &self.hold_map {
+            for hold in holds {
+                writeln!(
+                    out,
+                    "  {:?} held {:?} repeat {:?} -> {:?}",
+                    control, hold.threshold, hold.repeat, hold.action
+                )?;
+            }
+        }
+        for (control, gesture) in &self.gesture_map {
+            writeln!(
+                out,
+                "  {:?} gestures tap {:?} double {:?} long {:?} -> {:?}",
+                control,
+                gesture.tap_max,
+                gesture.double_tap_window,
+                gesture.long_press,
+                gesture.action
+            )?;
+        }
+        for composite in &self.composite_actions {
+            writeln!(
+                out,
+                "  {:?} -> {:?} value={:?}",
+                composite.composite(),
+                composite.action(),
+                composite.value()
+            )?;
+        }
+        for (action, mask) in &self.wildcard_actions {
+            writeln!(out, "  * -> {:?} mask={:?}", action, mask)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Composite<C: Control> {
+    // Four digital controls, e.g. WASD or a d-pad
+    Digital { up: C, down: C, left: C, right: C },
+    // Two one-dimensional analog controls, e.g. the axes of a stick
+    Axes { x: C, y: C },
+}
+
+pub struct CompositeBinding<C, Action>
+where
+    C: Control,
+    Action: Copy + Clone + Eq + Hash,
+{
+    action: Action,
+    composite: Composite<C>,
+    values: [f64; 4],
+}
+
+impl<C, Action> CompositeBinding<C, Action>
+where
+    C: Control,
+    Action: Copy + Clone + Eq + Hash,
+{
+    pub fn new(action: Action, composite: Composite<C>) -> Self {
+        Self {
+            action,
+            composite,
+            values: [0.0; 4],
+        }
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    pub fn composite(&self) -> &Composite<C> {
+        &self.composite
+    }
+
+    pub fn contains(&self, control: &C) -> bool {
+        match &self.composite {
+            Composite::Digital {
+                up,
+                down,
+                left,
+                right,
+            } => [up, down, left, right].contains(&control),
+            Composite::Axes { x, y } => [x, y].contains(&control),
+        }
+    }
+
+    // Records the new value of `control` if it's part of this composite and
+    // returns the updated vector, with a length of at most 1
+    pub fn update(&mut self, control: &C, value: InputValue) -> Option<(f64, f64)> {
+        let slot = match &self.composite {
+            Composite::Digital {
+                up,
+                down,
+                left,
+                right,
+            } => [up, down, left, right].iter().position(|x| *x == control)?,
+            Composite::Axes { x, y } => [x, y].iter().position(|x| *x == control)?,
+        };
+
+        self.values[slot] = match value {
+            InputValue::Digital(pressed) => {
+                if pressed {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            InputValue::Analog(x) => x,
+            InputValue::Analog2d(_, _) => return None,
+        };
+
+        Some(self.value())
+    }
+
+    pub fn value(&self) -> (f64, f64) {
+        let (x, y) = match &self.composite {
+            Composite::Digital { .. } => {
+                let [up, down, left, right] = self.values;
+                (right - left, up - down)
+            }
+            Composite::Axes { .. } => (self.values[0], self.values[1]),
+        };
+
+        let length = x.hypot(y);
+        if length > 1.0 {
+            (x / length, y / length)
+        } else {
+            (x, y)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TapBinding<Action> {
+    pub count: u32,
+    pub window: Duration,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HoldBinding<Action> {
+    pub id: HoldId,
+    pub threshold: Duration,
+    pub repeat: Option<Duration>,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    DoubleTap,
+    LongPress,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureBinding<Action> {
+    pub tap_max: Duration,
+    pub double_tap_window: Duration,
+    pub long_press: Duration,
+    pub action: Action,
+}
+
+impl<Action> HoldBinding<Action> {
+    // Number of times the binding should have fired after being held for
+    // `held_for`
+    pub fn due_count(&self, held_for: Duration) -> u32 {
+        if held_for < self.threshold {
+            return 0;
+        }
+        match self.repeat {
+            Some(repeat) => {
+                let since = (held_for - self.threshold).as_nanos() / repeat.as_nanos();
+                1 + u32::try_from(since).unwrap_or(u32::MAX - 1)
+            }
+            None => 1,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{CursorGrabMode, Window};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorMode {
+    Free,
+    // The cursor can move but can't leave the window
+    Confined,
+    // The cursor is fixed in place, e.g. for mouse-look. Use
+    // `MouseControl::Delta` to read motion while locked
+    Locked,
+}
+
+// Owns the cursor state of a window so the input layer and the app agree on
+// whether the cursor is grabbed
+pub struct CursorController {
+    window: Arc<Window>,
+    mode: CursorMode,
+    hidden: bool,
+}
+
+impl CursorController {
+    pub fn new(window: Arc<Window>) -> Self {
+        Self {
+            window,
+            mode: CursorMode::Free,
+            hidden: false,
+        }
+    }
+
+    pub fn mode(&self) -> CursorMode {
+        self.mode
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.mode != CursorMode::Free
+    }
+
+    // Not every platform supports both grab modes, so fall back to the other
+    // one when the requested mode isn't available. Returns the mode that was
+    // actually applied
+    pub fn set_mode(&mut self, mode: CursorMode) -> CursorMode {
+        let applied = match mode {
+            CursorMode::Free => {
+                self._set_grab(CursorGrabMode::None);
+                CursorMode::Free
+            }
+            CursorMode::Confined => {
+                if self._set_grab(CursorGrabMode::Confined) {
+                    CursorMode::Confined
+                } else if self._set_grab(CursorGrabMode::Locked) {
+                    CursorMode::Locked
+                } else {
+                    CursorMode::Free
+                }
+            }
+            CursorMode::Locked => {
+                if self._set_grab(CursorGrabMode::Locked) {
+                    CursorMode::Locked
+                } else if self._set_grab(CursorGrabMode::Confined) {
+                    CursorMode::Confined
+                } else {
+                    CursorMode::Free
+                }
+            }
+        };
+        self.mode = applied;
+        applied
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.window.set_cursor_visible(!hidden);
+        self.hidden = hidden;
+    }
+
+    // Grabs and hides the cursor for mouse-look
+    pub fn grab(&mut self) -> CursorMode {
+        let mode = self.set_mode(CursorMode::Locked);
+        self.set_hidden(mode != CursorMode::Free);
+        mode
+    }
+
+    pub fn release(&mut self) {
+        self.set_mode(CursorMode::Free);
+        self.set_hidden(false);
+    }
+
+    // Releases the cursor when the window loses focus or Escape is pressed.
+    // Returns true if the event released the cursor, in which case the app
+    // should treat it as consumed
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.is_grabbed() && !self.hidden {
+            return false;
+        }
+        let release = match event {
+            WindowEvent::Focused(false) => true,
+            WindowEvent::KeyboardInput { event, .. } => {
+                event.state == ElementState::Pressed
+                    && event.logical_key == Key::Named(NamedKey::Escape)
+            }
+            _ => false,
+        };
+        if release {
+            self.release();
+        }
+        release
+    }
+
+    fn _set_grab(&self, mode: CursorGrabMode) -> bool {
+        self.window.set_cursor_grab(mode).is_ok()
+    }
+}
@@ -1,6 +1,6 @@
 use super::DeviceId;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RawDeviceId {
     Keyboard(winit::event::DeviceId),
     Mouse(winit::event::DeviceId),
@@ -1,14 +1,27 @@
+use super::{
+    ActionBinding, Chord, Composite, ConsumePolicy, ContextId, Gesture, HoldId, InputClock,
+    InputContext, Modifier,
+};
 use enumflags2::{bitflags, BitFlags};
-use std::cell::OnceCell;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Write};
 use std::hash::Hash;
-use std::time::{Duration, Instant};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
 
-pub trait DeviceId: Clone + Copy + PartialEq + Eq {
+pub trait DeviceId: Clone + Copy + PartialEq + Eq + Hash {
     type Kind;
     fn kind(&self) -> Self::Kind;
 }
 
+pub const DEFAULT_CONTEXT_NAME: &str = "default";
+
+const CAPTURE_ANALOG_THRESHOLD: f64 = 0.5;
+
+// One-dimensional analog actions count as pressed past this
+const ACTION_PRESS_THRESHOLD: f64 = 0.5;
+
 #[derive(Debug, Clone, Copy)]
 pub enum InputValue {
     Digital(bool),
@@ -18,7 +31,7 @@ pub enum InputValue {
 
 #[bitflags]
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum InputKind {
     Digital,
     Analog,
@@ -33,26 +46,253 @@ impl InputValue {
             InputValue::Analog2d(_, _) => InputKind::Analog2d,
         }
     }
+
+    // Whether the value is a deliberate actuation of its control, used when
+    // capturing inputs for rebinding. Two-dimensional values (cursor position,
+    // wheel) are only considered when explicitly allowed by the mask
+    pub fn is_actuated(&self, mask: Option<BitFlags<InputKind>>) -> bool {
+        if let Some(mask) = mask {
+            if !mask.intersects(self.kind()) {
+                return false;
+            }
+        }
+        match self {
+            InputValue::Digital(pressed) => *pressed,
+            InputValue::Analog(x) => x.abs() >= CAPTURE_ANALOG_THRESHOLD,
+            InputValue::Analog2d(x, y) => {
+                mask.is_some_and(|mask| mask.contains(InputKind::Analog2d))
+                    && (*x != 0.0 || *y != 0.0)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEventKind {
+    // The value of the bound control changed
+    Change,
+    // The control was pressed `count` times within the tap window
+    Tap(u32),
+    // The control was held past the hold threshold. The count is 0 the first
+    // time and increments with each repeat
+    Hold(u32),
+    // A gesture binding recognized a tap, double tap or long press
+    Gesture(Gesture),
+    // The held control was repeated, e.g. keyboard auto-repeat. Only plain
+    // control bindings receive repeats, and they don't change action state
+    Repeat,
+}
+
+// An action's state as of the last flush, for code that polls once per frame
+// instead of consuming events. Presses and releases within a frame are
+// still reported through the `just_` flags
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ActionState {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+    value: (f64, f64),
+    // Relative values, e.g. mouse deltas, are summed over the frame and reset
+    // after it
+    relative: bool,
+}
+
+impl ActionState {
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    pub fn just_pressed(&self) -> bool {
+        self.just_pressed
+    }
+
+    pub fn just_released(&self) -> bool {
+        self.just_released
+    }
+
+    // Digital actions are 0 or 1
+    pub fn axis(&self) -> f64 {
+        self.value.0
+    }
+
+    pub fn axis2d(&self) -> (f64, f64) {
+        self.value
+    }
+
+    fn _apply(&mut self, value: InputValue, relative: bool) {
+        self.relative = relative;
+        let pressed = match value {
+            InputValue::Digital(pressed) => {
+                self.value = (if pressed { 1.0 } else { 0.0 }, 0.0);
+                pressed
+            }
+            InputValue::Analog(x) => {
+                self.value = if relative {
+                    (self.value.0 + x, 0.0)
+                } else {
+                    (x, 0.0)
+                };
+                self.value.0.abs() >= ACTION_PRESS_THRESHOLD
+            }
+            // Positions and sticks don't have a pressed state
+            InputValue::Analog2d(x, y) => {
+                self.value = if relative {
+                    (self.value.0 + x, self.value.1 + y)
+                } else {
+                    (x, y)
+                };
+                self.pressed
+            }
+        };
+        if pressed && !self.pressed {
+            self.just_pressed = true;
+        }
+        if !pressed && self.pressed {
+            self.just_released = true;
+        }
+        self.pressed = pressed;
+    }
+
+    fn _next_frame(&mut self) {
+        self.just_pressed = false;
+        self.just_released = false;
+        if self.relative {
+            self.value = (0.0, 0.0);
+        }
+    }
+}
+
+struct GestureState<DId> {
+    device_id: DId,
+    // Set while the control is held
+    pressed_at: Option<Duration>,
+    long_pressed: bool,
+    // Release time of a tap that may still become a double tap
+    pending_tap: Option<Duration>,
 }
 
-#[derive(Debug)]
+struct HoldState<DId> {
+    device_id: DId,
+    context: ContextId,
+    pressed_at: Duration,
+    // Times each binding fired since the press
+    fired: HashMap<HoldId, u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct InputEvent<DId: DeviceId, Action: Hash> {
     pub index: u64,
     pub created_at: Duration,
+    // Index of the frame during which the event was created
+    pub frame: u64,
     pub device_id: DId,
+    pub context: ContextId,
     pub action: Action,
+    pub kind: InputEventKind,
     pub value: InputValue,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type EventCallback<DId, Action> = Box<dyn FnMut(&InputEvent<DId, Action>)>;
+
+enum Subscriber<DId: DeviceId, Action: Hash> {
+    Callback(EventCallback<DId, Action>),
+    Channel(Sender<InputEvent<DId, Action>>),
+}
+
+struct Subscription<DId: DeviceId, Action: Hash> {
+    id: SubscriptionId,
+    // Subscriptions without an action receive every input event
+    action: Option<Action>,
+    subscriber: Subscriber<DId, Action>,
+}
+
+impl<DId: DeviceId, Action: Hash> InputEvent<DId, Action> {
+    // Time between the event being created and now, e.g. when it's consumed
+    pub fn latency(&self, clock: &InputClock) -> Duration {
+        clock.elapsed().saturating_sub(self.created_at)
+    }
+
+    // Number of frames that started after the event was created
+    pub fn frame_lag(&self, clock: &InputClock) -> u64 {
+        clock.frame_index().saturating_sub(self.frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawEventKind {
+    // The control's value changed, including presses and releases
+    Change,
+    // A control that is still held was repeated by the device or the OS
+    Repeat,
+    // Carries no input, e.g. a gamepad event that a gilrs filter dropped
+    Ignored,
+}
+
 pub trait RawEvent<DId: DeviceId> {
     type Control: Control;
     fn get_device_id(&self) -> DId;
     fn get_control(&self) -> Self::Control;
     fn get_input_value(&self) -> InputValue;
+
+    fn get_event_kind(&self) -> RawEventKind {
+        RawEventKind::Change
+    }
+
+    // A second way to identify the control, e.g. the logical key for a
+    // physical key, used only when the primary control isn't bound
+    fn get_alternate_control(&self) -> Option<Self::Control> {
+        None
+    }
 }
 
 pub trait Control: Copy + Clone + Eq + PartialEq + Hash {
     fn kind(&self) -> BitFlags<InputKind>;
+
+    // Relative controls report deltas that are summed over a frame, while
+    // absolute two-dimensional controls report positions that are differenced
+    fn is_relative(&self) -> bool {
+        false
+    }
+}
+
+// Events since the last flush. Indices keep counting up across flushes
+struct InputEventQueue<DId: DeviceId, Action: Hash> {
+    events: Vec<InputEvent<DId, Action>>,
+    next_index: u64,
+}
+
+impl<DId: DeviceId, Action: Copy + Hash> InputEventQueue<DId, Action> {
+    // Returns false if the binding's mask rejected the value
+    fn push(
+        &mut self,
+        clock: &InputClock,
+        device_id: DId,
+        context: ContextId,
+        (action, mask): ActionBinding<Action>,
+        kind: InputEventKind,
+        value: InputValue,
+    ) -> bool {
+        if let Some(mask) = mask {
+            if !mask.intersects(value.kind()) {
+                return false;
+            }
+        }
+        self.events.push(InputEvent {
+            index: self.next_index,
+            created_at: clock.elapsed(),
+            frame: clock.frame_index(),
+            device_id,
+            context,
+            action,
+            kind,
+            value,
+        });
+        self.next_index += 1;
+        true
+    }
 }
 
 pub struct InputManager<DId, REvent, Action>
@@ -61,12 +301,29 @@ where
     REvent: RawEvent<DId>,
     Action: Copy + Clone + Eq + Hash,
 {
-    start_time: Instant,
-    control_map: HashMap<REvent::Control, (Action, Option<BitFlags<InputKind>>)>,
-    control_map_rev: HashMap<Action, REvent::Control>,
-    wildcard_actions: Vec<(Action, Option<BitFlags<InputKind>>)>,
-    input_events: Vec<InputEvent<DId, Action>>,
-    next_index: u64,
+    clock: InputClock,
+    contexts: Vec<InputContext<DId, REvent::Control, Action>>,
+    context_stack: Vec<ContextId>,
+    capture: Option<ActionBinding<Action>>,
+    modifiers: BitFlags<Modifier>,
+    held_controls: HashSet<REvent::Control>,
+    control_values: HashMap<REvent::Control, InputValue>,
+    chord_latches: HashMap<(ContextId, REvent::Control), ActionBinding<Action>>,
+    tap_counters: HashMap<REvent::Control, (u32, Duration)>,
+    hold_states: HashMap<REvent::Control, HoldState<DId>>,
+    gesture_states: HashMap<(ContextId, REvent::Control), GestureState<DId>>,
+    frame_deltas: HashMap<REvent::Control, (f64, f64)>,
+    last_positions: HashMap<REvent::Control, (f64, f64)>,
+    input_events: InputEventQueue<DId, Action>,
+    // Updated as events come in, and copied into `sampled_action_states` on
+    // each flush
+    action_states: HashMap<Action, ActionState>,
+    sampled_action_states: HashMap<Action, ActionState>,
+    subscriptions: Vec<Subscription<DId, Action>>,
+    next_subscription_id: u64,
+    dispatched: usize,
+    coalesce: bool,
+    coalesce_slots: HashMap<(DId, REvent::Control, Action), usize>,
 }
 
 impl<DId, REvent, Action> InputManager<DId, REvent, Action>
@@ -75,120 +332,1336 @@ where
     REvent: RawEvent<DId>,
     Action: Copy + Clone + Eq + Hash,
 {
-    pub fn new(start_time: Instant) -> Self {
+    pub fn new(clock: InputClock) -> Self {
+        let default_context = InputContext::new(0, DEFAULT_CONTEXT_NAME);
+        let default_context_id = default_context.id();
         Self {
-            start_time,
-            control_map: HashMap::new(),
-            control_map_rev: HashMap::new(),
-            wildcard_actions: vec![],
-            input_events: vec![],
-            next_index: 0,
+            clock,
+            contexts: vec![default_context],
+            context_stack: vec![default_context_id],
+            capture: None,
+            modifiers: BitFlags::empty(),
+            held_controls: HashSet::new(),
+            control_values: HashMap::new(),
+            chord_latches: HashMap::new(),
+            tap_counters: HashMap::new(),
+            hold_states: HashMap::new(),
+            gesture_states: HashMap::new(),
+            frame_deltas: HashMap::new(),
+            last_positions: HashMap::new(),
+            input_events: InputEventQueue {
+                events: vec![],
+                next_index: 0,
+            },
+            action_states: HashMap::new(),
+            sampled_action_states: HashMap::new(),
+            subscriptions: vec![],
+            next_subscription_id: 0,
+            dispatched: 0,
+            coalesce: false,
+            coalesce_slots: HashMap::new(),
         }
     }
 
+    // Returns the id of the context with the given name, creating it if it
+    // doesn't exist yet
+    pub fn add_context(&mut self, name: &str) -> ContextId {
+        if let Some(id) = self.find_context(name) {
+            return id;
+        }
+        let context = InputContext::new(self.contexts.len(), name);
+        let id = context.id();
+        self.contexts.push(context);
+        id
+    }
+
+    pub fn find_context(&self, name: &str) -> Option<ContextId> {
+        self.contexts
+            .iter()
+            .find(|x| x.name() == name)
+            .map(|x| x.id())
+    }
+
+    pub fn context(&self, id: ContextId) -> &InputContext<DId, REvent::Control, Action> {
+        &self.contexts[id.index()]
+    }
+
+    pub fn context_mut(
+        &mut self,
+        id: ContextId,
+    ) -> &mut InputContext<DId, REvent::Control, Action> {
+        &mut self.contexts[id.index()]
+    }
+
+    pub fn active_context_id(&self) -> ContextId {
+        *self.context_stack.last().unwrap()
+    }
+
+    pub fn active_context(&self) -> &InputContext<DId, REvent::Control, Action> {
+        self.context(self.active_context_id())
+    }
+
+    pub fn active_context_mut(&mut self) -> &mut InputContext<DId, REvent::Control, Action> {
+        self.context_mut(self.active_context_id())
+    }
+
+    pub fn push_context(&mut self, id: ContextId) {
+        self.context_stack.push(id);
+    }
+
+    // The bottom context can't be popped, so there is always an active context
+    pub fn pop_context(&mut self) -> Option<ContextId> {
+        if self.context_stack.len() > 1 {
+            self.context_stack.pop()
+        } else {
+            None
+        }
+    }
+
+    // Replaces the active context without changing the depth of the stack
+    pub fn switch_context(&mut self, id: ContextId) -> ContextId {
+        let top = self.context_stack.last_mut().unwrap();
+        std::mem::replace(top, id)
+    }
+
     pub fn set_action(
         &mut self,
         control: REvent::Control,
         action: Action,
         mask: Option<BitFlags<InputKind>>,
     ) {
-        self.control_map.insert(control, (action, mask));
-        self.control_map_rev.insert(action, control);
+        self.active_context_mut().set_action(control, action, mask);
     }
 
     pub fn set_wildcard_action(&mut self, action: Action, mask: Option<BitFlags<InputKind>>) {
-        self.wildcard_actions.push((action, mask));
+        self.active_context_mut().set_wildcard_action(action, mask);
     }
 
-    pub fn get_action(
-        &self,
+    pub fn set_device_action(
+        &mut self,
+        device_id: DId,
+        control: REvent::Control,
+        action: Action,
+        mask: Option<BitFlags<InputKind>>,
+    ) {
+        self.active_context_mut()
+            .set_device_action(device_id, control, action, mask);
+    }
+
+    pub fn unbind_device(
+        &mut self,
+        device_id: DId,
         control: &REvent::Control,
-    ) -> Option<&(Action, Option<BitFlags<InputKind>>)> {
-        self.control_map.get(control)
+    ) -> Option<ActionBinding<Action>> {
+        self.active_context_mut().unbind_device(device_id, control)
+    }
+
+    // Unlike the other binding methods this applies to every context, since
+    // the device itself changed
+    pub fn remap_device(&mut self, from: DId, to: DId) {
+        for context in &mut self.contexts {
+            context.remap_device(from, to);
+        }
+    }
+
+    pub fn set_chord_action(
+        &mut self,
+        control: REvent::Control,
+        chord: Chord<REvent::Control>,
+        action: Action,
+        mask: Option<BitFlags<InputKind>>,
+    ) {
+        self.active_context_mut()
+            .set_chord_action(control, chord, action, mask);
+    }
+
+    // Modifier keys are tracked separately from held controls so that devices
+    // other than the keyboard can use them in chords
+    pub fn set_modifiers(&mut self, modifiers: BitFlags<Modifier>) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn modifiers(&self) -> BitFlags<Modifier> {
+        self.modifiers
+    }
+
+    pub fn is_held(&self, control: &REvent::Control) -> bool {
+        self.held_controls.contains(control)
+    }
+
+    pub fn set_tap_action(
+        &mut self,
+        control: REvent::Control,
+        count: u32,
+        window: Duration,
+        action: Action,
+    ) {
+        self.active_context_mut()
+            .set_tap_action(control, count, window, action);
+    }
+
+    pub fn set_hold_action(
+        &mut self,
+        control: REvent::Control,
+        threshold: Duration,
+        repeat: Option<Duration>,
+        action: Action,
+    ) {
+        self.active_context_mut()
+            .set_hold_action(control, threshold, repeat, action);
+    }
+
+    pub fn set_composite_action(&mut self, action: Action, composite: Composite<REvent::Control>) {
+        self.active_context_mut()
+            .set_composite_action(action, composite);
+    }
+
+    pub fn unbind(&mut self, control: &REvent::Control) -> Option<ActionBinding<Action>> {
+        self.active_context_mut().unbind(control)
+    }
+
+    pub fn clear_action(&mut self, action: &Action) {
+        self.active_context_mut().clear_action(action);
+    }
+
+    // Binds the next actuated, non-wildcard input to `action` in the active
+    // context, replacing any existing bindings for it. The input that
+    // completes the capture is consumed and does not produce an input event
+    pub fn begin_capture(&mut self, action: Action, mask: Option<BitFlags<InputKind>>) {
+        self.capture = Some((action, mask));
+    }
+
+    pub fn cancel_capture(&mut self) -> Option<Action> {
+        self.capture.take().map(|(action, _)| action)
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn get_action(&self, control: &REvent::Control) -> Option<&ActionBinding<Action>> {
+        self.active_context().get_action(control)
     }
 
     pub fn get_control(&self, action: &Action) -> Option<&REvent::Control> {
-        self.control_map_rev.get(action)
+        self.active_context().get_control(action)
+    }
+
+    // Merges analog change events for the same device, control and action
+    // until the next flush, so high-frequency devices only produce one event
+    // per frame. Relative values are summed so no motion is lost. While
+    // enabled, subscribers are notified on flush instead of on update
+    pub fn set_coalescing(&mut self, coalesce: bool) {
+        if self.coalesce && !coalesce {
+            self._dispatch_input_events();
+        }
+        self.coalesce = coalesce;
+    }
+
+    pub fn is_coalescing(&self) -> bool {
+        self.coalesce
     }
 
     pub fn update(&mut self, raw_event: &REvent) -> usize {
         let mut count: usize = 0;
+        let first = self.input_events.events.len();
         let device_id = raw_event.get_device_id();
-        let raw_control = raw_event.get_control();
-        let control_action = self.control_map.get(&raw_control);
-        let value: OnceCell<InputValue> = OnceCell::new();
+        let raw_control = self._resolve_control(raw_event);
+
+        match raw_event.get_event_kind() {
+            RawEventKind::Change => {}
+            RawEventKind::Repeat => return self._update_repeat(device_id, raw_control),
+            RawEventKind::Ignored => return 0,
+        }
+
+        if let Some((action, mask)) = self.capture {
+            let value = raw_event.get_input_value();
+            if value.is_actuated(mask) {
+                self.capture = None;
+                self.clear_action(&action);
+                self.set_action(raw_control, action, mask);
+                return 0;
+            }
+        }
+
+        // Each context sees the event in turn, from the top of the stack down
+        // to the first one that consumes it
+        let value = raw_event.get_input_value();
+        let routed = self._routed_contexts(&raw_control);
+        for context_id in &routed {
+            count += self._update_context(*context_id, device_id, raw_control, value);
+        }
+
+        self.control_values.insert(raw_control, value);
+
+        match value {
+            InputValue::Digital(true) => {
+                self.held_controls.insert(raw_control);
+
+                // Only the topmost context with holds on the control tracks
+                // them
+                let holds_context = routed.iter().copied().find(|x| {
+                    !self.contexts[x.index()]
+                        .get_hold_actions(&raw_control)
+                        .is_empty()
+                });
+                if let Some(context_id) = holds_context {
+                    if !self.hold_states.contains_key(&raw_control) {
+                        let state = HoldState {
+                            device_id,
+                            context: context_id,
+                            pressed_at: self.clock.elapsed(),
+                            fired: HashMap::new(),
+                        };
+                        self.hold_states.insert(raw_control, state);
+                    }
+                }
+            }
+            InputValue::Digital(false) => {
+                self.held_controls.remove(&raw_control);
+                self.hold_states.remove(&raw_control);
+                // e.g. the cursor leaving the window, the next position
+                // shouldn't produce a jump
+                self.last_positions.remove(&raw_control);
+            }
+            InputValue::Analog2d(x, y) => {
+                let delta = if raw_control.is_relative() {
+                    (x, y)
+                } else {
+                    match self.last_positions.insert(raw_control, (x, y)) {
+                        Some((last_x, last_y)) => (x - last_x, y - last_y),
+                        None => (0.0, 0.0),
+                    }
+                };
+                let total = self.frame_deltas.entry(raw_control).or_default();
+                total.0 += delta.0;
+                total.1 += delta.1;
+            }
+            _ => {}
+        }
+
+        // Before coalescing, so presses and releases within a frame are seen
+        for event in &self.input_events.events[first..] {
+            if event.kind == InputEventKind::Change {
+                self.action_states
+                    .entry(event.action)
+                    .or_default()
+                    ._apply(event.value, raw_control.is_relative());
+            }
+        }
+
+        if self.coalesce {
+            count -= self._coalesce_input_events(first, device_id, raw_control);
+        } else {
+            self._dispatch_input_events();
+        }
+        count
+    }
+
+    // Emits hold events for controls that have been held past their
+    // thresholds, and the gesture events that are due. Both are time based
+    // rather than event based, so this needs to be called regularly, e.g.
+    // once per frame
+    pub fn update_holds(&mut self) -> usize {
+        let mut count: usize = 0;
+        let now = self.clock.elapsed();
+
+        for (control, state) in &mut self.hold_states {
+            let holds = self.contexts[state.context.index()].get_hold_actions(control);
+            for hold in holds {
+                let fired = state.fired.entry(hold.id).or_default();
+                let due = hold.due_count(now - state.pressed_at);
+                while *fired < due {
+                    if self.input_events.push(
+                        &self.clock,
+                        state.device_id,
+                        state.context,
+                        (hold.action, None),
+                        InputEventKind::Hold(*fired),
+                        InputValue::Digital(true),
+                    ) {
+                        count += 1;
+                    }
+                    *fired += 1;
+                }
+            }
+        }
+
+        // Long presses fire while the control is still held, and single taps
+        // once they can no longer become double taps
+        for ((context_id, control), state) in &mut self.gesture_states {
+            let context = &self.contexts[context_id.index()];
+            let Some(gesture) = context.get_gesture_action(control) else {
+                continue;
+            };
+            let mut recognized = vec![];
+            if let Some(pressed_at) = state.pressed_at {
+                if !state.long_pressed && now - pressed_at >= gesture.long_press {
+                    state.long_pressed = true;
+                    recognized.push(Gesture::LongPress);
+                }
+            }
+            if let Some(released_at) = state.pending_tap {
+                if now - released_at > gesture.double_tap_window {
+                    state.pending_tap = None;
+                    recognized.push(Gesture::Tap);
+                }
+            }
+            for recognized in recognized {
+                if self.input_events.push(
+                    &self.clock,
+                    state.device_id,
+                    *context_id,
+                    (gesture.action, None),
+                    InputEventKind::Gesture(recognized),
+                    InputValue::Digital(true),
+                ) {
+                    count += 1;
+                }
+            }
+        }
+
+        if !self.coalesce {
+            self._dispatch_input_events();
+        }
+        count
+    }
+
+    // Calls `callback` for every input event reported to `action`, as an
+    // alternative to polling the event buffer
+    pub fn on_action(
+        &mut self,
+        action: Action,
+        callback: impl FnMut(&InputEvent<DId, Action>) + 'static,
+    ) -> SubscriptionId {
+        self._add_subscription(Some(action), Subscriber::Callback(Box::new(callback)))
+    }
+
+    pub fn on_any_action(
+        &mut self,
+        callback: impl FnMut(&InputEvent<DId, Action>) + 'static,
+    ) -> SubscriptionId {
+        self._add_subscription(None, Subscriber::Callback(Box::new(callback)))
+    }
+
+    // Returns a receiver for input events reported to `action`, or to every
+    // action if `None`. The subscription is dropped automatically once the
+    // receiver is dropped
+    pub fn subscribe(&mut self, action: Option<Action>) -> Receiver<InputEvent<DId, Action>> {
+        let (sender, receiver) = channel();
+        self._add_subscription(action, Subscriber::Channel(sender));
+        receiver
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscriptions.len();
+        self.subscriptions.retain(|x| x.id != id);
+        self.subscriptions.len() != len
+    }
+
+    fn _add_subscription(
+        &mut self,
+        action: Option<Action>,
+        subscriber: Subscriber<DId, Action>,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription_id);
+        self.next_subscription_id += 1;
+        self.subscriptions.push(Subscription {
+            id,
+            action,
+            subscriber,
+        });
+        id
+    }
+
+    // Delivers events pushed since the last dispatch to subscribers. Events
+    // stay in the buffer so polling keeps working alongside subscriptions
+    fn _dispatch_input_events(&mut self) {
+        let events = &self.input_events.events[self.dispatched..];
+        self.dispatched = self.input_events.events.len();
+        if events.is_empty() || self.subscriptions.is_empty() {
+            return;
+        }
+
+        self.subscriptions.retain_mut(|subscription| {
+            for event in events {
+                if subscription.action.is_some_and(|x| x != event.action) {
+                    continue;
+                }
+                match &mut subscription.subscriber {
+                    Subscriber::Callback(callback) => callback(event),
+                    Subscriber::Channel(sender) => {
+                        if sender.send(*event).is_err() {
+                            return false;
+                        }
+                    }
+                }
+            }
+            true
+        });
+    }
+
+    // Sum of the movement of a two-dimensional control since the last flush,
+    // e.g. the mouse delta or wheel scroll for the current frame
+    pub fn get_frame_delta(&self, control: &REvent::Control) -> (f64, f64) {
+        self.frame_deltas.get(control).copied().unwrap_or_default()
+    }
+
+    // Last value seen for a control, regardless of whether it's bound
+    pub fn get_control_value(&self, control: &REvent::Control) -> Option<InputValue> {
+        self.control_values.get(control).copied()
+    }
+
+    // Human readable snapshot of the manager's state, for diagnosing bindings
+    // that don't fire
+    pub fn debug_dump(&self) -> String
+    where
+        DId: Debug,
+        REvent::Control: Debug,
+        Action: Debug,
+    {
+        let mut out = String::new();
+        self._write_debug(&mut out)
+            .expect("failed to write input debug dump");
+        out
+    }
+
+    fn _write_debug(&self, out: &mut String) -> std::fmt::Result
+    where
+        DId: Debug,
+        REvent::Control: Debug,
+        Action: Debug,
+    {
+        let stack: Vec<&str> = self
+            .context_stack
+            .iter()
+            .map(|x| self.context(*x).name())
+            .collect();
+        writeln!(out, "context stack: {:?}", stack)?;
+        writeln!(out, "modifiers: {:?}", self.modifiers)?;
+        writeln!(out, "held: {:?}", self.held_controls)?;
+        if let Some((action, mask)) = &self.capture {
+            writeln!(out, "capturing: {:?} mask={:?}", action, mask)?;
+        }
+        for context in &self.contexts {
+            let active = if context.id() == self.active_context_id() {
+                " (active)"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "context {:?}{} consume={:?}:",
+                context.name(),
+                active,
+                context.consume_policy()
+            )?;
+            context.write_debug(out, &self.control_values)?;
+        }
+        writeln!(out, "values:")?;
+        for (control, value) in &self.control_values {
+            writeln!(out, "  {:?} = {:?}", control, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn clock(&self) -> &InputClock {
+        &self.clock
+    }
+
+    pub fn get_input_event_count(&self) -> usize {
+        self.input_events.events.len()
+    }
+
+    pub fn get_nth_last_input_event(&self, offset: usize) -> Option<&InputEvent<DId, Action>> {
+        if (offset + 1) > self.input_events.events.len() {
+            return None;
+        }
+        Some(&self.input_events.events[self.input_events.events.len() - (offset + 1)])
+    }
+
+    // Default if the action hasn't been reported yet
+    pub fn action_state(&self, action: &Action) -> ActionState {
+        self.sampled_action_states
+            .get(action)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn is_pressed(&self, action: &Action) -> bool {
+        self.action_state(action).is_pressed()
+    }
+
+    pub fn just_pressed(&self, action: &Action) -> bool {
+        self.action_state(action).just_pressed()
+    }
+
+    pub fn just_released(&self, action: &Action) -> bool {
+        self.action_state(action).just_released()
+    }
+
+    pub fn axis(&self, action: &Action) -> f64 {
+        self.action_state(action).axis()
+    }
+
+    pub fn axis2d(&self, action: &Action) -> (f64, f64) {
+        self.action_state(action).axis2d()
+    }
+
+    pub fn get_input_events(&self) -> &[InputEvent<DId, Action>] {
+        &self.input_events.events
+    }
+
+    // Also samples the action states, so this should be called once per
+    // frame
+    pub fn flush_input_events(&mut self) {
+        if self.coalesce {
+            self._dispatch_input_events();
+        }
+        self.sampled_action_states.clone_from(&self.action_states);
+        for state in self.action_states.values_mut() {
+            state._next_frame();
+        }
+        self.input_events.events.clear();
+        self.dispatched = 0;
+        self.coalesce_slots.clear();
+        self.frame_deltas.clear();
+    }
+
+    // Reports the event to the bindings of one context. Returns the number of
+    // events pushed
+    fn _update_context(
+        &mut self,
+        context_id: ContextId,
+        device_id: DId,
+        raw_control: REvent::Control,
+        value: InputValue,
+    ) -> usize {
+        let mut count: usize = 0;
+        let context = &self.contexts[context_id.index()];
+
+        // Bindings scoped to this device win over bindings for any device.
+        // Chorded bindings are resolved when the control is pressed and
+        // latched until it's released, so releasing a modifier first doesn't
+        // change which action the release is reported to
+        let device_action = context.get_device_action(device_id, &raw_control);
+        let control_action = if let Some(binding) = device_action {
+            Some(*binding)
+        } else if context.has_chords(&raw_control) {
+            match value {
+                InputValue::Digital(true) => {
+                    let resolved = context
+                        .resolve_action(&raw_control, self.modifiers, &self.held_controls)
+                        .copied();
+                    match resolved {
+                        Some(binding) => self
+                            .chord_latches
+                            .insert((context_id, raw_control), binding),
+                        None => self.chord_latches.remove(&(context_id, raw_control)),
+                    };
+                    resolved
+                }
+                InputValue::Digital(false) => self
+                    .chord_latches
+                    .remove(&(context_id, raw_control))
+                    .or(context.get_action(&raw_control).copied()),
+                _ => context
+                    .resolve_action(&raw_control, self.modifiers, &self.held_controls)
+                    .copied(),
+            }
+        } else {
+            context.get_action(&raw_control).copied()
+        };
 
         if let Some((action, mask)) = control_action {
-            let value = value.get_or_init(|| raw_event.get_input_value());
-            if Self::_push_input_event(
-                &mut self.next_index,
-                &mut self.input_events,
-                self.start_time.elapsed(),
+            if self.input_events.push(
+                &self.clock,
                 device_id,
-                *action,
-                *value,
-                mask,
+                context_id,
+                (action, mask),
+                InputEventKind::Change,
+                value,
             ) {
                 count += 1;
             }
         }
 
-        for (action, mask) in &self.wildcard_actions {
-            let value = value.get_or_init(|| raw_event.get_input_value());
-            if Self::_push_input_event(
-                &mut self.next_index,
-                &mut self.input_events,
-                self.start_time.elapsed(),
+        for (action, mask) in context.wildcard_actions() {
+            if self.input_events.push(
+                &self.clock,
                 device_id,
-                *action,
-                *value,
-                mask,
+                context_id,
+                (*action, *mask),
+                InputEventKind::Change,
+                value,
             ) {
                 count += 1;
             }
         }
 
+        let context = &mut self.contexts[context_id.index()];
+        for composite in context.composite_actions_mut() {
+            if let Some((x, y)) = composite.update(&raw_control, value) {
+                if self.input_events.push(
+                    &self.clock,
+                    device_id,
+                    context_id,
+                    (composite.action(), None),
+                    InputEventKind::Change,
+                    InputValue::Analog2d(x, y),
+                ) {
+                    count += 1;
+                }
+            }
+        }
+
+        let context = &self.contexts[context_id.index()];
+        let taps = context.get_tap_actions(&raw_control);
+        if !taps.is_empty() {
+            if let InputValue::Digital(true) = value {
+                let now = self.clock.elapsed();
+                let window = taps.iter().map(|x| x.window).max().unwrap();
+                let max_count = taps.iter().map(|x| x.count).max().unwrap();
+
+                let tap_count = match self.tap_counters.get(&raw_control) {
+                    Some((n, last)) if now - *last <= window && *n < max_count => n + 1,
+                    _ => 1,
+                };
+                self.tap_counters.insert(raw_control, (tap_count, now));
+
+                for tap in taps {
+                    if tap.count == tap_count
+                        && self.input_events.push(
+                            &self.clock,
+                            device_id,
+                            context_id,
+                            (tap.action, None),
+                            InputEventKind::Tap(tap_count),
+                            InputValue::Digital(true),
+                        )
+                    {
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        if let Some(gesture) = context.get_gesture_action(&raw_control) {
+            let now = self.clock.elapsed();
+            let state = self
+                .gesture_states
+                .entry((context_id, raw_control))
+                .or_insert(GestureState {
+                    device_id,
+                    pressed_at: None,
+                    long_pressed: false,
+                    pending_tap: None,
+                });
+
+            match value {
+                // Key repeats arrive as more presses
+                InputValue::Digital(true) if state.pressed_at.is_none() => {
+                    state.device_id = device_id;
+                    state.pressed_at = Some(now);
+                    state.long_pressed = false;
+                }
+                InputValue::Digital(false) => {
+                    let tapped = state
+                        .pressed_at
+                        .take()
+                        .is_some_and(|x| !state.long_pressed && now - x <= gesture.tap_max);
+                    let recognized = match state.pending_tap {
+                        _ if !tapped => None,
+                        Some(released_at) if now - released_at <= gesture.double_tap_window => {
+                            state.pending_tap = None;
+                            Some(Gesture::DoubleTap)
+                        }
+                        _ if gesture.double_tap_window.is_zero() => Some(Gesture::Tap),
+                        _ => {
+                            state.pending_tap = Some(now);
+                            None
+                        }
+                    };
+                    if let Some(recognized) = recognized {
+                        if self.input_events.push(
+                            &self.clock,
+                            device_id,
+                            context_id,
+                            (gesture.action, None),
+                            InputEventKind::Gesture(recognized),
+                            InputValue::Digital(true),
+                        ) {
+                            count += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
         count
     }
 
-    pub fn get_input_event_count(&self) -> usize {
-        self.input_events.len()
+    // Repeats go to the action the control is bound to in each routed
+    // context, or the latched chord action, and nothing else
+    fn _update_repeat(&mut self, device_id: DId, raw_control: REvent::Control) -> usize {
+        let mut count: usize = 0;
+        for context_id in self._routed_contexts(&raw_control) {
+            let context = &self.contexts[context_id.index()];
+            let binding = context
+                .get_device_action(device_id, &raw_control)
+                .or(self.chord_latches.get(&(context_id, raw_control)))
+                .or(context.get_action(&raw_control))
+                .copied();
+            if let Some((action, mask)) = binding {
+                if self.input_events.push(
+                    &self.clock,
+                    device_id,
+                    context_id,
+                    (action, mask),
+                    InputEventKind::Repeat,
+                    InputValue::Digital(true),
+                ) {
+                    count += 1;
+                }
+            }
+        }
+        if !self.coalesce {
+            self._dispatch_input_events();
+        }
+        count
     }
 
-    pub fn get_nth_last_input_event(&self, offset: usize) -> Option<&InputEvent<DId, Action>> {
-        if (offset + 1) > self.input_events.len() {
-            return None;
+    // The contexts that see events for `control`, from the top of the stack
+    // down to the first one that consumes them
+    fn _routed_contexts(&self, control: &REvent::Control) -> Vec<ContextId> {
+        let mut routed = vec![];
+        for id in self.context_stack.iter().rev() {
+            routed.push(*id);
+            let context = self.context(*id);
+            match context.consume_policy() {
+                ConsumePolicy::All => break,
+                ConsumePolicy::Bound if context.is_bound(control) => break,
+                _ => {}
+            }
         }
-        Some(&self.input_events[self.input_events.len() - (offset + 1)])
+        routed
     }
 
-    pub fn flush_input_events(&mut self) {
-        self.input_events.clear();
+    // The topmost context that binds either control decides which one is
+    // used
+    fn _resolve_control(&self, raw_event: &REvent) -> REvent::Control {
+        let control = raw_event.get_control();
+        let alternate = raw_event.get_alternate_control();
+        for id in self.context_stack.iter().rev() {
+            let context = self.context(*id);
+            if context.is_bound(&control) {
+                return control;
+            }
+            match alternate {
+                Some(alternate) if context.is_bound(&alternate) => return alternate,
+                _ => {}
+            }
+            if context.consume_policy() == ConsumePolicy::All {
+                break;
+            }
+        }
+        control
     }
 
-    fn _push_input_event(
-        next_index: &mut u64,
-        input_events: &mut Vec<InputEvent<DId, Action>>,
-        created_at: Duration,
+    // Folds the events pushed by the current update, starting at `first`,
+    // into earlier events for the same key. Returns the number of events
+    // that were merged away
+    fn _coalesce_input_events(
+        &mut self,
+        first: usize,
         device_id: DId,
-        action: Action,
-        value: InputValue,
-        mask: &Option<BitFlags<InputKind>>,
-    ) -> bool {
-        if let Some(mask) = mask {
-            if !mask.intersects(value.kind()) {
-                return false;
+        control: REvent::Control,
+    ) -> usize {
+        let mut merged = 0;
+        let mut i = first;
+        while i < self.input_events.events.len() {
+            let event = self.input_events.events[i];
+            if event.kind != InputEventKind::Change || event.value.kind() == InputKind::Digital {
+                i += 1;
+                continue;
+            }
+            let key = (device_id, control, event.action);
+            match self.coalesce_slots.get(&key) {
+                Some(slot) => {
+                    let target = &mut self.input_events.events[*slot];
+                    target.value = match (target.value, event.value) {
+                        (InputValue::Analog(x0), InputValue::Analog(x1))
+                            if control.is_relative() =>
+                        {
+                            InputValue::Analog(x0 + x1)
+                        }
+                        (InputValue::Analog2d(x0, y0), InputValue::Analog2d(x1, y1))
+                            if control.is_relative() =>
+                        {
+                            InputValue::Analog2d(x0 + x1, y0 + y1)
+                        }
+                        (_, value) => value,
+                    };
+                    target.created_at = event.created_at;
+                    target.frame = event.frame;
+                    self.input_events.events.remove(i);
+                    merged += 1;
+                }
+                None => {
+                    self.coalesce_slots.insert(key, i);
+                    i += 1;
+                }
             }
         }
-        let index = *next_index;
-        *next_index += 1;
-        input_events.push(InputEvent {
-            index,
-            created_at,
-            device_id,
-            action,
-            value,
-        });
-        true
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct TestDevice(u32);
+
+    impl DeviceId for TestDevice {
+        type Kind = ();
+        fn kind(&self) {}
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum TestControl {
+        A,
+        B,
+        Stick,
+    }
+
+    impl Control for TestControl {
+        fn kind(&self) -> BitFlags<InputKind> {
+            match self {
+                TestControl::Stick => InputKind::Analog.into(),
+                _ => InputKind::Digital.into(),
+            }
+        }
+    }
+
+    struct TestEvent(TestControl, InputValue);
+
+    impl RawEvent<TestDevice> for TestEvent {
+        type Control = TestControl;
+
+        fn get_device_id(&self) -> TestDevice {
+            TestDevice(0)
+        }
+
+        fn get_control(&self) -> TestControl {
+            self.0
+        }
+
+        fn get_input_value(&self) -> InputValue {
+            self.1
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum TestAction {
+        Jump,
+        Fire,
+        Menu,
+    }
+
+    type TestManager = InputManager<TestDevice, TestEvent, TestAction>;
+
+    fn _manager() -> TestManager {
+        InputManager::new(InputClock::new(Instant::now()))
+    }
+
+    fn _press(manager: &mut TestManager, control: TestControl) -> usize {
+        manager.update(&TestEvent(control, InputValue::Digital(true)))
+    }
+
+    fn _release(manager: &mut TestManager, control: TestControl) -> usize {
+        manager.update(&TestEvent(control, InputValue::Digital(false)))
+    }
+
+    fn _events(manager: &TestManager) -> Vec<(TestAction, InputEventKind)> {
+        manager
+            .get_input_events()
+            .iter()
+            .map(|x| (x.action, x.kind))
+            .collect()
+    }
+
+    #[test]
+    fn capture_binds_the_next_actuated_input() {
+        let mut manager = _manager();
+        manager.begin_capture(TestAction::Jump, None);
+        assert_eq!(_press(&mut manager, TestControl::B), 0);
+        assert!(!manager.is_capturing());
+        assert!(_events(&manager).is_empty());
+        assert_eq!(
+            manager.get_control(&TestAction::Jump),
+            Some(&TestControl::B)
+        );
+
+        _release(&mut manager, TestControl::B);
+        manager.flush_input_events();
+        _press(&mut manager, TestControl::B);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Change)]
+        );
+    }
+
+    #[test]
+    fn capture_skips_analog_values_below_the_threshold() {
+        let mut manager = _manager();
+        manager.begin_capture(TestAction::Jump, None);
+        manager.update(&TestEvent(TestControl::Stick, InputValue::Analog(0.2)));
+        assert!(manager.is_capturing());
+        manager.update(&TestEvent(TestControl::Stick, InputValue::Analog(-0.8)));
+        assert!(!manager.is_capturing());
+        assert_eq!(
+            manager.get_control(&TestAction::Jump),
+            Some(&TestControl::Stick)
+        );
+    }
+
+    #[test]
+    fn capture_replaces_the_existing_binding() {
+        let mut manager = _manager();
+        manager.set_action(TestControl::A, TestAction::Jump, None);
+        manager.begin_capture(TestAction::Jump, None);
+        _press(&mut manager, TestControl::B);
+        assert!(manager.get_action(&TestControl::A).is_none());
+        assert_eq!(
+            manager.get_control(&TestAction::Jump),
+            Some(&TestControl::B)
+        );
+    }
+
+    #[test]
+    fn cancel_capture_keeps_the_bindings() {
+        let mut manager = _manager();
+        manager.set_action(TestControl::A, TestAction::Jump, None);
+        manager.begin_capture(TestAction::Jump, None);
+        assert_eq!(manager.cancel_capture(), Some(TestAction::Jump));
+        _press(&mut manager, TestControl::B);
+        assert_eq!(
+            manager.get_control(&TestAction::Jump),
+            Some(&TestControl::A)
+        );
+        assert!(manager.get_action(&TestControl::B).is_none());
+    }
+
+    #[test]
+    fn remapped_device_bindings_follow_the_new_id() {
+        let mut manager = _manager();
+        manager.set_device_action(TestDevice(1), TestControl::A, TestAction::Jump, None);
+        assert_eq!(_press(&mut manager, TestControl::A), 0);
+        _release(&mut manager, TestControl::A);
+
+        manager.remap_device(TestDevice(1), TestDevice(0));
+        assert!(manager
+            .active_context()
+            .get_device_action(TestDevice(1), &TestControl::A)
+            .is_none());
+        manager.flush_input_events();
+        assert_eq!(_press(&mut manager, TestControl::A), 1);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Change)]
+        );
+    }
+
+    #[test]
+    fn hold_fires_once_past_the_threshold() {
+        let mut manager = _manager();
+        manager.set_hold_action(TestControl::A, Duration::ZERO, None, TestAction::Jump);
+        _press(&mut manager, TestControl::A);
+        manager.flush_input_events();
+
+        assert_eq!(manager.update_holds(), 1);
+        assert_eq!(manager.update_holds(), 0);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Hold(0))]
+        );
+
+        _release(&mut manager, TestControl::A);
+        assert_eq!(manager.update_holds(), 0);
+    }
+
+    #[test]
+    fn hold_progress_follows_bindings_changed_while_held() {
+        let mut manager = _manager();
+        manager.set_hold_action(TestControl::A, Duration::ZERO, None, TestAction::Jump);
+        _press(&mut manager, TestControl::A);
+        manager.update_holds();
+        manager.flush_input_events();
+
+        // The new binding takes the old one's place in the list, but hasn't
+        // fired yet
+        manager.clear_action(&TestAction::Jump);
+        manager.set_hold_action(TestControl::A, Duration::ZERO, None, TestAction::Fire);
+        assert_eq!(manager.update_holds(), 1);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Fire, InputEventKind::Hold(0))]
+        );
+
+        // And one added after it doesn't shift it
+        manager.flush_input_events();
+        manager.set_hold_action(TestControl::A, Duration::ZERO, None, TestAction::Jump);
+        assert_eq!(manager.update_holds(), 1);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Hold(0))]
+        );
+    }
+
+    // A menu layer binding B to Menu, pushed over a base layer binding both
+    // A and B
+    fn _layered_manager(consume_policy: ConsumePolicy) -> TestManager {
+        let mut manager = _manager();
+        manager.set_action(TestControl::A, TestAction::Jump, None);
+        manager.set_action(TestControl::B, TestAction::Fire, None);
+        let menu = manager.add_context("menu");
+        manager.context_mut(menu).set_consume_policy(consume_policy);
+        manager
+            .context_mut(menu)
+            .set_action(TestControl::B, TestAction::Menu, None);
+        manager.push_context(menu);
+        manager
+    }
+
+    #[test]
+    fn layer_consuming_bound_controls_passes_others_down() {
+        let mut manager = _layered_manager(ConsumePolicy::Bound);
+        _press(&mut manager, TestControl::B);
+        _press(&mut manager, TestControl::A);
+        assert_eq!(
+            _events(&manager),
+            [
+                (TestAction::Menu, InputEventKind::Change),
+                (TestAction::Jump, InputEventKind::Change),
+            ]
+        );
+    }
+
+    #[test]
+    fn layer_consuming_all_controls_blocks_the_layers_below() {
+        let mut manager = _layered_manager(ConsumePolicy::All);
+        _press(&mut manager, TestControl::B);
+        _press(&mut manager, TestControl::A);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Menu, InputEventKind::Change)]
+        );
+    }
+
+    #[test]
+    fn layer_consuming_nothing_passes_everything_down() {
+        let mut manager = _layered_manager(ConsumePolicy::None);
+        _press(&mut manager, TestControl::B);
+        assert_eq!(
+            _events(&manager),
+            [
+                (TestAction::Menu, InputEventKind::Change),
+                (TestAction::Fire, InputEventKind::Change),
+            ]
+        );
+    }
+
+    #[test]
+    fn popping_a_layer_restores_the_one_below() {
+        let mut manager = _layered_manager(ConsumePolicy::All);
+        assert!(manager.pop_context().is_some());
+        assert!(manager.pop_context().is_none());
+        _press(&mut manager, TestControl::B);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Fire, InputEventKind::Change)]
+        );
+    }
+
+    fn _set_gesture(manager: &mut TestManager, double_tap_window: Duration) {
+        manager.active_context_mut().set_gesture_action(
+            TestControl::A,
+            Duration::from_secs(5),
+            double_tap_window,
+            Duration::from_secs(10),
+            TestAction::Jump,
+        );
+    }
+
+    #[test]
+    fn gesture_tap_is_immediate_without_a_double_tap_window() {
+        let mut manager = _manager();
+        _set_gesture(&mut manager, Duration::ZERO);
+        assert_eq!(_press(&mut manager, TestControl::A), 0);
+        assert_eq!(_release(&mut manager, TestControl::A), 1);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Gesture(Gesture::Tap))]
+        );
+    }
+
+    #[test]
+    fn gesture_double_tap_replaces_the_pending_tap() {
+        let mut manager = _manager();
+        _set_gesture(&mut manager, Duration::from_secs(5));
+        _press(&mut manager, TestControl::A);
+        assert_eq!(_release(&mut manager, TestControl::A), 0);
+        assert_eq!(manager.update_holds(), 0);
+
+        _press(&mut manager, TestControl::A);
+        assert_eq!(_release(&mut manager, TestControl::A), 1);
+        assert_eq!(manager.update_holds(), 0);
+        assert_eq!(
+            _events(&manager),
+            [(
+                TestAction::Jump,
+                InputEventKind::Gesture(Gesture::DoubleTap)
+            )]
+        );
+    }
+
+    #[test]
+    fn gesture_single_tap_fires_once_the_window_passes() {
+        let mut manager = _manager();
+        _set_gesture(&mut manager, Duration::from_millis(1));
+        _press(&mut manager, TestControl::A);
+        _release(&mut manager, TestControl::A);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(manager.update_holds(), 1);
+        assert_eq!(manager.update_holds(), 0);
+        assert_eq!(
+            _events(&manager),
+            [(TestAction::Jump, InputEventKind::Gesture(Gesture::Tap))]
+        );
+    }
+
+    #[test]
+    fn gesture_long_press_is_not_followed_by_a_tap() {
+        let mut manager = _manager();
+        manager.active_context_mut().set_gesture_action(
+            TestControl::A,
+            Duration::from_millis(1),
+            Duration::ZERO,
+            Duration::from_millis(2),
+            TestAction::Jump,
+        );
+        _press(&mut manager, TestControl::A);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(manager.update_holds(), 1);
+        assert_eq!(manager.update_holds(), 0);
+        assert_eq!(_release(&mut manager, TestControl::A), 0);
+        assert_eq!(
+            _events(&manager),
+            [(
+                TestAction::Jump,
+                InputEventKind::Gesture(Gesture::LongPress)
+            )]
+        );
+    }
+
+    #[test]
+    fn action_state_digital_press_and_release() {
+        let mut state = ActionState::default();
+        state._apply(InputValue::Digital(true), false);
+        assert!(state.is_pressed());
+        assert!(state.just_pressed());
+        assert_eq!(state.axis(), 1.0);
+
+        state._next_frame();
+        assert!(state.is_pressed());
+        assert!(!state.just_pressed());
+
+        state._apply(InputValue::Digital(false), false);
+        assert!(!state.is_pressed());
+        assert!(state.just_released());
+        assert_eq!(state.axis(), 0.0);
+    }
+
+    #[test]
+    fn action_state_press_and_release_within_a_frame() {
+        let mut state = ActionState::default();
+        state._apply(InputValue::Digital(true), false);
+        state._apply(InputValue::Digital(false), false);
+        assert!(!state.is_pressed());
+        assert!(state.just_pressed());
+        assert!(state.just_released());
+    }
+
+    #[test]
+    fn action_state_analog_threshold() {
+        let mut state = ActionState::default();
+        state._apply(InputValue::Analog(0.25), false);
+        assert!(!state.is_pressed());
+        state._apply(InputValue::Analog(-0.75), false);
+        assert!(state.is_pressed());
+        assert_eq!(state.axis(), -0.75);
+    }
+
+    #[test]
+    fn action_state_relative_values_reset_each_frame() {
+        let mut state = ActionState::default();
+        state._apply(InputValue::Analog2d(1.0, 2.0), true);
+        state._apply(InputValue::Analog2d(3.0, -1.0), true);
+        assert_eq!(state.axis2d(), (4.0, 1.0));
+
+        state._next_frame();
+        assert_eq!(state.axis2d(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn action_state_absolute_values_persist() {
+        let mut state = ActionState::default();
+        state._apply(InputValue::Analog2d(0.5, -0.5), false);
+        state._apply(InputValue::Analog2d(0.25, 0.0), false);
+        state._next_frame();
+        assert_eq!(state.axis2d(), (0.25, 0.0));
+    }
+
+    #[test]
+    fn action_states_are_sampled_on_flush() {
+        let mut manager = _manager();
+        manager.set_action(TestControl::A, TestAction::Jump, None);
+        _press(&mut manager, TestControl::A);
+        assert!(!manager.is_pressed(&TestAction::Jump));
+
+        manager.flush_input_events();
+        assert!(manager.is_pressed(&TestAction::Jump));
+        assert!(manager.just_pressed(&TestAction::Jump));
+
+        manager.flush_input_events();
+        assert!(manager.is_pressed(&TestAction::Jump));
+        assert!(!manager.just_pressed(&TestAction::Jump));
+
+        _release(&mut manager, TestControl::A);
+        manager.flush_input_events();
+        assert!(!manager.is_pressed(&TestAction::Jump));
+        assert!(manager.just_released(&TestAction::Jump));
+    }
+
+    #[test]
+    fn action_states_keep_presses_between_flushes() {
+        let mut manager = _manager();
+        manager.set_action(TestControl::A, TestAction::Jump, None);
+        manager.set_action(TestControl::Stick, TestAction::Fire, None);
+        _press(&mut manager, TestControl::A);
+        _release(&mut manager, TestControl::A);
+        manager.update(&TestEvent(TestControl::Stick, InputValue::Analog(0.75)));
+        manager.flush_input_events();
+
+        let jump = manager.action_state(&TestAction::Jump);
+        assert!(!jump.is_pressed());
+        assert!(jump.just_pressed());
+        assert!(jump.just_released());
+        assert!(manager.is_pressed(&TestAction::Fire));
+        assert_eq!(manager.axis(&TestAction::Fire), 0.75);
     }
 }
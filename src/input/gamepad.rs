@@ -1,6 +1,8 @@
-use super::{Control, InputKind, InputValue, RawDeviceId, RawEvent};
+use super::{AnalogFilter, Control, InputKind, InputValue, RawDeviceId, RawEvent, RawEventKind};
 use enumflags2::BitFlags;
 use gilrs::{Event, EventType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct RawGamepadEvent {
@@ -17,7 +19,7 @@ impl RawGamepadEvent {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GamepadControl {
     Connection,
     Button(gilrs::Button),
@@ -34,26 +36,37 @@ impl RawEvent<RawDeviceId> for RawGamepadEvent {
     fn get_control(&self) -> Self::Control {
         match self.event {
             EventType::ButtonPressed(button, _) => GamepadControl::Button(button),
-            EventType::ButtonRepeated(_, _) => todo!(),
+            EventType::ButtonRepeated(button, _) => GamepadControl::Button(button),
             EventType::ButtonReleased(button, _) => GamepadControl::Button(button),
             EventType::ButtonChanged(button, _, _) => GamepadControl::Button(button),
             EventType::AxisChanged(axis, _, _) => GamepadControl::Axis(axis),
             EventType::Connected => GamepadControl::Connection,
             EventType::Disconnected => GamepadControl::Connection,
-            EventType::Dropped => todo!(),
+            // Ignored, see `get_event_kind`
+            EventType::Dropped => GamepadControl::Connection,
         }
     }
 
     fn get_input_value(&self) -> InputValue {
         match self.event {
             EventType::ButtonPressed(_, _) => InputValue::Digital(true),
-            EventType::ButtonRepeated(_, _) => todo!(),
+            EventType::ButtonRepeated(_, _) => InputValue::Digital(true),
             EventType::ButtonReleased(_, _) => InputValue::Digital(false),
             EventType::ButtonChanged(_, value, _) => InputValue::Analog(f64::from(value)),
             EventType::AxisChanged(_, value, _) => InputValue::Analog(f64::from(value)),
             EventType::Connected => InputValue::Digital(true),
             EventType::Disconnected => InputValue::Digital(false),
-            EventType::Dropped => todo!(),
+            EventType::Dropped => InputValue::Digital(false),
+        }
+    }
+
+    // gilrs reports events that one of its filters discarded as `Dropped`,
+    // with the original event gone
+    fn get_event_kind(&self) -> RawEventKind {
+        match self.event {
+            EventType::ButtonRepeated(_, _) => RawEventKind::Repeat,
+            EventType::Dropped => RawEventKind::Ignored,
+            _ => RawEventKind::Change,
         }
     }
 }
@@ -67,3 +80,94 @@ impl Control for GamepadControl {
         }
     }
 }
+
+// Applies per-control dead zones, saturation and response curves to analog
+// gamepad values before they reach an `InputManager`
+pub struct GamepadAnalogProcessor {
+    filters: HashMap<GamepadControl, AnalogFilter>,
+    radial_pairs: HashMap<gilrs::Axis, gilrs::Axis>,
+    axis_values: HashMap<(gilrs::GamepadId, gilrs::Axis), f32>,
+}
+
+impl GamepadAnalogProcessor {
+    pub fn new() -> Self {
+        Self {
+            filters: HashMap::new(),
+            radial_pairs: HashMap::new(),
+            axis_values: HashMap::new(),
+        }
+    }
+
+    pub fn set_filter(&mut self, control: GamepadControl, filter: AnalogFilter) {
+        if let GamepadControl::Axis(axis) = control {
+            if let Some(partner) = self.radial_pairs.remove(&axis) {
+                self.radial_pairs.remove(&partner);
+            }
+        }
+        self.filters.insert(control, filter);
+    }
+
+    // Filters two axes (usually the X and Y axes of a stick) as a single
+    // vector so the dead zone is circular
+    pub fn set_radial_filter(&mut self, x: gilrs::Axis, y: gilrs::Axis, filter: AnalogFilter) {
+        self.filters.insert(GamepadControl::Axis(x), filter);
+        self.filters.insert(GamepadControl::Axis(y), filter);
+        self.radial_pairs.insert(x, y);
+        self.radial_pairs.insert(y, x);
+    }
+
+    pub fn clear_filter(&mut self, control: &GamepadControl) {
+        if let GamepadControl::Axis(axis) = control {
+            if let Some(partner) = self.radial_pairs.remove(axis) {
+                self.radial_pairs.remove(&partner);
+                self.filters.remove(&GamepadControl::Axis(partner));
+            }
+        }
+        self.filters.remove(control);
+    }
+
+    pub fn get_filter(&self, control: &GamepadControl) -> Option<&AnalogFilter> {
+        self.filters.get(control)
+    }
+
+    pub fn process(&mut self, mut raw_event: RawGamepadEvent) -> RawGamepadEvent {
+        let device_id = raw_event.device_id;
+        match &mut raw_event.event {
+            EventType::AxisChanged(axis, value, _) => {
+                self.axis_values.insert((device_id, *axis), *value);
+
+                if let Some(filter) = self.filters.get(&GamepadControl::Axis(*axis)) {
+                    let x = f64::from(*value);
+                    let filtered = match self.radial_pairs.get(axis) {
+                        Some(partner) => {
+                            let y = self
+                                .axis_values
+                                .get(&(device_id, *partner))
+                                .map(|y| f64::from(*y))
+                                .unwrap_or(0.0);
+                            filter.apply_radial(x, y).0
+                        }
+                        None => filter.apply(x),
+                    };
+                    *value = filtered as f32;
+                }
+            }
+            EventType::ButtonChanged(button, value, _) => {
+                if let Some(filter) = self.filters.get(&GamepadControl::Button(*button)) {
+                    *value = filter.apply(f64::from(*value)) as f32;
+                }
+            }
+            EventType::Disconnected => {
+                self.axis_values.retain(|(id, _), _| *id != device_id);
+            }
+            _ => {}
+        }
+        raw_event
+    }
+}
+
+impl Default for GamepadAnalogProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,205 @@
+use super::RawGamepadEvent;
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisCalibration {
+    pub axis: Axis,
+    #[serde(default)]
+    pub invert: bool,
+    // Subtracted from the raw value to correct sticks that don't rest at 0
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default = "_default_scale")]
+    pub scale: f32,
+}
+
+fn _default_scale() -> f32 {
+    1.0
+}
+
+impl AxisCalibration {
+    pub fn new(axis: Axis) -> Self {
+        Self {
+            axis,
+            invert: false,
+            offset: 0.0,
+            scale: 1.0,
+        }
+    }
+
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = ((value - self.offset) * self.scale).clamp(-1.0, 1.0);
+        if self.invert {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ButtonRemap {
+    pub from: Button,
+    pub to: Button,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GamepadConfig {
+    #[serde(default)]
+    pub axes: Vec<AxisCalibration>,
+    #[serde(default)]
+    pub buttons: Vec<ButtonRemap>,
+}
+
+impl GamepadConfig {
+    pub fn calibration(&self, axis: Axis) -> Option<&AxisCalibration> {
+        self.axes.iter().find(|x| x.axis == axis)
+    }
+
+    pub fn calibration_mut(&mut self, axis: Axis) -> &mut AxisCalibration {
+        let index = match self.axes.iter().position(|x| x.axis == axis) {
+            Some(index) => index,
+            None => {
+                self.axes.push(AxisCalibration::new(axis));
+                self.axes.len() - 1
+            }
+        };
+        &mut self.axes[index]
+    }
+
+    pub fn set_inverted(&mut self, axis: Axis, invert: bool) {
+        self.calibration_mut(axis).invert = invert;
+    }
+
+    pub fn set_remap(&mut self, from: Button, to: Button) {
+        self.buttons.retain(|x| x.from != from);
+        if from != to {
+            self.buttons.push(ButtonRemap { from, to });
+        }
+    }
+
+    pub fn remap(&self, button: Button) -> Button {
+        self.buttons
+            .iter()
+            .find(|x| x.from == button)
+            .map_or(button, |x| x.to)
+    }
+}
+
+// Per-gamepad configuration keyed by the gamepad's UUID, with a fallback used
+// for gamepads that don't have their own entry
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GamepadConfigs {
+    #[serde(default)]
+    pub default: GamepadConfig,
+    #[serde(default)]
+    pub gamepads: HashMap<String, GamepadConfig>,
+}
+
+impl GamepadConfigs {
+    // A missing file isn't an error, it just means nothing has been
+    // configured yet. A file that can't be read or parsed is skipped with a
+    // warning, so a bad edit doesn't keep the app from starting
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(error) => {
+                warn!(
+                    "failed to read gamepad config {}: {}",
+                    path.display(),
+                    error
+                );
+                return Self::default();
+            }
+        };
+        toml::from_str(&text).unwrap_or_else(|error| {
+            warn!(
+                "failed to parse gamepad config {}: {}",
+                path.display(),
+                error
+            );
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let text = toml::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, text)
+    }
+
+    pub fn get(&self, uuid: &[u8; 16]) -> &GamepadConfig {
+        self.gamepads
+            .get(&Self::_uuid_key(uuid))
+            .unwrap_or(&self.default)
+    }
+
+    pub fn get_mut(&mut self, uuid: &[u8; 16]) -> &mut GamepadConfig {
+        let default = self.default.clone();
+        self.gamepads
+            .entry(Self::_uuid_key(uuid))
+            .or_insert(default)
+    }
+
+    fn _uuid_key(uuid: &[u8; 16]) -> String {
+        uuid.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+}
+
+// Applies calibration, axis inversion and button remaps from the config to
+// raw gamepad events, before analog filtering and action mapping
+pub struct GamepadConfigProcessor {
+    configs: GamepadConfigs,
+    uuids: HashMap<GamepadId, [u8; 16]>,
+}
+
+impl GamepadConfigProcessor {
+    pub fn new(configs: GamepadConfigs) -> Self {
+        Self {
+            configs,
+            uuids: HashMap::new(),
+        }
+    }
+
+    pub fn configs(&self) -> &GamepadConfigs {
+        &self.configs
+    }
+
+    pub fn configs_mut(&mut self) -> &mut GamepadConfigs {
+        &mut self.configs
+    }
+
+    pub fn process(&mut self, gilrs: &Gilrs, mut raw_event: RawGamepadEvent) -> RawGamepadEvent {
+        let device_id = raw_event.device_id;
+        let uuid = *self
+            .uuids
+            .entry(device_id)
+            .or_insert_with(|| gilrs.gamepad(device_id).uuid());
+        let config = self.configs.get(&uuid);
+
+        match &mut raw_event.event {
+            EventType::AxisChanged(axis, value, _) => {
+                if let Some(calibration) = config.calibration(*axis) {
+                    *value = calibration.apply(*value);
+                }
+            }
+            EventType::ButtonPressed(button, _)
+            | EventType::ButtonRepeated(button, _)
+            | EventType::ButtonReleased(button, _)
+            | EventType::ButtonChanged(button, _, _) => {
+                *button = config.remap(*button);
+            }
+            // Ids can be reused by a different gamepad after a disconnect
+            EventType::Disconnected => {
+                self.uuids.remove(&device_id);
+            }
+            _ => {}
+        }
+        raw_event
+    }
+}
@@ -0,0 +1,204 @@
+use super::RawGamepadEvent;
+use gilrs::{EventType, GamepadId, Gilrs, PowerInfo};
+
+const LOW_BATTERY_PERCENT: u8 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerSlot(usize);
+
+impl PlayerSlot {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadConnectionEvent {
+    // `previous_device_id` is set when the gamepad got back the slot it had
+    // before it was disconnected, to the id it had then. gilrs may reuse it
+    // or not, so bindings to the old id can be moved over
+    Connected {
+        player: PlayerSlot,
+        device_id: GamepadId,
+        previous_device_id: Option<GamepadId>,
+    },
+    Disconnected {
+        player: PlayerSlot,
+        device_id: GamepadId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamepadStatus {
+    pub device_id: GamepadId,
+    pub name: String,
+    pub power: PowerInfo,
+    pub connected: bool,
+}
+
+impl GamepadStatus {
+    pub fn query(gilrs: &Gilrs, device_id: GamepadId) -> Option<Self> {
+        let gamepad = gilrs.connected_gamepad(device_id)?;
+        Some(Self {
+            device_id,
+            name: String::from(gamepad.name()),
+            power: gamepad.power_info(),
+            connected: gamepad.is_connected(),
+        })
+    }
+
+    // Battery charge in percent, if the gamepad is running on battery
+    pub fn battery(&self) -> Option<u8> {
+        match self.power {
+            PowerInfo::Discharging(x) | PowerInfo::Charging(x) => Some(x),
+            PowerInfo::Charged => Some(100),
+            PowerInfo::Unknown | PowerInfo::Wired => None,
+        }
+    }
+
+    pub fn is_low_battery(&self) -> bool {
+        matches!(self.power, PowerInfo::Discharging(x) if x <= LOW_BATTERY_PERCENT)
+    }
+}
+
+struct SlotState {
+    uuid: [u8; 16],
+    device_id: Option<GamepadId>,
+    last_device_id: GamepadId,
+}
+
+// Tracks connected gamepads and assigns each one a player slot. Slots are
+// remembered by gamepad UUID after a disconnect, so a controller that drops
+// out and comes back is given the same player
+pub struct GamepadRegistry {
+    slots: Vec<Option<SlotState>>,
+    events: Vec<GamepadConnectionEvent>,
+}
+
+impl GamepadRegistry {
+    pub fn new(max_players: usize) -> Self {
+        assert!(max_players > 0);
+        Self {
+            slots: (0..max_players).map(|_| None).collect(),
+            events: vec![],
+        }
+    }
+
+    // gilrs doesn't report `Connected` for gamepads that were already plugged
+    // in when it was created, so register those up front
+    pub fn scan(&mut self, gilrs: &Gilrs) {
+        for (device_id, _) in gilrs.gamepads() {
+            self._connect(gilrs, device_id);
+        }
+    }
+
+    pub fn process(&mut self, gilrs: &Gilrs, raw_event: &RawGamepadEvent) {
+        match raw_event.event {
+            EventType::Connected => self._connect(gilrs, raw_event.device_id),
+            EventType::Disconnected => self._disconnect(raw_event.device_id),
+            _ => {}
+        }
+    }
+
+    pub fn max_players(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn player(&self, device_id: GamepadId) -> Option<PlayerSlot> {
+        self.slots
+            .iter()
+            .position(|x| x.as_ref().is_some_and(|x| x.device_id == Some(device_id)))
+            .map(PlayerSlot)
+    }
+
+    pub fn gamepad(&self, player: PlayerSlot) -> Option<GamepadId> {
+        self.slots
+            .get(player.0)
+            .and_then(|x| x.as_ref())
+            .and_then(|x| x.device_id)
+    }
+
+    pub fn is_connected(&self, player: PlayerSlot) -> bool {
+        self.gamepad(player).is_some()
+    }
+
+    pub fn status(&self, gilrs: &Gilrs, player: PlayerSlot) -> Option<GamepadStatus> {
+        GamepadStatus::query(gilrs, self.gamepad(player)?)
+    }
+
+    pub fn connected(&self) -> impl Iterator<Item = (PlayerSlot, GamepadId)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(i, x)| {
+            x.as_ref()
+                .and_then(|x| x.device_id)
+                .map(|device_id| (PlayerSlot(i), device_id))
+        })
+    }
+
+    // Frees a slot, forgetting the gamepad that was assigned to it
+    pub fn release(&mut self, player: PlayerSlot) {
+        if let Some(slot) = self.slots.get_mut(player.0) {
+            if let Some(device_id) = slot.take().and_then(|x| x.device_id) {
+                self.events
+                    .push(GamepadConnectionEvent::Disconnected { player, device_id });
+            }
+        }
+    }
+
+    pub fn events(&self) -> &[GamepadConnectionEvent] {
+        &self.events
+    }
+
+    pub fn flush_events(&mut self) {
+        self.events.clear();
+    }
+
+    fn _connect(&mut self, gilrs: &Gilrs, device_id: GamepadId) {
+        if self.player(device_id).is_some() {
+            return;
+        }
+        let uuid = gilrs.gamepad(device_id).uuid();
+
+        // Prefer the slot this gamepad had before, then an empty slot, and
+        // finally a slot remembered for a gamepad that is still disconnected
+        let remembered = self.slots.iter().position(|x| {
+            x.as_ref()
+                .is_some_and(|x| x.device_id.is_none() && x.uuid == uuid)
+        });
+        let (index, previous_device_id) = match remembered {
+            Some(index) => (index, self.slots[index].as_ref().map(|x| x.last_device_id)),
+            None => {
+                let free = self.slots.iter().position(|x| x.is_none()).or_else(|| {
+                    self.slots
+                        .iter()
+                        .position(|x| x.as_ref().is_some_and(|x| x.device_id.is_none()))
+                });
+                match free {
+                    Some(index) => (index, None),
+                    // More gamepads than players, leave it unassigned
+                    None => return,
+                }
+            }
+        };
+
+        self.slots[index] = Some(SlotState {
+            uuid,
+            device_id: Some(device_id),
+            last_device_id: device_id,
+        });
+        self.events.push(GamepadConnectionEvent::Connected {
+            player: PlayerSlot(index),
+            device_id,
+            previous_device_id,
+        });
+    }
+
+    fn _disconnect(&mut self, device_id: GamepadId) {
+        if let Some(player) = self.player(device_id) {
+            if let Some(slot) = &mut self.slots[player.0] {
+                slot.device_id = None;
+            }
+            self.events
+                .push(GamepadConnectionEvent::Disconnected { player, device_id });
+        }
+    }
+}
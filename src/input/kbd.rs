@@ -1,5 +1,8 @@
-use super::{Control, InputKind, InputValue, RawDeviceId, RawEvent};
+use super::{Control, InputKind, InputValue, RawDeviceId, RawEvent, RawEventKind};
 use enumflags2::BitFlags;
+use serde::{Deserialize, Serialize};
+use winit::keyboard::{Key, KeyCode, NamedKey, PhysicalKey};
+use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
 
 #[derive(Debug)]
 pub struct RawKeyboardEvent {
@@ -7,23 +10,86 @@ pub struct RawKeyboardEvent {
     pub event: winit::event::KeyEvent,
 }
 
+// Physical keys are bound by position and ignore the keyboard layout, which
+// suits movement keys like WASD. Logical keys follow the layout, which suits
+// mnemonic shortcuts like Z for undo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyboardControl {
+    Physical(PhysicalKey),
+    Logical(LogicalKey),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LogicalKey {
+    Named(NamedKey),
+    // Always lowercase, so bindings don't depend on Shift or Caps Lock
+    Character(char),
+}
+
+impl LogicalKey {
+    pub fn from_winit(key: &Key) -> Option<Self> {
+        match key {
+            Key::Named(key) => Some(LogicalKey::Named(*key)),
+            Key::Character(text) => {
+                let mut chars = text.chars().flat_map(char::to_lowercase);
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(LogicalKey::Character(c)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<PhysicalKey> for KeyboardControl {
+    fn from(key: PhysicalKey) -> Self {
+        KeyboardControl::Physical(key)
+    }
+}
+
+impl From<KeyCode> for KeyboardControl {
+    fn from(key: KeyCode) -> Self {
+        KeyboardControl::Physical(PhysicalKey::Code(key))
+    }
+}
+
+impl From<LogicalKey> for KeyboardControl {
+    fn from(key: LogicalKey) -> Self {
+        KeyboardControl::Logical(key)
+    }
+}
+
 impl RawEvent<RawDeviceId> for RawKeyboardEvent {
-    type Control = winit::keyboard::PhysicalKey;
+    type Control = KeyboardControl;
 
     fn get_device_id(&self) -> RawDeviceId {
         RawDeviceId::Keyboard(self.device_id)
     }
 
     fn get_control(&self) -> Self::Control {
-        self.event.physical_key
+        KeyboardControl::Physical(self.event.physical_key)
+    }
+
+    // Used when the physical key isn't bound in the active context
+    fn get_alternate_control(&self) -> Option<Self::Control> {
+        LogicalKey::from_winit(&self.event.key_without_modifiers()).map(KeyboardControl::Logical)
     }
 
     fn get_input_value(&self) -> InputValue {
         InputValue::Digital(self.event.state.is_pressed())
     }
+
+    fn get_event_kind(&self) -> RawEventKind {
+        if self.event.repeat {
+            RawEventKind::Repeat
+        } else {
+            RawEventKind::Change
+        }
+    }
 }
 
-impl Control for winit::keyboard::PhysicalKey {
+impl Control for KeyboardControl {
     fn kind(&self) -> BitFlags<InputKind> {
         InputKind::Digital.into()
     }
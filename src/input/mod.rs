@@ -1,11 +1,33 @@
+mod analog;
+mod bindings;
+mod chord;
+mod clock;
+mod context;
+mod cursor;
 mod device_id;
 mod event;
 mod gamepad;
+mod gamepad_config;
+mod gamepad_registry;
 mod kbd;
 mod mouse;
+mod router;
+mod text;
+mod unified;
 
+pub use analog::*;
+pub use bindings::*;
+pub use chord::*;
+pub use clock::*;
+pub use context::*;
+pub use cursor::*;
 pub use device_id::*;
 pub use event::*;
 pub use gamepad::*;
+pub use gamepad_config::*;
+pub use gamepad_registry::*;
 pub use kbd::*;
 pub use mouse::*;
+pub use router::*;
+pub use text::*;
+pub use unified::*;
@@ -1,7 +1,10 @@
 use super::{Control, InputKind, InputValue, RawDeviceId, RawEvent};
 use enumflags2::BitFlags;
+use serde::{Deserialize, Serialize};
 use winit::dpi::PhysicalPosition;
-use winit::event::{DeviceId, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event::{
+    DeviceEvent, DeviceId, ElementState, MouseButton, MouseScrollDelta, WindowEvent,
+};
 
 #[derive(Debug)]
 pub struct RawMouseEvent {
@@ -14,15 +17,19 @@ pub enum RawMouseEventData {
     Button(MouseButton, ElementState),
     Wheel(MouseScrollDelta),
     Move(PhysicalPosition<f64>),
+    // Raw relative motion reported by the device. Unlike `Move` it isn't
+    // clipped to the window and keeps arriving while the cursor is grabbed
+    Delta(f64, f64),
     Entered,
     Left,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MouseControl {
     Button(MouseButton),
     Wheel,
     Cursor,
+    Delta,
 }
 
 impl RawMouseEvent {
@@ -60,6 +67,16 @@ impl RawMouseEvent {
             _ => panic!(),
         }
     }
+
+    pub fn from_device_event(device_id: DeviceId, event: DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta: (x, y) } => Some(RawMouseEvent {
+                device_id,
+                data: RawMouseEventData::Delta(x, y),
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl RawEvent<RawDeviceId> for RawMouseEvent {
@@ -74,6 +91,7 @@ impl RawEvent<RawDeviceId> for RawMouseEvent {
             RawMouseEventData::Button(button, _) => MouseControl::Button(*button),
             RawMouseEventData::Wheel { .. } => MouseControl::Wheel,
             RawMouseEventData::Move { .. } => MouseControl::Cursor,
+            RawMouseEventData::Delta { .. } => MouseControl::Delta,
             RawMouseEventData::Entered => MouseControl::Cursor,
             RawMouseEventData::Left => MouseControl::Cursor,
         }
@@ -91,6 +109,7 @@ impl RawEvent<RawDeviceId> for RawMouseEvent {
                 }
             },
             RawMouseEventData::Move(position) => InputValue::Analog2d(position.x, position.y),
+            RawMouseEventData::Delta(x, y) => InputValue::Analog2d(*x, *y),
             RawMouseEventData::Entered => InputValue::Digital(true),
             RawMouseEventData::Left => InputValue::Digital(false),
         }
@@ -103,6 +122,11 @@ impl Control for MouseControl {
             MouseControl::Button(_) => InputKind::Digital.into(),
             MouseControl::Wheel => InputKind::Analog2d.into(),
             MouseControl::Cursor => InputKind::Analog2d.into(),
+            MouseControl::Delta => InputKind::Analog2d.into(),
         }
     }
+
+    fn is_relative(&self) -> bool {
+        matches!(self, MouseControl::Wheel | MouseControl::Delta)
+    }
 }
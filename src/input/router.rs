@@ -0,0 +1,294 @@
+use super::{
+    ActionState, ConsumePolicy, ContextId, CursorController, CursorMode, GamepadAnalogProcessor,
+    GamepadConfigProcessor, GamepadConfigs, GamepadConnectionEvent, GamepadRegistry, InputClock,
+    InputContext, InputControl, InputEvent, InputValue, Modifier, PlayerSlot, RawDeviceId,
+    RawGamepadEvent, RawKeyboardEvent, RawMouseEvent, UnifiedInputManager,
+};
+use gilrs::{GamepadId, Gilrs};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
+use winit::window::Window;
+
+// Single entry point for every input device. Owns the unified manager and the
+// gamepad processing in front of it, so the app only hands over winit and
+// gilrs events and reads actions back out
+pub struct InputRouter<Action>
+where
+    Action: Copy + Clone + Eq + Hash,
+{
+    manager: UnifiedInputManager<Action>,
+    gilrs: Gilrs,
+    gamepad_config: GamepadConfigProcessor,
+    gamepad_analog: GamepadAnalogProcessor,
+    gamepad_registry: GamepadRegistry,
+    // Created for the window passed to `set_cursor_captured`
+    cursor: Option<CursorController>,
+    // The previous frame's events from every device, ordered by creation time
+    events: Vec<InputEvent<RawDeviceId, Action>>,
+    // The previous frame's gamepad connects and disconnects
+    connection_events: Vec<GamepadConnectionEvent>,
+    // Last value reported for each action. Kept across frames
+    action_values: HashMap<Action, InputValue>,
+}
+
+impl<Action> InputRouter<Action>
+where
+    Action: Copy + Clone + Eq + Hash,
+{
+    pub fn new(
+        clock: InputClock,
+        gilrs: Gilrs,
+        gamepad_configs: GamepadConfigs,
+        max_players: usize,
+    ) -> Self {
+        let mut gamepad_registry = GamepadRegistry::new(max_players);
+        gamepad_registry.scan(&gilrs);
+
+        Self {
+            manager: UnifiedInputManager::new(clock),
+            gilrs,
+            gamepad_config: GamepadConfigProcessor::new(gamepad_configs),
+            gamepad_analog: GamepadAnalogProcessor::new(),
+            gamepad_registry,
+            cursor: None,
+            events: vec![],
+            connection_events: vec![],
+            action_values: HashMap::new(),
+        }
+    }
+
+    pub fn manager(&self) -> &UnifiedInputManager<Action> {
+        &self.manager
+    }
+
+    pub fn manager_mut(&mut self) -> &mut UnifiedInputManager<Action> {
+        &mut self.manager
+    }
+
+    // Pushes the named context on top of the stack, creating it if needed,
+    // so its bindings see events first. `consume_policy` decides what the
+    // layers below still see
+    pub fn push_layer(&mut self, name: &str, consume_policy: ConsumePolicy) -> ContextId {
+        let id = self.manager.add_context(name);
+        self.manager
+            .context_mut(id)
+            .set_consume_policy(consume_policy);
+        self.manager.push_context(id);
+        id
+    }
+
+    // None if only the bottom layer is left
+    pub fn pop_layer(&mut self) -> Option<ContextId> {
+        self.manager.pop_context()
+    }
+
+    pub fn layer_mut(
+        &mut self,
+        id: ContextId,
+    ) -> &mut InputContext<RawDeviceId, InputControl, Action> {
+        self.manager.context_mut(id)
+    }
+
+    pub fn gilrs(&self) -> &Gilrs {
+        &self.gilrs
+    }
+
+    pub fn gamepad_config(&self) -> &GamepadConfigProcessor {
+        &self.gamepad_config
+    }
+
+    pub fn gamepad_config_mut(&mut self) -> &mut GamepadConfigProcessor {
+        &mut self.gamepad_config
+    }
+
+    pub fn gamepad_analog_mut(&mut self) -> &mut GamepadAnalogProcessor {
+        &mut self.gamepad_analog
+    }
+
+    pub fn gamepad_registry(&self) -> &GamepadRegistry {
+        &self.gamepad_registry
+    }
+
+    pub fn gamepad_registry_mut(&mut self) -> &mut GamepadRegistry {
+        &mut self.gamepad_registry
+    }
+
+    pub fn is_cursor_captured(&self) -> bool {
+        self.cursor.as_ref().is_some_and(|x| x.is_grabbed())
+    }
+
+    // Captured, the cursor is locked and hidden and mouse motion is read in
+    // relative mode from `MouseControl::Delta`, fed by raw device events.
+    // Released, the cursor moves freely and only its absolute position is
+    // reported. Returns the mode that was applied, which is Free if the
+    // platform can't grab the cursor
+    pub fn set_cursor_captured(&mut self, window: &Arc<Window>, captured: bool) -> CursorMode {
+        let cursor = self
+            .cursor
+            .get_or_insert_with(|| CursorController::new(window.clone()));
+        if captured {
+            cursor.grab()
+        } else {
+            cursor.release();
+            CursorMode::Free
+        }
+    }
+
+    // Releases a captured cursor on Escape or focus loss. Returns true if the
+    // event released it, in which case the app should treat it as consumed
+    pub fn handle_cursor_event(&mut self, event: &WindowEvent) -> bool {
+        self.cursor
+            .as_mut()
+            .is_some_and(|x| x.handle_window_event(event))
+    }
+
+    // Returns false for events that aren't input, e.g. resizes, so the
+    // caller can handle them itself
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            // A locked cursor's position doesn't mean anything
+            WindowEvent::CursorMoved { .. } if self.is_cursor_captured() => {}
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.manager
+                    .set_modifiers(Modifier::from_winit(modifiers.state()));
+            }
+            WindowEvent::KeyboardInput {
+                device_id, event, ..
+            } => {
+                let raw = RawKeyboardEvent {
+                    device_id: *device_id,
+                    event: event.clone(),
+                };
+                self.manager.update(&raw.into());
+            }
+            WindowEvent::MouseInput { .. }
+            | WindowEvent::MouseWheel { .. }
+            | WindowEvent::CursorMoved { .. } => {
+                let raw = RawMouseEvent::from_window_event(event.clone());
+                self.manager.update(&raw.into());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Raw motion arrives whether or not the cursor is captured, so it's only
+    // passed on in relative mode
+    pub fn handle_device_event(&mut self, device_id: DeviceId, event: DeviceEvent) {
+        if !self.is_cursor_captured() {
+            return;
+        }
+        if let Some(raw) = RawMouseEvent::from_device_event(device_id, event) {
+            self.manager.update(&raw.into());
+        }
+    }
+
+    // Remaps, calibrates and filters the event before the manager sees it
+    pub fn handle_gamepad_event(&mut self, event: gilrs::Event) {
+        let raw = RawGamepadEvent::from_gilrs_event(event);
+        let raw = self
+            .gamepad_analog
+            .process(self.gamepad_config.process(&self.gilrs, raw));
+        let connection_count = self.gamepad_registry.events().len();
+        self.gamepad_registry.process(&self.gilrs, &raw);
+
+        // A gamepad that got its player slot back keeps the bindings that
+        // were scoped to it, even if gilrs gave it a new id
+        for event in &self.gamepad_registry.events()[connection_count..] {
+            if let GamepadConnectionEvent::Connected {
+                device_id,
+                previous_device_id: Some(previous_device_id),
+                ..
+            } = *event
+            {
+                self.manager.remap_device(
+                    RawDeviceId::Gamepad(previous_device_id),
+                    RawDeviceId::Gamepad(device_id),
+                );
+            }
+        }
+
+        self.manager.update(&raw.into());
+    }
+
+    // gilrs has no callback, so this needs to be called regularly, e.g. once
+    // per frame
+    pub fn poll_gamepads(&mut self) {
+        while let Some(event) = self.gilrs.next_event() {
+            self.handle_gamepad_event(event);
+        }
+    }
+
+    // Polls gamepads and holds, then moves the frame's events into the merged
+    // queue and flushes the manager. Gamepads are polled once per frame while
+    // window events arrive as they happen, so events are sorted by their
+    // creation time rather than the order the manager saw them in
+    pub fn end_frame(&mut self) {
+        self.poll_gamepads();
+        self.manager.update_holds();
+
+        self.events.clear();
+        self.events
+            .extend_from_slice(self.manager.get_input_events());
+        self.events.sort_by_key(|x| (x.created_at, x.index));
+        for event in &self.events {
+            self.action_values.insert(event.action, event.value);
+        }
+
+        self.manager.flush_input_events();
+
+        self.connection_events.clear();
+        self.connection_events
+            .extend_from_slice(self.gamepad_registry.events());
+        self.gamepad_registry.flush_events();
+    }
+
+    pub fn events(&self) -> &[InputEvent<RawDeviceId, Action>] {
+        &self.events
+    }
+
+    pub fn events_for(
+        &self,
+        action: Action,
+    ) -> impl Iterator<Item = &InputEvent<RawDeviceId, Action>> + '_ {
+        self.events.iter().filter(move |x| x.action == action)
+    }
+
+    pub fn connection_events(&self) -> &[GamepadConnectionEvent] {
+        &self.connection_events
+    }
+
+    pub fn gamepad(&self, player: PlayerSlot) -> Option<GamepadId> {
+        self.gamepad_registry.gamepad(player)
+    }
+
+    // The player whose gamepad sent an event. None for keyboard and mouse
+    // events and for gamepads without a slot
+    pub fn player(&self, device_id: RawDeviceId) -> Option<PlayerSlot> {
+        match device_id {
+            RawDeviceId::Gamepad(device_id) => self.gamepad_registry.player(device_id),
+            _ => None,
+        }
+    }
+
+    pub fn events_for_player(
+        &self,
+        player: PlayerSlot,
+    ) -> impl Iterator<Item = &InputEvent<RawDeviceId, Action>> + '_ {
+        self.events
+            .iter()
+            .filter(move |x| self.player(x.device_id) == Some(player))
+    }
+
+    // Sampled at the end of the previous frame, see `InputManager::action_state`
+    pub fn action_state(&self, action: &Action) -> ActionState {
+        self.manager.action_state(action)
+    }
+
+    // None if the action hasn't been reported since the router was created
+    pub fn action_value(&self, action: &Action) -> Option<InputValue> {
+        self.action_values.get(action).copied()
+    }
+}
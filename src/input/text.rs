@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use winit::event::{ElementState, Ime, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::Window;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEvent {
+    // Committed text, either typed directly or produced by the IME
+    Text(String),
+    // In-progress IME composition. An empty string clears it. The cursor is a
+    // byte range into the text
+    Preedit {
+        text: String,
+        cursor: Option<(usize, usize)>,
+    },
+    // Editing keys that don't produce text, e.g. backspace or the arrow keys
+    Edit(NamedKey),
+}
+
+// Collects text typed while text entry is active. Keyboard events are
+// consumed while active so they don't also trigger actions
+pub struct TextInput {
+    window: Arc<Window>,
+    active: bool,
+    preedit: String,
+    text: String,
+    events: Vec<TextEvent>,
+}
+
+impl TextInput {
+    pub fn new(window: Arc<Window>) -> Self {
+        Self {
+            window,
+            active: false,
+            preedit: String::new(),
+            text: String::new(),
+            events: vec![],
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn begin_text_entry(&mut self) {
+        if !self.active {
+            self.window.set_ime_allowed(true);
+            self.active = true;
+        }
+    }
+
+    pub fn end_text_entry(&mut self) {
+        if self.active {
+            self.window.set_ime_allowed(false);
+            self.active = false;
+            if !self.preedit.is_empty() {
+                self.preedit.clear();
+                self.events.push(TextEvent::Preedit {
+                    text: String::new(),
+                    cursor: None,
+                });
+            }
+        }
+    }
+
+    // Returns true if the event was consumed as text input
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.active {
+            return false;
+        }
+        match event {
+            WindowEvent::Ime(Ime::Commit(text)) => {
+                self.preedit.clear();
+                self._push_text(text);
+                true
+            }
+            WindowEvent::Ime(Ime::Preedit(text, cursor)) => {
+                self.preedit.clone_from(text);
+                self.events.push(TextEvent::Preedit {
+                    text: text.clone(),
+                    cursor: *cursor,
+                });
+                true
+            }
+            WindowEvent::Ime(_) => true,
+            WindowEvent::KeyboardInput { event, .. } => {
+                // While composing, the IME owns the keyboard and reports the
+                // result through `Ime::Commit`
+                if event.state == ElementState::Released || !self.preedit.is_empty() {
+                    return true;
+                }
+                match &event.logical_key {
+                    Key::Named(
+                        key @ (NamedKey::Backspace
+                        | NamedKey::Delete
+                        | NamedKey::Enter
+                        | NamedKey::Tab
+                        | NamedKey::Escape
+                        | NamedKey::ArrowLeft
+                        | NamedKey::ArrowRight
+                        | NamedKey::ArrowUp
+                        | NamedKey::ArrowDown
+                        | NamedKey::Home
+                        | NamedKey::End),
+                    ) => {
+                        self.events.push(TextEvent::Edit(*key));
+                    }
+                    _ => {
+                        if let Some(text) = &event.text {
+                            let text: String = text.chars().filter(|x| !x.is_control()).collect();
+                            if !text.is_empty() {
+                                self._push_text(&text);
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Text committed since the last flush
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn preedit(&self) -> &str {
+        &self.preedit
+    }
+
+    pub fn events(&self) -> &[TextEvent] {
+        &self.events
+    }
+
+    pub fn flush(&mut self) {
+        self.text.clear();
+        self.events.clear();
+    }
+
+    fn _push_text(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.events.push(TextEvent::Text(String::from(text)));
+    }
+}
@@ -0,0 +1,138 @@
+use super::{Control, InputKind, InputManager, InputValue, RawDeviceId, RawEvent, RawEventKind};
+use super::{GamepadControl, KeyboardControl, LogicalKey, MouseControl};
+use super::{RawGamepadEvent, RawKeyboardEvent, RawMouseEvent};
+use enumflags2::BitFlags;
+use serde::{Deserialize, Serialize};
+use winit::keyboard::PhysicalKey;
+
+// Manager that accepts raw events from every kind of device, so that a single
+// action can be bound to keyboard, mouse and gamepad controls at once
+pub type UnifiedInputManager<Action> = InputManager<RawDeviceId, RawInputEvent, Action>;
+
+#[derive(Debug)]
+pub enum RawInputEvent {
+    Keyboard(RawKeyboardEvent),
+    Mouse(RawMouseEvent),
+    Gamepad(RawGamepadEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputControl {
+    Keyboard(KeyboardControl),
+    Mouse(MouseControl),
+    Gamepad(GamepadControl),
+}
+
+impl From<RawKeyboardEvent> for RawInputEvent {
+    fn from(event: RawKeyboardEvent) -> Self {
+        RawInputEvent::Keyboard(event)
+    }
+}
+
+impl From<RawMouseEvent> for RawInputEvent {
+    fn from(event: RawMouseEvent) -> Self {
+        RawInputEvent::Mouse(event)
+    }
+}
+
+impl From<RawGamepadEvent> for RawInputEvent {
+    fn from(event: RawGamepadEvent) -> Self {
+        RawInputEvent::Gamepad(event)
+    }
+}
+
+impl From<KeyboardControl> for InputControl {
+    fn from(control: KeyboardControl) -> Self {
+        InputControl::Keyboard(control)
+    }
+}
+
+impl From<PhysicalKey> for InputControl {
+    fn from(control: PhysicalKey) -> Self {
+        InputControl::Keyboard(control.into())
+    }
+}
+
+impl From<LogicalKey> for InputControl {
+    fn from(control: LogicalKey) -> Self {
+        InputControl::Keyboard(control.into())
+    }
+}
+
+impl From<MouseControl> for InputControl {
+    fn from(control: MouseControl) -> Self {
+        InputControl::Mouse(control)
+    }
+}
+
+impl From<GamepadControl> for InputControl {
+    fn from(control: GamepadControl) -> Self {
+        InputControl::Gamepad(control)
+    }
+}
+
+impl RawEvent<RawDeviceId> for RawInputEvent {
+    type Control = InputControl;
+
+    fn get_device_id(&self) -> RawDeviceId {
+        match self {
+            RawInputEvent::Keyboard(event) => event.get_device_id(),
+            RawInputEvent::Mouse(event) => event.get_device_id(),
+            RawInputEvent::Gamepad(event) => event.get_device_id(),
+        }
+    }
+
+    fn get_control(&self) -> Self::Control {
+        match self {
+            RawInputEvent::Keyboard(event) => InputControl::Keyboard(event.get_control()),
+            RawInputEvent::Mouse(event) => InputControl::Mouse(event.get_control()),
+            RawInputEvent::Gamepad(event) => InputControl::Gamepad(event.get_control()),
+        }
+    }
+
+    fn get_alternate_control(&self) -> Option<Self::Control> {
+        match self {
+            RawInputEvent::Keyboard(event) => {
+                event.get_alternate_control().map(InputControl::Keyboard)
+            }
+            RawInputEvent::Mouse(event) => event.get_alternate_control().map(InputControl::Mouse),
+            RawInputEvent::Gamepad(event) => {
+                event.get_alternate_control().map(InputControl::Gamepad)
+            }
+        }
+    }
+
+    fn get_input_value(&self) -> InputValue {
+        match self {
+            RawInputEvent::Keyboard(event) => event.get_input_value(),
+            RawInputEvent::Mouse(event) => event.get_input_value(),
+            RawInputEvent::Gamepad(event) => event.get_input_value(),
+        }
+    }
+
+    fn get_event_kind(&self) -> RawEventKind {
+        match self {
+            RawInputEvent::Keyboard(event) => event.get_event_kind(),
+            RawInputEvent::Mouse(event) => event.get_event_kind(),
+            RawInputEvent::Gamepad(event) => event.get_event_kind(),
+        }
+    }
+}
+
+impl Control for InputControl {
+    fn kind(&self) -> BitFlags<InputKind> {
+        match self {
+            InputControl::Keyboard(control) => control.kind(),
+            InputControl::Mouse(control) => control.kind(),
+            InputControl::Gamepad(control) => control.kind(),
+        }
+    }
+
+    fn is_relative(&self) -> bool {
+        match self {
+            InputControl::Keyboard(control) => control.is_relative(),
+            InputControl::Mouse(control) => control.is_relative(),
+            InputControl::Gamepad(control) => control.is_relative(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// A fixed set of worker threads running jobs in the order they're spawned.
+// Dropping it finishes the jobs that were already spawned
+pub struct JobSystem {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobSystem {
+    // A worker count of 0 uses one worker per available core, minus one for
+    // the render thread
+    pub fn new(worker_count: usize) -> Self {
+        let worker_count = match worker_count {
+            0 => std::thread::available_parallelism().map_or(1, |x| x.get().max(2) - 1),
+            n => n,
+        };
+
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..worker_count)
+            .map(|i| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("vulka-job-{}", i))
+                    .spawn(move || loop {
+                        // The lock is only held while waiting for a job
+                        let job = receiver.lock().expect("job worker panicked").recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("failed to spawn job worker")
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> JobHandle<T> {
+        let (sender, receiver) = channel();
+        let job = Box::new(move || {
+            // Nothing is waiting on the result if the handle was dropped
+            let _ = sender.send(catch_unwind(AssertUnwindSafe(job)));
+        });
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("job workers exited");
+        JobHandle { receiver }
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        // Workers exit once the queue is closed and empty
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// The result of a spawned job. A panic in the job is resumed by whichever of
+// `poll` or `wait` receives it
+pub struct JobHandle<T> {
+    receiver: Receiver<std::thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    // Takes the result if the job has finished. Returns None after that
+    pub fn poll(&self) -> Option<T> {
+        match self.receiver.try_recv() {
+            Ok(Ok(result)) => Some(result),
+            Ok(Err(panic)) => resume_unwind(panic),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => None,
+        }
+    }
+
+    pub fn wait(self) -> T {
+        match self.receiver.recv().expect("job was dropped") {
+            Ok(result) => result,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// Vulkan wrappers, input handling and a forward renderer built on them. See
+// `examples/cube.rs` for a complete app
+pub mod animation;
+pub mod asset_server;
+mod bloom;
+pub mod camera;
+pub mod config;
+mod culling;
+pub mod debug_draw;
+mod depth_pyramid;
+pub mod frame_stats;
+pub mod gpu;
+pub mod input;
+pub mod jobs;
+pub mod lights;
+pub mod lod;
+pub mod material;
+pub mod mesh_optimizer;
+pub mod model;
+pub mod post_process;
+pub mod projection;
+pub mod render_context;
+pub mod render_world;
+pub mod renderer;
+mod shadow;
+pub mod skybox;
+mod ssao;
+mod taa;
+pub mod texture_cache;
+mod texture_file;
+pub mod tonemap;
+pub mod window_controller;
+
+pub use config::Config;
+pub use render_context::{ModelId, Renderer};
+pub use renderer::RendererBuilder;
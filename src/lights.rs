@@ -0,0 +1,430 @@
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3};
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::gpu::{
+    Buffer, BufferHandle, BufferUsage, CommandBuffer, ComputePipeline, DescriptorPool,
+    DescriptorSet, DescriptorSetLayout, DescriptorWriter, Device, HotReload, MemoryPriority,
+    PipelineLayout, RenderGraph, ShaderId, ShaderKind, ShaderRegistry,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// The view is split into CLUSTER_GRID[0] x CLUSTER_GRID[1] screen tiles, each
+// with CLUSTER_GRID[2] depth slices spaced exponentially between the near and
+// far planes
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+pub const MAX_LIGHTS: usize = 1024;
+// Lights past this in a single cluster are dropped from it
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 127;
+
+// Values match the light kinds in light_cull.glsl and fragment.glsl
+const POINT_LIGHT: u32 = 0;
+const SPOT_LIGHT: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: Vec3,
+    // Linear color, scaled by intensity
+    pub color: Vec3,
+    // Distance at which the light fades out completely
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpotLight {
+    pub position: Vec3,
+    // Direction the light points in
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub radius: f32,
+    // Half angles in radians. The light fades out between the inner and outer
+    // cone
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl Light {
+    pub fn position(&self) -> Vec3 {
+        match self {
+            Light::Point(x) => x.position,
+            Light::Spot(x) => x.position,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match self {
+            Light::Point(x) => x.radius,
+            Light::Spot(x) => x.radius,
+        }
+    }
+
+    pub fn color(&self) -> Vec3 {
+        match self {
+            Light::Point(x) => x.color,
+            Light::Spot(x) => x.color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+// Matches `Light` in light_cull.glsl and fragment.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+    position: Vec3,
+    radius: f32,
+    color: Vec3,
+    kind: u32,
+    direction: Vec3,
+    cos_outer: f32,
+    cos_inner: f32,
+    _padding: [u32; 3],
+}
+
+impl GpuLight {
+    fn new(light: &Light) -> Self {
+        match light {
+            Light::Point(x) => Self {
+                position: x.position,
+                radius: x.radius,
+                color: x.color,
+                kind: POINT_LIGHT,
+                direction: Vec3::ZERO,
+                cos_outer: -1.0,
+                cos_inner: -1.0,
+                _padding: [0; 3],
+            },
+            Light::Spot(x) => Self {
+                position: x.position,
+                radius: x.radius,
+                color: x.color,
+                kind: SPOT_LIGHT,
+                direction: x.direction.normalize(),
+                cos_outer: x.outer_angle.cos(),
+                cos_inner: x.inner_angle.cos(),
+                _padding: [0; 3],
+            },
+        }
+    }
+}
+
+// Matches `Clusters` in light_cull.glsl and fragment.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    inverse_projection: Mat4,
+    view: Mat4,
+    grid: [u32; 3],
+    light_count: u32,
+    screen_size: Vec2,
+    near: f32,
+    far: f32,
+}
+
+struct LightFrame {
+    uniform_buffer: Buffer,
+    light_buffer: Buffer,
+    // A count followed by MAX_LIGHTS_PER_CLUSTER light indices per cluster
+    cluster_buffer: Buffer,
+    descriptor_set: DescriptorSet,
+}
+
+// Point and spot lights, uploaded to a storage buffer every frame. A compute
+// pass bins them into view space clusters so the fragment shader only loops
+// over the lights that can reach the fragment's cluster. Lights are stored in
+// slots that are reused once removed
+pub struct LightManager {
+    shader_id: ShaderId,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<ComputePipeline>,
+    frames: Vec<LightFrame>,
+    slots: Vec<Option<Light>>,
+    free_slots: Vec<u32>,
+}
+
+impl LightManager {
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        shader_registry: &mut ShaderRegistry,
+        shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_id = shader_registry.load(shader_path, ShaderKind::Compute, "main");
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let uniform_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let light_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let cluster_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[uniform_binding, light_binding, cluster_binding],
+            )
+        };
+
+        let pipeline_layout = device.get_pipeline_layout(std::slice::from_ref(&set_layout), &[]);
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            shader_registry.get(shader_id),
+            &pipeline_layout,
+        );
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            frames_in_flight as u32,
+            &[
+                (vk::DescriptorType::UNIFORM_BUFFER, frames_in_flight as u32),
+                (
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    2 * frames_in_flight as u32,
+                ),
+            ],
+        );
+        let descriptor_sets = descriptor_pool.allocate(&vec![&*set_layout; frames_in_flight]);
+
+        let frames = descriptor_sets
+            .into_vec()
+            .into_iter()
+            .map(|descriptor_set| LightFrame {
+                uniform_buffer: Buffer::new(
+                    device.clone(),
+                    allocator.clone(),
+                    size_of::<ClusterUniform>(),
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vma::MemoryUsage::AutoPreferHost,
+                    vma::AllocationCreateFlags::MAPPED
+                        | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+                    MemoryPriority::Normal,
+                ),
+                light_buffer: Buffer::new(
+                    device.clone(),
+                    allocator.clone(),
+                    LightManager::_light_buffer_size(),
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vma::MemoryUsage::AutoPreferHost,
+                    vma::AllocationCreateFlags::MAPPED
+                        | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+                    MemoryPriority::Normal,
+                ),
+                cluster_buffer: Buffer::new(
+                    device.clone(),
+                    allocator.clone(),
+                    LightManager::_cluster_buffer_size(),
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vma::MemoryUsage::AutoPreferDevice,
+                    vma::AllocationCreateFlags::empty(),
+                    MemoryPriority::High,
+                ),
+                descriptor_set,
+            })
+            .collect::<Vec<_>>();
+
+        {
+            let mut writer = DescriptorWriter::new(device.clone());
+            for frame in &frames {
+                let set = &frame.descriptor_set;
+                writer
+                    .write_buffer(
+                        set,
+                        &frame.uniform_buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        0,
+                        vk::DescriptorType::UNIFORM_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        &frame.light_buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        1,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        &frame.cluster_buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        2,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    );
+            }
+            writer.flush();
+        }
+
+        Self {
+            shader_id,
+            pipeline_layout,
+            pipeline,
+            frames,
+            slots: vec![],
+            free_slots: vec![],
+        }
+    }
+
+    fn _light_buffer_size() -> usize {
+        size_of::<GpuLight>() * MAX_LIGHTS
+    }
+
+    fn _cluster_buffer_size() -> usize {
+        let [x, y, z] = CLUSTER_GRID;
+        size_of::<u32>() * (x * y * z * (MAX_LIGHTS_PER_CLUSTER + 1)) as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, light: Light) -> LightId {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                assert!(self.slots.len() < MAX_LIGHTS, "too many lights");
+                self.slots.push(None);
+                (self.slots.len() - 1) as u32
+            }
+        };
+        self.slots[slot as usize] = Some(light);
+        LightId(slot)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id.0 as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.slots.get_mut(id.0 as usize)?.as_mut()
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Light {
+        let light = self.slots[id.0 as usize]
+            .take()
+            .expect("light was already removed");
+        self.free_slots.push(id.0);
+        light
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.free_slots.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| Some((LightId(i as u32), x.as_ref()?)))
+    }
+
+    // Read by the fragment shader, see fragment.glsl
+    pub fn uniform_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].uniform_buffer
+    }
+
+    pub fn light_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].light_buffer
+    }
+
+    pub fn cluster_buffer(&self, frame_index: usize) -> &Buffer {
+        &self.frames[frame_index].cluster_buffer
+    }
+
+    // Uploads the lights and adds the pass binning them into clusters. Must be
+    // called after the frame's fence has been waited on. Returns the cluster
+    // buffer for passes shading with it to read. `depth_range` holds the near
+    // and far planes of the perspective projection `proj`
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        view: Mat4,
+        proj: Mat4,
+        extent: vk::Extent2D,
+        (near, far): (f32, f32),
+    ) -> BufferHandle {
+        let frame = &self.frames[frame_index];
+
+        let lights = self
+            .slots
+            .iter()
+            .flatten()
+            .map(GpuLight::new)
+            .collect::<Vec<_>>();
+        frame.light_buffer.copy_nonoverlapping(&lights);
+
+        frame.uniform_buffer.copy_nonoverlapping(&[ClusterUniform {
+            inverse_projection: proj.inverse(),
+            view,
+            grid: CLUSTER_GRID,
+            light_count: lights.len() as u32,
+            screen_size: Vec2::new(extent.width as f32, extent.height as f32),
+            near,
+            far,
+        }]);
+
+        let clusters = graph.import_buffer(&frame.cluster_buffer);
+
+        graph
+            .add_pass("light_cull")
+            .write_buffer(clusters, BufferUsage::Storage)
+            .record(move |cmd| self._record(cmd, frame_index));
+
+        clusters
+    }
+
+    fn _record(&self, cmd_buf: &CommandBuffer, frame_index: usize) {
+        let [x, y, z] = CLUSTER_GRID;
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[&self.frames[frame_index].descriptor_set],
+        );
+        cmd_buf.dispatch((x * y * z).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+impl HotReload for LightManager {
+    type Pipelines = Arc<ComputePipeline>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Arc<ComputePipeline> {
+        ComputePipeline::new(
+            self.pipeline_layout.device().clone(),
+            shader_registry.get(self.shader_id),
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Arc<ComputePipeline> {
+        &mut self.pipeline
+    }
+}
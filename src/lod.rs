@@ -0,0 +1,135 @@
+use glam::{IVec3, Vec3};
+use std::collections::HashMap;
+
+use crate::model::Vertex;
+
+// Most levels a mesh gets, including the full detail one
+pub const MAX_LODS: usize = 4;
+
+// Meshes with fewer triangles than this at a level don't get a coarser one
+const MIN_TRIANGLES: usize = 32;
+
+// When to switch levels. A mesh is drawn at full detail while its bounding
+// sphere's projected diameter covers at least `screen_size` of the viewport
+// height, and each level after that takes over at half the size of the one
+// before
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSettings {
+    pub enabled: bool,
+    pub screen_size: f32,
+    // How far past a switch point, in levels, the size has to move before an
+    // object drawn at one level switches to the next. Keeps objects sitting
+    // right at a switch point from flickering between levels
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            screen_size: 0.25,
+            hysteresis: 0.2,
+        }
+    }
+}
+
+impl LodSettings {
+    // Picks the level for an object covering `screen_size` of the viewport
+    // height, which was drawn at `previous` before
+    pub fn select(&self, screen_size: f32, previous: usize, lod_count: usize) -> usize {
+        if !self.enabled || lod_count <= 1 {
+            return 0;
+        }
+
+        // 0 at `self.screen_size` and one more each time the size halves.
+        // Level n covers (n - 1, n]
+        let level = (self.screen_size / screen_size.max(1e-6)).log2();
+        let last = lod_count - 1;
+        let previous = previous.min(last);
+
+        let lower = match previous {
+            0 => f32::NEG_INFINITY,
+            x => x as f32 - 1.0 - self.hysteresis,
+        };
+        let upper = match previous {
+            x if x == last => f32::INFINITY,
+            x => x as f32 + self.hysteresis,
+        };
+        if level > lower && level <= upper {
+            return previous;
+        }
+        (level.ceil().max(0.0) as usize).min(last)
+    }
+}
+
+// Simplifies the mesh into up to `MAX_LODS - 1` coarser index lists over the
+// same vertices, each with about half the triangles of the one before.
+// Vertices are clustered on a grid and each cluster is collapsed into its
+// most central vertex, which keeps the overall shape but not fine details or
+// UV seams. Stops early once a level barely removes anything
+pub fn generate_lods(vertices: &[Vertex], indices: &[u32]) -> Vec<Vec<u32>> {
+    let mut lods = vec![];
+    if vertices.is_empty() {
+        return lods;
+    }
+
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), x| (min.min(x.position), max.max(x.position)),
+    );
+    let extent = (max - min).max_element();
+    if extent <= 0.0 {
+        return lods;
+    }
+
+    let mut previous = indices.len();
+    for level in 1..MAX_LODS {
+        let target = (indices.len() / 3) >> level;
+        if target < MIN_TRIANGLES {
+            break;
+        }
+
+        // A closed surface through n^3 cells crosses about 6 n^2 of them,
+        // with two triangles each once collapsed
+        let cells = (target as f32 / 12.0).sqrt().ceil().max(1.0);
+        let lod = _cluster(vertices, indices, min, extent / cells);
+        if lod.is_empty() || lod.len() * 4 > previous * 3 {
+            break;
+        }
+        previous = lod.len();
+        lods.push(lod);
+    }
+    lods
+}
+
+fn _cluster(vertices: &[Vertex], indices: &[u32], min: Vec3, cell_size: f32) -> Vec<u32> {
+    let cell = |position: Vec3| ((position - min) / cell_size).floor().as_ivec3();
+
+    let mut sums = HashMap::<IVec3, (Vec3, u32)>::new();
+    for vertex in vertices {
+        let sum = sums.entry(cell(vertex.position)).or_default();
+        sum.0 += vertex.position;
+        sum.1 += 1;
+    }
+
+    // The vertex closest to the average of its cell represents it
+    let mut representatives = HashMap::<IVec3, (u32, f32)>::new();
+    for (i, vertex) in vertices.iter().enumerate() {
+        let key = cell(vertex.position);
+        let (sum, count) = sums[&key];
+        let distance = vertex.position.distance_squared(sum / count as f32);
+        let representative = representatives.entry(key).or_insert((i as u32, distance));
+        if distance < representative.1 {
+            *representative = (i as u32, distance);
+        }
+    }
+
+    // Triangles that collapsed into a line or a point are dropped
+    let remap = |i: u32| representatives[&cell(vertices[i as usize].position)].0;
+    indices
+        .chunks_exact(3)
+        .map(|x| [remap(x[0]), remap(x[1]), remap(x[2])])
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect()
+}
@@ -0,0 +1,274 @@
+use ash::vk;
+use glam::Vec4;
+use std::mem::size_of;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::gpu::{
+    Buffer, DescriptorAllocator, DescriptorSet, DescriptorSetLayout, DescriptorWriter, Device,
+    UploadQueue,
+};
+use crate::texture_cache::Texture;
+
+// The optional parts of a material that change how it's shaded. Each
+// combination gets its own pipeline, with the maps as specialization
+// constants in fragment.glsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaterialFeatures {
+    pub normal_map: bool,
+    pub metallic_roughness_map: bool,
+    // Back faces aren't culled
+    pub double_sided: bool,
+    // Blended over the opaque meshes in the transparent pass
+    pub alpha_blend: bool,
+    // Set from the mesh rather than the material, see `Mesh::is_skinned`
+    pub skinned: bool,
+}
+
+impl MaterialFeatures {
+    // Only the features that affect depth, for depth-only pipelines. The maps
+    // only change shading, and blended materials don't write depth at all
+    pub fn depth_only(&self) -> Self {
+        Self {
+            double_sided: self.double_sided,
+            skinned: self.skinned,
+            ..Self::default()
+        }
+    }
+}
+
+// How the base color's alpha is used, as in glTF. Masking isn't supported, so
+// masked materials are treated as opaque
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    // Drawn after the opaque meshes, back to front, without writing depth
+    Blend,
+}
+
+// Metallic-roughness material, as in glTF. Textures are multiplied by their
+// factors, and missing ones leave the factors as they are
+#[derive(Clone)]
+pub struct Material {
+    pub base_color_factor: Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub double_sided: bool,
+    pub alpha_mode: AlphaMode,
+    // sRGB
+    pub albedo: Option<Arc<Texture>>,
+    // Linear, tangent space
+    pub normal: Option<Arc<Texture>>,
+    // Linear, with roughness in G and metalness in B
+    pub metallic_roughness: Option<Arc<Texture>>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color_factor: Vec4::ONE,
+            metallic_factor: 0.0,
+            roughness_factor: 0.5,
+            normal_scale: 1.0,
+            double_sided: false,
+            alpha_mode: AlphaMode::Opaque,
+            albedo: None,
+            normal: None,
+            metallic_roughness: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn features(&self) -> MaterialFeatures {
+        MaterialFeatures {
+            normal_map: self.normal.is_some(),
+            metallic_roughness_map: self.metallic_roughness.is_some(),
+            double_sided: self.double_sided,
+            alpha_blend: self.alpha_mode == AlphaMode::Blend,
+            skinned: false,
+        }
+    }
+}
+
+// Matches `MaterialUniform` in fragment.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color_factor: Vec4,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    _padding: f32,
+}
+
+// A descriptor set for each material, with every material's uniform block in
+// one buffer. Sets are bound to set 1 of the main pipeline
+pub struct MaterialTable {
+    uniform_buffer: Rc<Buffer>,
+    sets: Vec<DescriptorSet>,
+    features: Vec<MaterialFeatures>,
+}
+
+impl MaterialTable {
+    // Binding 0 is the uniform block, followed by the albedo, normal and
+    // metallic-roughness textures
+    pub fn create_set_layout(device: &Arc<Device>) -> Arc<DescriptorSetLayout> {
+        let mut builder = DescriptorSetLayout::builder();
+
+        let uniform_binding = builder
+            .binding()
+            .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+            .stage(vk::ShaderStageFlags::FRAGMENT);
+        let albedo_binding = builder
+            .binding()
+            .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage(vk::ShaderStageFlags::FRAGMENT);
+        let normal_binding = builder
+            .binding()
+            .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage(vk::ShaderStageFlags::FRAGMENT);
+        let metallic_roughness_binding = builder
+            .binding()
+            .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage(vk::ShaderStageFlags::FRAGMENT);
+
+        builder.build(
+            device.clone(),
+            vk::DescriptorSetLayoutCreateFlags::empty(),
+            &[
+                uniform_binding,
+                albedo_binding,
+                normal_binding,
+                metallic_roughness_binding,
+            ],
+        )
+    }
+
+    // Missing textures are bound to `fallback`, which should be opaque white.
+    // The shader doesn't sample missing normal or metallic-roughness maps
+    pub fn new(
+        device: &Arc<Device>,
+        uploads: &UploadQueue,
+        descriptor_allocator: &mut DescriptorAllocator,
+        set_layout: &DescriptorSetLayout,
+        materials: &[Material],
+        fallback: &Arc<Texture>,
+    ) -> Self {
+        assert!(!materials.is_empty(), "material table needs a material");
+
+        // Each block starts at a multiple of the device's offset alignment
+        let alignment = device
+            .physical_device()
+            .device_limits()
+            .min_uniform_buffer_offset_alignment as usize;
+        let stride = size_of::<MaterialUniform>().next_multiple_of(alignment.max(1));
+
+        let mut uniform_data = vec![0_u8; stride * materials.len()];
+        for (i, material) in materials.iter().enumerate() {
+            let uniform = MaterialUniform {
+                base_color_factor: material.base_color_factor,
+                metallic_factor: material.metallic_factor,
+                roughness_factor: material.roughness_factor,
+                normal_scale: material.normal_scale,
+                _padding: 0.0,
+            };
+            uniform_data[i * stride..][..size_of::<MaterialUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+
+        let uniform_buffer =
+            uploads.upload_buffer(&uniform_data, vk::BufferUsageFlags::UNIFORM_BUFFER);
+
+        let sets = materials
+            .iter()
+            .map(|_| descriptor_allocator.allocate(set_layout))
+            .collect::<Vec<_>>();
+
+        {
+            let mut writer = DescriptorWriter::new(device.clone());
+
+            for (i, (set, material)) in sets.iter().zip(materials).enumerate() {
+                writer.write_buffer(
+                    set,
+                    &uniform_buffer,
+                    (i * stride) as u64,
+                    size_of::<MaterialUniform>() as u64,
+                    0,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                );
+
+                let textures = [
+                    &material.albedo,
+                    &material.normal,
+                    &material.metallic_roughness,
+                ];
+                for (binding, texture) in (1..).zip(textures) {
+                    let texture = texture.as_ref().unwrap_or(fallback);
+                    writer.write_image(
+                        set,
+                        texture.sampler(),
+                        texture.view(),
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        binding,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    );
+                }
+            }
+
+            writer.flush();
+        }
+
+        Self {
+            uniform_buffer,
+            sets,
+            features: materials.iter().map(|x| x.features()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    pub fn uniform_buffer(&self) -> &Buffer {
+        &self.uniform_buffer
+    }
+
+    pub fn descriptor_set(&self, material: usize) -> &DescriptorSet {
+        &self.sets[material]
+    }
+
+    pub fn features(&self, material: usize) -> MaterialFeatures {
+        self.features[material]
+    }
+
+    // Every combination of features used by the table, which each need a
+    // pipeline
+    pub fn feature_variants(&self) -> Vec<MaterialFeatures> {
+        let mut variants = vec![];
+        for features in &self.features {
+            if !variants.contains(features) {
+                variants.push(*features);
+            }
+        }
+        variants
+    }
+
+    // Like `feature_variants`, for depth-only pipelines. Blended materials
+    // aren't drawn depth-only
+    pub fn depth_variants(&self) -> Vec<MaterialFeatures> {
+        let mut variants = vec![];
+        for features in self.features.iter().filter(|x| !x.alpha_blend) {
+            if !variants.contains(&features.depth_only()) {
+                variants.push(features.depth_only());
+            }
+        }
+        variants
+    }
+}
@@ -0,0 +1,58 @@
+use crate::model::Vertex;
+
+// How much worse the vertex cache hit rate can get in exchange for less
+// overdraw, 1.05 allows 5%
+#[cfg(feature = "meshopt")]
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+// Processing applied to meshes as they're loaded, so models render well
+// without having been preprocessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshImportSettings {
+    // Reorders triangles and vertices for the GPU. Needs the meshopt feature,
+    // without it meshes are uploaded in the order they're loaded
+    pub optimize: bool,
+    // Uses 16-bit indices for meshes with few enough vertices
+    pub small_indices: bool,
+}
+
+impl Default for MeshImportSettings {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            small_indices: true,
+        }
+    }
+}
+
+// Reorders the triangles for the post-transform vertex cache, and then
+// reorders clusters of them so ones facing outwards are drawn first, which
+// cuts overdraw
+#[cfg(feature = "meshopt")]
+pub fn optimize_triangles(vertices: &[Vertex], indices: &mut [u32]) {
+    meshopt::optimize_vertex_cache_in_place(indices, vertices.len());
+
+    let positions = meshopt::VertexDataAdapter::new(
+        meshopt::typed_to_bytes(vertices),
+        std::mem::size_of::<Vertex>(),
+        std::mem::offset_of!(Vertex, position),
+    )
+    .expect("failed to read mesh positions");
+    meshopt::optimize_overdraw_in_place(indices, &positions, OVERDRAW_THRESHOLD);
+}
+
+#[cfg(not(feature = "meshopt"))]
+pub fn optimize_triangles(_vertices: &[Vertex], _indices: &mut [u32]) {}
+
+// Reorders the vertices into the order the indices first use them, so vertex
+// fetches read memory mostly in order, and drops unused ones. `indices` is
+// every index list using the vertices, with the most drawn one first. Any
+// vertex type works, e.g. a vertex zipped with its skin vertex
+#[cfg(feature = "meshopt")]
+pub fn optimize_vertex_fetch<T>(vertices: &mut Vec<T>, indices: &mut [u32]) {
+    let vertex_count = meshopt::optimize_vertex_fetch_in_place(indices, vertices);
+    vertices.truncate(vertex_count);
+}
+
+#[cfg(not(feature = "meshopt"))]
+pub fn optimize_vertex_fetch<T>(_vertices: &mut Vec<T>, _indices: &mut [u32]) {}
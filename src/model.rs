@@ -0,0 +1,727 @@
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::animation::{AnimationClip, Skeleton};
+use crate::gpu::{Buffer, BufferArena, BufferSlice, Half2, UploadQueue, VertexLayout};
+use crate::lod::generate_lods;
+use crate::material::{AlphaMode, Material};
+use crate::mesh_optimizer::{optimize_triangles, optimize_vertex_fetch, MeshImportSettings};
+use crate::texture_cache::{ColorSpace, Texture, TextureCache};
+use crate::vertex_field;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub tex_coord: Half2,
+}
+
+impl Vertex {
+    pub fn layout() -> VertexLayout {
+        VertexLayout::builder()
+            .binding::<Vertex>(vk::VertexInputRate::VERTEX)
+            .attribute(vertex_field!(Vertex, position))
+            .attribute(vertex_field!(Vertex, normal))
+            .attribute(vertex_field!(Vertex, tex_coord))
+            .build()
+    }
+
+    // `Vertex` in binding 0 and `SkinVertex` in binding 1
+    pub fn skinned_layout() -> VertexLayout {
+        VertexLayout::builder()
+            .binding::<Vertex>(vk::VertexInputRate::VERTEX)
+            .attribute(vertex_field!(Vertex, position))
+            .attribute(vertex_field!(Vertex, normal))
+            .attribute(vertex_field!(Vertex, tex_coord))
+            .binding::<SkinVertex>(vk::VertexInputRate::VERTEX)
+            .attribute(vertex_field!(SkinVertex, joints))
+            .attribute(vertex_field!(SkinVertex, weights))
+            .build()
+    }
+}
+
+// The joints a vertex of a skinned mesh follows, in a separate stream so
+// meshes that aren't skinned don't pay for it. Joints are indices into the
+// skin's joints, and the weights add up to 1
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SkinVertex {
+    pub joints: [u16; 4],
+    pub weights: Vec4,
+}
+
+// A range of a mesh's index buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+// A mesh's vertices and indices after import processing, ready to be
+// uploaded. Building it doesn't touch the device, so it can be done on any
+// thread
+pub struct MeshData {
+    vertices: Vec<Vertex>,
+    // One for each vertex if the mesh is skinned
+    skin_vertices: Option<Vec<SkinVertex>>,
+    // Every level of detail, full detail first
+    indices: Vec<u32>,
+    lods: Vec<MeshLod>,
+    material: Option<usize>,
+    small_indices: bool,
+}
+
+impl MeshData {
+    pub fn new(
+        vertices: Vec<Vertex>,
+        skin_vertices: Option<Vec<SkinVertex>>,
+        indices: &[u32],
+        material: Option<usize>,
+        settings: &MeshImportSettings,
+    ) -> Self {
+        // Each level's triangles are optimized on their own, and then the
+        // vertices for all of them together
+        let mut vertices = vertices;
+        let mut all_indices = indices.to_vec();
+        if settings.optimize {
+            optimize_triangles(&vertices, &mut all_indices);
+        }
+        let mut lods = vec![MeshLod {
+            first_index: 0,
+            index_count: indices.len().try_into().unwrap(),
+        }];
+        for mut lod in generate_lods(&vertices, &all_indices) {
+            if settings.optimize {
+                optimize_triangles(&vertices, &mut lod);
+            }
+            lods.push(MeshLod {
+                first_index: all_indices.len() as u32,
+                index_count: lod.len() as u32,
+            });
+            all_indices.extend(lod);
+        }
+        // Skin vertices are reordered along with the vertices
+        let mut skin_vertices = skin_vertices;
+        if settings.optimize {
+            match skin_vertices {
+                Some(skin) => {
+                    let mut zipped = vertices.into_iter().zip(skin).collect::<Vec<_>>();
+                    optimize_vertex_fetch(&mut zipped, &mut all_indices);
+                    let (x, y) = zipped.into_iter().unzip();
+                    (vertices, skin_vertices) = (x, Some(y));
+                }
+                None => optimize_vertex_fetch(&mut vertices, &mut all_indices),
+            }
+        }
+
+        Self {
+            small_indices: settings.small_indices && vertices.len() <= u16::MAX as usize + 1,
+            vertices,
+            skin_vertices,
+            indices: all_indices,
+            lods,
+            material,
+        }
+    }
+}
+
+// A single indexed triangle list with one material, which is an index into
+// the model's materials. Coarser levels of detail follow the full one in the
+// index buffer and share its vertices
+pub struct Mesh {
+    vertex_buffer: BufferSlice,
+    // `SkinVertex`s, if the mesh is skinned
+    skin_buffer: Option<BufferSlice>,
+    index_buffer: BufferSlice,
+    // UINT16 when the settings allow it and the vertices fit
+    index_type: vk::IndexType,
+    // Full detail first
+    lods: Vec<MeshLod>,
+    material: Option<usize>,
+    // Bounding box and sphere in mesh space, used for culling
+    bounds_min: Vec3,
+    bounds_max: Vec3,
+    bounds_center: Vec3,
+    bounds_radius: f32,
+}
+
+impl Mesh {
+    // `buffer_arena` needs VERTEX_BUFFER and INDEX_BUFFER usage
+    pub fn new(buffer_arena: &BufferArena, uploads: &UploadQueue, data: &MeshData) -> Self {
+        let vertices = &data.vertices;
+        let vertex_buffer = buffer_arena.upload(uploads, vertices);
+        let skin_buffer = data
+            .skin_vertices
+            .as_ref()
+            .map(|x| buffer_arena.upload(uploads, x));
+        let (index_buffer, index_type) = if data.small_indices {
+            let indices = data.indices.iter().map(|x| *x as u16).collect::<Vec<_>>();
+            (
+                buffer_arena.upload(uploads, &indices),
+                vk::IndexType::UINT16,
+            )
+        } else {
+            (
+                buffer_arena.upload(uploads, &data.indices),
+                vk::IndexType::UINT32,
+            )
+        };
+
+        // Centered on the bounding box, which is close enough to the minimal
+        // sphere for culling
+        let (bounds_min, bounds_max) = if vertices.is_empty() {
+            (Vec3::ZERO, Vec3::ZERO)
+        } else {
+            vertices.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), x| (min.min(x.position), max.max(x.position)),
+            )
+        };
+        let bounds_center = (bounds_min + bounds_max) / 2.0;
+        let bounds_radius = vertices
+            .iter()
+            .map(|x| x.position.distance(bounds_center))
+            .fold(0.0, f32::max);
+
+        Self {
+            vertex_buffer,
+            skin_buffer,
+            index_buffer,
+            index_type,
+            lods: data.lods.clone(),
+            material: data.material,
+            bounds_min,
+            bounds_max,
+            bounds_center,
+            bounds_radius,
+        }
+    }
+
+    pub fn vertex_buffer(&self) -> &BufferSlice {
+        &self.vertex_buffer
+    }
+
+    pub fn skin_buffer(&self) -> Option<&BufferSlice> {
+        self.skin_buffer.as_ref()
+    }
+
+    // Skinned meshes are drawn with `Vertex::skinned_layout`
+    pub fn is_skinned(&self) -> bool {
+        self.skin_buffer.is_some()
+    }
+
+    // Buffers and offsets for `CommandBuffer::bind_vertex_buffers`, in
+    // binding order
+    pub fn vertex_bindings(&self) -> Vec<(&Buffer, vk::DeviceSize)> {
+        std::iter::once(&self.vertex_buffer)
+            .chain(&self.skin_buffer)
+            .map(|x| (x.buffer(), x.offset()))
+            .collect()
+    }
+
+    pub fn index_buffer(&self) -> &BufferSlice {
+        &self.index_buffer
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
+
+    // Of the full detail level
+    pub fn index_count(&self) -> u32 {
+        self.lods[0].index_count
+    }
+
+    pub fn lods(&self) -> &[MeshLod] {
+        &self.lods
+    }
+
+    pub fn material(&self) -> Option<usize> {
+        self.material
+    }
+
+    // Min and max corners
+    pub fn bounding_box(&self) -> (Vec3, Vec3) {
+        (self.bounds_min, self.bounds_max)
+    }
+
+    pub fn bounding_sphere(&self) -> (Vec3, f32) {
+        (self.bounds_center, self.bounds_radius)
+    }
+}
+
+// Where a mesh is drawn. Meshes referenced by several nodes are only uploaded
+// once and have an instance per node
+#[derive(Debug, Clone, Copy)]
+pub struct MeshInstance {
+    pub mesh: usize,
+    pub transform: Mat4,
+    // Index into the skeleton's skins for instances of skinned meshes. Their
+    // transform only places the model, since the joints pose the mesh
+    pub skin: Option<usize>,
+}
+
+// Decoded RGBA8 pixels of a model texture
+pub struct ImageData {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub color_space: ColorSpace,
+}
+
+// A `Material` whose textures are indices into `ModelData::images`
+#[derive(Debug, Clone)]
+pub struct MaterialData {
+    pub base_color_factor: Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub double_sided: bool,
+    pub alpha_mode: AlphaMode,
+    pub albedo: Option<usize>,
+    pub normal: Option<usize>,
+    pub metallic_roughness: Option<usize>,
+}
+
+// A model parsed, decoded and processed on the CPU, which `Model::upload`
+// turns into GPU resources. Loading doesn't touch the device, so it can run
+// on a worker thread
+pub struct ModelData {
+    meshes: Vec<MeshData>,
+    instances: Vec<MeshInstance>,
+    materials: Vec<MaterialData>,
+    images: Vec<ImageData>,
+    skeleton: Skeleton,
+    animations: Vec<AnimationClip>,
+    // Files the model was loaded from
+    sources: Vec<PathBuf>,
+}
+
+impl ModelData {
+    // Loads every triangle primitive in the default scene of a .gltf or .glb
+    // file, with its skins and animations. Only positions, normals, the first
+    // UV set, the first joint and weight set and metallic-roughness materials
+    // are used. Fails if the file can't be read or isn't a usable model
+    pub fn load(path: impl AsRef<Path>, settings: &MeshImportSettings) -> Result<Self, String> {
+        let path = path.as_ref();
+        let (document, buffers, images) = gltf::import(path)
+            .map_err(|error| format!("failed to load gltf model {}: {}", path.display(), error))?;
+
+        // External buffers and images are relative to the model, while data
+        // URIs are part of it
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let buffer_uris = document.buffers().filter_map(|x| match x.source() {
+            gltf::buffer::Source::Uri(uri) => Some(uri),
+            gltf::buffer::Source::Bin => None,
+        });
+        let image_uris = document.images().filter_map(|x| match x.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        });
+        let sources = std::iter::once(path.to_path_buf())
+            .chain(
+                buffer_uris
+                    .chain(image_uris)
+                    .filter(|x| !x.starts_with("data:"))
+                    .map(|x| directory.join(x)),
+            )
+            .collect();
+
+        // Images are converted once for each color space the materials use
+        // them in
+        let mut image_data = vec![];
+        let mut image_indices = HashMap::<(usize, ColorSpace), usize>::new();
+        let mut add_image = |image: usize, color_space: ColorSpace| {
+            *image_indices
+                .entry((image, color_space))
+                .or_insert_with(|| {
+                    let data = &images[image];
+                    image_data.push(ImageData {
+                        pixels: ModelData::_to_rgba8(data),
+                        width: data.width,
+                        height: data.height,
+                        color_space,
+                    });
+                    image_data.len() - 1
+                })
+        };
+
+        let materials = document
+            .materials()
+            .map(|x| {
+                let pbr = x.pbr_metallic_roughness();
+                MaterialData {
+                    base_color_factor: Vec4::from_array(pbr.base_color_factor()),
+                    metallic_factor: pbr.metallic_factor(),
+                    roughness_factor: pbr.roughness_factor(),
+                    normal_scale: x.normal_texture().map_or(1.0, |x| x.scale()),
+                    double_sided: x.double_sided(),
+                    alpha_mode: match x.alpha_mode() {
+                        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                        _ => AlphaMode::Opaque,
+                    },
+                    albedo: pbr
+                        .base_color_texture()
+                        .map(|x| add_image(x.texture().source().index(), ColorSpace::Srgb)),
+                    normal: x
+                        .normal_texture()
+                        .map(|x| add_image(x.texture().source().index(), ColorSpace::Linear)),
+                    metallic_roughness: pbr
+                        .metallic_roughness_texture()
+                        .map(|x| add_image(x.texture().source().index(), ColorSpace::Linear)),
+                }
+            })
+            .collect();
+
+        // Each glTF mesh can have several primitives, which become separate
+        // meshes here
+        let mut meshes = vec![];
+        let mut primitive_meshes: Vec<Vec<usize>> = vec![];
+        for mesh in document.meshes() {
+            let mut indices = vec![];
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn!("skipping non-triangle primitive in mesh {}", mesh.index());
+                    continue;
+                }
+                let Some(data) = ModelData::_load_primitive(&buffers, &primitive, settings) else {
+                    warn!(
+                        "skipping primitive without positions in mesh {}",
+                        mesh.index()
+                    );
+                    continue;
+                };
+                indices.push(meshes.len());
+                meshes.push(data);
+            }
+            primitive_meshes.push(indices);
+        }
+
+        let mut instances = vec![];
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| format!("gltf model {} has no scenes", path.display()))?;
+        // glTF is Y-up and the renderer is Z-up
+        let root_transform = Mat4::from_rotation_x(90_f32.to_radians());
+        for node in scene.nodes() {
+            ModelData::_add_node_instances(
+                &node,
+                root_transform,
+                root_transform,
+                &primitive_meshes,
+                &mut instances,
+            );
+        }
+
+        Ok(Self {
+            meshes,
+            instances,
+            materials,
+            images: image_data,
+            skeleton: Skeleton::load(&document, &buffers),
+            animations: document
+                .animations()
+                .map(|x| AnimationClip::load(&x, &buffers))
+                .collect(),
+            sources,
+        })
+    }
+
+    // The unit cube used when no model is given, with the default material on
+    // every face
+    pub fn cube(settings: &MeshImportSettings) -> Self {
+        #[rustfmt::skip]
+        let indices: Vec<u32> = vec![
+             0,  1,  2,  2,  1,  3,
+             4,  6,  5,  5,  6,  7,
+             8, 10,  9,  9, 10, 11,
+            12, 13, 14, 14, 13, 15,
+            16, 17, 18, 18, 17, 19,
+            20, 22, 21, 21, 22, 23,
+        ];
+
+        #[rustfmt::skip]
+        let vertices = [
+            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new( 0.0,  0.0, -1.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), normal: Vec3::new( 0.0,  0.0, -1.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), normal: Vec3::new( 0.0,  0.0, -1.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), normal: Vec3::new( 0.0,  0.0, -1.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+
+            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), normal: Vec3::new( 0.0,  0.0,  1.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), normal: Vec3::new( 0.0,  0.0,  1.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), normal: Vec3::new( 0.0,  0.0,  1.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), normal: Vec3::new( 0.0,  0.0,  1.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+
+            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new( 0.0, -1.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), normal: Vec3::new( 0.0, -1.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), normal: Vec3::new( 0.0, -1.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), normal: Vec3::new( 0.0, -1.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+
+            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), normal: Vec3::new( 0.0,  1.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), normal: Vec3::new( 0.0,  1.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), normal: Vec3::new( 0.0,  1.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), normal: Vec3::new( 0.0,  1.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+
+
+            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), normal: Vec3::new(-1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), normal: Vec3::new(-1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), normal: Vec3::new(-1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(-1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+
+            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), normal: Vec3::new( 1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), normal: Vec3::new( 1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(0.0, 1.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), normal: Vec3::new( 1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 0.0)) },
+            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), normal: Vec3::new( 1.0,  0.0,  0.0), tex_coord: Half2::new(Vec2::new(1.0, 1.0)) },
+        ];
+
+        Self {
+            meshes: vec![MeshData::new(
+                vertices.to_vec(),
+                None,
+                &indices,
+                None,
+                settings,
+            )],
+            instances: vec![MeshInstance {
+                mesh: 0,
+                transform: Mat4::IDENTITY,
+                skin: None,
+            }],
+            materials: vec![],
+            images: vec![],
+            skeleton: Skeleton::default(),
+            animations: vec![],
+            sources: vec![],
+        }
+    }
+
+    // The model file and the external buffers and images it uses
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    fn _load_primitive(
+        buffers: &[gltf::buffer::Data],
+        primitive: &gltf::Primitive,
+        settings: &MeshImportSettings,
+    ) -> Option<MeshData> {
+        let reader = primitive.reader(|x| Some(&buffers[x.index()]));
+
+        let positions: Vec<[f32; 3]> = reader.read_positions()?.collect();
+
+        // Missing attributes are zeroed rather than generated
+        let normals: Vec<[f32; 3]> = match reader.read_normals() {
+            Some(normals) => normals.collect(),
+            None => vec![[0.0; 3]; positions.len()],
+        };
+
+        let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(tex_coords) => tex_coords.into_f32().collect(),
+            None => vec![[0.0; 2]; positions.len()],
+        };
+
+        let vertices = positions
+            .iter()
+            .zip(&normals)
+            .zip(&tex_coords)
+            .map(|((position, normal), tex_coord)| Vertex {
+                position: Vec3::from_array(*position),
+                normal: Vec3::from_array(*normal),
+                tex_coord: Half2::new(Vec2::from_array(*tex_coord)),
+            })
+            .collect::<Vec<_>>();
+
+        // Both are needed for skinning
+        let skin_vertices = match (reader.read_joints(0), reader.read_weights(0)) {
+            (Some(joints), Some(weights)) => Some(
+                joints
+                    .into_u16()
+                    .zip(weights.into_f32())
+                    .map(|(joints, weights)| SkinVertex {
+                        joints,
+                        weights: Vec4::from_array(weights),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        };
+
+        // Non-indexed primitives draw their vertices in order
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+
+        // Primitives without a material use the default one
+        let material = primitive.material().index();
+        Some(MeshData::new(
+            vertices,
+            skin_vertices,
+            &indices,
+            material,
+            settings,
+        ))
+    }
+
+    // Skinned meshes ignore their node's transform, as in glTF, and are only
+    // placed by the root transform
+    fn _add_node_instances(
+        node: &gltf::Node,
+        root_transform: Mat4,
+        parent_transform: Mat4,
+        primitive_meshes: &[Vec<usize>],
+        instances: &mut Vec<MeshInstance>,
+    ) {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            let skin = node.skin().map(|x| x.index());
+            for x in &primitive_meshes[mesh.index()] {
+                instances.push(MeshInstance {
+                    mesh: *x,
+                    transform: if skin.is_some() {
+                        root_transform
+                    } else {
+                        transform
+                    },
+                    skin,
+                });
+            }
+        }
+
+        for child in node.children() {
+            ModelData::_add_node_instances(
+                &child,
+                root_transform,
+                transform,
+                primitive_meshes,
+                instances,
+            );
+        }
+    }
+
+    // Textures are always uploaded as RGBA8. Formats that can't be expanded
+    // to it are replaced with opaque white
+    fn _to_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+        use gltf::image::Format;
+        match image.format {
+            Format::R8G8B8A8 => image.pixels.clone(),
+            Format::R8G8B8 => image
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|x| [x[0], x[1], x[2], 255])
+                .collect(),
+            Format::R8G8 => image
+                .pixels
+                .chunks_exact(2)
+                .flat_map(|x| [x[0], x[0], x[0], x[1]])
+                .collect(),
+            Format::R8 => image
+                .pixels
+                .iter()
+                .flat_map(|x| [*x, *x, *x, 255])
+                .collect(),
+            format => {
+                warn!("unsupported gltf image format {:?}", format);
+                vec![255; (image.width * image.height * 4) as usize]
+            }
+        }
+    }
+}
+
+pub struct Model {
+    meshes: Vec<Mesh>,
+    instances: Vec<MeshInstance>,
+    materials: Vec<Material>,
+    skeleton: Skeleton,
+    animations: Vec<AnimationClip>,
+}
+
+impl Model {
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn instances(&self) -> &[MeshInstance] {
+        &self.instances
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn animations(&self) -> &[AnimationClip] {
+        &self.animations
+    }
+
+    pub fn into_parts(self) -> (Vec<Mesh>, Vec<MeshInstance>, Vec<Material>) {
+        (self.meshes, self.instances, self.materials)
+    }
+
+    // Uploads the meshes and textures. `buffer_arena` needs VERTEX_BUFFER
+    // and INDEX_BUFFER usage
+    pub fn upload(
+        data: ModelData,
+        buffer_arena: &BufferArena,
+        uploads: &UploadQueue,
+        texture_cache: &mut TextureCache,
+    ) -> Self {
+        let textures = data
+            .images
+            .iter()
+            .map(|x| texture_cache.load_rgba8(uploads, &x.pixels, x.width, x.height, x.color_space))
+            .collect::<Vec<Arc<Texture>>>();
+        let texture = |x: Option<usize>| x.map(|x| textures[x].clone());
+
+        let materials = data
+            .materials
+            .iter()
+            .map(|x| Material {
+                base_color_factor: x.base_color_factor,
+                metallic_factor: x.metallic_factor,
+                roughness_factor: x.roughness_factor,
+                normal_scale: x.normal_scale,
+                double_sided: x.double_sided,
+                alpha_mode: x.alpha_mode,
+                albedo: texture(x.albedo),
+                normal: texture(x.normal),
+                metallic_roughness: texture(x.metallic_roughness),
+            })
+            .collect();
+
+        Self {
+            meshes: data
+                .meshes
+                .iter()
+                .map(|x| Mesh::new(buffer_arena, uploads, x))
+                .collect(),
+            instances: data.instances,
+            materials,
+            skeleton: data.skeleton,
+            animations: data.animations,
+        }
+    }
+
+    // Loads and uploads a glTF model on the calling thread, see
+    // `ModelData::load`. Fails without touching the device if loading does
+    pub fn load(
+        buffer_arena: &BufferArena,
+        uploads: &UploadQueue,
+        texture_cache: &mut TextureCache,
+        path: impl AsRef<Path>,
+        settings: &MeshImportSettings,
+    ) -> Result<Self, String> {
+        let data = ModelData::load(path, settings)?;
+        Ok(Model::upload(data, buffer_arena, uploads, texture_cache))
+    }
+}
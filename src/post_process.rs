@@ -0,0 +1,438 @@
+use ash::vk;
+use glam::Vec4;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, HotReload, Image, ImageConfig, ImageHandle, ImageUsage, ImageView,
+    MemoryPriority, PipelineLayout, RenderGraph, Sampler, ShaderId, ShaderKind, ShaderRegistry,
+};
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Matches `local_size_x` and `local_size_y` in the post_*.glsl shaders
+const WORKGROUP_SIZE: u32 = 8;
+
+// Effects share a layout with this many sampled inputs, see
+// `PostProcessEffect::inputs`
+pub const MAX_INPUTS: usize = 3;
+
+// Descriptor sets are allocated up front for this many effects per frame
+const MAX_EFFECTS: usize = 16;
+
+// Matches `Constants` in the post_*.glsl shaders
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessConstants {
+    texel_size: [f32; 2],
+    time: f32,
+    _padding: f32,
+    params: Vec4,
+}
+
+// An image an effect samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostProcessInput {
+    // The previous enabled effect's output, or the scene for the first one
+    Previous,
+    // The HDR image before any effect ran
+    Scene,
+    // The bloom pass's result, at half resolution
+    Bloom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PostProcessEffectId(usize);
+
+// A compute shader run over the whole image. It samples its inputs at
+// bindings 1 and up and writes its output to the storage image at binding 0,
+// which the next effect reads as `PostProcessInput::Previous`. What `params`
+// means is up to the shader
+pub struct PostProcessEffect {
+    pub name: String,
+    pub inputs: Vec<PostProcessInput>,
+    pub params: Vec4,
+    pub enabled: bool,
+    shader_id: ShaderId,
+}
+
+struct PostProcessFrame {
+    // One per effect slot
+    descriptor_sets: Vec<DescriptorSet>,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// One enabled effect's dispatch, reading `inputs` and writing `output`
+struct PostProcessDispatch<'a> {
+    set: &'a DescriptorSet,
+    effect: &'a PostProcessEffect,
+    inputs: Vec<(ImageHandle, Arc<ImageView>)>,
+    output: Arc<ImageView>,
+}
+
+// Compute effects run in order over the HDR image between the bloom and
+// tonemap passes, ping-ponging between two storage images. Vignette,
+// chromatic aberration and FXAA are built in and start disabled, and custom
+// shaders can be added with `register`
+pub struct PostProcessChain {
+    pipeline_layout: Arc<PipelineLayout>,
+    sampler: Arc<Sampler>,
+    frames: Vec<PostProcessFrame>,
+    effects: Vec<PostProcessEffect>,
+    // One per effect
+    pipelines: Vec<Arc<ComputePipeline>>,
+}
+
+impl PostProcessChain {
+    // x is the strength and y where the darkening starts, as a fraction of
+    // the distance from the center to the corners
+    pub const VIGNETTE: PostProcessEffectId = PostProcessEffectId(0);
+    // x is how far apart the channels are at the corners, in pixels
+    pub const CHROMATIC_ABERRATION: PostProcessEffectId = PostProcessEffectId(1);
+    // x is the contrast an edge needs relative to its brightest neighbor, y
+    // the contrast below which dark edges are skipped and z how much
+    // subpixel aliasing is smoothed
+    pub const FXAA: PostProcessEffectId = PostProcessEffectId(2);
+
+    // `shader_path` builds the path of a built-in shader from its name
+    pub fn new(
+        device: &Arc<Device>,
+        shader_registry: &mut ShaderRegistry,
+        shader_path: impl Fn(&str) -> String,
+        frames_in_flight: usize,
+    ) -> Self {
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let mut bindings = vec![builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_IMAGE)
+                .stage(vk::ShaderStageFlags::COMPUTE)];
+            for _ in 0..MAX_INPUTS {
+                bindings.push(
+                    builder
+                        .binding()
+                        .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .stage(vk::ShaderStageFlags::COMPUTE),
+                );
+            }
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &bindings,
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<PostProcessConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let set_count = (MAX_EFFECTS * frames_in_flight) as u32;
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            set_count,
+            &[
+                (vk::DescriptorType::STORAGE_IMAGE, set_count),
+                (
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    MAX_INPUTS as u32 * set_count,
+                ),
+            ],
+        );
+        let frames = (0..frames_in_flight)
+            .map(|_| PostProcessFrame {
+                descriptor_sets: descriptor_pool
+                    .allocate(&[&*set_layout; MAX_EFFECTS])
+                    .into_vec(),
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        let mut chain = Self {
+            pipeline_layout,
+            sampler: Sampler::clamped(device.clone()),
+            frames,
+            effects: vec![],
+            pipelines: vec![],
+        };
+
+        // In the order of the ids above
+        let builtins = [
+            ("vignette", "post_vignette", Vec4::new(0.4, 0.5, 0.0, 0.0)),
+            (
+                "chromatic aberration",
+                "post_chromatic_aberration",
+                Vec4::new(2.0, 0.0, 0.0, 0.0),
+            ),
+            ("fxaa", "post_fxaa", Vec4::new(0.125, 0.0312, 0.75, 0.0)),
+        ];
+        for (name, shader, params) in builtins {
+            let id = chain.register(
+                shader_registry,
+                name,
+                &shader_path(shader),
+                &[PostProcessInput::Previous],
+                params,
+            );
+            chain.effects[id.0].enabled = false;
+        }
+
+        chain
+    }
+
+    // Adds an effect after the others, enabled. The shader has to match the
+    // layout described on `PostProcessEffect`, see post_vignette.glsl
+    pub fn register(
+        &mut self,
+        shader_registry: &mut ShaderRegistry,
+        name: &str,
+        shader_path: &str,
+        inputs: &[PostProcessInput],
+        params: Vec4,
+    ) -> PostProcessEffectId {
+        assert!(self.effects.len() < MAX_EFFECTS, "too many post effects");
+        assert!(inputs.len() <= MAX_INPUTS, "too many post effect inputs");
+
+        let shader_id = shader_registry.load(shader_path, ShaderKind::Compute, "main");
+        let pipeline = ComputePipeline::new(
+            self.pipeline_layout.device().clone(),
+            shader_registry.get(shader_id),
+            &self.pipeline_layout,
+        );
+        self.effects.push(PostProcessEffect {
+            name: name.to_string(),
+            inputs: inputs.to_vec(),
+            params,
+            enabled: true,
+            shader_id,
+        });
+        self.pipelines.push(pipeline);
+        PostProcessEffectId(self.effects.len() - 1)
+    }
+
+    pub fn effect(&self, id: PostProcessEffectId) -> &PostProcessEffect {
+        &self.effects[id.0]
+    }
+
+    pub fn effect_mut(&mut self, id: PostProcessEffectId) -> &mut PostProcessEffect {
+        &mut self.effects[id.0]
+    }
+
+    pub fn find(&self, name: &str) -> Option<PostProcessEffectId> {
+        self.effects
+            .iter()
+            .position(|x| x.name == name)
+            .map(PostProcessEffectId)
+    }
+
+    pub fn effects(&self) -> impl Iterator<Item = (PostProcessEffectId, &PostProcessEffect)> {
+        self.effects
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (PostProcessEffectId(i), x))
+    }
+
+    // The two images effects alternate writing to. Like the draw images these
+    // are per frame and have to be recreated when the extent changes
+    pub fn create_images(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Vec<Arc<Image>> {
+        (0..2)
+            .map(|_| {
+                Image::new(
+                    device.clone(),
+                    allocator.clone(),
+                    ImageConfig::new(
+                        FORMAT,
+                        extent,
+                        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                    )
+                    .priority(MemoryPriority::High),
+                )
+            })
+            .collect()
+    }
+
+    // Adds a pass for each enabled effect to the graph. `images` are the
+    // frame's images from `create_images`. Returns the last effect's output,
+    // or `scene` if none are enabled
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        scene: (ImageHandle, Arc<ImageView>),
+        bloom: (ImageHandle, Arc<ImageView>),
+        images: &'a [Arc<Image>],
+        time: f32,
+    ) -> (ImageHandle, Arc<ImageView>) {
+        let frame = &self.frames[frame_index];
+        frame.views.borrow_mut().clear();
+
+        let targets = images
+            .iter()
+            .map(|image| {
+                let handle = graph.import_image(
+                    image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags2::NONE,
+                );
+                (handle, image.get_default_view(vk::ImageAspectFlags::COLOR))
+            })
+            .collect::<Vec<_>>();
+
+        let mut previous = scene.clone();
+        let enabled = self
+            .effects
+            .iter()
+            .zip(&self.pipelines)
+            .zip(&frame.descriptor_sets);
+        for (i, ((effect, pipeline), set)) in enabled.filter(|((x, _), _)| x.enabled).enumerate() {
+            let output = targets[i % 2].clone();
+            let inputs = effect
+                .inputs
+                .iter()
+                .map(|x| match x {
+                    PostProcessInput::Previous => previous.clone(),
+                    PostProcessInput::Scene => scene.clone(),
+                    PostProcessInput::Bloom => bloom.clone(),
+                })
+                .collect::<Vec<_>>();
+
+            let mut pass = graph
+                .add_pass("post process")
+                .write_image(output.0, ImageUsage::Storage);
+            let mut read = vec![];
+            for (handle, _) in &inputs {
+                if !read.contains(handle) {
+                    pass = pass.read_image(*handle, ImageUsage::Sampled);
+                    read.push(*handle);
+                }
+            }
+            let output_view = output.1.clone();
+            pass.record(move |cmd| {
+                cmd.bind_pipeline(pipeline.as_ref());
+                let dispatch = PostProcessDispatch {
+                    set,
+                    effect,
+                    inputs,
+                    output: output_view,
+                };
+                self._record_effect(cmd, frame, dispatch, time);
+            });
+
+            previous = output;
+        }
+
+        previous
+    }
+
+    fn _record_effect(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame: &PostProcessFrame,
+        dispatch: PostProcessDispatch,
+        time: f32,
+    ) {
+        let PostProcessDispatch {
+            set,
+            effect,
+            inputs,
+            output,
+        } = dispatch;
+
+        // Unused inputs are bound to the first one, so every binding is valid
+        let input_views = (0..MAX_INPUTS)
+            .map(|i| inputs.get(i).or(inputs.first()).map(|x| x.1.clone()))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_else(|| vec![output.clone(); MAX_INPUTS]);
+
+        {
+            let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+            writer.write_images(
+                set,
+                0,
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                None,
+                &[(&output, vk::ImageLayout::GENERAL)],
+            );
+            for (i, view) in input_views.iter().enumerate() {
+                // Unused inputs may be the output itself, which is in GENERAL
+                let layout = if inputs.is_empty() {
+                    vk::ImageLayout::GENERAL
+                } else {
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                };
+                writer.write_image(
+                    set,
+                    &self.sampler,
+                    view,
+                    layout,
+                    1 + i as u32,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                );
+            }
+            writer.flush();
+        }
+
+        let extent = output.image().extent();
+        let constants = PostProcessConstants {
+            texel_size: [1.0 / extent.width as f32, 1.0 / extent.height as f32],
+            time,
+            _padding: 0.0,
+            params: effect.params,
+        };
+
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &constants,
+        );
+        cmd_buf.dispatch(
+            extent.width.div_ceil(WORKGROUP_SIZE),
+            extent.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        let mut views = frame.views.borrow_mut();
+        views.extend(input_views);
+        views.push(output);
+    }
+}
+
+impl HotReload for PostProcessChain {
+    type Pipelines = Vec<Arc<ComputePipeline>>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Vec<Arc<ComputePipeline>> {
+        self.effects
+            .iter()
+            .map(|effect| {
+                ComputePipeline::new(
+                    self.pipeline_layout.device().clone(),
+                    shader_registry.get(effect.shader_id),
+                    &self.pipeline_layout,
+                )
+            })
+            .collect()
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Vec<Arc<ComputePipeline>> {
+        &mut self.pipelines
+    }
+}
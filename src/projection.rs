@@ -0,0 +1,88 @@
+use ash::vk;
+use glam::Mat4;
+
+// Which way depth runs in [0, 1]. Reversed depth puts the near plane at 1 and
+// the far plane at 0, where float depth is most precise, which evens out the
+// precision over distance and keeps distant surfaces from z-fighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DepthDirection {
+    #[default]
+    Standard,
+    Reversed,
+}
+
+impl DepthDirection {
+    // Depth of the far plane, which is what depth buffers are cleared to
+    pub fn far_depth(self) -> f32 {
+        match self {
+            DepthDirection::Standard => 1.0,
+            DepthDirection::Reversed => 0.0,
+        }
+    }
+
+    pub fn clear_value(self) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: self.far_depth(),
+                stencil: 0,
+            },
+        }
+    }
+
+    // Passes fragments nearer than the depth buffer
+    pub fn compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthDirection::Standard => vk::CompareOp::LESS,
+            DepthDirection::Reversed => vk::CompareOp::GREATER,
+        }
+    }
+
+    // Also passes fragments at the same depth, e.g. the sky on the far plane
+    pub fn compare_op_or_equal(self) -> vk::CompareOp {
+        match self {
+            DepthDirection::Standard => vk::CompareOp::LESS_OR_EQUAL,
+            DepthDirection::Reversed => vk::CompareOp::GREATER_OR_EQUAL,
+        }
+    }
+}
+
+// Right-handed perspective projection into [0, 1] depth running in
+// `direction`. `far` can be `f32::INFINITY`, which leaves out the far plane
+pub fn perspective(
+    fov_y: f32,
+    aspect_ratio: f32,
+    near: f32,
+    far: f32,
+    direction: DepthDirection,
+) -> Mat4 {
+    match (direction, far.is_finite()) {
+        (DepthDirection::Standard, true) => Mat4::perspective_rh(fov_y, aspect_ratio, near, far),
+        (DepthDirection::Standard, false) => {
+            Mat4::perspective_infinite_rh(fov_y, aspect_ratio, near)
+        }
+        // Swapping the planes maps near to 1 and far to 0
+        (DepthDirection::Reversed, true) => Mat4::perspective_rh(fov_y, aspect_ratio, far, near),
+        (DepthDirection::Reversed, false) => {
+            Mat4::perspective_infinite_reverse_rh(fov_y, aspect_ratio, near)
+        }
+    }
+}
+
+// Right-handed orthographic projection into [0, 1] depth running in
+// `direction`. Depth is linear, so reversing it doesn't change its precision,
+// only which way the depth test has to compare
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+    direction: DepthDirection,
+) -> Mat4 {
+    assert!(far.is_finite(), "orthographic projections need a far plane");
+    match direction {
+        DepthDirection::Standard => Mat4::orthographic_rh(left, right, bottom, top, near, far),
+        DepthDirection::Reversed => Mat4::orthographic_rh(left, right, bottom, top, far, near),
+    }
+}
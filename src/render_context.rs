@@ -1,117 +1,260 @@
 extern crate ash;
 
 use ash::vk;
-use glam::{f32::Mat4, Vec2, Vec3};
-use image::EncodableLayout;
-use memoffset::offset_of;
-use std::{borrow::BorrowMut, mem::size_of, rc::Rc, sync::Arc, time::Instant};
+use glam::{f32::Mat4, Vec2, Vec3, Vec4};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{mem::size_of, sync::Arc, time::Instant};
+use tracing::{debug, debug_span, info, trace_span, warn};
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::animation::{AnimationPlayer, Skeleton};
+use crate::asset_server::{AssetServer, LoadedAsset};
+use crate::bloom::BloomPass;
+use crate::camera::{Camera, Projection};
+use crate::config::Config;
+use crate::culling::{CullView, CullingPass};
+use crate::debug_draw::{DebugDraw, DebugDrawPass};
+use crate::depth_pyramid::DepthPyramidPass;
+use crate::frame_stats::{FrameStats, PassTime};
+use crate::lights::{Light, LightManager};
+use crate::lod::LodSettings;
+use crate::material::{Material, MaterialFeatures, MaterialTable};
+use crate::mesh_optimizer::MeshImportSettings;
+use crate::model::{Model, ModelData, Vertex};
+use crate::post_process::{PostProcessChain, PostProcessEffectId, PostProcessInput};
+use crate::projection::DepthDirection;
+use crate::render_world::{MeshId, ModelInstance, RenderWorld, Renderable};
+use crate::shadow::{DirectionalLight, ShadowPass, ShadowShaders};
+use crate::skybox::{EquirectImage, SkyboxPass, SkyboxShaders};
+use crate::ssao::{self, SsaoPass, SsaoShaders};
+use crate::taa::{self, TaaPass};
+use crate::texture_cache::{ColorSpace, Texture, TextureCache};
+use crate::tonemap::{TonemapOperator, TonemapPass};
+
 use crate::gpu::{
-    Buffer, CommandBuffer, CommandPool, DescriptorPool, DescriptorSet, DescriptorSetLayout, Device,
-    Fence, GraphicsPipeline, HasRawAshHandle, HasRawVkHandle, Image, ImageView, Instance,
-    PhysicalDevice, PipelineLayout, Sampler, Semaphore, ShaderKind, ShaderModule, Swapchain,
+    alpha_blend_attachment, opaque_blend_attachment, rendering_attachment, resolved_attachment,
+    Buffer, BufferArena, BufferUsage, CommandBuffer, CommandPool, DescriptorAllocator,
+    DescriptorPool, DescriptorSet, DescriptorSetLayout, DescriptorWriter, Device, DeviceFeature,
+    DeviceFeaturesRequest, DeviceSelector, FeatureChain, Fence, FrameRing, GraphicsPipeline,
+    HasRawAshHandle, HasRawVkHandle, HotReload, Image, ImageConfig, ImageUsage, Instance,
+    MemoryPriority, PhysicalDevice, PipelineLayout, PipelineStatistics, PresentModePreference,
+    QueryPool, RenderGraph, RenderingConfig, Sampler, Semaphore, ShaderId, ShaderKind,
+    ShaderModule, ShaderRegistry, StatsQuery, SurfaceFormat, SurfaceFormatPreference, Swapchain,
+    UploadQueue, VertexLayout,
 };
 
-pub struct RenderContext {
+// What to do when the swapchain reports it's suboptimal for the surface but
+// can still be presented to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuboptimalPolicy {
+    // Skip the frame as soon as the acquire reports suboptimal and recreate
+    RecreateImmediately,
+    // Render and present the frame, then recreate
+    RecreateAfterPresent,
+    // Keep presenting to a suboptimal swapchain for up to N frames in a row
+    // before recreating, to avoid thrashing while a resize is in progress
+    IgnoreFor(u32),
+}
+
+// Each pass takes two timestamp queries
+const MAX_TIMED_PASSES: u32 = 32;
+
+enum FrameStatus {
+    Presented { suboptimal: bool },
+    // The frame was submitted but couldn't be presented
+    OutOfDate,
+    // Nothing was submitted and the swapchain has to be recreated
+    Skipped,
+}
+
+// Draws a `RenderWorld` into a window. The app fills the world with models
+// and renderables and sets up the camera and lights
+pub struct Renderer {
     start_time: Instant,
-    frame_count: u64,
+    last_frame_start: Instant,
+    frame_stats: FrameStats,
     window: Arc<Window>,
-    instance: Arc<Instance>,
     physical_device: Arc<PhysicalDevice>,
     device: Arc<Device>,
     allocator: Arc<vma::Allocator>,
     swapchain: Swapchain,
-    shader_modules: Vec<Arc<ShaderModule>>,
-    graphics_pipeline: Arc<GraphicsPipeline>,
-    draw_images: Vec<Arc<Image>>,
+    shader_registry: ShaderRegistry,
+    shader_ids: Vec<ShaderId>,
+    // One for each combination of material features in use
+    graphics_pipelines: HashMap<MaterialFeatures, Arc<GraphicsPipeline>>,
+    // Depth tests meshes that were occluded last time without shading them
+    occlusion_probe_pipeline: Arc<GraphicsPipeline>,
+    // vertex_pulling.glsl, if buffer device addresses are supported
+    vertex_pulling_shader: Option<ShaderId>,
+    // Replaces vertex.glsl in the skinned copy of each variant
+    skinned_vertex_shader: ShaderId,
+    // Gives the depth prepass a fragment shader writing the G-buffer while
+    // SSAO is on
+    gbuffer_shader: ShaderId,
+    // Same variants as `graphics_pipelines` without vertex input bindings
+    vertex_pulling_pipelines: HashMap<MaterialFeatures, Arc<GraphicsPipeline>>,
+    vertex_pulling: bool,
+    // Depth-only pipelines for the prepass, only built while it's enabled
+    depth_prepass_pipelines: HashMap<MaterialFeatures, Arc<GraphicsPipeline>>,
+    depth_prepass: bool,
+    occlusion_culling: bool,
+    // Needs the depth prepass, see `set_hi_z_culling`
+    hi_z_culling: bool,
+    cull_distance: Option<f32>,
+    lod_settings: LodSettings,
+    draw_extent: vk::Extent3D,
+    msaa_samples: vk::SampleCountFlags,
     pipeline_layout: Arc<PipelineLayout>,
-    descriptor_pool: DescriptorPool,
-    descriptor_sets: Box<[DescriptorSet]>,
-    uniform_buffers: Vec<Buffer>,
-    texture_image: Arc<Image>,
-    texture_image_view: Arc<ImageView>,
-    sampler: Arc<Sampler>,
-    indices: Vec<u16>,
-    index_buffer: Buffer,
-    vertex_buffers: Vec<Buffer>,
-    cmd_pool: Rc<CommandPool>,
-    render_frames: Vec<RenderFrame>,
-    current_frame: usize,
+    texture_cache: TextureCache,
+    descriptor_allocator: DescriptorAllocator,
+    // The default material followed by each model's, in model order
+    materials: Vec<Material>,
+    material_set_layout: Arc<DescriptorSetLayout>,
+    material_table: MaterialTable,
+    // Bound in place of a material's missing textures
+    white_texture: Arc<Texture>,
+    world: RenderWorld,
+    // Indexed by `ModelId`, None once removed
+    models: Vec<Option<RendererModel>>,
+    // Meshes of replaced and removed models, removed from the world once
+    // nothing draws them anymore
+    retired_meshes: Vec<MeshId>,
+    asset_server: AssetServer,
+    mesh_import_settings: MeshImportSettings,
+    culling_pass: CullingPass,
+    depth_pyramid_pass: DepthPyramidPass,
+    shadow_pass: ShadowPass,
+    light: DirectionalLight,
+    shadow_pcf: bool,
+    light_manager: LightManager,
+    skybox_pass: SkyboxPass,
+    debug_draw: DebugDraw,
+    debug_draw_pass: DebugDrawPass,
+    debug_overlay: bool,
+    bloom_pass: BloomPass,
+    post_process: PostProcessChain,
+    taa_pass: TaaPass,
+    // Replaces MSAA while on, see `set_taa`
+    taa: bool,
+    ssao_pass: SsaoPass,
+    // Needs the depth prepass, see `set_ssao`
+    ssao: bool,
+    // Of the camera's depth buffer, see `set_depth_direction`
+    depth_direction: DepthDirection,
+    tonemap_pass: TonemapPass,
+    camera: Camera,
+    buffer_arena: BufferArena,
+    uploads: UploadQueue,
+    frames: FrameRing<RenderFrame>,
+    frame_dump_path: Option<PathBuf>,
+    suboptimal_policy: SuboptimalPolicy,
+    suboptimal_frames: u32,
+    pending_resize: Option<(u32, u32)>,
+    surface_format_preference: SurfaceFormatPreference,
+    present_mode_preference: PresentModePreference,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelId(usize);
+
+// A model added with `Renderer::add_model` or `Renderer::load_model`
+struct RendererModel {
+    // Set for models loaded from a file, which are replaced when it changes
+    path: Option<PathBuf>,
+    // Where the model's materials are in the material table
+    first_material: usize,
+    material_count: usize,
+    // Empty until a model loaded in the background is ready
+    instances: Vec<ModelInstance>,
+    // Where the instances are placed, see `Renderer::place_model`
+    transform: Mat4,
+    // Poses the skinned instances every frame
+    animation_player: AnimationPlayer,
+}
+
+// Settings baked into the main pass' pipelines, which are rebuilt when any of
+// them change
+#[derive(Clone, Copy)]
+struct PipelineSettings {
+    samples: vk::SampleCountFlags,
+    shadow_pcf: bool,
+    vertex_pulling: bool,
+    depth_prepass: bool,
+    motion_vectors: bool,
+    depth_direction: DepthDirection,
 }
 
 struct SurfaceDetails {
     present_mode: vk::PresentModeKHR,
-    format: vk::SurfaceFormatKHR,
+    format: SurfaceFormat,
     extent: vk::Extent2D,
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct Uniform {
-    model: Mat4,
     view: Mat4,
     proj: Mat4,
+    light_space: Mat4,
+    light_direction: Vec4,
+    light_color: Vec4,
+    camera_position: Vec4,
+    // Only read by fragment.glsl, for the motion vectors
+    previous_view_proj: Mat4,
+    jitter: Vec4,
 }
 
-#[repr(C)]
-struct Vertex {
-    position: Vec3,
-    color: Vec3,
-    tex_coord: Vec2,
-}
-
-impl RenderContext {
-    pub fn new(window: Arc<Window>, max_frames_in_flight: usize) -> Self {
-        let instance = Instance::new(&window);
+impl Renderer {
+    // Starts with an empty world, see `add_model` and `world_mut`. Everything
+    // is drawn in front of the config's equirectangular environment map,
+    // which is loaded in the background with a gradient shown until it's
+    // ready, or just the gradient
+    pub fn new(window: Arc<Window>, config: &Config) -> Self {
+        let max_frames_in_flight = config.frames_in_flight;
+        let instance = Instance::new(&window, config.validation);
 
-        let required_queue_flags = &[vk::QueueFlags::GRAPHICS];
+        let required_queue_flags = vk::QueueFlags::GRAPHICS;
 
         let required_extensions: &[&[u8]] = &[
             // b"VK_EXT_debug_utils\0",
             b"VK_KHR_swapchain\0",
-            b"VK_KHR_dynamic_rendering\0",
-            b"VK_KHR_synchronization2\0",
         ];
 
-        // Find the first physical device that supports the swapchain extension
-        // and is preferably a discrete GPU
-        let physical_device = instance
-            .get_physical_devices()
-            .into_iter()
-            .min_by_key(|x| match x.device_type() {
-                // Rank each physical device by the type, with preference for discrete
-                vk::PhysicalDeviceType::DISCRETE_GPU => 0,
-                vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
-                _ => 4,
-            })
-            .filter(|x| {
-                // Filter for physical devices that support all of the required
-                // queue flags and the window surface
-                let mut supports_surface = false;
-                let mut flags = Vec::from(required_queue_flags);
-                for (i, properties) in x.get_queue_family_properties().iter().enumerate() {
-                    if x.supports_surface(i.try_into().unwrap()) {
-                        supports_surface = true;
-                    }
-                    flags.retain(|x| !properties.queue_flags.contains(*x));
-                }
-                supports_surface && flags.len() == 0
-            })
-            .filter(|x| {
-                let features = x.device_features();
-                features.sampler_anisotropy == vk::TRUE
-            })
-            .filter(|x| {
-                // Filter for physical devices that support all of the required
-                // extensions
-                let extensions_hashset = x.extension_name_hashset();
-                required_extensions
-                    .iter()
-                    .all(|ext| extensions_hashset.contains(ext))
-            })
-            .unwrap();
+        // Enabled if available, check `Device::is_extension_enabled` before
+        // using them
+        let optional_extensions: &[&[u8]] = &[
+            b"VK_EXT_memory_budget\0",
+            b"VK_KHR_push_descriptor\0",
+            b"VK_EXT_device_fault\0",
+            b"VK_EXT_memory_priority\0",
+            b"VK_EXT_pageable_device_local_memory\0",
+        ];
 
-        println!("physical_device.name = {}", physical_device.device_name());
+        // Pick a physical device that supports the window surface and all of
+        // the requirements, preferably a discrete GPU. The config and then the
+        // environment can name one explicitly
+        let features = DeviceFeaturesRequest::new()
+            .request(DeviceFeature::SamplerAnisotropy)
+            .request(DeviceFeature::PipelineStatisticsQuery)
+            .request(DeviceFeature::OcclusionQueryPrecise)
+            .request(DeviceFeature::MultiDrawIndirect);
+        let adapters =
+            instance.enumerate_adapters(required_queue_flags, required_extensions, &features);
+        for adapter in &adapters {
+            debug!("adapter{}", adapter);
+        }
+        let physical_device = DeviceSelector::new()
+            .index(config.gpu_index)
+            .name(config.gpu_name.clone())
+            .env_override()
+            .select(&adapters)
+            .expect("no suitable physical device")
+            .physical_device
+            .clone();
 
         // Select queue family indices for logical device creation
         let mut queue_family_indices = vec![];
@@ -120,7 +263,7 @@ impl RenderContext {
             .iter()
             .enumerate()
         {
-            println!(
+            debug!(
                 "queue_family[{}] = (queue_count = {}, {:?})",
                 i, x.queue_count, x.queue_flags
             );
@@ -141,61 +284,142 @@ impl RenderContext {
             }
         }
 
-        let device = physical_device.get_device(&queue_family_indices, required_extensions);
+        let device = physical_device.get_device(
+            &queue_family_indices,
+            required_extensions,
+            optional_extensions,
+            &features,
+            FeatureChain::new(),
+        );
+
+        for name in optional_extensions {
+            if device.is_extension_enabled(name) {
+                debug!(
+                    "optional_extension = {}",
+                    CStr::from_bytes_with_nul(name).unwrap().to_string_lossy()
+                );
+            }
+        }
+
+        // The config's environment map starts loading right away, so it
+        // overlaps with the rest of the setup
+        let mut asset_server = AssetServer::new(0);
+        if let Some(path) = &config.environment_path {
+            asset_server.load_environment(path);
+        }
 
         let allocator = unsafe {
+            let mut flags = vma::AllocatorCreateFlags::empty();
+            if device.is_extension_enabled(b"VK_EXT_memory_budget\0") {
+                flags |= vma::AllocatorCreateFlags::EXT_MEMORY_BUDGET;
+            }
+            if device.is_extension_enabled(b"VK_EXT_memory_priority\0") {
+                flags |= vma::AllocatorCreateFlags::EXT_MEMORY_PRIORITY;
+            }
+            if device.supports_buffer_device_address() {
+                flags |= vma::AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
+            }
+
             let info = vma::AllocatorCreateInfo::new(
                 instance.get_ash_handle(),
                 device.get_ash_handle(),
                 physical_device.get_vk_handle(),
-            );
+            )
+            .flags(flags);
             Arc::new(vma::Allocator::new(info).expect("failed to create vma allocator"))
         };
 
         let swapchain = {
             let inner_size = window.inner_size();
-            RenderContext::_create_swapchain(
+            Renderer::_create_swapchain(
                 device.clone(),
                 inner_size.width,
                 inner_size.height,
                 None,
+                SurfaceFormatPreference::default(),
+                config.present_mode,
             )
         };
 
-        let shader_compiler = shaderc::Compiler::new().unwrap();
-
-        let shader_modules = vec![
-            ShaderModule::new(
-                device.clone(),
-                &shader_compiler,
-                include_str!("./shaders/vertex.glsl"),
-                ShaderKind::Vertex,
-                "vertex.glsl",
-                "main",
-                None,
-            ),
-            ShaderModule::new(
-                device.clone(),
-                &shader_compiler,
-                include_str!("./shaders/fragment.glsl"),
-                ShaderKind::Fragment,
-                "fragment.glsl",
-                "main",
-                None,
-            ),
+        // Loaded from disk rather than embedded so they can be edited while
+        // running, see `ShaderRegistry::poll`. Without shaderc they have to be
+        // precompiled next to the sources, which build.rs does with glslc
+        let shader_extension = if cfg!(feature = "shaderc") {
+            "glsl"
+        } else {
+            "spv"
+        };
+        let shader_path = |name: &str| {
+            config
+                .shader_dir
+                .join(format!("{}.{}", name, shader_extension))
+                .display()
+                .to_string()
+        };
+        let mut shader_registry = ShaderRegistry::new(device.clone());
+        let shader_ids = vec![
+            shader_registry.load(shader_path("vertex"), ShaderKind::Vertex, "main"),
+            shader_registry.load(shader_path("fragment"), ShaderKind::Fragment, "main"),
         ];
+        // Buffer references need buffer device addresses to compile
+        let vertex_pulling_shader = device.supports_buffer_device_address().then(|| {
+            shader_registry.load(shader_path("vertex_pulling"), ShaderKind::Vertex, "main")
+        });
+        let skinned_vertex_shader =
+            shader_registry.load(shader_path("skinned_vertex"), ShaderKind::Vertex, "main");
+        let gbuffer_shader = shader_registry.load(
+            shader_path("gbuffer_fragment"),
+            ShaderKind::Fragment,
+            "main",
+        );
 
-        let draw_image_format = vk::Format::R16G16B16A16_SFLOAT;
-
+        // Set 0 is per frame and set 1 is per material
         let descriptor_set_layout = {
             let mut builder = DescriptorSetLayout::builder();
 
             let uniform_binding = builder
                 .binding()
                 .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+            // Mesh instances and the ones that survived culling
+            let object_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX);
+            let visible_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX);
+
+            // The frame's shadow map
+            let shadow_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+
+            // Cluster parameters, lights and the lights in each cluster
+            let cluster_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+            let light_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+            let cluster_light_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+
+            // Joint matrices of skinned meshes
+            let joint_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
                 .stage(vk::ShaderStageFlags::VERTEX);
 
-            let sampler_binding = builder
+            // The frame's ambient occlusion
+            let ao_binding = builder
                 .binding()
                 .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                 .stage(vk::ShaderStageFlags::FRAGMENT);
@@ -203,12 +427,32 @@ impl RenderContext {
             builder.build(
                 device.clone(),
                 vk::DescriptorSetLayoutCreateFlags::empty(),
-                &[uniform_binding, sampler_binding],
+                &[
+                    uniform_binding,
+                    object_binding,
+                    visible_binding,
+                    shadow_binding,
+                    cluster_binding,
+                    light_binding,
+                    cluster_light_binding,
+                    joint_binding,
+                    ao_binding,
+                ],
             )
         };
 
-        let pipeline_layout =
-            PipelineLayout::new(device.clone(), &[descriptor_set_layout.clone()], &[]);
+        let material_set_layout = MaterialTable::create_set_layout(&device);
+
+        // Mesh instance transforms are read from the culling pass' buffers.
+        // The push constant is the vertex address for vertex_pulling.glsl
+        let pipeline_layout = device.get_pipeline_layout(
+            &[descriptor_set_layout.clone(), material_set_layout.clone()],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: size_of::<vk::DeviceAddress>() as u32,
+            }],
+        );
 
         let uniform_buffers = {
             let buffer_size = size_of::<Uniform>();
@@ -223,6 +467,7 @@ impl RenderContext {
                     vma::MemoryUsage::AutoPreferHost,
                     vma::AllocationCreateFlags::MAPPED
                         | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+                    MemoryPriority::Normal,
                 );
 
                 uniform_buffers.push(uniform_buffer);
@@ -239,70 +484,182 @@ impl RenderContext {
             vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
         );
 
-        let texture_image: Arc<Image>;
-        let texture_image_view: Arc<ImageView>;
-        let sampler: Arc<Sampler>;
-
-        {
-            let image_path = "./checker-map.png";
-            let image_buffer = image::open(image_path).unwrap().to_rgba8();
-            let image_bytes = image_buffer.as_bytes();
+        // Every upload, staged in a 16 MiB ring where it fits and recorded at
+        // the start of the next frame
+        let uploads = UploadQueue::new(
+            device.clone(),
+            allocator.clone(),
+            16 * 1024 * 1024,
+            max_frames_in_flight,
+        );
 
-            let staging_buffer = Buffer::new(
-                device.clone(),
-                allocator.clone(),
-                image_bytes.len(),
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vma::MemoryUsage::AutoPreferHost,
-                vma::AllocationCreateFlags::MAPPED
-                    | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
-            );
+        let mut texture_cache = TextureCache::new(device.clone(), Sampler::new(device.clone()));
 
-            staging_buffer.copy_nonoverlapping(image_bytes);
+        // Vertex and index data for every mesh
+        let mut arena_usage =
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER;
+        if vertex_pulling_shader.is_some() {
+            arena_usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+        let buffer_arena = BufferArena::new(
+            device.clone(),
+            allocator.clone(),
+            arena_usage,
+            32 * 1024 * 1024,
+        );
 
-            texture_image = Image::new(
-                device.clone(),
-                allocator.clone(),
-                vk::ImageType::TYPE_2D,
-                vk::Format::R8G8B8A8_SRGB,
-                vk::Extent3D {
-                    width: image_buffer.width(),
-                    height: image_buffer.height(),
-                    depth: 1,
-                },
-                1,
-                1,
-                vk::SampleCountFlags::TYPE_1,
-                vk::ImageTiling::OPTIMAL,
-                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-                vma::MemoryUsage::AutoPreferDevice,
-                vma::AllocationCreateFlags::empty(),
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            );
+        let culling_pass = CullingPass::new(
+            &device,
+            &allocator,
+            &mut shader_registry,
+            &shader_path("cull"),
+            max_frames_in_flight,
+        );
 
-            let cmds = cmd_pool.allocate_one(vk::CommandBufferLevel::PRIMARY);
+        let depth_pyramid_pass = DepthPyramidPass::new(
+            &device,
+            &mut shader_registry,
+            &shader_path("depth_pyramid"),
+            &shader_path("depth_pyramid_msaa"),
+            max_frames_in_flight,
+        );
 
-            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            cmds.transition_image(
-                &texture_image,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-            cmds.copy_buffer_to_image(&staging_buffer, &texture_image);
-            cmds.transition_image(
-                &texture_image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            );
-            cmds.end();
+        let shadow_pass = ShadowPass::new(
+            &device,
+            &allocator,
+            &mut shader_registry,
+            ShadowShaders {
+                vertex: &shader_path("shadow"),
+                skinned_vertex: &shader_path("shadow_skinned"),
+            },
+            &culling_pass,
+            2048,
+            max_frames_in_flight,
+        );
 
-            graphics_queue.submit(None, &[&cmds], None, None);
-            graphics_queue.wait_idle();
+        let light_manager = LightManager::new(
+            &device,
+            &allocator,
+            &mut shader_registry,
+            &shader_path("light_cull"),
+            max_frames_in_flight,
+        );
 
-            texture_image_view = texture_image.get_default_view(vk::ImageAspectFlags::COLOR);
-            sampler = Sampler::new(device.clone());
+        let msaa_samples = match config.msaa_sample_flags() {
+            x if physical_device.supports_sample_count(x) => x,
+            x => {
+                warn!("MSAA {:?} is not supported, falling back to 1 sample", x);
+                vk::SampleCountFlags::TYPE_1
+            }
         };
 
+        // Likewise the gradient for the environment map
+        let environment = EquirectImage::gradient();
+        let skybox_pass = SkyboxPass::new(
+            &device,
+            &allocator,
+            &uploads,
+            &mut shader_registry,
+            SkyboxShaders {
+                vertex: &shader_path("skybox_vertex"),
+                fragment: &shader_path("skybox_fragment"),
+                convert: &shader_path("equirect_to_cube"),
+            },
+            &environment,
+            msaa_samples,
+        );
+
+        let debug_draw_pass = DebugDrawPass::new(
+            &device,
+            &allocator,
+            &mut shader_registry,
+            &shader_path("debug_vertex"),
+            &shader_path("debug_fragment"),
+            max_frames_in_flight,
+        );
+
+        let bloom_pass = BloomPass::new(
+            &device,
+            &mut shader_registry,
+            &shader_path("fullscreen"),
+            &shader_path("bloom_downsample"),
+            &shader_path("bloom_upsample"),
+            max_frames_in_flight,
+        );
+
+        let taa_pass = TaaPass::new(
+            &device,
+            &mut shader_registry,
+            &shader_path("taa_resolve"),
+            max_frames_in_flight,
+        );
+
+        let ssao_pass = SsaoPass::new(
+            &device,
+            &allocator,
+            &uploads,
+            &mut texture_cache,
+            &mut shader_registry,
+            SsaoShaders {
+                ssao: &shader_path("ssao"),
+                blur: &shader_path("ssao_blur"),
+            },
+            max_frames_in_flight,
+        );
+
+        let post_process = PostProcessChain::new(
+            &device,
+            &mut shader_registry,
+            shader_path,
+            max_frames_in_flight,
+        );
+
+        let tonemap_pass = TonemapPass::new(
+            &device,
+            &mut shader_registry,
+            &shader_path("fullscreen"),
+            &shader_path("tonemap"),
+            *swapchain.format(),
+            max_frames_in_flight,
+        );
+
+        let white_texture = texture_cache.load_rgba8(&uploads, &[255; 4], 1, 1, ColorSpace::Linear);
+        // Meshes without a material use the checker map, or white without it
+        let default_texture_path = "./checker-map.png";
+        let default_texture = texture_cache
+            .load(&uploads, default_texture_path, ColorSpace::Srgb)
+            .unwrap_or_else(|error| {
+                warn!("{}", error);
+                white_texture.clone()
+            });
+        asset_server.watch_texture(default_texture_path, ColorSpace::Srgb);
+
+        let materials = vec![Material {
+            albedo: Some(default_texture),
+            ..Default::default()
+        }];
+
+        // Material sets have a uniform block and three textures each
+        // Sets are freed when models are added, replaced or removed
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            16,
+            &[
+                (vk::DescriptorType::UNIFORM_BUFFER, 1.0),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 3.0),
+            ],
+        );
+
+        let material_table = MaterialTable::new(
+            &device,
+            &uploads,
+            &mut descriptor_allocator,
+            &material_set_layout,
+            &materials,
+            &white_texture,
+        );
+
         let descriptor_pool = DescriptorPool::new(
             device.clone(),
             vk::DescriptorPoolCreateFlags::empty(),
@@ -310,11 +667,15 @@ impl RenderContext {
             &[
                 (
                     vk::DescriptorType::UNIFORM_BUFFER,
-                    max_frames_in_flight.try_into().unwrap(),
+                    (2 * max_frames_in_flight).try_into().unwrap(),
+                ),
+                (
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    (5 * max_frames_in_flight).try_into().unwrap(),
                 ),
                 (
                     vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                    max_frames_in_flight.try_into().unwrap(),
+                    (2 * max_frames_in_flight).try_into().unwrap(),
                 ),
             ],
         );
@@ -327,286 +688,266 @@ impl RenderContext {
             descriptor_pool.allocate(&layouts)
         };
 
-        for (i, uniform_buffer) in uniform_buffers.iter().enumerate() {
-            descriptor_sets[i].write_buffer(
-                uniform_buffer,
-                0,
-                size_of::<Uniform>().try_into().unwrap(),
-                0,
-                0,
-                vk::DescriptorType::UNIFORM_BUFFER,
-            );
+        // All of the sets are written in one update
+        {
+            let mut writer = DescriptorWriter::new(device.clone());
+
+            for (i, (set, uniform_buffer)) in
+                descriptor_sets.iter().zip(&uniform_buffers).enumerate()
+            {
+                writer
+                    .write_buffer(
+                        set,
+                        uniform_buffer,
+                        0,
+                        size_of::<Uniform>().try_into().unwrap(),
+                        0,
+                        vk::DescriptorType::UNIFORM_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        culling_pass.object_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        1,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        culling_pass.visible_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        2,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_image(
+                        set,
+                        shadow_pass.sampler(),
+                        shadow_pass.view(i),
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        3,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    )
+                    .write_buffer(
+                        set,
+                        light_manager.uniform_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        4,
+                        vk::DescriptorType::UNIFORM_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        light_manager.light_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        5,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        light_manager.cluster_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        6,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_buffer(
+                        set,
+                        culling_pass.joint_buffer(i),
+                        0,
+                        vk::WHOLE_SIZE,
+                        7,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                    )
+                    .write_image(
+                        set,
+                        white_texture.sampler(),
+                        white_texture.view(),
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        8,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    );
+            }
 
-            descriptor_sets[i].write_image(
-                &sampler,
-                &texture_image_view,
-                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                1,
-                0,
-                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            )
+            writer.flush();
         }
 
-        #[rustfmt::skip]
-        let indices: Vec<u16> = vec![
-             0,  1,  2,  2,  1,  3,
-             4,  6,  5,  5,  6,  7,
-             8, 10,  9,  9, 10, 11,
-            12, 13, 14, 14, 13, 15,
-            16, 17, 18, 18, 17, 19,
-            20, 22, 21, 21, 22, 23,
-        ];
-
-        #[rustfmt::skip]
-        let vertices = [
-            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-
-            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-
-            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-
-            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-
-
-            Vertex { position: Vec3::new(-0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new(-0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new(-0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-
-            Vertex { position: Vec3::new( 0.5,  0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5,  0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(0.0, 1.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5,  0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 0.0) },
-            Vertex { position: Vec3::new( 0.5, -0.5, -0.5), color: Vec3::new(1.0, 0.0, 0.0), tex_coord: Vec2::new(1.0, 1.0) },
-        ];
-
-        let vertex_bindings = vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: size_of::<Vertex>().try_into().unwrap(),
-            input_rate: vk::VertexInputRate::VERTEX,
-        };
-
-        let vertex_attributes = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Vertex, position).try_into().unwrap(),
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Vertex, color).try_into().unwrap(),
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: offset_of!(Vertex, tex_coord).try_into().unwrap(),
-            },
-        ];
-
-        let index_buffer = {
-            let buffer_size = size_of::<u16>() * indices.len();
-
-            let staging_buffer = Buffer::new(
-                device.clone(),
-                allocator.clone(),
-                buffer_size,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vma::MemoryUsage::AutoPreferHost,
-                vma::AllocationCreateFlags::MAPPED
-                    | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
-            );
-
-            staging_buffer.copy_nonoverlapping(&indices);
-
-            let index_buffer = Buffer::new(
-                device.clone(),
-                allocator.clone(),
-                buffer_size,
-                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-                vma::MemoryUsage::AutoPreferDevice,
-                vma::AllocationCreateFlags::empty(),
-            );
-
-            let xfer_cmd_buf = cmd_pool.allocate_one(vk::CommandBufferLevel::PRIMARY);
-            xfer_cmd_buf.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            xfer_cmd_buf.copy_buffer(
-                &staging_buffer,
-                &index_buffer,
-                &[vk::BufferCopy {
-                    src_offset: 0,
-                    dst_offset: 0,
-                    size: buffer_size.try_into().unwrap(),
-                }],
-            );
-            xfer_cmd_buf.end();
-
-            graphics_queue.submit(None, &[&xfer_cmd_buf], None, None);
-            graphics_queue.wait_idle();
-
-            index_buffer
+        let shadow_pcf = true;
+        let pipeline_settings = PipelineSettings {
+            samples: msaa_samples,
+            shadow_pcf,
+            vertex_pulling: false,
+            depth_prepass: false,
+            motion_vectors: false,
+            depth_direction: DepthDirection::Standard,
         };
 
-        let vertex_buffers = {
-            let buffer_size = size_of::<Vertex>() * vertices.len();
-
-            let staging_buffer = Buffer::new(
-                device.clone(),
-                allocator.clone(),
-                buffer_size,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vma::MemoryUsage::AutoPreferHost,
-                vma::AllocationCreateFlags::MAPPED
-                    | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
-            );
-
-            staging_buffer.copy_nonoverlapping(&vertices);
-
-            let vertex_buffer = Buffer::new(
-                device.clone(),
-                allocator.clone(),
-                buffer_size,
-                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
-                vma::MemoryUsage::AutoPreferDevice,
-                vma::AllocationCreateFlags::empty(),
-            );
-
-            let xfer_cmd_buf = cmd_pool.allocate_one(vk::CommandBufferLevel::PRIMARY);
-            xfer_cmd_buf.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            xfer_cmd_buf.copy_buffer(
-                &staging_buffer,
-                &vertex_buffer,
-                &[vk::BufferCopy {
-                    src_offset: 0,
-                    dst_offset: 0,
-                    size: buffer_size.try_into().unwrap(),
-                }],
-            );
-            xfer_cmd_buf.end();
-
-            graphics_queue.submit(None, &[&xfer_cmd_buf], None, None);
-            graphics_queue.wait_idle();
-
-            vec![vertex_buffer]
+        let graphics_pipelines = Renderer::_create_graphics_pipelines(
+            &device,
+            &shader_registry.modules(&shader_ids),
+            Some(shader_registry.get(skinned_vertex_shader)),
+            &pipeline_layout,
+            &material_table.feature_variants(),
+            pipeline_settings,
+        );
+        let vertex_pulling_pipelines = match vertex_pulling_shader {
+            // Skinned meshes use the skinned `graphics_pipelines` instead
+            Some(id) => Renderer::_create_graphics_pipelines(
+                &device,
+                &shader_registry.modules(&[id, shader_ids[1]]),
+                None,
+                &pipeline_layout,
+                &material_table.feature_variants(),
+                PipelineSettings {
+                    vertex_pulling: true,
+                    ..pipeline_settings
+                },
+            ),
+            None => HashMap::new(),
         };
-
-        let graphics_pipeline = GraphicsPipeline::new(
-            device.clone(),
-            &shader_modules,
-            Some(&[vertex_bindings]),
-            Some(&vertex_attributes),
-            &vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
-            vk::PrimitiveTopology::TRIANGLE_LIST,
-            false,
-            None,
-            None,
+        let occlusion_probe_pipeline = Renderer::_create_occlusion_probe_pipeline(
+            &device,
+            &shader_registry.modules(&shader_ids[..1]),
             &pipeline_layout,
-            &[draw_image_format],
-            vk::Format::UNDEFINED,
-            vk::Format::UNDEFINED,
+            msaa_samples,
+            false,
+            DepthDirection::Standard,
         );
 
-        let draw_images = RenderContext::_create_draw_images(
-            &device,
-            &allocator,
-            max_frames_in_flight,
-            vk::Extent3D {
-                width: swapchain.extent().width,
-                height: swapchain.extent().height,
-                depth: 1,
-            },
+        let draw_extent = vk::Extent3D {
+            width: swapchain.extent().width,
+            height: swapchain.extent().height,
+            depth: 1,
+        };
+
+        let render_frames = uniform_buffers
+            .into_iter()
+            .zip(descriptor_sets.into_vec())
+            .enumerate()
+            .map(|(i, (uniform_buffer, descriptor_set))| {
+                RenderFrame::new(
+                    i,
+                    &device,
+                    &cmd_pool,
+                    uniform_buffer,
+                    descriptor_set,
+                    FrameImages::new(&device, &allocator, draw_extent, msaa_samples),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let api_version = physical_device.api_version();
+        info!(
+            device = physical_device.device_name(),
+            device_type = ?physical_device.device_type(),
+            api_version = format!(
+                "{}.{}.{}",
+                vk::api_version_major(api_version),
+                vk::api_version_minor(api_version),
+                vk::api_version_patch(api_version)
+            ),
+            format = ?swapchain.surface_format(),
+            extent = ?swapchain.extent(),
+            present_mode = ?config.present_mode,
+            msaa = ?msaa_samples,
+            frames_in_flight = max_frames_in_flight,
+            validation = config.validation,
+            "renderer initialized"
         );
 
-        let mut render_context = Self {
+        Self {
             start_time: std::time::Instant::now(),
-            frame_count: 0,
+            last_frame_start: std::time::Instant::now(),
+            frame_stats: FrameStats::default(),
             window,
-            instance,
             physical_device,
             device,
             allocator,
             swapchain,
-            shader_modules,
-            graphics_pipeline,
-            draw_images,
+            shader_registry,
+            shader_ids,
+            graphics_pipelines,
+            occlusion_probe_pipeline,
+            vertex_pulling_shader,
+            skinned_vertex_shader,
+            gbuffer_shader,
+            vertex_pulling_pipelines,
+            vertex_pulling: false,
+            depth_prepass_pipelines: HashMap::new(),
+            depth_prepass: false,
+            occlusion_culling: false,
+            hi_z_culling: false,
+            cull_distance: config.cull_distance,
+            lod_settings: LodSettings::default(),
+            draw_extent,
+            msaa_samples,
             pipeline_layout,
-            descriptor_pool,
-            descriptor_sets,
-            uniform_buffers,
-            texture_image,
-            texture_image_view,
-            sampler,
-            indices,
-            index_buffer,
-            vertex_buffers,
-            cmd_pool,
-            render_frames: vec![],
-            current_frame: 0,
-        };
-
-        render_context.render_frames.reserve(max_frames_in_flight);
-        for i in 0..max_frames_in_flight {
-            render_context
-                .render_frames
-                .push(RenderFrame::new(i, &render_context));
+            texture_cache,
+            descriptor_allocator,
+            materials,
+            material_set_layout,
+            material_table,
+            white_texture,
+            world: RenderWorld::new(),
+            models: vec![],
+            retired_meshes: vec![],
+            asset_server,
+            mesh_import_settings: config.mesh_import_settings(),
+            culling_pass,
+            depth_pyramid_pass,
+            shadow_pass,
+            light: DirectionalLight::default(),
+            shadow_pcf,
+            light_manager,
+            skybox_pass,
+            debug_draw: DebugDraw::new(),
+            debug_draw_pass,
+            debug_overlay: false,
+            bloom_pass,
+            post_process,
+            taa_pass,
+            taa: false,
+            ssao_pass,
+            ssao: false,
+            depth_direction: DepthDirection::Standard,
+            tonemap_pass,
+            camera: Camera::look_at(
+                Vec3::new(2.0, 2.0, 2.0),
+                Vec3::ZERO,
+                Projection::Perspective {
+                    fov_y: 45_f32.to_radians(),
+                    near: 0.1,
+                    far: 100.0,
+                },
+            ),
+            buffer_arena,
+            uploads,
+            frames: FrameRing::new(render_frames),
+            frame_dump_path: None,
+            suboptimal_policy: SuboptimalPolicy::RecreateAfterPresent,
+            suboptimal_frames: 0,
+            pending_resize: None,
+            surface_format_preference: SurfaceFormatPreference::default(),
+            present_mode_preference: config.present_mode,
         }
-
-        render_context
     }
 
     fn _get_surface_details(
         physical_device: &Arc<PhysicalDevice>,
         width: u32,
         height: u32,
+        surface_format_preference: SurfaceFormatPreference,
+        present_mode_preference: PresentModePreference,
     ) -> SurfaceDetails {
-        let present_mode = physical_device
-            .get_surface_present_modes()
-            .into_iter()
-            .min_by_key(|x| match *x {
-                // vk::PresentModeKHR::MAILBOX => 0, // uncapped
-                vk::PresentModeKHR::FIFO_RELAXED => 0, // caps framerate
-                vk::PresentModeKHR::FIFO => 1,
-                _ => 2,
-            })
-            .unwrap();
+        let present_mode =
+            present_mode_preference.choose(&physical_device.get_surface_present_modes());
 
-        // Chose the swapchain surface format to use, preferring B8G8R8A8_SRGB
-        // with a SRGB_NONLINEAR color space, and otherwise taking the first
-        // option
-        let format = physical_device
-            .get_surface_formats()
-            .into_iter()
-            .enumerate()
-            .min_by_key(|(index, x)| {
-                if x.format == vk::Format::B8G8R8A8_SRGB {
-                    if x.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
-                        return 0;
-                    }
-                }
-                index + 1
-            })
-            .map(|(_, x)| x)
-            .unwrap();
+        let format = surface_format_preference.choose(&physical_device.get_surface_formats());
 
         let extent = physical_device.get_surface_current_extent_clamped(width, height);
 
-        println!("present_mode = {:?}", present_mode);
-        println!("present_format = {:?}", format);
-        println!("extent = {:?}", extent);
+        debug!(?present_mode, ?format, ?extent, "surface details");
 
         SurfaceDetails {
             present_mode,
@@ -620,6 +961,8 @@ impl RenderContext {
         width: u32,
         height: u32,
         old_swapchain: Option<&Swapchain>,
+        surface_format_preference: SurfaceFormatPreference,
+        present_mode_preference: PresentModePreference,
     ) -> Swapchain {
         let physical_device = device.physical_device();
         let min_image_count = physical_device.get_surface_ideal_image_count();
@@ -628,101 +971,1457 @@ impl RenderContext {
             present_mode,
             format,
             extent,
-        } = RenderContext::_get_surface_details(physical_device, width, height);
+        } = Renderer::_get_surface_details(
+            physical_device,
+            width,
+            height,
+            surface_format_preference,
+            present_mode_preference,
+        );
 
-        let swapchain = device.get_swapchain(
+        device.get_swapchain(
             min_image_count,
-            format.format,
-            format.color_space,
+            format,
             extent,
             vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
             present_mode,
             old_swapchain,
-        );
-
-        swapchain
+        )
     }
 
-    fn _create_draw_images(
+    // With a skinned vertex shader, each variant also gets a skinned copy
+    // using it in place of the first module
+    fn _create_graphics_pipelines(
         device: &Arc<Device>,
-        allocator: &Arc<vma::Allocator>,
-        max_frames_in_flight: usize,
-        extent: vk::Extent3D,
-    ) -> Vec<Arc<Image>> {
-        let mut draw_images = vec![];
-        for _ in 0..max_frames_in_flight {
-            draw_images.push(Image::new(
-                device.clone(),
-                allocator.clone(),
-                vk::ImageType::TYPE_2D,
-                vk::Format::R16G16B16A16_SFLOAT,
-                extent,
-                1,
-                1,
-                vk::SampleCountFlags::TYPE_1,
-                vk::ImageTiling::OPTIMAL,
-                vk::ImageUsageFlags::TRANSFER_SRC
-                        | vk::ImageUsageFlags::TRANSFER_DST // why dst? shouldn't be srconly?
-                        | vk::ImageUsageFlags::STORAGE
-                        | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                vma::MemoryUsage::AutoPreferDevice,
-                vma::AllocationCreateFlags::empty(),
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            ));
+        shader_modules: &[Arc<ShaderModule>],
+        skinned_vertex_module: Option<&Arc<ShaderModule>>,
+        pipeline_layout: &PipelineLayout,
+        variants: &[MaterialFeatures],
+        settings: PipelineSettings,
+    ) -> HashMap<MaterialFeatures, Arc<GraphicsPipeline>> {
+        Renderer::_with_skinned_variants(shader_modules, skinned_vertex_module, variants)
+            .into_iter()
+            .map(|(features, shader_modules)| {
+                let pipeline = Renderer::_create_graphics_pipeline(
+                    device,
+                    &shader_modules,
+                    pipeline_layout,
+                    features,
+                    settings,
+                );
+                (features, pipeline)
+            })
+            .collect()
+    }
+
+    // Each variant with its shader modules, followed by its skinned copy
+    fn _with_skinned_variants(
+        shader_modules: &[Arc<ShaderModule>],
+        skinned_vertex_module: Option<&Arc<ShaderModule>>,
+        variants: &[MaterialFeatures],
+    ) -> Vec<(MaterialFeatures, Vec<Arc<ShaderModule>>)> {
+        let mut result = vec![];
+        for features in variants {
+            result.push((*features, shader_modules.to_vec()));
+            if let Some(module) = skinned_vertex_module {
+                let skinned_modules = std::iter::once(module.clone())
+                    .chain(shader_modules[1..].iter().cloned())
+                    .collect();
+                result.push((
+                    MaterialFeatures {
+                        skinned: true,
+                        ..*features
+                    },
+                    skinned_modules,
+                ));
+            }
         }
-        draw_images
+        result
     }
 
-    pub fn recreate_swapchain(&mut self, width: u32, height: u32) {
-        self.device.wait_idle();
+    fn _create_graphics_pipeline(
+        device: &Arc<Device>,
+        shader_modules: &[Arc<ShaderModule>],
+        pipeline_layout: &PipelineLayout,
+        features: MaterialFeatures,
+        settings: PipelineSettings,
+    ) -> Arc<GraphicsPipeline> {
+        let PipelineSettings {
+            samples,
+            shadow_pcf,
+            vertex_pulling,
+            depth_prepass,
+            motion_vectors,
+            depth_direction,
+        } = settings;
+
+        // vertex_pulling.glsl reads the vertices itself
+        let vertex_layout = if vertex_pulling {
+            VertexLayout::default()
+        } else if features.skinned {
+            Vertex::skinned_layout()
+        } else {
+            Vertex::layout()
+        };
 
-        self.swapchain = RenderContext::_create_swapchain(
-            self.device.clone(),
-            width,
+        let cull_mode = if features.double_sided {
+            vk::CullModeFlags::NONE
+        } else {
+            vk::CullModeFlags::BACK
+        };
+
+        // After a depth prepass only the nearest surface passes, and depth is
+        // already written. Blended materials aren't in the prepass and never
+        // write depth, so they're tested against the opaque meshes
+        let (depth_write, depth_compare_op) = if features.alpha_blend {
+            (false, depth_direction.compare_op())
+        } else if depth_prepass {
+            (false, vk::CompareOp::EQUAL)
+        } else {
+            (true, depth_direction.compare_op())
+        };
+        let blend_attachment = if features.alpha_blend {
+            alpha_blend_attachment()
+        } else {
+            opaque_blend_attachment()
+        };
+
+        // Constant ids match fragment.glsl
+        GraphicsPipeline::builder()
+            .shader_modules(shader_modules)
+            .specialization_constant(vk::ShaderStageFlags::FRAGMENT, 0, shadow_pcf as u32)
+            .specialization_constant(
+                vk::ShaderStageFlags::FRAGMENT,
+                1,
+                features.normal_map as u32,
+            )
+            .specialization_constant(
+                vk::ShaderStageFlags::FRAGMENT,
+                2,
+                features.metallic_roughness_map as u32,
+            )
+            .cull_mode(cull_mode)
+            .vertex_layout(&vertex_layout)
+            .depth_test(true)
+            .depth_write(depth_write)
+            .depth_compare_op(depth_compare_op)
+            .samples(samples)
+            .color_attachments(&taa::main_pass_attachments(
+                blend_attachment,
+                motion_vectors,
+                !features.alpha_blend,
+            ))
+            .depth_format(vk::Format::D32_SFLOAT)
+            .build(device.clone(), pipeline_layout)
+    }
+
+    // Depth-only versions of the graphics pipelines, one for each depth
+    // variant of the materials. With a G-buffer fragment shader they also
+    // write the G-buffer for SSAO
+    fn _create_depth_prepass_pipelines(
+        device: &Arc<Device>,
+        vertex_shader_modules: &[Arc<ShaderModule>],
+        skinned_vertex_module: Option<&Arc<ShaderModule>>,
+        gbuffer_fragment_module: Option<&Arc<ShaderModule>>,
+        pipeline_layout: &PipelineLayout,
+        variants: &[MaterialFeatures],
+        settings: PipelineSettings,
+    ) -> HashMap<MaterialFeatures, Arc<GraphicsPipeline>> {
+        let PipelineSettings {
+            samples,
+            depth_direction,
+            ..
+        } = settings;
+        let shader_modules = vertex_shader_modules
+            .iter()
+            .chain(gbuffer_fragment_module)
+            .cloned()
+            .collect::<Vec<_>>();
+        let color_attachments = match gbuffer_fragment_module {
+            Some(_) => vec![(ssao::GBUFFER_FORMAT, opaque_blend_attachment())],
+            None => vec![],
+        };
+        Renderer::_with_skinned_variants(&shader_modules, skinned_vertex_module, variants)
+            .into_iter()
+            .map(|(features, shader_modules)| {
+                let cull_mode = if features.double_sided {
+                    vk::CullModeFlags::NONE
+                } else {
+                    vk::CullModeFlags::BACK
+                };
+                let vertex_layout = if features.skinned {
+                    Vertex::skinned_layout()
+                } else {
+                    Vertex::layout()
+                };
+                let pipeline = GraphicsPipeline::builder()
+                    .shader_modules(&shader_modules)
+                    .cull_mode(cull_mode)
+                    .vertex_layout(&vertex_layout)
+                    .depth_test(true)
+                    .depth_write(true)
+                    .depth_compare_op(depth_direction.compare_op())
+                    .samples(samples)
+                    .color_attachments(&color_attachments)
+                    .depth_format(vk::Format::D32_SFLOAT)
+                    .build(device.clone(), pipeline_layout);
+                (features, pipeline)
+            })
+            .collect()
+    }
+
+    // Only the vertex shader runs and color writes are off, so a hidden mesh
+    // costs its vertex work and depth tests
+    fn _create_occlusion_probe_pipeline(
+        device: &Arc<Device>,
+        vertex_shader_modules: &[Arc<ShaderModule>],
+        pipeline_layout: &PipelineLayout,
+        samples: vk::SampleCountFlags,
+        motion_vectors: bool,
+        depth_direction: DepthDirection,
+    ) -> Arc<GraphicsPipeline> {
+        let masked = vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::empty(),
+            ..opaque_blend_attachment()
+        };
+        GraphicsPipeline::builder()
+            .shader_modules(vertex_shader_modules)
+            .cull_mode(vk::CullModeFlags::BACK)
+            .vertex_layout(&Vertex::layout())
+            .depth_test(true)
+            .depth_write(false)
+            .depth_compare_op(depth_direction.compare_op())
+            .samples(samples)
+            .color_attachments(&taa::main_pass_attachments(masked, motion_vectors, false))
+            .depth_format(vk::Format::D32_SFLOAT)
+            .build(device.clone(), pipeline_layout)
+    }
+
+    fn _create_draw_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Arc<Image> {
+        Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(
+                vk::Format::R16G16B16A16_SFLOAT,
+                extent,
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST // why dst? shouldn't be srconly?
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            )
+            .priority(MemoryPriority::High),
+        )
+    }
+
+    // Multisampled color target that's resolved into the draw image. Not
+    // needed without MSAA, in which case the draw image is rendered to
+    fn _create_msaa_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+        samples: vk::SampleCountFlags,
+    ) -> Option<Arc<Image>> {
+        if samples == vk::SampleCountFlags::TYPE_1 {
+            return None;
+        }
+        Some(Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(
+                vk::Format::R16G16B16A16_SFLOAT,
+                extent,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            )
+            .samples(samples)
+            .priority(MemoryPriority::High),
+        ))
+    }
+
+    fn _create_depth_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+        samples: vk::SampleCountFlags,
+    ) -> Arc<Image> {
+        Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(
+                vk::Format::D32_SFLOAT,
+                extent,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            )
+            .samples(samples)
+            .priority(MemoryPriority::High),
+        )
+    }
+
+    // Resize events come in bursts while the window is being dragged, so only
+    // the last size is kept and the swapchain is recreated before the next
+    // frame instead of once per event
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.pending_resize = Some((width, height));
+        self.window.request_redraw();
+    }
+
+    // Frames still in flight can be using the old swapchain and draw images,
+    // so instead of waiting for the device to go idle they are retired to the
+    // deletion queue and dropped once those frames' fences have signaled
+    pub fn recreate_swapchain(&mut self, width: u32, height: u32) {
+        let swapchain = Renderer::_create_swapchain(
+            self.device.clone(),
+            width,
             height,
             Some(&self.swapchain),
+            self.surface_format_preference,
+            self.present_mode_preference,
+        );
+        let old_swapchain = std::mem::replace(&mut self.swapchain, swapchain);
+        self.frames.defer_delete(old_swapchain);
+
+        // The tonemap pass renders straight into the swapchain images
+        if *self.swapchain.format() != self.tonemap_pass.color_format() {
+            let old_tonemap_pipeline = self
+                .tonemap_pass
+                .set_color_format(&self.shader_registry, *self.swapchain.format());
+            self.frames.defer_delete(old_tonemap_pipeline);
+        }
+        self.suboptimal_frames = 0;
+
+        // The swapchain extent can differ from the requested size, so compare
+        // against what was actually created. Render frames don't depend on the
+        // extent and are always kept
+        let extent = *self.swapchain.extent();
+        let draw_extent = vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+
+        if self.draw_extent != draw_extent {
+            self.draw_extent = draw_extent;
+
+            let mut old_images = vec![];
+            for frame in self.frames.iter_mut() {
+                let images = FrameImages::new(
+                    &self.device,
+                    &self.allocator,
+                    draw_extent,
+                    self.msaa_samples,
+                );
+                old_images.push(std::mem::replace(&mut frame.images, images));
+            }
+            self.frames.defer_delete(old_images);
+            self.taa_pass.reset();
+        }
+    }
+
+    fn _recreate_sampled_images(&mut self) {
+        let mut old_images = vec![];
+        for frame in self.frames.iter_mut() {
+            let msaa_image = Renderer::_create_msaa_image(
+                &self.device,
+                &self.allocator,
+                self.draw_extent,
+                self.msaa_samples,
+            );
+            let depth_image = Renderer::_create_depth_image(
+                &self.device,
+                &self.allocator,
+                self.draw_extent,
+                self.msaa_samples,
+            );
+            old_images.extend(std::mem::replace(&mut frame.images.msaa_image, msaa_image));
+            old_images.push(std::mem::replace(
+                &mut frame.images.depth_image,
+                depth_image,
+            ));
+
+            // The G-buffer has the draw image's format
+            let gbuffer_msaa_image = Renderer::_create_msaa_image(
+                &self.device,
+                &self.allocator,
+                self.draw_extent,
+                self.msaa_samples,
+            );
+            old_images.extend(std::mem::replace(
+                &mut frame.images.gbuffer_msaa_image,
+                gbuffer_msaa_image,
+            ));
+        }
+        self.frames.defer_delete(old_images);
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn msaa_samples(&self) -> vk::SampleCountFlags {
+        self.msaa_samples
+    }
+
+    pub fn supports_msaa_samples(&self, samples: vk::SampleCountFlags) -> bool {
+        self.physical_device.supports_sample_count(samples)
+    }
+
+    // The sample count is baked into the pipeline, so it's rebuilt along with
+    // the multisampled images. The old ones are retired like on resize. More
+    // than one sample turns TAA off
+    pub fn set_msaa_samples(&mut self, samples: vk::SampleCountFlags) {
+        assert!(
+            self.supports_msaa_samples(samples),
+            "unsupported MSAA sample count"
+        );
+        if samples == self.msaa_samples {
+            return;
+        }
+        self.msaa_samples = samples;
+        if samples != vk::SampleCountFlags::TYPE_1 {
+            self.taa = false;
+        }
+        self._recreate_graphics_pipeline();
+        self._recreate_sampled_images();
+    }
+
+    pub fn taa(&self) -> bool {
+        self.taa
+    }
+
+    // Temporal anti-aliasing, see `TaaPass`. It replaces MSAA, so turning it
+    // on drops the sample count to one. The main pass' pipelines gain the
+    // motion vector attachment, so they're rebuilt
+    pub fn set_taa(&mut self, taa: bool) {
+        if taa == self.taa {
+            return;
+        }
+        self.taa = taa;
+        self.taa_pass.reset();
+        if taa && self.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            self.msaa_samples = vk::SampleCountFlags::TYPE_1;
+            self._recreate_sampled_images();
+        }
+        self._recreate_graphics_pipeline();
+    }
+
+    pub fn taa_blend(&self) -> f32 {
+        self.taa_pass.blend()
+    }
+
+    // How much of each frame is blended into the history. Lower is smoother
+    // but ghosts more and takes longer to converge
+    pub fn set_taa_blend(&mut self, blend: f32) {
+        self.taa_pass.set_blend(blend);
+    }
+
+    pub fn depth_direction(&self) -> DepthDirection {
+        self.depth_direction
+    }
+
+    // Reversed depth keeps large scenes from z-fighting, along with an
+    // infinite far plane on the camera's projection. The depth tests are
+    // baked into the pipelines, so they're rebuilt. Shadow maps keep standard
+    // depth, which is linear for their orthographic projection
+    pub fn set_depth_direction(&mut self, direction: DepthDirection) {
+        if direction == self.depth_direction {
+            return;
+        }
+        self.depth_direction = direction;
+        self._recreate_graphics_pipeline();
+    }
+
+    fn _recreate_graphics_pipeline(&mut self) {
+        let settings = PipelineSettings {
+            samples: self.msaa_samples,
+            shadow_pcf: self.shadow_pcf,
+            vertex_pulling: false,
+            depth_prepass: self.depth_prepass,
+            motion_vectors: self.taa,
+            depth_direction: self.depth_direction,
+        };
+
+        let graphics_pipelines = Renderer::_create_graphics_pipelines(
+            &self.device,
+            &self.shader_registry.modules(&self.shader_ids),
+            Some(self.shader_registry.get(self.skinned_vertex_shader)),
+            &self.pipeline_layout,
+            &self.material_table.feature_variants(),
+            settings,
+        );
+        let old_graphics_pipelines =
+            std::mem::replace(&mut self.graphics_pipelines, graphics_pipelines);
+        self.frames.defer_delete(old_graphics_pipelines);
+
+        if let Some(id) = self.vertex_pulling_shader {
+            let vertex_pulling_pipelines = Renderer::_create_graphics_pipelines(
+                &self.device,
+                &self.shader_registry.modules(&[id, self.shader_ids[1]]),
+                None,
+                &self.pipeline_layout,
+                &self.material_table.feature_variants(),
+                PipelineSettings {
+                    vertex_pulling: true,
+                    ..settings
+                },
+            );
+            let old_vertex_pulling_pipelines =
+                std::mem::replace(&mut self.vertex_pulling_pipelines, vertex_pulling_pipelines);
+            self.frames.defer_delete(old_vertex_pulling_pipelines);
+        }
+
+        let depth_prepass_pipelines = if self.depth_prepass {
+            Renderer::_create_depth_prepass_pipelines(
+                &self.device,
+                &self.shader_registry.modules(&self.shader_ids[..1]),
+                Some(self.shader_registry.get(self.skinned_vertex_shader)),
+                self.ssao
+                    .then(|| self.shader_registry.get(self.gbuffer_shader)),
+                &self.pipeline_layout,
+                &self.material_table.depth_variants(),
+                settings,
+            )
+        } else {
+            HashMap::new()
+        };
+        let old_depth_prepass_pipelines =
+            std::mem::replace(&mut self.depth_prepass_pipelines, depth_prepass_pipelines);
+        self.frames.defer_delete(old_depth_prepass_pipelines);
+
+        let occlusion_probe_pipeline = Renderer::_create_occlusion_probe_pipeline(
+            &self.device,
+            &self.shader_registry.modules(&self.shader_ids[..1]),
+            &self.pipeline_layout,
+            self.msaa_samples,
+            self.taa,
+            self.depth_direction,
+        );
+        let old_occlusion_probe_pipeline =
+            std::mem::replace(&mut self.occlusion_probe_pipeline, occlusion_probe_pipeline);
+        self.frames.defer_delete(old_occlusion_probe_pipeline);
+
+        // The skybox is drawn in the same pass, so it shares the sample count
+        let old_skybox_pipeline = self.skybox_pass.recreate_pipeline(
+            &self.shader_registry,
+            self.msaa_samples,
+            self.taa,
+            self.depth_direction,
+        );
+        self.frames.defer_delete(old_skybox_pipeline);
+    }
+
+    // Writes the structure of the next recorded frame to `path` with .json and
+    // .dot extensions
+    pub fn request_frame_dump(&mut self, path: impl Into<PathBuf>) {
+        self.frame_dump_path = Some(path.into());
+    }
+
+    // What the swapchain was actually created with, which can differ from the
+    // preference when the surface doesn't support it
+    pub fn surface_format(&self) -> SurfaceFormat {
+        self.swapchain.surface_format()
+    }
+
+    pub fn surface_format_preference(&self) -> SurfaceFormatPreference {
+        self.surface_format_preference
+    }
+
+    // Takes effect when the swapchain is recreated before the next frame
+    pub fn set_surface_format_preference(&mut self, preference: SurfaceFormatPreference) {
+        if preference == self.surface_format_preference {
+            return;
+        }
+        self.surface_format_preference = preference;
+        let PhysicalSize { width, height } = self.window.inner_size();
+        self.resize(width, height);
+    }
+
+    pub fn present_mode_preference(&self) -> PresentModePreference {
+        self.present_mode_preference
+    }
+
+    // Takes effect when the swapchain is recreated before the next frame
+    pub fn set_present_mode_preference(&mut self, preference: PresentModePreference) {
+        if preference == self.present_mode_preference {
+            return;
+        }
+        self.present_mode_preference = preference;
+        let PhysicalSize { width, height } = self.window.inner_size();
+        self.resize(width, height);
+    }
+
+    pub fn tonemap_operator(&self) -> TonemapOperator {
+        self.tonemap_pass.operator()
+    }
+
+    pub fn set_tonemap_operator(&mut self, operator: TonemapOperator) {
+        self.tonemap_pass.set_operator(operator);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.tonemap_pass.exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.tonemap_pass.set_exposure(exposure);
+    }
+
+    pub fn light(&self) -> DirectionalLight {
+        self.light
+    }
+
+    pub fn set_light(&mut self, light: DirectionalLight) {
+        self.light = light;
+    }
+
+    // Point and spot lights, which are uploaded every frame
+    pub fn lights(&self) -> &LightManager {
+        &self.light_manager
+    }
+
+    pub fn lights_mut(&mut self) -> &mut LightManager {
+        &mut self.light_manager
+    }
+
+    pub fn skybox_intensity(&self) -> f32 {
+        self.skybox_pass.intensity()
+    }
+
+    pub fn set_skybox_intensity(&mut self, intensity: f32) {
+        self.skybox_pass.set_intensity(intensity);
+    }
+
+    pub fn shadow_pcf(&self) -> bool {
+        self.shadow_pcf
+    }
+
+    // PCF is a specialization constant, so the pipeline is rebuilt
+    pub fn set_shadow_pcf(&mut self, shadow_pcf: bool) {
+        if shadow_pcf == self.shadow_pcf {
+            return;
+        }
+        self.shadow_pcf = shadow_pcf;
+        self._recreate_graphics_pipeline();
+    }
+
+    pub fn bloom_intensity(&self) -> f32 {
+        self.bloom_pass.intensity()
+    }
+
+    pub fn set_bloom_intensity(&mut self, intensity: f32) {
+        self.bloom_pass.set_intensity(intensity);
+    }
+
+    pub fn bloom_threshold(&self) -> f32 {
+        self.bloom_pass.threshold()
+    }
+
+    pub fn set_bloom_threshold(&mut self, threshold: f32) {
+        self.bloom_pass.set_threshold(threshold);
+    }
+
+    // Effects can be enabled and tuned through `PostProcessChain::effect_mut`
+    pub fn post_process(&self) -> &PostProcessChain {
+        &self.post_process
+    }
+
+    pub fn post_process_mut(&mut self) -> &mut PostProcessChain {
+        &mut self.post_process
+    }
+
+    // Adds a compute shader to the end of the post process chain, enabled.
+    // Unlike the built-in shaders the path is used as is, see
+    // `PostProcessChain::register`
+    pub fn register_post_process_effect(
+        &mut self,
+        name: &str,
+        shader_path: &str,
+        inputs: &[PostProcessInput],
+        params: Vec4,
+    ) -> PostProcessEffectId {
+        self.post_process
+            .register(&mut self.shader_registry, name, shader_path, inputs, params)
+    }
+
+    // Lines added here are drawn over the next frame, then cleared
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn debug_overlay(&self) -> bool {
+        self.debug_overlay
+    }
+
+    // Draws the world axes, mesh bounds, light volumes and the shadow map's
+    // frustum every frame
+    pub fn set_debug_overlay(&mut self, debug_overlay: bool) {
+        self.debug_overlay = debug_overlay;
+    }
+
+    pub fn world(&self) -> &RenderWorld {
+        &self.world
+    }
+
+    // Renderables added here use the renderer's material table, see
+    // `Renderable::material`
+    pub fn world_mut(&mut self) -> &mut RenderWorld {
+        &mut self.world
+    }
+
+    // Uploads the model with the next frame and adds its instances to the
+    // world at the origin
+    pub fn add_model(&mut self, data: ModelData) -> ModelId {
+        let id = self._insert_model(None);
+        self._upload_model(id, data);
+        id
+    }
+
+    // Loads a glTF model in the background and adds it once it's ready. It's
+    // replaced when its files change. Loading a file again returns the model
+    // it's already loaded into
+    pub fn load_model(&mut self, path: impl Into<PathBuf>) -> ModelId {
+        let path = path.into();
+        if let Some(id) = self._find_model(&path) {
+            return id;
+        }
+        self.asset_server
+            .load_model(path.clone(), self.mesh_import_settings);
+        self._insert_model(Some(path))
+    }
+
+    // Removes the model's instances from the world. Its meshes are removed
+    // once nothing draws them anymore
+    pub fn remove_model(&mut self, id: ModelId) {
+        let model = self.models[id.0].take().expect("model was removed");
+        self._retire_instances(&model.instances);
+        self._rebuild_materials(None);
+    }
+
+    // Moves the model, keeping its instances where they are relative to each
+    // other. Also places a model that's still loading once it's ready
+    pub fn place_model(&mut self, id: ModelId, transform: Mat4) {
+        let model = self.models[id.0].as_mut().expect("model was removed");
+        model.transform = transform;
+        for instance in &model.instances {
+            self.world
+                .set_transform(instance.transform, transform * instance.instance_transform);
+        }
+    }
+
+    // Empty while the model is loading. Replaced along with the model
+    pub fn model_instances(&self, id: ModelId) -> &[ModelInstance] {
+        &self._model(id).instances
+    }
+
+    // Plays the model's animations. Replaced along with the model
+    pub fn animation_player(&self, id: ModelId) -> &AnimationPlayer {
+        &self._model(id).animation_player
+    }
+
+    pub fn animation_player_mut(&mut self, id: ModelId) -> &mut AnimationPlayer {
+        &mut self.models[id.0]
+            .as_mut()
+            .expect("model was removed")
+            .animation_player
+    }
+
+    fn _model(&self, id: ModelId) -> &RendererModel {
+        self.models[id.0].as_ref().expect("model was removed")
+    }
+
+    fn _insert_model(&mut self, path: Option<PathBuf>) -> ModelId {
+        self.models.push(Some(RendererModel {
+            path,
+            first_material: self.materials.len(),
+            material_count: 0,
+            instances: vec![],
+            transform: Mat4::IDENTITY,
+            animation_player: AnimationPlayer::new(Skeleton::default(), vec![]),
+        }));
+        ModelId(self.models.len() - 1)
+    }
+
+    fn _find_model(&self, path: &Path) -> Option<ModelId> {
+        self.models
+            .iter()
+            .position(|x| x.as_ref().is_some_and(|x| x.path.as_deref() == Some(path)))
+            .map(ModelId)
+    }
+
+    // Advances each model's animation and poses its skinned instances
+    fn _update_animation(&mut self, delta: Duration) {
+        for model in self.models.iter_mut().flatten() {
+            model.animation_player.update(delta.as_secs_f32());
+            for instance in &model.instances {
+                if let Some((skin, joints)) = instance.skin {
+                    self.world
+                        .set_joints(joints, &model.animation_player.joint_matrices(skin));
+                }
+            }
+        }
+    }
+
+    // Loads an equirectangular environment map in the background and shows
+    // it once it's ready
+    pub fn load_environment(&mut self, path: impl Into<PathBuf>) {
+        self.asset_server.load_environment(path);
+    }
+
+    // Models and environment maps that are still loading
+    pub fn pending_assets(&self) -> usize {
+        self.asset_server.pending_count()
+    }
+
+    // Uploads the assets that finished loading and swaps them in for what
+    // was shown in the meantime. The uploads are recorded at the start of the
+    // frame about to be drawn, see `UploadQueue`
+    fn _update_assets(&mut self) {
+        let world = &mut self.world;
+        let frames = &mut self.frames;
+        self.retired_meshes.retain(|&id| {
+            if world.is_mesh_used(id) {
+                return true;
+            }
+            if let Some(mesh) = world.remove_mesh(id) {
+                frames.defer_delete(mesh);
+            }
+            false
+        });
+
+        for asset in self.asset_server.poll() {
+            match asset {
+                // Models removed while loading are dropped
+                LoadedAsset::Model(path, data) => {
+                    if let Some(id) = self._find_model(&path) {
+                        self._upload_model(id, data);
+                    }
+                }
+                LoadedAsset::Texture(path, color_space, data) => {
+                    match self
+                        .texture_cache
+                        .replace(&self.uploads, &path, color_space, data)
+                    {
+                        Ok((texture, Some(old_texture))) => {
+                            self._replace_texture(&old_texture, &texture)
+                        }
+                        Ok((_, None)) => {}
+                        Err(error) => warn!("{}, keeping the previous version", error),
+                    }
+                }
+                LoadedAsset::Environment(environment) => {
+                    let old_environment = self.skybox_pass.set_environment(
+                        &self.allocator,
+                        &self.uploads,
+                        &self.shader_registry,
+                        &environment,
+                    );
+                    self.frames.defer_delete(old_environment);
+                }
+            }
+        }
+    }
+
+    // Replaces the model's instances and materials with the data's. The old
+    // meshes are removed once other renderables stop using them too
+    fn _upload_model(&mut self, id: ModelId, data: ModelData) {
+        let model = Model::upload(
+            data,
+            &self.buffer_arena,
+            &self.uploads,
+            &mut self.texture_cache,
+        );
+        debug!("buffer_arena = {:?}", self.buffer_arena.stats());
+
+        let old_instances = std::mem::take(
+            &mut self.models[id.0]
+                .as_mut()
+                .expect("model was removed")
+                .instances,
         );
+        self._retire_instances(&old_instances);
+        self._rebuild_materials(Some((id, model.materials().to_vec())));
+
+        let animation_player =
+            AnimationPlayer::new(model.skeleton().clone(), model.animations().to_vec());
+        let instances = self.world.add_model(model, self._model(id).first_material);
+        let renderer_model = self.models[id.0].as_mut().expect("model was removed");
+        renderer_model.instances = instances;
+        renderer_model.animation_player = animation_player;
+        let transform = renderer_model.transform;
+        self.place_model(id, transform);
+
+        // The new materials may need pipelines the old ones didn't
+        self._recreate_graphics_pipeline();
+    }
+
+    fn _retire_instances(&mut self, instances: &[ModelInstance]) {
+        for instance in instances {
+            if let Some(renderable) = self.world.renderable(instance.renderable) {
+                self.retired_meshes.push(renderable.mesh);
+            }
+            self.world.remove(instance.renderable);
+            self.world.remove_transform(instance.transform);
+            if let Some((_, joints)) = instance.skin {
+                self.world.remove_joints(joints);
+            }
+        }
+        self.retired_meshes.sort();
+        self.retired_meshes.dedup();
+    }
+
+    // Builds the material table from the default material and each model's,
+    // with the replaced model's materials swapped for the given ones. The
+    // renderables of models whose materials moved are added again with the
+    // new indices
+    fn _rebuild_materials(&mut self, mut replaced: Option<(ModelId, Vec<Material>)>) {
+        let mut materials = vec![self.materials[0].clone()];
+        for (i, model) in self.models.iter_mut().enumerate() {
+            let Some(model) = model else {
+                continue;
+            };
+
+            let first_material = materials.len();
+            match replaced.take_if(|(id, _)| id.0 == i) {
+                Some((_, model_materials)) => {
+                    model.material_count = model_materials.len();
+                    materials.extend(model_materials);
+                }
+                None => materials.extend_from_slice(
+                    &self.materials[model.first_material..][..model.material_count],
+                ),
+            }
+
+            if first_material != model.first_material {
+                for instance in &mut model.instances {
+                    let renderable = *self
+                        .world
+                        .renderable(instance.renderable)
+                        .expect("model renderable was removed");
+                    // Meshes without a material use the default one
+                    let material = match renderable.material {
+                        0 => 0,
+                        x => x - model.first_material + first_material,
+                    };
+                    self.world.remove(instance.renderable);
+                    instance.renderable = self.world.add(Renderable {
+                        material,
+                        ..renderable
+                    });
+                }
+                model.first_material = first_material;
+            }
+        }
+        self._set_materials(materials);
+    }
+
+    // Points the materials using `old_texture` at `texture` instead
+    fn _replace_texture(&mut self, old_texture: &Arc<Texture>, texture: &Arc<Texture>) {
+        let mut materials = self.materials.clone();
+        let mut changed = false;
+        for material in &mut materials {
+            let slots = [
+                &mut material.albedo,
+                &mut material.normal,
+                &mut material.metallic_roughness,
+            ];
+            for slot in slots.into_iter().flatten() {
+                if Arc::ptr_eq(slot, old_texture) {
+                    *slot = texture.clone();
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self._set_materials(materials);
+        }
+    }
+
+    // Builds a new material table, since the current one may be in use by
+    // frames in flight. The old table and textures are deleted once those
+    // are done
+    fn _set_materials(&mut self, materials: Vec<Material>) {
+        let material_table = MaterialTable::new(
+            &self.device,
+            &self.uploads,
+            &mut self.descriptor_allocator,
+            &self.material_set_layout,
+            &materials,
+            &self.white_texture,
+        );
+        let old_material_table = std::mem::replace(&mut self.material_table, material_table);
+        let old_materials = std::mem::replace(&mut self.materials, materials);
+        self.frames
+            .defer_delete((old_material_table, old_materials));
+    }
+
+    fn _add_debug_overlay(&mut self) {
+        let debug_draw = &mut self.debug_draw;
+
+        debug_draw.axis(Mat4::IDENTITY, 1.0);
+
+        for (_, renderable) in self.world.renderables() {
+            let transform = self.world.transform(renderable.transform);
+            let (center, radius) = self.world.mesh(renderable.mesh).bounding_sphere();
+            let (scale, _, _) = transform.to_scale_rotation_translation();
+            debug_draw.sphere(
+                transform.transform_point3(center),
+                radius * scale.max_element(),
+                Vec4::new(1.0, 1.0, 0.0, 1.0),
+            );
+        }
+
+        // Light colors are normalized, since they're usually brighter than 1
+        for (_, light) in self.light_manager.iter() {
+            let color = light.color() / light.color().max_element().max(1e-4);
+            let color = color.extend(1.0);
+            debug_draw.sphere(light.position(), light.radius(), color);
+            if let Light::Spot(x) = light {
+                debug_draw.line(
+                    x.position,
+                    x.position + x.direction.normalize() * x.radius,
+                    color,
+                );
+            }
+        }
+
+        debug_draw.frustum(
+            self.shadow_pass.light_space(&self.light),
+            Vec4::new(1.0, 0.5, 0.0, 1.0),
+        );
+    }
+
+    pub fn supports_vertex_pulling(&self) -> bool {
+        self.vertex_pulling_shader.is_some()
+    }
+
+    pub fn vertex_pulling(&self) -> bool {
+        self.vertex_pulling
+    }
+
+    // Draws meshes with vertex_pulling.glsl, which reads each mesh's vertices
+    // through a device address pushed as a constant instead of from vertex
+    // input bindings. Occlusion probes still use the bindings
+    pub fn set_vertex_pulling(&mut self, vertex_pulling: bool) {
+        assert!(
+            !vertex_pulling || self.supports_vertex_pulling(),
+            "vertex pulling needs buffer device addresses"
+        );
+        self.vertex_pulling = vertex_pulling;
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    // Draws the meshes depth-only before the main pass, which then only
+    // shades the nearest surface with an EQUAL depth test. The depth test is
+    // baked into the pipelines, so they're rebuilt. SSAO and Hi-Z culling
+    // need the prepass and are turned off without it
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        if depth_prepass == self.depth_prepass {
+            return;
+        }
+        self.depth_prepass = depth_prepass;
+        if !depth_prepass {
+            self.ssao = false;
+            self.hi_z_culling = false;
+        }
+        self._recreate_graphics_pipeline();
+    }
+
+    pub fn ssao(&self) -> bool {
+        self.ssao
+    }
+
+    // Screen space ambient occlusion, see `SsaoPass`. The depth prepass
+    // writes the G-buffer it needs, so it's turned on along with it, and its
+    // pipelines are rebuilt with a fragment shader
+    pub fn set_ssao(&mut self, ssao: bool) {
+        if ssao == self.ssao {
+            return;
+        }
+        self.ssao = ssao;
+        self.depth_prepass |= ssao;
+        self._recreate_graphics_pipeline();
+    }
+
+    pub fn ssao_radius(&self) -> f32 {
+        self.ssao_pass.radius()
+    }
+
+    // How far from a surface occluders count, in world units
+    pub fn set_ssao_radius(&mut self, radius: f32) {
+        self.ssao_pass.set_radius(radius);
+    }
+
+    pub fn ssao_intensity(&self) -> f32 {
+        self.ssao_pass.intensity()
+    }
+
+    // 0 leaves the ambient light alone, 1 takes all of it from fully
+    // occluded pixels
+    pub fn set_ssao_intensity(&mut self, intensity: f32) {
+        self.ssao_pass.set_intensity(intensity);
+    }
+
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion_culling
+    }
+
+    // Wraps each batch's draw in an occlusion query. Batches whose query came
+    // back with no samples are drawn with the probe pipeline until they're
+    // visible again. Results arrive frames in flight late. Without the depth
+    // prepass they only cover the meshes drawn before, with it they're
+    // tested against the whole scene's depth
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: bool) {
+        self.occlusion_culling = occlusion_culling;
+    }
+
+    pub fn hi_z_culling(&self) -> bool {
+        self.hi_z_culling
+    }
+
+    // Builds a depth pyramid from the depth prepass and culls the objects
+    // hidden behind it again before the main pass, see `DepthPyramidPass`.
+    // Unlike the occlusion queries it's exact for the current frame, but it
+    // only saves the main pass' work, so the prepass is turned on along with
+    // it
+    pub fn set_hi_z_culling(&mut self, hi_z_culling: bool) {
+        if hi_z_culling == self.hi_z_culling {
+            return;
+        }
+        self.hi_z_culling = hi_z_culling;
+        if hi_z_culling && !self.depth_prepass {
+            self.depth_prepass = true;
+            self._recreate_graphics_pipeline();
+        }
+    }
+
+    pub fn cull_distance(&self) -> Option<f32> {
+        self.cull_distance
+    }
+
+    // Objects entirely farther than this from the camera are culled on the
+    // CPU, along with the ones outside the frustum. None only culls by the
+    // frustum
+    pub fn set_cull_distance(&mut self, cull_distance: Option<f32>) {
+        self.cull_distance = cull_distance;
+    }
+
+    pub fn lod_settings(&self) -> LodSettings {
+        self.lod_settings
+    }
+
+    pub fn set_lod_settings(&mut self, lod_settings: LodSettings) {
+        self.lod_settings = lod_settings;
+    }
+
+    // The batch's material features, with `skinned` set from its mesh
+    fn _batch_features(&self, batch: usize) -> MaterialFeatures {
+        let batch = &self.world.batches()[batch];
+        MaterialFeatures {
+            skinned: self.world.mesh(batch.mesh).is_skinned(),
+            ..self.material_table.features(batch.material)
+        }
+    }
+
+    fn _is_transparent(&self, batch: usize) -> bool {
+        let material = self.world.batches()[batch].material;
+        self.material_table.features(material).alpha_blend
+    }
+
+    // Blended batches sorted back to front by the view depth of their
+    // renderables' bounds, averaged over the renderables. A batch is drawn
+    // at once, so its renderables aren't sorted against each other
+    fn _transparent_batches(&self, frame_index: usize, view: Mat4) -> Vec<usize> {
+        let world = &self.world;
+        let mut depths: Vec<(usize, f32)> = (0..world.batches().len())
+            .filter(|x| self._is_transparent(*x))
+            .filter(|x| self.culling_pass.batch_visible(frame_index, *x))
+            .map(|i| {
+                let batch = &world.batches()[i];
+                let (center, _) = world.mesh(batch.mesh).bounding_sphere();
+                let depth_sum = batch
+                    .renderables
+                    .iter()
+                    .map(|x| {
+                        let transform = world.transform(world.renderable(*x).unwrap().transform);
+                        // The view looks down -Z
+                        -(view * transform).transform_point3(center).z
+                    })
+                    .sum::<f32>();
+                (i, depth_sum / batch.renderables.len().max(1) as f32)
+            })
+            .collect();
+        depths.sort_by(|a, b| b.1.total_cmp(&a.1));
+        depths.into_iter().map(|x| x.0).collect()
+    }
+
+    // Stats of the last drawn frame, see `FrameStats` for the GPU timing
+    // latency
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    pub fn suboptimal_policy(&self) -> SuboptimalPolicy {
+        self.suboptimal_policy
+    }
+
+    pub fn set_suboptimal_policy(&mut self, policy: SuboptimalPolicy) {
+        self.suboptimal_policy = policy;
+    }
+
+    // Points the frame's sets at the culling pass's buffers after they were
+    // recreated
+    fn _write_object_descriptors(&self, frame_index: usize) {
+        let descriptor_set = &self.frames.get(frame_index).descriptor_set;
+        let mut writer = DescriptorWriter::new(self.device.clone());
+        writer
+            .write_buffer(
+                descriptor_set,
+                self.culling_pass.object_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                1,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+            .write_buffer(
+                descriptor_set,
+                self.culling_pass.visible_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                2,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+            .write_buffer(
+                descriptor_set,
+                self.culling_pass.joint_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                7,
+                vk::DescriptorType::STORAGE_BUFFER,
+            );
+        writer.flush();
 
-        let max_frames_in_flight = self.render_frames.len();
+        self.shadow_pass
+            .write_descriptor_set(frame_index, &self.culling_pass);
+    }
 
-        self.draw_images = RenderContext::_create_draw_images(
-            &self.device,
-            &self.allocator,
-            max_frames_in_flight,
-            vk::Extent3D {
-                width,
-                height,
-                depth: 1,
-            },
+    // The frame's blurred occlusion with SSAO, or white without it. Written
+    // every frame since both the setting and the images can change
+    fn _write_ao_descriptor(&self, frame_index: usize) {
+        let frame = self.frames.get(frame_index);
+        let view = if self.ssao {
+            frame.images.ao_images[1].get_default_view(vk::ImageAspectFlags::COLOR)
+        } else {
+            self.white_texture.view().clone()
+        };
+        let mut writer = DescriptorWriter::new(self.device.clone());
+        writer.write_image(
+            &frame.descriptor_set,
+            self.white_texture.sampler(),
+            &view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            8,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
         );
+        writer.flush();
+    }
 
-        self.render_frames.clear();
+    pub fn draw_next_frame(&mut self) {
+        let _span = debug_span!("frame", number = self.frames.frame_count()).entered();
 
-        for i in 0..max_frames_in_flight {
-            self.render_frames.push(RenderFrame::new(i, &self));
+        // A minimized window has a zero sized surface, which a swapchain can't
+        // be created for. Nothing is drawn until it's resized again
+        if let Some((width, height)) = self.pending_resize {
+            if width == 0 || height == 0 {
+                return;
+            }
+            self.pending_resize = None;
+            self.recreate_swapchain(width, height);
         }
-    }
 
-    pub fn draw_next_frame(&mut self) {
-        let success = self.render_frames[self.current_frame].draw_frame(self);
+        let frame_start = Instant::now();
+        let cpu_frame_time = frame_start - self.last_frame_start;
+        self.last_frame_start = frame_start;
+
+        if self.shader_registry.poll() {
+            self._recreate_graphics_pipeline();
+            let old_culling_pipeline = self.culling_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_culling_pipeline);
+            let old_shadow_pipeline = self.shadow_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_shadow_pipeline);
+            let old_light_pipeline = self.light_manager.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_light_pipeline);
+            let old_tonemap_pipeline = self.tonemap_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_tonemap_pipeline);
+            let old_bloom_pipelines = self.bloom_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_bloom_pipelines);
+            let old_post_process_pipelines =
+                self.post_process.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_post_process_pipelines);
+            let old_taa_pipeline = self.taa_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_taa_pipeline);
+            let old_ssao_pipelines = self.ssao_pass.recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_ssao_pipelines);
+            let old_depth_pyramid_pipelines = self
+                .depth_pyramid_pass
+                .recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_depth_pyramid_pipelines);
+            let old_debug_draw_pipeline = self
+                .debug_draw_pass
+                .recreate_pipelines(&self.shader_registry);
+            self.frames.defer_delete(old_debug_draw_pipeline);
+        }
+
+        // The frame's buffers are rewritten from the world below, so its last
+        // submission has to be done with them
+        let frame_index = self.frames.current().index;
+        self.device
+            .wait_for_fences(&[&self.frames.current().in_flight], true, None);
+
+        let cull_view = {
+            let extent = self.swapchain.extent();
+            let aspect_ratio = extent.width as f32 / extent.height as f32;
+            CullView {
+                clip_from_world: self
+                    .camera
+                    .projection_matrix(aspect_ratio, self.depth_direction)
+                    * self.camera.view_matrix(),
+                camera_position: self.camera.position,
+                projection: self.camera.projection,
+                max_distance: self.cull_distance,
+                lod: self.lod_settings,
+            }
+        };
+        if self.taa {
+            self.taa_pass.advance(cull_view.clip_from_world);
+        }
+        self._update_assets();
+        self._update_animation(cpu_frame_time);
+        self.world.update_batches();
+        if self
+            .culling_pass
+            .prepare(frame_index, &self.world, &cull_view)
+        {
+            self._write_object_descriptors(frame_index);
+        }
+        let depth_pyramid_view = self
+            .frames
+            .get(frame_index)
+            .images
+            .depth_pyramid_image
+            .get_default_view(vk::ImageAspectFlags::COLOR);
+        self.culling_pass
+            .set_depth_pyramid(frame_index, depth_pyramid_view);
+        self._write_ao_descriptor(frame_index);
+        self.shadow_pass.fit(&self.world);
+        self.frames
+            .current_mut()
+            ._update_batches(&self.device, &self.world);
+
+        if self.debug_overlay {
+            self._add_debug_overlay();
+        }
+
+        let status = self.frames.current().draw_frame(self);
+        self.debug_draw.clear();
+        self.frame_stats = FrameStats {
+            cpu_frame_time,
+            ..self.frames.current().stats.take()
+        };
+
+        // `draw_frame` waited on this frame's fence. Descriptor sets dropped
+        // since the last frame may still be in use by frames in flight
+        self.frames.collect();
+        let dropped_sets = self.descriptor_allocator.take_dropped_sets();
+        self.frames.defer_delete(dropped_sets);
 
-        if success {
-            self.current_frame = (self.current_frame + 1) % self.render_frames.len();
-            self.frame_count += 1;
+        if let FrameStatus::Presented { .. } | FrameStatus::OutOfDate = status {
+            self.frame_dump_path = None;
+            self.frames.advance();
             self.window.request_redraw();
-        } else {
+        }
+
+        let recreate = match status {
+            FrameStatus::Presented { suboptimal } => {
+                if suboptimal {
+                    self.suboptimal_frames += 1;
+                    match self.suboptimal_policy {
+                        SuboptimalPolicy::IgnoreFor(frames) => self.suboptimal_frames > frames,
+                        _ => true,
+                    }
+                } else {
+                    self.suboptimal_frames = 0;
+                    false
+                }
+            }
+            FrameStatus::OutOfDate | FrameStatus::Skipped => true,
+        };
+
+        if recreate {
             let PhysicalSize { width, height } = self.window.inner_size();
-            self.recreate_swapchain(width, height)
+            self.resize(width, height)
         }
     }
 }
 
-impl Drop for RenderContext {
+impl Drop for Renderer {
     fn drop(&mut self) {
         // Wait for GPU to finish all pending work before dropping the render
         // context. This gives command buffers time to finish before we drop any
         // resources they may be referencing
         self.device.wait_idle();
+        self.frames.clear_deferred();
+    }
+}
+
+// The images a frame draws into, which are sized to the draw extent and so
+// are all recreated when it changes
+struct FrameImages {
+    draw_image: Arc<Image>,
+    msaa_image: Option<Arc<Image>>,
+    depth_image: Arc<Image>,
+    bloom_images: Vec<Arc<Image>>,
+    post_process_images: Vec<Arc<Image>>,
+    // Only used while TAA is on. The history image is this frame's resolved
+    // output, which the next frame reads
+    motion_image: Arc<Image>,
+    history_image: Arc<Image>,
+    // Only used while SSAO is on. The G-buffer is multisampled along with the
+    // draw image and resolved into `gbuffer_image`. The occlusion is raw and
+    // then blurred, see `SsaoPass::create_ao_images`
+    gbuffer_image: Arc<Image>,
+    gbuffer_msaa_image: Option<Arc<Image>>,
+    ao_images: Vec<Arc<Image>>,
+    // Only built while Hi-Z culling is on, see `DepthPyramidPass`
+    depth_pyramid_image: Arc<Image>,
+}
+
+impl FrameImages {
+    fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        Self {
+            draw_image: Renderer::_create_draw_image(device, allocator, extent),
+            msaa_image: Renderer::_create_msaa_image(device, allocator, extent, samples),
+            depth_image: Renderer::_create_depth_image(device, allocator, extent, samples),
+            bloom_images: BloomPass::create_images(device, allocator, extent),
+            post_process_images: PostProcessChain::create_images(device, allocator, extent),
+            motion_image: TaaPass::create_motion_image(device, allocator, extent),
+            history_image: TaaPass::create_history_image(device, allocator, extent),
+            gbuffer_image: SsaoPass::create_gbuffer_image(device, allocator, extent),
+            // The G-buffer has the draw image's format
+            gbuffer_msaa_image: Renderer::_create_msaa_image(device, allocator, extent, samples),
+            ao_images: SsaoPass::create_ao_images(device, allocator, extent),
+            depth_pyramid_image: DepthPyramidPass::create_image(device, allocator, extent),
+        }
     }
 }
 
@@ -732,17 +2431,48 @@ struct RenderFrame {
     image_available: Semaphore,
     render_finished: Semaphore,
     in_flight: Fence,
+    uniform_buffer: Buffer,
+    descriptor_set: DescriptorSet,
+    images: FrameImages,
+    // None if the device can't write timestamps
+    timestamp_queries: Option<QueryPool>,
+    // Passes timed by the last submission, in query order
+    timed_passes: RefCell<Vec<&'static str>>,
+    // Covers the whole frame. None without the pipelineStatisticsQuery
+    // feature
+    stats_query: Option<StatsQuery>,
+    // One query for each of the world's batches, grown as needed
+    occlusion_queries: QueryPool,
+    // Whether the last submission wrote the stats or occlusion queries
+    stats_written: Cell<bool>,
+    occlusion_written: Cell<bool>,
+    batch_occluded: RefCell<Vec<bool>>,
+    // The world's batches the occlusion state was sized for, see
+    // `RenderWorld::generation`
+    world_generation: u64,
+    stats: RefCell<FrameStats>,
 }
 
 impl RenderFrame {
-    pub fn new(index: usize, context: &RenderContext) -> Self {
-        let cmd_buf = context
-            .cmd_pool
-            .allocate_one(vk::CommandBufferLevel::PRIMARY);
-
-        let image_available = Semaphore::new(context.device.clone());
-        let render_finished = Semaphore::new(context.device.clone());
-        let in_flight = Fence::signaled(context.device.clone());
+    pub fn new(
+        index: usize,
+        device: &Arc<Device>,
+        cmd_pool: &Arc<CommandPool>,
+        uniform_buffer: Buffer,
+        descriptor_set: DescriptorSet,
+        images: FrameImages,
+    ) -> Self {
+        let cmd_buf = cmd_pool.allocate_one(vk::CommandBufferLevel::PRIMARY);
+
+        let image_available = Semaphore::new(device.clone());
+        let render_finished = Semaphore::new(device.clone());
+        let in_flight = Fence::signaled(device.clone());
+
+        let timestamp_queries = QueryPool::supports_timestamps(device)
+            .then(|| QueryPool::timestamps(device.clone(), 2 * MAX_TIMED_PASSES));
+        let stats_query = QueryPool::supports_pipeline_statistics(device)
+            .then(|| StatsQuery::new(device.clone(), 1));
+        let occlusion_queries = QueryPool::occlusion(device.clone(), 64);
 
         Self {
             index,
@@ -750,43 +2480,139 @@ impl RenderFrame {
             image_available,
             render_finished,
             in_flight,
+            uniform_buffer,
+            descriptor_set,
+            images,
+            timestamp_queries,
+            timed_passes: RefCell::new(vec![]),
+            stats_query,
+            occlusion_queries,
+            stats_written: Cell::new(false),
+            occlusion_written: Cell::new(false),
+            batch_occluded: RefCell::new(vec![]),
+            world_generation: 0,
+            stats: RefCell::new(FrameStats::default()),
         }
     }
 
-    pub fn update_uniform_buffer(&self, context: &RenderContext) {
-        let time = context.start_time.elapsed().as_secs_f32();
+    // Returns the time of each pass and of the whole graph. Only called once
+    // the frame's fence has signaled, so the queries written by its last
+    // submission are all available
+    fn _read_timestamps(&self, device: &Device) -> (Vec<PassTime>, Option<Duration>) {
+        let timed_passes = self.timed_passes.borrow();
+        let Some(query_pool) = &self.timestamp_queries else {
+            return (vec![], None);
+        };
+        if timed_passes.is_empty() {
+            return (vec![], None);
+        }
 
-        let aspect_ratio = {
-            let extent = context.swapchain.extent();
-            extent.width as f32 / extent.height as f32
+        let Some(timestamps) = query_pool.get_results(0, 2 * timed_passes.len() as u32) else {
+            return (vec![], None);
         };
 
-        let model = Mat4::from_rotation_z(time * 90_f32.to_radians());
+        let period = QueryPool::timestamp_period(device);
+        let duration = |begin: u64, end: u64| {
+            Duration::from_nanos((end.saturating_sub(begin) as f64 * period) as u64)
+        };
 
-        let view = Mat4::look_at_rh(
-            Vec3::new(2.0, 2.0, 2.0),
-            Vec3::ZERO,
-            Vec3::new(0.0, 0.0, 1.0),
-        );
+        let passes = timed_passes
+            .iter()
+            .zip(timestamps.chunks_exact(2))
+            .map(|(name, x)| PassTime {
+                name,
+                time: duration(x[0], x[1]),
+            })
+            .collect();
+        let total = duration(timestamps[0], timestamps[timestamps.len() - 1]);
+        (passes, Some(total))
+    }
+
+    fn _read_pipeline_statistics(&self) -> Option<PipelineStatistics> {
+        let stats_query = self.stats_query.as_ref()?;
+        if !self.stats_written.get() {
+            return None;
+        }
+        stats_query.results(0)
+    }
+
+    // Sizes the occlusion state for the world's batches. Results from before
+    // they were regrouped don't line up with them anymore, so they're dropped
+    fn _update_batches(&mut self, device: &Arc<Device>, world: &RenderWorld) {
+        if self.world_generation == world.generation() {
+            return;
+        }
+        self.world_generation = world.generation();
+
+        let batch_count = world.batches().len();
+        if batch_count > self.occlusion_queries.count() as usize {
+            self.occlusion_queries =
+                QueryPool::occlusion(device.clone(), batch_count.next_power_of_two() as u32);
+        }
+        *self.batch_occluded.get_mut() = vec![false; batch_count];
+        self.occlusion_written.set(false);
+    }
+
+    // Updates which batches were occluded in the last submission. Batches
+    // it didn't test are assumed visible. Ones culled on the CPU never write
+    // their query, so each query is read on its own
+    fn _read_occlusion(&self) {
+        let mut batch_occluded = self.batch_occluded.borrow_mut();
+        if !self.occlusion_written.get() {
+            batch_occluded.fill(false);
+            return;
+        }
+
+        for (i, occluded) in batch_occluded.iter_mut().enumerate() {
+            let samples = self.occlusion_queries.get_results(i as u32, 1);
+            *occluded = samples.is_some_and(|x| x[0] == 0);
+        }
+    }
 
-        let proj = {
-            let mut m = Mat4::perspective_rh(45_f32.to_radians(), aspect_ratio, 0.1, 10.0);
-            let col = m.col_mut(1).borrow_mut();
-            col.y *= -1.0;
-            m
+    // Returns what was written so the culling pass can use the same matrices
+    pub fn update_uniform_buffer(&self, context: &Renderer) -> Uniform {
+        let aspect_ratio = {
+            let extent = context.swapchain.extent();
+            extent.width as f32 / extent.height as f32
         };
 
-        let ubo = Uniform { model, view, proj };
-        let buffer = &context.uniform_buffers[self.index];
+        let view = context.camera.view_matrix();
+        let mut proj = context
+            .camera
+            .projection_matrix(aspect_ratio, context.depth_direction);
 
-        buffer.copy_nonoverlapping(&[ubo]);
+        // Shifts the whole image by a subpixel offset, so over a few frames
+        // the resolve sees every part of each pixel
+        let jitter = if context.taa {
+            context.taa_pass.jitter(*context.swapchain.extent())
+        } else {
+            Vec2::ZERO
+        };
+        proj = Mat4::from_translation(jitter.extend(0.0)) * proj;
+
+        let light = context.light;
+        let ubo = Uniform {
+            view,
+            proj,
+            light_space: context.shadow_pass.light_space(&light),
+            light_direction: light.direction.normalize().extend(0.0),
+            light_color: light.color.extend(0.0),
+            camera_position: context.camera.position.extend(1.0),
+            previous_view_proj: context.taa_pass.previous_view_proj(),
+            jitter: jitter.extend(0.0).extend(0.0),
+        };
+        self.uniform_buffer.copy_nonoverlapping(&[ubo]);
+        ubo
     }
 
-    pub fn draw_frame(&self, context: &RenderContext) -> bool {
-        self.update_uniform_buffer(context);
+    // The frame's fence has to have been waited on, see
+    // `Renderer::draw_next_frame`
+    pub fn draw_frame(&self, context: &Renderer) -> FrameStatus {
+        let uniform = self.update_uniform_buffer(context);
 
-        let fences = &[&self.in_flight];
-        context.device.wait_for_fences(fences, true, None);
+        let (gpu_passes, gpu_frame_time) = self._read_timestamps(&context.device);
+        let pipeline_statistics = self._read_pipeline_statistics();
+        self._read_occlusion();
 
         let acquire_result =
             context
@@ -794,82 +2620,198 @@ impl RenderFrame {
                 .acquire_next_image(None, Some(&self.image_available), None);
 
         let image_index: u32;
+        let acquire_suboptimal: bool;
 
         match acquire_result {
             Ok((acquired_index, suboptimal)) => {
                 image_index = acquired_index;
-
-                if suboptimal {
-                    return false;
-                }
+                acquire_suboptimal = suboptimal;
             }
             Err(result) => match result {
                 vk::Result::NOT_READY => todo!(),
                 vk::Result::TIMEOUT => todo!(),
-                vk::Result::ERROR_OUT_OF_DATE_KHR => return false,
+                vk::Result::ERROR_OUT_OF_DATE_KHR => return FrameStatus::Skipped,
+                vk::Result::ERROR_DEVICE_LOST => context.device.report_device_lost(),
                 vk::Result::ERROR_SURFACE_LOST_KHR => todo!(),
                 vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => todo!(),
                 _ => panic!("acquire_result = {:?}", result),
             },
         }
 
-        context.device.reset_fences(fences);
-        self.cmd_buf.reset();
-
-        self.record_commands(context, image_index);
-
         let graphics_queue = context
             .device
             .get_first_queue(vk::QueueFlags::GRAPHICS)
             .unwrap();
 
+        context.device.reset_fences(&[&self.in_flight]);
+
+        // The acquire has already signaled `image_available`, so an empty
+        // submit is needed to wait on it before the frame can be skipped
+        if acquire_suboptimal && context.suboptimal_policy == SuboptimalPolicy::RecreateImmediately
+        {
+            graphics_queue.submit(
+                Some(&[(&self.image_available, vk::PipelineStageFlags2::ALL_COMMANDS)]),
+                &[],
+                &[],
+                None,
+                &[],
+                Some(&self.in_flight),
+            );
+            return FrameStatus::Skipped;
+        }
+
+        // The submit below signals `in_flight`. A skipped frame records no
+        // uploads, so it doesn't free any staging memory either
+        context.uploads.begin_frame(self.index);
+
+        self.cmd_buf.reset();
+
+        if context.frame_dump_path.is_some() {
+            self.cmd_buf.begin_trace();
+        }
+
+        let record_start = Instant::now();
+        self.record_commands(context, image_index, &uniform);
+        let cpu_cull_stats = context.culling_pass.cpu_cull_stats(self.index);
+
+        *self.stats.borrow_mut() = FrameStats {
+            cpu_record_time: record_start.elapsed(),
+            gpu_frame_time,
+            gpu_passes,
+            pipeline_statistics,
+            occluded_batches: if context.occlusion_culling {
+                self.batch_occluded.borrow().iter().filter(|x| **x).count() as u32
+            } else {
+                0
+            },
+            cpu_frustum_culled: cpu_cull_stats.frustum_culled,
+            cpu_distance_culled: cpu_cull_stats.distance_culled,
+            cpu_culled_batches: cpu_cull_stats.culled_batches,
+            draw_calls: self.cmd_buf.draw_count(),
+            dispatches: self.cmd_buf.dispatch_count(),
+            ..Default::default()
+        };
+
+        if let Some(trace) = self.cmd_buf.end_trace() {
+            // A failed dump isn't worth losing the frame over
+            let path = context.frame_dump_path.as_ref().unwrap();
+            for (extension, contents) in [("json", trace.to_json()), ("dot", trace.to_dot())] {
+                let path = path.with_extension(extension);
+                if let Err(error) = std::fs::write(&path, contents) {
+                    warn!("failed to write frame dump {}: {}", path.display(), error);
+                }
+            }
+        }
+
         let present_queue = context.device.get_first_present_queue().unwrap();
 
+        context.device.push_checkpoint(format!(
+            "frame {} (slot {}): submit, image {}",
+            context.frames.frame_count(),
+            self.index,
+            image_index
+        ));
+
         graphics_queue.submit(
             Some(&[(
                 &self.image_available,
                 vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
             )]),
+            &[],
             &[&self.cmd_buf],
             Some(&[(&self.render_finished, vk::PipelineStageFlags2::ALL_GRAPHICS)]),
+            &[],
             Some(&self.in_flight),
         );
+        context.uploads.end_frame();
+
+        context
+            .device
+            .push_checkpoint(format!("frame {}: present", context.frames.frame_count()));
 
-        let present_result =
-            present_queue.submit_present(&[&self.render_finished], &context.swapchain, image_index);
+        let present_result = present_queue
+            .submit_present(
+                &[&self.render_finished],
+                &[(&context.swapchain, image_index)],
+            )
+            .remove(0);
 
         match present_result {
-            Ok(suboptimal) => {
-                if suboptimal {
-                    return false;
-                }
-            }
+            Ok(suboptimal) => FrameStatus::Presented {
+                suboptimal: suboptimal || acquire_suboptimal,
+            },
             Err(result) => match result {
-                vk::Result::ERROR_OUT_OF_DATE_KHR => return false,
+                vk::Result::ERROR_OUT_OF_DATE_KHR => FrameStatus::OutOfDate,
+                vk::Result::ERROR_DEVICE_LOST => context.device.report_device_lost(),
                 vk::Result::ERROR_SURFACE_LOST_KHR => todo!(),
                 vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => todo!(),
                 _ => panic!("present_result = {:?}", result),
             },
         }
-
-        true
     }
 
-    pub fn record_commands(&self, context: &RenderContext, image_index: u32) {
+    pub fn record_commands(&self, context: &Renderer, image_index: u32, uniform: &Uniform) {
+        let _span = trace_span!("record_commands", image_index).entered();
+
         self.cmd_buf
             .begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
-        let extent = context.swapchain.extent();
+        // Ahead of everything that could use what they upload
+        context.uploads.record(&self.cmd_buf);
+
+        // Queries are reset outside of any rendering
+        if let Some(stats_query) = &self.stats_query {
+            stats_query.reset(&self.cmd_buf);
+            stats_query.begin(&self.cmd_buf, 0);
+        }
+        self.stats_written.set(self.stats_query.is_some());
+
+        if context.occlusion_culling {
+            self.cmd_buf.reset_query_pool(
+                &self.occlusion_queries,
+                0,
+                self.occlusion_queries.count(),
+            );
+        }
+        self.occlusion_written.set(context.occlusion_culling);
 
-        let draw_image = &context.draw_images[self.index];
+        let draw_image = &self.images.draw_image;
         let draw_image_view = draw_image.get_default_view(vk::ImageAspectFlags::COLOR);
+        let msaa_image = self.images.msaa_image.as_ref();
+        let msaa_image_view = msaa_image.map(|x| x.get_default_view(vk::ImageAspectFlags::COLOR));
+        let depth_image = &self.images.depth_image;
+        let depth_image_view = depth_image.get_default_view(vk::ImageAspectFlags::DEPTH);
+        let motion_image_view = self
+            .images
+            .motion_image
+            .get_default_view(vk::ImageAspectFlags::COLOR);
+        let gbuffer_image_view = self
+            .images
+            .gbuffer_image
+            .get_default_view(vk::ImageAspectFlags::COLOR);
+        let gbuffer_msaa_image = self.images.gbuffer_msaa_image.as_ref();
+        let gbuffer_msaa_image_view =
+            gbuffer_msaa_image.map(|x| x.get_default_view(vk::ImageAspectFlags::COLOR));
         let swapchain_image = &context.swapchain.images()[image_index as usize];
 
-        self.cmd_buf.transition_image(
-            &draw_image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
-        );
+        unsafe {
+            self.cmd_buf
+                .trace_name(draw_image.get_vk_handle(), "draw image");
+            self.cmd_buf
+                .trace_name(draw_image_view.get_vk_handle(), "draw image");
+            self.cmd_buf
+                .trace_name(depth_image.get_vk_handle(), "depth image");
+            if let (Some(msaa_image), Some(msaa_image_view)) = (msaa_image, &msaa_image_view) {
+                self.cmd_buf
+                    .trace_name(msaa_image.get_vk_handle(), "msaa image");
+                self.cmd_buf
+                    .trace_name(msaa_image_view.get_vk_handle(), "msaa image");
+            }
+            self.cmd_buf
+                .trace_name(depth_image_view.get_vk_handle(), "depth image");
+            self.cmd_buf
+                .trace_name(swapchain_image.get_vk_handle(), "swapchain image");
+        }
 
         let time =
             0.5 * f32::cos(std::f32::consts::PI + context.start_time.elapsed().as_secs_f32()) + 0.5;
@@ -878,177 +2820,636 @@ impl RenderFrame {
             float32: [0.0, time, 0.0, 0.0],
         };
 
-        let clear_range = vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: vk::REMAINING_MIP_LEVELS,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS,
-        };
+        let mut color_attachment = rendering_attachment(
+            &draw_image_view,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::LOAD,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue { color: clear_value },
+        );
 
-        self.cmd_buf
-            .clear_color_image(&draw_image, clear_value, &[clear_range]);
+        if let Some(msaa_image_view) = &msaa_image_view {
+            color_attachment = vk::RenderingAttachmentInfo {
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                ..resolved_attachment(
+                    color_attachment,
+                    msaa_image_view,
+                    vk::ResolveModeFlags::AVERAGE,
+                )
+            };
+        }
 
-        self.cmd_buf.transition_image(
-            &draw_image,
-            vk::ImageLayout::GENERAL,
+        // With TAA the main pass also writes the motion vectors. They're
+        // cleared to zero, which is what the sky keeps
+        let motion_attachment = rendering_attachment(
+            &motion_image_view,
             vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0; 4] },
+            },
         );
 
-        let color_attachment = unsafe {
-            vk::RenderingAttachmentInfo {
-                s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
-                p_next: std::ptr::null(),
-                image_view: draw_image_view.get_vk_handle(),
-                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                resolve_mode: vk::ResolveModeFlags::NONE,
-                resolve_image_view: vk::ImageView::null(),
-                resolve_image_layout: vk::ImageLayout::UNDEFINED,
-                load_op: vk::AttachmentLoadOp::DONT_CARE,
-                store_op: vk::AttachmentStoreOp::STORE,
-                clear_value: vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
-                    },
-                },
-            }
+        let mut depth_attachment = rendering_attachment(
+            &depth_image_view,
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::AttachmentStoreOp::DONT_CARE,
+            context.depth_direction.clear_value(),
+        );
+
+        // The prepass clears depth and the main pass tests against it
+        let prepass_depth_attachment = vk::RenderingAttachmentInfo {
+            store_op: vk::AttachmentStoreOp::STORE,
+            ..depth_attachment
         };
 
-        self.cmd_buf.begin_rendering(
-            vk::RenderingFlags::empty(),
-            vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: vk::Extent2D {
-                    width: extent.width,
-                    height: extent.height,
-                },
+        // With SSAO the prepass also writes the G-buffer, where depth is
+        // cleared to zero for nothing drawn. It's resolved like the draw image
+        let mut gbuffer_attachment = rendering_attachment(
+            &gbuffer_image_view,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0; 4] },
             },
-            1,
-            0,
-            Some(&[color_attachment]),
-            None,
-            None,
         );
+        if let Some(gbuffer_msaa_image_view) = &gbuffer_msaa_image_view {
+            gbuffer_attachment = vk::RenderingAttachmentInfo {
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                ..resolved_attachment(
+                    gbuffer_attachment,
+                    gbuffer_msaa_image_view,
+                    vk::ResolveModeFlags::AVERAGE,
+                )
+            };
+        }
+        let prepass_color_attachments = if context.ssao {
+            vec![gbuffer_attachment]
+        } else {
+            vec![]
+        };
+        if context.depth_prepass {
+            depth_attachment.load_op = vk::AttachmentLoadOp::LOAD;
+        }
 
-        self.cmd_buf
-            .bind_pipeline(context.graphics_pipeline.as_ref());
+        // Blended meshes are drawn in their own pass after the main one, which
+        // then has to keep its color and depth. The multisampled image is
+        // resolved again at the end
+        let transparent = context._transparent_batches(self.index, uniform.view);
+        let transparent_color_attachment = vk::RenderingAttachmentInfo {
+            load_op: vk::AttachmentLoadOp::LOAD,
+            ..color_attachment
+        };
+        let transparent_depth_attachment = vk::RenderingAttachmentInfo {
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            ..depth_attachment
+        };
+        if !transparent.is_empty() {
+            color_attachment.store_op = vk::AttachmentStoreOp::STORE;
+            depth_attachment.store_op = vk::AttachmentStoreOp::STORE;
+        }
 
-        self.cmd_buf.set_viewport(
-            0,
-            &[vk::Viewport {
-                x: 0.0,
-                y: 0.0,
-                width: extent.width as f32,
-                height: extent.height as f32,
-                min_depth: 0.0,
-                max_depth: 1.0,
-            }],
+        let mut color_attachments = vec![color_attachment];
+        let mut transparent_color_attachments = vec![transparent_color_attachment];
+        if context.taa {
+            color_attachments.push(motion_attachment);
+            transparent_color_attachments.push(vk::RenderingAttachmentInfo {
+                load_op: vk::AttachmentLoadOp::LOAD,
+                ..motion_attachment
+            });
+        }
+
+        // Images are imported as UNDEFINED since nothing from the previous
+        // frame is kept. The swapchain image's acquire semaphore is waited on
+        // at COLOR_ATTACHMENT_OUTPUT, so its first barrier has to chain with
+        // that stage
+        let mut graph = RenderGraph::new();
+        let draw = graph.import_image(
+            draw_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags2::NONE,
+        );
+        let msaa = msaa_image.map(|msaa_image| {
+            graph.import_image(
+                msaa_image,
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags2::NONE,
+            )
+        });
+        let depth = graph.import_image(
+            depth_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags2::NONE,
+        );
+        let gbuffer = context.ssao.then(|| {
+            graph.import_image(
+                &self.images.gbuffer_image,
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags2::NONE,
+            )
+        });
+        let gbuffer_msaa = gbuffer_msaa_image.filter(|_| context.ssao).map(|image| {
+            graph.import_image(
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags2::NONE,
+            )
+        });
+        let motion = context.taa.then(|| {
+            graph.import_image(
+                &self.images.motion_image,
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags2::NONE,
+            )
+        });
+        let swapchain = graph.import_image(
+            swapchain_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        );
+        let draws = graph.import_buffer(context.culling_pass.draw_buffer(self.index));
+        let visible = graph.import_buffer(context.culling_pass.visible_buffer(self.index));
+
+        graph
+            .add_pass("cull")
+            .write_buffer(draws, BufferUsage::Storage)
+            .write_buffer(visible, BufferUsage::Storage)
+            .record(|cmd| {
+                context.culling_pass.record(
+                    cmd,
+                    self.index,
+                    uniform.proj * uniform.view,
+                    context.depth_direction,
+                );
+            });
+
+        // The multisampled image is cleared on load and resolved into the
+        // draw image at the end of the pass, otherwise the draw image is
+        // cleared beforehand
+        if msaa.is_none() {
+            graph
+                .add_pass("clear")
+                .write_image(draw, ImageUsage::Clear)
+                .record(move |cmd| {
+                    let clear_range = vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    };
+                    cmd.clear_color_image(draw_image, clear_value, &[clear_range]);
+                });
+        }
+
+        let shadow_map = context.shadow_pass.add_pass(
+            &mut graph,
+            self.index,
+            &context.world,
+            &context.culling_pass,
+            uniform.light_space,
         );
 
-        self.cmd_buf.set_scissor(
-            0,
-            &[vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: *extent,
-            }],
+        // Without a far plane the clusters end past the farthest renderable
+        let (near, mut far) = context.camera.projection.depth_range();
+        if far.is_infinite() {
+            far = context.camera.position.length() + context.world.bounding_radius();
+        }
+        let clusters = context.light_manager.add_pass(
+            &mut graph,
+            self.index,
+            uniform.view,
+            uniform.proj,
+            *context.swapchain.extent(),
+            (near, far),
         );
 
-        let mut vertex_buffers = vec![];
-        for x in &context.vertex_buffers {
-            vertex_buffers.push((x, 0u64));
+        if context.depth_prepass {
+            let mut prepass = graph
+                .add_pass("depth_prepass")
+                .write_image(depth, ImageUsage::DepthAttachment)
+                .read_buffer(draws, BufferUsage::Indirect)
+                .read_buffer(visible, BufferUsage::Storage);
+            for image in [gbuffer, gbuffer_msaa].into_iter().flatten() {
+                prepass = prepass.write_image(image, ImageUsage::ColorAttachment);
+            }
+            prepass.record(move |cmd| {
+                self._draw_depth_prepass(
+                    cmd,
+                    context,
+                    prepass_color_attachments,
+                    prepass_depth_attachment,
+                )
+            });
         }
 
-        self.cmd_buf
-            .bind_index_buffer(&context.index_buffer, 0, vk::IndexType::UINT16);
+        // Objects hidden behind the prepass' depth are culled from the draws
+        // again before the main pass. Hi-Z culling implies the prepass, see
+        // `Renderer::set_hi_z_culling`
+        if context.hi_z_culling {
+            let pyramid = context.depth_pyramid_pass.add_pass(
+                &mut graph,
+                self.index,
+                (depth, depth_image_view.clone()),
+                &self.images.depth_pyramid_image,
+                context.depth_direction,
+            );
+            graph
+                .add_pass("occlusion cull")
+                .read_image(pyramid, ImageUsage::Sampled)
+                .write_buffer(draws, BufferUsage::TransferDst)
+                .write_buffer(draws, BufferUsage::Storage)
+                .write_buffer(visible, BufferUsage::Storage)
+                .record(move |cmd| context.culling_pass.record_occlusion(cmd, self.index));
+        }
+
+        // SSAO implies the prepass, see `Renderer::set_ssao`
+        let ao = gbuffer.map(|gbuffer| {
+            let (ao, _) = context.ssao_pass.add_passes(
+                &mut graph,
+                self.index,
+                (gbuffer, gbuffer_image_view.clone()),
+                &self.images.ao_images,
+                uniform.proj,
+            );
+            ao
+        });
+
+        // Depth is cleared on load, unless there was a prepass, and not needed
+        // after the pass
+        let mut main_pass = graph
+            .add_pass("main")
+            .write_image(draw, ImageUsage::ColorAttachment)
+            .write_image(depth, ImageUsage::DepthAttachment)
+            .read_image(shadow_map, ImageUsage::Sampled)
+            .read_buffer(clusters, BufferUsage::Storage)
+            .read_buffer(draws, BufferUsage::Indirect)
+            .read_buffer(visible, BufferUsage::Storage);
+        if let Some(msaa) = msaa {
+            main_pass = main_pass.write_image(msaa, ImageUsage::ColorAttachment);
+        }
+        if let Some(motion) = motion {
+            main_pass = main_pass.write_image(motion, ImageUsage::ColorAttachment);
+        }
+        if let Some(ao) = ao {
+            main_pass = main_pass.read_image(ao, ImageUsage::Sampled);
+        }
+        main_pass.record(move |cmd| {
+            self._draw(
+                cmd,
+                context,
+                color_attachments,
+                depth_attachment,
+                uniform.view,
+                uniform.proj,
+            )
+        });
+
+        if !transparent.is_empty() {
+            let mut transparent_pass = graph
+                .add_pass("transparent")
+                .write_image(draw, ImageUsage::ColorAttachment)
+                .read_image(depth, ImageUsage::DepthAttachment)
+                .read_image(shadow_map, ImageUsage::Sampled)
+                .read_buffer(clusters, BufferUsage::Storage)
+                .read_buffer(draws, BufferUsage::Indirect)
+                .read_buffer(visible, BufferUsage::Storage);
+            if let Some(msaa) = msaa {
+                transparent_pass = transparent_pass.write_image(msaa, ImageUsage::ColorAttachment);
+            }
+            if let Some(motion) = motion {
+                transparent_pass =
+                    transparent_pass.write_image(motion, ImageUsage::ColorAttachment);
+            }
+            if let Some(ao) = ao {
+                transparent_pass = transparent_pass.read_image(ao, ImageUsage::Sampled);
+            }
+            transparent_pass.record(move |cmd| {
+                self._draw_transparent(
+                    cmd,
+                    context,
+                    transparent_color_attachments,
+                    transparent_depth_attachment,
+                    transparent,
+                )
+            });
+        }
+
+        context.debug_draw_pass.add_pass(
+            &mut graph,
+            self.index,
+            &context.debug_draw,
+            (draw, draw_image_view.clone()),
+            *context.swapchain.extent(),
+            uniform.proj * uniform.view,
+        );
+
+        // Everything after works on the resolved image. This frame's history
+        // may still be read by the next frame's last submission, and the
+        // previous frame's was left sampled by its bloom pass
+        let (scene, scene_view) = match motion {
+            Some(motion) => {
+                let previous = context
+                    .frames
+                    .get((self.index + context.frames.len() - 1) % context.frames.len());
+                let history = graph.import_image(
+                    &previous.images.history_image,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                );
+                let output = graph.import_image(
+                    &self.images.history_image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                );
+                let output_view = self
+                    .images
+                    .history_image
+                    .get_default_view(vk::ImageAspectFlags::COLOR);
+                let resolved = context.taa_pass.add_pass(
+                    &mut graph,
+                    self.index,
+                    (draw, draw_image_view.clone()),
+                    (motion, motion_image_view.clone()),
+                    (
+                        history,
+                        previous
+                            .images
+                            .history_image
+                            .get_default_view(vk::ImageAspectFlags::COLOR),
+                    ),
+                    (output, output_view.clone()),
+                );
+                (resolved, output_view)
+            }
+            None => (draw, draw_image_view.clone()),
+        };
+
+        let bloom = context.bloom_pass.add_passes(
+            &mut graph,
+            self.index,
+            scene,
+            scene_view.clone(),
+            &self.images.bloom_images,
+        );
+        let bloom_image_view =
+            self.images.bloom_images[0].get_default_view(vk::ImageAspectFlags::COLOR);
+
+        let (post, post_view) = context.post_process.add_passes(
+            &mut graph,
+            self.index,
+            (scene, scene_view),
+            (bloom, bloom_image_view.clone()),
+            &self.images.post_process_images,
+            context.start_time.elapsed().as_secs_f32(),
+        );
+
+        graph
+            .add_pass("tonemap")
+            .read_image(post, ImageUsage::Sampled)
+            .read_image(bloom, ImageUsage::Sampled)
+            .write_image(swapchain, ImageUsage::ColorAttachment)
+            .record(|cmd| {
+                context.tonemap_pass.record(
+                    cmd,
+                    self.index,
+                    post_view,
+                    (bloom_image_view, context.bloom_pass.intensity()),
+                    swapchain_image.get_default_view(vk::ImageAspectFlags::COLOR),
+                    context.swapchain.surface_format(),
+                );
+            });
 
-        self.cmd_buf.bind_vertex_buffers(0, &vertex_buffers);
+        graph
+            .add_pass("present")
+            .read_image(swapchain, ImageUsage::Present)
+            .record(|_| {});
 
-        self.cmd_buf.bind_descriptor_sets(
+        match &self.timestamp_queries {
+            Some(query_pool) => {
+                *self.timed_passes.borrow_mut() = graph.execute_timed(&self.cmd_buf, query_pool)
+            }
+            None => graph.execute(&self.cmd_buf),
+        }
+
+        if let Some(stats_query) = &self.stats_query {
+            stats_query.end(&self.cmd_buf, 0);
+        }
+
+        self.cmd_buf.end();
+    }
+
+    fn _draw_depth_prepass(
+        &self,
+        cmd_buf: &CommandBuffer,
+        context: &Renderer,
+        color_attachments: Vec<vk::RenderingAttachmentInfo>,
+        depth_attachment: vk::RenderingAttachmentInfo,
+    ) {
+        let extent = context.swapchain.extent();
+
+        cmd_buf.begin_rendering(
+            RenderingConfig::new(*extent)
+                .color_attachments(&color_attachments)
+                .depth_attachment(depth_attachment),
+        );
+
+        cmd_buf.set_full_viewport_scissor_flipped(*extent);
+
+        cmd_buf.bind_descriptor_sets(
             vk::PipelineBindPoint::GRAPHICS,
             &context.pipeline_layout,
             0,
-            &[&context.descriptor_sets[self.index]],
+            &[&self.descriptor_set],
         );
 
-        self.cmd_buf
-            .draw_indexed(context.indices.len().try_into().unwrap(), 1, 0, 0, 0);
+        // Batches the main pass only probes for occlusion are left out, so they
+        // can't hide anything, and so are blended ones
+        let mut bound_features = None;
+        for (i, batch) in context.world.batches().iter().enumerate() {
+            let occluded = context.occlusion_culling && self.batch_occluded.borrow()[i];
+            if occluded
+                || context._is_transparent(i)
+                || !context.culling_pass.batch_visible(self.index, i)
+            {
+                continue;
+            }
+
+            let mesh = context.world.mesh(batch.mesh);
+            let features = context._batch_features(i).depth_only();
+            if bound_features != Some(features) {
+                cmd_buf.bind_pipeline(context.depth_prepass_pipelines[&features].as_ref());
+                bound_features = Some(features);
+            }
 
-        self.cmd_buf.end_rendering();
+            cmd_buf.bind_index_buffer(
+                mesh.index_buffer().buffer(),
+                mesh.index_buffer().offset(),
+                mesh.index_type(),
+            );
+            cmd_buf.bind_vertex_buffers(0, &mesh.vertex_bindings());
+            context.culling_pass.draw_batch(cmd_buf, self.index, i);
+        }
 
-        self.cmd_buf.transition_image(
-            &draw_image,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-        );
+        cmd_buf.end_rendering();
+    }
 
-        self.cmd_buf.transition_image(
-            &swapchain_image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    fn _draw(
+        &self,
+        cmd_buf: &CommandBuffer,
+        context: &Renderer,
+        color_attachments: Vec<vk::RenderingAttachmentInfo>,
+        depth_attachment: vk::RenderingAttachmentInfo,
+        view: Mat4,
+        proj: Mat4,
+    ) {
+        let extent = context.swapchain.extent();
+
+        cmd_buf.begin_rendering(
+            RenderingConfig::new(*extent)
+                .color_attachments(&color_attachments)
+                .depth_attachment(depth_attachment),
         );
 
-        self.copy_image_to_image(&self.cmd_buf, &draw_image, &swapchain_image);
+        cmd_buf.set_full_viewport_scissor_flipped(*extent);
 
-        self.cmd_buf.transition_image(
-            &swapchain_image,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::PRESENT_SRC_KHR,
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &context.pipeline_layout,
+            0,
+            &[&self.descriptor_set],
         );
 
-        self.cmd_buf.end();
+        let opaque = (0..context.world.batches().len()).filter(|x| !context._is_transparent(*x));
+        self._draw_batches(cmd_buf, context, opaque);
+
+        // Last, so it's only shaded where no geometry was drawn
+        context.skybox_pass.record(cmd_buf, view, proj);
+
+        cmd_buf.end_rendering();
     }
 
-    pub fn copy_image_to_image(&self, cmd: &CommandBuffer, src: &Image, dst: &Image) {
-        let src_size = src.extent();
-        let dst_size = dst.extent();
+    // Draws the world's batches in the given order. Expects set 0 to be bound
+    fn _draw_batches(
+        &self,
+        cmd_buf: &CommandBuffer,
+        context: &Renderer,
+        batches: impl Iterator<Item = usize>,
+    ) {
+        // Each batch is one indirect draw covering its visible renderables
+        let mut bound_features = None;
+        for i in batches {
+            // Nothing's left of batches culled on the CPU, so they aren't
+            // drawn or queried
+            if !context.culling_pass.batch_visible(self.index, i) {
+                continue;
+            }
+
+            let batch = &context.world.batches()[i];
+            let mesh = context.world.mesh(batch.mesh);
+            cmd_buf.bind_index_buffer(
+                mesh.index_buffer().buffer(),
+                mesh.index_buffer().offset(),
+                mesh.index_type(),
+            );
 
-        let blit_region = vk::ImageBlit2 {
-            s_type: vk::StructureType::IMAGE_BLIT_2,
-            p_next: std::ptr::null(),
-            src_offsets: [
-                vk::Offset3D { x: 0, y: 0, z: 0 },
-                vk::Offset3D {
-                    x: src_size.width.try_into().unwrap(),
-                    y: src_size.height.try_into().unwrap(),
-                    z: 1,
-                },
-            ],
-            dst_offsets: [
-                vk::Offset3D { x: 0, y: 0, z: 0 },
-                vk::Offset3D {
-                    x: dst_size.width.try_into().unwrap(),
-                    y: dst_size.height.try_into().unwrap(),
-                    z: 1,
-                },
-            ],
-            src_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            dst_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-        };
+            cmd_buf.bind_vertex_buffers(0, &mesh.vertex_bindings());
+
+            let material = batch.material;
+
+            // Batches that were hidden last time are only depth tested, to
+            // find out when they come back into view
+            let occlusion_query = context.occlusion_culling.then_some(i as u32);
+            let occluded = occlusion_query.is_some() && self.batch_occluded.borrow()[i];
+
+            // Pipelines share a layout, so set 0 stays bound across them.
+            // Skinned meshes always use vertex input
+            let features = context._batch_features(i);
+            let vertex_pulling = context.vertex_pulling && !features.skinned;
+            if occluded {
+                cmd_buf.bind_pipeline(context.occlusion_probe_pipeline.as_ref());
+                bound_features = None;
+            } else if bound_features != Some(features) {
+                let pipelines = if vertex_pulling {
+                    &context.vertex_pulling_pipelines
+                } else {
+                    &context.graphics_pipelines
+                };
+                cmd_buf.bind_pipeline(pipelines[&features].as_ref());
+                bound_features = Some(features);
+            }
 
-        let blit_info = unsafe {
-            vk::BlitImageInfo2 {
-                s_type: vk::StructureType::BLIT_IMAGE_INFO_2,
-                p_next: std::ptr::null(),
-                src_image: src.get_vk_handle(),
-                src_image_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                dst_image: dst.get_vk_handle(),
-                dst_image_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                filter: vk::Filter::LINEAR,
-                p_regions: &blit_region,
-                region_count: 1,
+            if vertex_pulling && !occluded {
+                let vertex_buffer = mesh.vertex_buffer();
+                let address =
+                    unsafe { vertex_buffer.buffer().get_device_address().get_vk_handle() }
+                        + vertex_buffer.offset();
+                cmd_buf.push_constants(
+                    &context.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    &address,
+                );
             }
-        };
 
-        cmd.blit_image(&blit_info)
+            cmd_buf.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                &context.pipeline_layout,
+                1,
+                &[context.material_table.descriptor_set(material)],
+            );
+
+            if let Some(query) = occlusion_query {
+                cmd_buf.begin_query(
+                    &self.occlusion_queries,
+                    query,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+            context.culling_pass.draw_batch(cmd_buf, self.index, i);
+            if let Some(query) = occlusion_query {
+                cmd_buf.end_query(&self.occlusion_queries, query);
+            }
+        }
+    }
+
+    // Blended batches over the opaque ones, back to front. The color and
+    // depth attachments are loaded from the main pass, and depth is only
+    // tested
+    fn _draw_transparent(
+        &self,
+        cmd_buf: &CommandBuffer,
+        context: &Renderer,
+        color_attachments: Vec<vk::RenderingAttachmentInfo>,
+        depth_attachment: vk::RenderingAttachmentInfo,
+        batches: Vec<usize>,
+    ) {
+        let extent = context.swapchain.extent();
+
+        cmd_buf.begin_rendering(
+            RenderingConfig::new(*extent)
+                .color_attachments(&color_attachments)
+                .depth_attachment(depth_attachment),
+        );
+
+        cmd_buf.set_full_viewport_scissor_flipped(*extent);
+
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &context.pipeline_layout,
+            0,
+            &[&self.descriptor_set],
+        );
+
+        self._draw_batches(cmd_buf, context, batches.into_iter());
+
+        cmd_buf.end_rendering();
     }
 }
@@ -0,0 +1,330 @@
+use glam::Mat4;
+use std::collections::BTreeMap;
+
+use crate::model::{Mesh, Model};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransformHandle(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JointsHandle(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderableId(usize);
+
+impl RenderableId {
+    // Slot index, which is reused once the renderable is removed
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renderable {
+    pub mesh: MeshId,
+    // Index into the renderer's material table, where 0 is the default
+    // material and each model's materials follow
+    pub material: usize,
+    pub transform: TransformHandle,
+    // Joint matrices posing a skinned mesh, see `AnimationPlayer`. Skinned
+    // meshes without them are drawn in their bind pose, and other meshes
+    // ignore them
+    pub joints: Option<JointsHandle>,
+}
+
+// A renderable added for one of a model's instances, see
+// `RenderWorld::add_model`
+#[derive(Debug, Clone, Copy)]
+pub struct ModelInstance {
+    pub renderable: RenderableId,
+    pub transform: TransformHandle,
+    // The instance's transform within the model
+    pub instance_transform: Mat4,
+    // The model's skin and the instance's joint matrices, for instances of
+    // skinned meshes
+    pub skin: Option<(usize, JointsHandle)>,
+}
+
+// Renderables sharing a mesh and material, which are drawn together by one
+// indirect draw
+pub struct RenderBatch {
+    pub mesh: MeshId,
+    pub material: usize,
+    pub renderables: Vec<RenderableId>,
+}
+
+// Everything the renderer draws. Meshes, renderables and transforms can be
+// added and removed at any time. Transforms are meant to be updated every
+// frame, and several renderables can share one
+pub struct RenderWorld {
+    // Removed meshes are None. Their slots aren't reused, so a stale id
+    // panics instead of drawing another mesh
+    meshes: Vec<Option<Mesh>>,
+    // Removed slots are None and reused by later additions
+    transforms: Vec<Option<Mat4>>,
+    free_transforms: Vec<usize>,
+    joints: Vec<Option<Vec<Mat4>>>,
+    free_joints: Vec<usize>,
+    renderables: Vec<Option<Renderable>>,
+    free_renderables: Vec<usize>,
+    batches: Vec<RenderBatch>,
+    batches_dirty: bool,
+    // Bumped whenever the batches are rebuilt
+    generation: u64,
+}
+
+impl RenderWorld {
+    pub fn new() -> Self {
+        Self {
+            meshes: vec![],
+            transforms: vec![],
+            free_transforms: vec![],
+            joints: vec![],
+            free_joints: vec![],
+            renderables: vec![],
+            free_renderables: vec![],
+            batches: vec![],
+            batches_dirty: false,
+            generation: 0,
+        }
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+        self.meshes.push(Some(mesh));
+        MeshId(self.meshes.len() - 1)
+    }
+
+    pub fn mesh(&self, id: MeshId) -> &Mesh {
+        self.meshes[id.0].as_ref().expect("mesh was removed")
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.iter().filter(|x| x.is_some()).count()
+    }
+
+    // Whether any renderable draws the mesh
+    pub fn is_mesh_used(&self, id: MeshId) -> bool {
+        self.renderables().any(|(_, x)| x.mesh == id)
+    }
+
+    // Renderables still using the mesh have to be removed first. The mesh is
+    // returned so the caller can keep it alive until the GPU is done with it
+    pub fn remove_mesh(&mut self, id: MeshId) -> Option<Mesh> {
+        self.meshes[id.0].take()
+    }
+
+    pub fn add_transform(&mut self, transform: Mat4) -> TransformHandle {
+        match self.free_transforms.pop() {
+            Some(i) => {
+                self.transforms[i] = Some(transform);
+                TransformHandle(i)
+            }
+            None => {
+                self.transforms.push(Some(transform));
+                TransformHandle(self.transforms.len() - 1)
+            }
+        }
+    }
+
+    pub fn transform(&self, handle: TransformHandle) -> Mat4 {
+        self.transforms[handle.0].expect("transform was removed")
+    }
+
+    pub fn set_transform(&mut self, handle: TransformHandle, transform: Mat4) {
+        let slot = &mut self.transforms[handle.0];
+        assert!(slot.is_some(), "transform was removed");
+        *slot = Some(transform);
+    }
+
+    // Renderables still using the transform have to be removed first
+    pub fn remove_transform(&mut self, handle: TransformHandle) {
+        if self.transforms[handle.0].take().is_some() {
+            self.free_transforms.push(handle.0);
+        }
+    }
+
+    pub fn add_joints(&mut self, joints: Vec<Mat4>) -> JointsHandle {
+        match self.free_joints.pop() {
+            Some(i) => {
+                self.joints[i] = Some(joints);
+                JointsHandle(i)
+            }
+            None => {
+                self.joints.push(Some(joints));
+                JointsHandle(self.joints.len() - 1)
+            }
+        }
+    }
+
+    pub fn joints(&self, handle: JointsHandle) -> &[Mat4] {
+        self.joints[handle.0].as_ref().expect("joints were removed")
+    }
+
+    pub fn set_joints(&mut self, handle: JointsHandle, joints: &[Mat4]) {
+        let slot = self.joints[handle.0].as_mut().expect("joints were removed");
+        slot.clear();
+        slot.extend_from_slice(joints);
+    }
+
+    // Renderables still using the joints have to be removed first
+    pub fn remove_joints(&mut self, handle: JointsHandle) {
+        if self.joints[handle.0].take().is_some() {
+            self.free_joints.push(handle.0);
+        }
+    }
+
+    pub fn add(&mut self, renderable: Renderable) -> RenderableId {
+        assert!(
+            self.meshes
+                .get(renderable.mesh.0)
+                .is_some_and(|x| x.is_some()),
+            "unknown mesh"
+        );
+        assert!(
+            self.transforms[renderable.transform.0].is_some(),
+            "transform was removed"
+        );
+        assert!(
+            renderable.joints.is_none_or(|x| self.joints[x.0].is_some()),
+            "joints were removed"
+        );
+
+        self.batches_dirty = true;
+        match self.free_renderables.pop() {
+            Some(i) => {
+                self.renderables[i] = Some(renderable);
+                RenderableId(i)
+            }
+            None => {
+                self.renderables.push(Some(renderable));
+                RenderableId(self.renderables.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: RenderableId) {
+        if self.renderables[id.0].take().is_some() {
+            self.free_renderables.push(id.0);
+            self.batches_dirty = true;
+        }
+    }
+
+    pub fn renderable(&self, id: RenderableId) -> Option<&Renderable> {
+        self.renderables[id.0].as_ref()
+    }
+
+    pub fn renderables(&self) -> impl Iterator<Item = (RenderableId, &Renderable)> + '_ {
+        self.renderables
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| x.as_ref().map(|x| (RenderableId(i), x)))
+    }
+
+    pub fn renderable_count(&self) -> usize {
+        self.renderables.len() - self.free_renderables.len()
+    }
+
+    // Adds the model's meshes and a renderable for each of its instances,
+    // with one transform per instance. The caller can place an instance by
+    // setting its transform to `transform * instance_transform`. Instances of
+    // skinned meshes also get joints, in the bind pose. The model's materials
+    // are expected at `first_material` in the material table, see
+    // `Renderable::material`
+    pub fn add_model(&mut self, model: Model, first_material: usize) -> Vec<ModelInstance> {
+        let joint_counts = model
+            .skeleton()
+            .skins()
+            .iter()
+            .map(|x| x.joints.len())
+            .collect::<Vec<_>>();
+        let (meshes, instances, _) = model.into_parts();
+        let mesh_ids = meshes
+            .into_iter()
+            .map(|x| self.add_mesh(x))
+            .collect::<Vec<_>>();
+
+        instances
+            .iter()
+            .map(|x| {
+                let mesh = mesh_ids[x.mesh];
+                let transform = self.add_transform(x.transform);
+                let skin = x.skin.filter(|_| self.mesh(mesh).is_skinned()).map(|skin| {
+                    let joints = vec![Mat4::IDENTITY; joint_counts[skin]];
+                    (skin, self.add_joints(joints))
+                });
+                let renderable = self.add(Renderable {
+                    mesh,
+                    material: self.mesh(mesh).material().map_or(0, |x| first_material + x),
+                    transform,
+                    joints: skin.map(|(_, joints)| joints),
+                });
+                ModelInstance {
+                    renderable,
+                    transform,
+                    instance_transform: x.transform,
+                    skin,
+                }
+            })
+            .collect()
+    }
+
+    // Regroups the renderables if any were added or removed since the last
+    // call. Returns true if the batches changed
+    pub fn update_batches(&mut self) -> bool {
+        if !self.batches_dirty {
+            return false;
+        }
+
+        // Sorted by material so draws with the same pipeline are adjacent
+        let mut batches = BTreeMap::<(usize, MeshId), Vec<RenderableId>>::new();
+        for (id, renderable) in self.renderables() {
+            batches
+                .entry((renderable.material, renderable.mesh))
+                .or_default()
+                .push(id);
+        }
+        self.batches = batches
+            .into_iter()
+            .map(|((material, mesh), renderables)| RenderBatch {
+                mesh,
+                material,
+                renderables,
+            })
+            .collect();
+
+        self.batches_dirty = false;
+        self.generation += 1;
+        true
+    }
+
+    // As of the last `update_batches`
+    pub fn batches(&self) -> &[RenderBatch] {
+        &self.batches
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Radius of a sphere around the origin containing every renderable's
+    // bounds, and at least 1
+    pub fn bounding_radius(&self) -> f32 {
+        self.renderables()
+            .map(|(_, x)| {
+                let transform = self.transform(x.transform);
+                let (center, radius) = self.mesh(x.mesh).bounding_sphere();
+                let (scale, _, _) = transform.to_scale_rotation_translation();
+                transform.transform_point3(center).length() + radius * scale.max_element()
+            })
+            .fold(1.0, f32::max)
+    }
+}
+
+impl Default for RenderWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
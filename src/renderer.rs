@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use winit::window::Window;
+
+use crate::config::Config;
+use crate::gpu::PresentModePreference;
+use crate::render_context::Renderer;
+
+// Creates a renderer for a window. Starts from the default `Config`, or one
+// given with `config`, e.g. from `Config::from_args`, and overrides its options
+#[derive(Debug, Clone, Default)]
+pub struct RendererBuilder {
+    config: Config,
+}
+
+impl RendererBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.config.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentModePreference) -> Self {
+        self.config.present_mode = present_mode;
+        self
+    }
+
+    pub fn gpu_name(mut self, gpu_name: impl Into<String>) -> Self {
+        self.config.gpu_name = Some(gpu_name.into());
+        self
+    }
+
+    pub fn validation(mut self, validation: bool) -> Self {
+        self.config.validation = validation;
+        self
+    }
+
+    pub fn msaa_samples(mut self, msaa_samples: u32) -> Self {
+        self.config.msaa_samples = msaa_samples;
+        self
+    }
+
+    pub fn environment(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.environment_path = Some(path.into());
+        self
+    }
+
+    pub fn cull_distance(mut self, cull_distance: f32) -> Self {
+        self.config.cull_distance = Some(cull_distance);
+        self
+    }
+
+    pub fn shader_dir(mut self, shader_dir: impl Into<PathBuf>) -> Self {
+        self.config.shader_dir = shader_dir.into();
+        self
+    }
+
+    pub fn build(self, window: Arc<Window>) -> Renderer {
+        Renderer::new(window, &self.config)
+    }
+}
+
+impl Renderer {
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder::new()
+    }
+}
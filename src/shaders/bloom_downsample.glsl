@@ -0,0 +1,44 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D srcImage;
+
+// Matches `BloomConstants` in bloom.rs
+layout(push_constant) uniform Constants {
+    vec2 srcTexelSize;
+    float threshold;
+    float knee;
+    uint prefilter;
+} constants;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+// Soft threshold, so colors fade into the bloom over `knee` below the
+// threshold instead of popping in
+vec3 prefilterColor(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - constants.threshold + constants.knee, 0.0, 2.0 * constants.knee);
+    soft = soft * soft / (4.0 * constants.knee + 0.0001);
+    float contribution = max(soft, brightness - constants.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+vec3 tap(float x, float y) {
+    return texture(srcImage, fragUv + constants.srcTexelSize * vec2(x, y)).rgb;
+}
+
+// 13 tap filter from Jimenez's "Next Generation Post Processing in Call of
+// Duty: Advanced Warfare", which avoids the flickering of a plain box filter
+void main() {
+    vec3 color = tap(0.0, 0.0) * 0.125;
+    color += (tap(-2.0, 2.0) + tap(2.0, 2.0) + tap(-2.0, -2.0) + tap(2.0, -2.0)) * 0.03125;
+    color += (tap(0.0, 2.0) + tap(-2.0, 0.0) + tap(2.0, 0.0) + tap(0.0, -2.0)) * 0.0625;
+    color += (tap(-1.0, 1.0) + tap(1.0, 1.0) + tap(-1.0, -1.0) + tap(1.0, -1.0)) * 0.125;
+
+    if (constants.prefilter != 0) {
+        color = prefilterColor(color);
+    }
+
+    outColor = vec4(max(color, 0.0), 1.0);
+}
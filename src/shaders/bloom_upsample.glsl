@@ -0,0 +1,28 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D srcImage;
+
+// Matches `BloomConstants` in bloom.rs
+layout(push_constant) uniform Constants {
+    vec2 srcTexelSize;
+    float threshold;
+    float knee;
+    uint prefilter;
+} constants;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 tap(float x, float y) {
+    return texture(srcImage, fragUv + constants.srcTexelSize * vec2(x, y)).rgb;
+}
+
+// 3x3 tent filter, added onto the next larger level by the blend state
+void main() {
+    vec3 color = tap(0.0, 0.0) * 4.0;
+    color += (tap(0.0, 1.0) + tap(-1.0, 0.0) + tap(1.0, 0.0) + tap(0.0, -1.0)) * 2.0;
+    color += tap(-1.0, 1.0) + tap(1.0, 1.0) + tap(-1.0, -1.0) + tap(1.0, -1.0);
+
+    outColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,134 @@
+#version 450
+
+layout(local_size_x = 64) in;
+
+struct Object {
+    mat4 transform;
+    vec4 bounds;
+    uint draw;
+    uint cpuCulled;
+    uint jointOffset;
+};
+
+struct DrawCommand {
+    uint indexCount;
+    uint instanceCount;
+    uint firstIndex;
+    int vertexOffset;
+    uint firstInstance;
+};
+
+layout(binding = 0) uniform CullUniform {
+    mat4 clipFromWorld;
+    vec4 planes[6];
+    uint objectCount;
+    uint reversedDepth;
+} cull;
+
+layout(std430, binding = 1) readonly buffer Objects {
+    Object objects[];
+};
+
+// One draw per batch, with instanceCount reset to zero before the dispatch
+layout(std430, binding = 2) buffer Draws {
+    DrawCommand draws[];
+};
+
+layout(std430, binding = 3) writeonly buffer VisibleObjects {
+    uint visibleObjects[];
+};
+
+// Farthest depth under each texel of the depth prepass, see
+// `DepthPyramidPass`. Only read by the occlusion dispatch
+layout(binding = 4) uniform sampler2D depthPyramid;
+
+// Matches `CullConstants` in culling.rs
+layout(push_constant) uniform Constants {
+    uint occlusion;
+} constants;
+
+// Whether the sphere is behind what the depth prepass drew. Its bounding box
+// is projected to a screen rectangle and its nearest depth, which is compared
+// against the pyramid level where the rectangle covers at most 2x2 texels
+bool isOccluded(vec3 center, float radius) {
+    bool reversed = cull.reversedDepth != 0;
+    vec2 lo = vec2(1.0);
+    vec2 hi = vec2(-1.0);
+    float nearest = reversed ? 0.0 : 1.0;
+    for (int i = 0; i < 8; i++) {
+        vec3 corner = center + radius * vec3(
+            (i & 1) != 0 ? 1.0 : -1.0,
+            (i & 2) != 0 ? 1.0 : -1.0,
+            (i & 4) != 0 ? 1.0 : -1.0
+        );
+        vec4 clip = cull.clipFromWorld * vec4(corner, 1.0);
+        // Crosses the camera plane, where the projection wraps around
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        vec3 ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc.xy);
+        hi = max(hi, ndc.xy);
+        nearest = reversed ? max(nearest, ndc.z) : min(nearest, ndc.z);
+    }
+
+    // In front of the near plane
+    if (reversed ? nearest > 1.0 : nearest < 0.0) {
+        return false;
+    }
+
+    // The viewport is flipped, so +y in NDC is the top row
+    vec2 uvMin = clamp(vec2(lo.x, -hi.y) * 0.5 + 0.5, 0.0, 1.0);
+    vec2 uvMax = clamp(vec2(hi.x, -lo.y) * 0.5 + 0.5, 0.0, 1.0);
+
+    vec2 extent = (uvMax - uvMin) * vec2(textureSize(depthPyramid, 0));
+    int level = int(ceil(log2(max(max(extent.x, extent.y), 1.0))));
+    level = clamp(level, 0, textureQueryLevels(depthPyramid) - 1);
+
+    ivec2 size = textureSize(depthPyramid, level);
+    ivec2 first = clamp(ivec2(uvMin * vec2(size)), ivec2(0), size - 1);
+    ivec2 last = clamp(ivec2(uvMax * vec2(size)), ivec2(0), size - 1);
+
+    float farthest = reversed ? 1.0 : 0.0;
+    for (int y = first.y; y <= last.y; y++) {
+        for (int x = first.x; x <= last.x; x++) {
+            float depth = texelFetch(depthPyramid, ivec2(x, y), level).r;
+            farthest = reversed ? min(farthest, depth) : max(farthest, depth);
+        }
+    }
+
+    return reversed ? nearest < farthest : nearest > farthest;
+}
+
+void main() {
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= cull.objectCount) {
+        return;
+    }
+
+    Object object = objects[index];
+    if (object.cpuCulled != 0) {
+        return;
+    }
+
+    vec3 center = (object.transform * vec4(object.bounds.xyz, 1.0)).xyz;
+    float scale = max(
+        max(length(object.transform[0].xyz), length(object.transform[1].xyz)),
+        length(object.transform[2].xyz)
+    );
+    float radius = object.bounds.w * scale;
+
+    for (int i = 0; i < 6; i++) {
+        if (dot(cull.planes[i].xyz, center) + cull.planes[i].w < -radius) {
+            return;
+        }
+    }
+
+    if (constants.occlusion != 0 && isOccluded(center, radius)) {
+        return;
+    }
+
+    // Each batch's visible objects are packed from its draw's firstInstance
+    uint slot = atomicAdd(draws[object.draw].instanceCount, 1);
+    visibleObjects[draws[object.draw].firstInstance + slot] = index;
+}
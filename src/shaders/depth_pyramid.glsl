@@ -0,0 +1,41 @@
+#version 450
+
+// Reduces the depth image, or the pyramid's previous level, into the next
+// level. Each texel keeps the farthest depth under it, so anything nearer
+// than a texel can't be hidden behind it, see `DepthPyramidPass`
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, r32f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D inputImage;
+
+// Matches `PyramidConstants` in depth_pyramid.rs
+layout(push_constant) uniform Constants {
+    uint reversedDepth;
+} constants;
+
+float farthest(float a, float b) {
+    return constants.reversedDepth != 0 ? min(a, b) : max(a, b);
+}
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    // The input is less than twice the output's size, so a texel covers up to
+    // three input texels on each side once its edges are rounded outwards
+    ivec2 inputSize = textureSize(inputImage, 0);
+    ivec2 first = id * inputSize / size;
+    ivec2 last = ((id + 1) * inputSize + size - 1) / size;
+
+    float depth = texelFetch(inputImage, first, 0).r;
+    for (int y = first.y; y < last.y; y++) {
+        for (int x = first.x; x < last.x; x++) {
+            depth = farthest(depth, texelFetch(inputImage, ivec2(x, y), 0).r);
+        }
+    }
+
+    imageStore(outputImage, id, vec4(depth));
+}
@@ -0,0 +1,41 @@
+#version 450
+
+// Like depth_pyramid.glsl, for the first level when the depth image is
+// multisampled. Every sample counts, since any of them can be what's seen
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, r32f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2DMS inputImage;
+
+// Matches `PyramidConstants` in depth_pyramid.rs
+layout(push_constant) uniform Constants {
+    uint reversedDepth;
+} constants;
+
+float farthest(float a, float b) {
+    return constants.reversedDepth != 0 ? min(a, b) : max(a, b);
+}
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    ivec2 inputSize = textureSize(inputImage);
+    int samples = textureSamples(inputImage);
+    ivec2 first = id * inputSize / size;
+    ivec2 last = ((id + 1) * inputSize + size - 1) / size;
+
+    float depth = texelFetch(inputImage, first, 0).r;
+    for (int y = first.y; y < last.y; y++) {
+        for (int x = first.x; x < last.x; x++) {
+            for (int i = 0; i < samples; i++) {
+                depth = farthest(depth, texelFetch(inputImage, ivec2(x, y), i).r);
+            }
+        }
+    }
+
+    imageStore(outputImage, id, vec4(depth));
+}
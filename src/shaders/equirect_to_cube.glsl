@@ -0,0 +1,44 @@
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+const float PI = 3.14159265359;
+
+layout(binding = 0) uniform sampler2D equirect;
+
+// The cubemap's faces as array layers
+layout(binding = 1, rgba16f) uniform writeonly image2DArray cube;
+
+// Direction through a point on a cube face, with the face orientations
+// cubemaps are sampled with
+vec3 cubeDirection(uint face, vec2 uv) {
+    uv = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0: return vec3(1.0, -uv.y, -uv.x);
+        case 1: return vec3(-1.0, -uv.y, uv.x);
+        case 2: return vec3(uv.x, 1.0, uv.y);
+        case 3: return vec3(uv.x, -1.0, -uv.y);
+        case 4: return vec3(uv.x, -uv.y, 1.0);
+        default: return vec3(-uv.x, -uv.y, -1.0);
+    }
+}
+
+void main() {
+    ivec3 id = ivec3(gl_GlobalInvocationID);
+    ivec2 size = imageSize(cube).xy;
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    vec2 uv = (vec2(id.xy) + 0.5) / vec2(size);
+    vec3 direction = normalize(cubeDirection(id.z, uv));
+
+    // World space is Z-up. The middle of the image faces +X and the top row
+    // is straight up
+    vec2 equirectUv = vec2(
+        atan(direction.y, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.z, -1.0, 1.0)) / PI
+    );
+
+    imageStore(cube, ivec3(id), textureLod(equirect, equirectUv, 0.0));
+}
@@ -1,12 +1,250 @@
 #version 450
 
-layout(binding = 1) uniform sampler2D texSampler;
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+    // Only used for the motion vectors, the vertex shaders stop at the fields
+    // above
+    mat4 previousViewProj;
+    // The offset applied to `proj` by TAA, in normalized device coordinates
+    vec4 jitter;
+} ubo;
 
-layout(location = 0) in vec3 fragColor;
+// Rendered by the shadow pass, see shadow.rs
+layout(binding = 3) uniform sampler2DShadow shadowMap;
+
+// Point and spot lights binned by the light culling pass, see lights.rs and
+// light_cull.glsl
+const uint MAX_LIGHTS_PER_CLUSTER = 127;
+const uint SPOT_LIGHT = 1;
+
+struct Light {
+    vec3 position;
+    float radius;
+    vec3 color;
+    uint kind;
+    vec3 direction;
+    float cosOuter;
+    float cosInner;
+};
+
+layout(binding = 4) uniform Clusters {
+    mat4 inverseProjection;
+    mat4 view;
+    uvec3 grid;
+    uint lightCount;
+    vec2 screenSize;
+    float near;
+    float far;
+} clusters;
+
+layout(std430, binding = 5) readonly buffer Lights {
+    Light lights[];
+};
+
+layout(std430, binding = 6) readonly buffer ClusterLights {
+    uint clusterLights[];
+};
+
+// Ambient occlusion from the SSAO pass, or a single white texel while it's
+// off, see ssao.rs
+layout(binding = 8) uniform sampler2D aoMap;
+
+// Per material, see material.rs
+layout(set = 1, binding = 0) uniform MaterialUniform {
+    vec4 baseColorFactor;
+    float metallicFactor;
+    float roughnessFactor;
+    float normalScale;
+} material;
+
+layout(set = 1, binding = 1) uniform sampler2D albedoMap;
+layout(set = 1, binding = 2) uniform sampler2D normalMap;
+// Roughness in G and metalness in B
+layout(set = 1, binding = 3) uniform sampler2D metallicRoughnessMap;
+
+// Averages a 3x3 block of comparisons to soften shadow edges
+layout(constant_id = 0) const bool PCF = true;
+
+// Material features, see MaterialFeatures
+layout(constant_id = 1) const bool NORMAL_MAP = false;
+layout(constant_id = 2) const bool METALLIC_ROUGHNESS_MAP = false;
+
+const float PI = 3.14159265359;
+const float AMBIENT = 0.15;
+
+layout(location = 0) in vec3 fragNormal;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec4 fragLightSpacePosition;
+layout(location = 3) in vec3 fragPosition;
 
 layout(location = 0) out vec4 outColor;
+// How far the surface moved on screen since the previous frame, in UV units.
+// Only has an attachment while TAA is on, see taa.rs
+layout(location = 1) out vec2 outMotion;
+
+// 1 when lit and 0 when in shadow
+float shadow() {
+    vec3 position = fragLightSpacePosition.xyz / fragLightSpacePosition.w;
+    if (position.z > 1.0) {
+        return 1.0;
+    }
+    vec2 uv = position.xy * 0.5 + 0.5;
+
+    if (!PCF) {
+        return texture(shadowMap, vec3(uv, position.z));
+    }
+
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadowMap, vec3(uv + vec2(x, y) * texelSize, position.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Meshes don't have tangents, so the tangent frame is built from screen space
+// derivatives of the position and texture coordinates
+vec3 perturbNormal(vec3 normal) {
+    vec3 tangentNormal = texture(normalMap, fragTexCoord).xyz * 2.0 - 1.0;
+    tangentNormal.xy *= material.normalScale;
+
+    vec3 dp1 = dFdx(fragPosition);
+    vec3 dp2 = dFdy(fragPosition);
+    vec2 duv1 = dFdx(fragTexCoord);
+    vec2 duv2 = dFdy(fragTexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float scale = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    mat3 tbn = mat3(tangent * scale, bitangent * scale, normal);
+    return normalize(tbn * tangentNormal);
+}
+
+// GGX normal distribution
+float distribution(float nDotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with the Schlick-GGX approximation for both directions
+float geometry(float nDotV, float nDotL, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float v = nDotV / (nDotV * (1.0 - k) + k);
+    float l = nDotL / (nDotL * (1.0 - k) + k);
+    return v * l;
+}
+
+vec3 fresnel(float vDotH, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - vDotH, 5.0);
+}
+
+// Outgoing light towards `v` from unit light arriving from `l`. Scaled by PI
+// so a white diffuse surface facing the light is as bright as the light
+vec3 brdf(vec3 n, vec3 v, vec3 l, vec3 albedo, float metallic, float roughness) {
+    vec3 h = normalize(v + l);
+
+    float nDotL = max(dot(n, l), 0.0);
+    float nDotV = max(dot(n, v), 1e-4);
+    float nDotH = max(dot(n, h), 0.0);
+    float vDotH = max(dot(v, h), 0.0);
+
+    // Dielectrics reflect 4% at normal incidence
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = fresnel(vDotH, f0);
+
+    vec3 specular = distribution(nDotH, roughness) * geometry(nDotV, nDotL, roughness) * f
+        / (4.0 * nDotV * max(nDotL, 1e-4));
+    vec3 diffuse = (1.0 - f) * (1.0 - metallic) * albedo / PI;
+
+    return (diffuse + specular) * nDotL * PI;
+}
+
+uint clusterIndex() {
+    uvec3 grid = clusters.grid;
+    uvec2 tile = uvec2(gl_FragCoord.xy / clusters.screenSize * vec2(grid.xy));
+    tile = min(tile, grid.xy - 1);
+
+    float depth = -(clusters.view * vec4(fragPosition, 1.0)).z;
+    float slice = log(depth / clusters.near) / log(clusters.far / clusters.near);
+    uint z = uint(clamp(slice * float(grid.z), 0.0, float(grid.z - 1)));
+
+    return tile.x + tile.y * grid.x + z * grid.x * grid.y;
+}
+
+// Light from the point and spot lights in the fragment's cluster
+vec3 clusterLighting(vec3 n, vec3 v, vec3 albedo, float metallic, float roughness) {
+    uint base = clusterIndex() * (MAX_LIGHTS_PER_CLUSTER + 1);
+    uint count = clusterLights[base];
+
+    vec3 result = vec3(0.0);
+    for (uint i = 0; i < count; i++) {
+        Light light = lights[clusterLights[base + 1 + i]];
+
+        vec3 toLight = light.position - fragPosition;
+        float distance = length(toLight);
+        vec3 l = toLight / max(distance, 1e-4);
+
+        // Inverse square, windowed to reach zero at the radius
+        float window = clamp(1.0 - pow(distance / light.radius, 4.0), 0.0, 1.0);
+        float attenuation = window * window / (distance * distance + 1.0);
+
+        if (light.kind == SPOT_LIGHT) {
+            float cosAngle = dot(-l, light.direction);
+            attenuation *= smoothstep(light.cosOuter, light.cosInner, cosAngle);
+        }
+
+        if (attenuation > 0.0) {
+            result += brdf(n, v, l, albedo, metallic, roughness) * light.color * attenuation;
+        }
+    }
+    return result;
+}
 
 void main() {
-    outColor = texture(texSampler, fragTexCoord);
-}
\ No newline at end of file
+    vec4 albedo = texture(albedoMap, fragTexCoord) * material.baseColorFactor;
+
+    float metallic = material.metallicFactor;
+    float roughness = material.roughnessFactor;
+    if (METALLIC_ROUGHNESS_MAP) {
+        vec4 metallicRoughness = texture(metallicRoughnessMap, fragTexCoord);
+        roughness *= metallicRoughness.g;
+        metallic *= metallicRoughness.b;
+    }
+    // Perfectly smooth surfaces have an infinitely small highlight
+    roughness = clamp(roughness, 0.04, 1.0);
+
+    vec3 n = normalize(fragNormal);
+    if (NORMAL_MAP) {
+        n = perturbNormal(n);
+    }
+    vec3 v = normalize(ubo.cameraPosition.xyz - fragPosition);
+    vec3 l = -normalize(ubo.lightDirection.xyz);
+
+    vec3 direct = brdf(n, v, l, albedo.rgb, metallic, roughness) * ubo.lightColor.rgb * shadow();
+    direct += clusterLighting(n, v, albedo.rgb, metallic, roughness);
+    // Clamped so the single texel works for every pixel
+    ivec2 aoCoord = min(ivec2(gl_FragCoord.xy), textureSize(aoMap, 0) - 1);
+    vec3 ambient = AMBIENT * albedo.rgb * texelFetch(aoMap, aoCoord, 0).r;
+
+    outColor = vec4(direct + ambient, albedo.a);
+
+    // Without the jitter, so still surfaces don't move. Skinned and moving
+    // objects only get the camera's motion
+    vec4 current = ubo.proj * ubo.view * vec4(fragPosition, 1.0);
+    vec4 previous = ubo.previousViewProj * vec4(fragPosition, 1.0);
+    vec2 delta = (current.xy / current.w - ubo.jitter.xy) - previous.xy / previous.w;
+    // The viewport is flipped, so UV y runs the other way from NDC y
+    outMotion = vec2(delta.x, -delta.y) * 0.5;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+// Replaces the depth prepass' missing fragment shader while SSAO is on, see
+// ssao.rs
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} ubo;
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 3) in vec3 fragPosition;
+
+// View space normal and linear view depth
+layout(location = 0) out vec4 outGbuffer;
+
+void main() {
+    vec3 normal = normalize(mat3(ubo.view) * fragNormal);
+    // Double sided surfaces seen from behind face the camera
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+    float depth = -(ubo.view * vec4(fragPosition, 1.0)).z;
+    outGbuffer = vec4(normal, depth);
+}
@@ -0,0 +1,94 @@
+#version 450
+
+layout(local_size_x = 64) in;
+
+// Must match MAX_LIGHTS_PER_CLUSTER in lights.rs
+const uint MAX_LIGHTS_PER_CLUSTER = 127;
+
+struct Light {
+    vec3 position;
+    float radius;
+    vec3 color;
+    uint kind;
+    vec3 direction;
+    float cosOuter;
+    float cosInner;
+};
+
+layout(binding = 0) uniform Clusters {
+    mat4 inverseProjection;
+    mat4 view;
+    uvec3 grid;
+    uint lightCount;
+    vec2 screenSize;
+    float near;
+    float far;
+} clusters;
+
+layout(std430, binding = 1) readonly buffer Lights {
+    Light lights[];
+};
+
+// A count followed by MAX_LIGHTS_PER_CLUSTER light indices per cluster
+layout(std430, binding = 2) writeonly buffer ClusterLights {
+    uint clusterLights[];
+};
+
+// View space point under a pixel, halfway through the depth range so it's
+// finite with reversed depth or without a far plane. Y is flipped by the
+// viewport, so the top row of pixels is at +1
+vec3 unproject(vec2 pixel) {
+    vec2 uv = pixel / clusters.screenSize;
+    vec2 ndc = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    vec4 position = clusters.inverseProjection * vec4(ndc, 0.5, 1.0);
+    return position.xyz / position.w;
+}
+
+// Where the ray from the eye through `point` crosses the plane at `depth`
+vec3 atDepth(vec3 point, float depth) {
+    return point * (-depth / point.z);
+}
+
+float sliceDepth(uint slice) {
+    float t = float(slice) / float(clusters.grid.z);
+    return clusters.near * pow(clusters.far / clusters.near, t);
+}
+
+void main() {
+    uvec3 grid = clusters.grid;
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= grid.x * grid.y * grid.z) {
+        return;
+    }
+
+    uint x = index % grid.x;
+    uint y = (index / grid.x) % grid.y;
+    uint z = index / (grid.x * grid.y);
+
+    vec2 tileSize = clusters.screenSize / vec2(grid.xy);
+    vec3 minPoint = unproject(vec2(x, y) * tileSize);
+    vec3 maxPoint = unproject(vec2(x + 1, y + 1) * tileSize);
+    float nearDepth = sliceDepth(z);
+    float farDepth = sliceDepth(z + 1);
+
+    vec3 a = atDepth(minPoint, nearDepth);
+    vec3 b = atDepth(maxPoint, nearDepth);
+    vec3 c = atDepth(minPoint, farDepth);
+    vec3 d = atDepth(maxPoint, farDepth);
+    vec3 boundsMin = min(min(a, b), min(c, d));
+    vec3 boundsMax = max(max(a, b), max(c, d));
+
+    // Spot lights are tested by their whole sphere
+    uint base = index * (MAX_LIGHTS_PER_CLUSTER + 1);
+    uint count = 0;
+    for (uint i = 0; i < clusters.lightCount && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        vec3 center = (clusters.view * vec4(lights[i].position, 1.0)).xyz;
+        vec3 offset = clamp(center, boundsMin, boundsMax) - center;
+        float radius = lights[i].radius;
+        if (dot(offset, offset) <= radius * radius) {
+            clusterLights[base + 1 + count] = i;
+            count++;
+        }
+    }
+    clusterLights[base] = count;
+}
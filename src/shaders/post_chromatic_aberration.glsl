@@ -0,0 +1,35 @@
+#version 450
+
+// See post_vignette.glsl for the layout
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, rgba16f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D inputImage;
+
+// Matches `PostProcessConstants` in post_process.rs
+layout(push_constant) uniform Constants {
+    vec2 texelSize;
+    float time;
+    // x is how far red and blue are pulled apart at the corners, in pixels
+    vec4 params;
+} constants;
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    // Red and blue are shifted outwards and inwards along the direction from
+    // the center, more towards the edges like a real lens
+    vec2 uv = (vec2(id) + 0.5) * constants.texelSize;
+    vec2 direction = (uv - 0.5) * 2.0;
+    vec2 shift = direction * 0.5 * constants.params.x * constants.texelSize;
+
+    vec4 color = texture(inputImage, uv);
+    color.r = texture(inputImage, uv + shift).r;
+    color.b = texture(inputImage, uv - shift).b;
+
+    imageStore(outputImage, id, color);
+}
@@ -0,0 +1,143 @@
+#version 450
+
+// See post_vignette.glsl for the layout
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, rgba16f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D inputImage;
+
+// Matches `PostProcessConstants` in post_process.rs
+layout(push_constant) uniform Constants {
+    vec2 texelSize;
+    float time;
+    // x is the contrast an edge needs relative to its brightest neighbor, y
+    // the contrast below which dark edges are skipped and z how much
+    // subpixel aliasing is smoothed
+    vec4 params;
+} constants;
+
+// The image is still HDR, so luma is taken after a Reinhard curve, which is
+// close enough to what the tonemapper shows to find the visible edges
+float luma(vec3 color) {
+    color = color / (1.0 + color);
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+float lumaAt(vec2 uv) {
+    return luma(textureLod(inputImage, uv, 0.0).rgb);
+}
+
+// A simplified FXAA 3.11: finds the local edge direction, walks along it to
+// both ends, and blends across the edge by how far the pixel is from its
+// nearest end
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    vec2 texel = constants.texelSize;
+    vec2 uv = (vec2(id) + 0.5) * texel;
+    vec4 center = textureLod(inputImage, uv, 0.0);
+
+    float lumaCenter = luma(center.rgb);
+    float lumaN = lumaAt(uv + vec2(0.0, -texel.y));
+    float lumaS = lumaAt(uv + vec2(0.0, texel.y));
+    float lumaW = lumaAt(uv + vec2(-texel.x, 0.0));
+    float lumaE = lumaAt(uv + vec2(texel.x, 0.0));
+
+    float lumaMin = min(lumaCenter, min(min(lumaN, lumaS), min(lumaW, lumaE)));
+    float lumaMax = max(lumaCenter, max(max(lumaN, lumaS), max(lumaW, lumaE)));
+    float range = lumaMax - lumaMin;
+    if (range < max(constants.params.y, lumaMax * constants.params.x)) {
+        imageStore(outputImage, id, center);
+        return;
+    }
+
+    float lumaNW = lumaAt(uv + vec2(-texel.x, -texel.y));
+    float lumaNE = lumaAt(uv + vec2(texel.x, -texel.y));
+    float lumaSW = lumaAt(uv + vec2(-texel.x, texel.y));
+    float lumaSE = lumaAt(uv + vec2(texel.x, texel.y));
+
+    // Subpixel blend, for aliasing smaller than a pixel
+    float average = (2.0 * (lumaN + lumaS + lumaW + lumaE) + lumaNW + lumaNE + lumaSW + lumaSE) / 12.0;
+    float subpixel = clamp(abs(average - lumaCenter) / range, 0.0, 1.0);
+    subpixel = smoothstep(0.0, 1.0, subpixel);
+    subpixel = subpixel * subpixel * constants.params.z;
+
+    float horizontal = abs(lumaNW + lumaNE - 2.0 * lumaN)
+        + 2.0 * abs(lumaW + lumaE - 2.0 * lumaCenter)
+        + abs(lumaSW + lumaSE - 2.0 * lumaS);
+    float vertical = abs(lumaNW + lumaSW - 2.0 * lumaW)
+        + 2.0 * abs(lumaN + lumaS - 2.0 * lumaCenter)
+        + abs(lumaNE + lumaSE - 2.0 * lumaE);
+    bool isHorizontal = horizontal >= vertical;
+
+    // Which side of the pixel the edge is on
+    float luma1 = isHorizontal ? lumaN : lumaW;
+    float luma2 = isHorizontal ? lumaS : lumaE;
+    float gradient1 = abs(luma1 - lumaCenter);
+    float gradient2 = abs(luma2 - lumaCenter);
+    float stepLength = isHorizontal ? texel.y : texel.x;
+    float lumaEdge;
+    float gradient;
+    if (gradient1 >= gradient2) {
+        stepLength = -stepLength;
+        lumaEdge = 0.5 * (luma1 + lumaCenter);
+        gradient = gradient1;
+    } else {
+        lumaEdge = 0.5 * (luma2 + lumaCenter);
+        gradient = gradient2;
+    }
+
+    // Walk along the edge, halfway between the pixel and its neighbor
+    vec2 edgeUv = uv;
+    vec2 edgeStep = isHorizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+    if (isHorizontal) {
+        edgeUv.y += 0.5 * stepLength;
+    } else {
+        edgeUv.x += 0.5 * stepLength;
+    }
+
+    float threshold = 0.25 * gradient;
+    vec2 uv1 = edgeUv - edgeStep;
+    vec2 uv2 = edgeUv + edgeStep;
+    float delta1 = lumaAt(uv1) - lumaEdge;
+    float delta2 = lumaAt(uv2) - lumaEdge;
+    bool done1 = abs(delta1) >= threshold;
+    bool done2 = abs(delta2) >= threshold;
+    for (int i = 0; i < 10 && !(done1 && done2); i++) {
+        if (!done1) {
+            uv1 -= edgeStep * 1.5;
+            delta1 = lumaAt(uv1) - lumaEdge;
+            done1 = abs(delta1) >= threshold;
+        }
+        if (!done2) {
+            uv2 += edgeStep * 1.5;
+            delta2 = lumaAt(uv2) - lumaEdge;
+            done2 = abs(delta2) >= threshold;
+        }
+    }
+
+    float distance1 = isHorizontal ? uv.x - uv1.x : uv.y - uv1.y;
+    float distance2 = isHorizontal ? uv2.x - uv.x : uv2.y - uv.y;
+    bool nearest1 = distance1 < distance2;
+    float nearestDistance = min(distance1, distance2);
+    float edgeLength = distance1 + distance2;
+
+    // Only blend if the pixel is on the side of the edge the walk ended on
+    bool centerSmaller = lumaCenter < lumaEdge;
+    bool correct = ((nearest1 ? delta1 : delta2) < 0.0) != centerSmaller;
+    float edgeBlend = correct ? 0.5 - nearestDistance / edgeLength : 0.0;
+
+    float blend = max(edgeBlend, subpixel);
+    vec2 blendUv = uv;
+    if (isHorizontal) {
+        blendUv.y += blend * stepLength;
+    } else {
+        blendUv.x += blend * stepLength;
+    }
+
+    imageStore(outputImage, id, vec4(textureLod(inputImage, blendUv, 0.0).rgb, center.a));
+}
@@ -0,0 +1,36 @@
+#version 450
+
+// Post process effects all share this layout, see `PostProcessEffect` in
+// post_process.rs. Custom effects can start from a copy of this one
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(binding = 0, rgba16f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D inputImage;
+
+// Matches `PostProcessConstants` in post_process.rs
+layout(push_constant) uniform Constants {
+    vec2 texelSize;
+    float time;
+    // x is the strength and y the distance from the center the darkening
+    // starts at, relative to the corners
+    vec4 params;
+} constants;
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    vec2 uv = (vec2(id) + 0.5) * constants.texelSize;
+    vec4 color = texture(inputImage, uv);
+
+    // 1 at the corners, corrected for the aspect ratio so the falloff is round
+    vec2 offset = (uv - 0.5) * vec2(constants.texelSize.y / constants.texelSize.x, 1.0);
+    vec2 corner = 0.5 * vec2(constants.texelSize.y / constants.texelSize.x, 1.0);
+    float distance = length(offset) / length(corner);
+    float falloff = smoothstep(constants.params.y, 1.0, distance);
+
+    imageStore(outputImage, id, vec4(color.rgb * (1.0 - constants.params.x * falloff), color.a));
+}
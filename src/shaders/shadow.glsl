@@ -0,0 +1,29 @@
+#version 450
+
+struct Object {
+    mat4 transform;
+    vec4 bounds;
+    uint draw;
+    uint cpuCulled;
+    uint jointOffset;
+};
+
+layout(std430, binding = 0) readonly buffer Objects {
+    Object objects[];
+};
+
+// Every object grouped by batch, see `CullingPass::unculled_instance_buffer`
+layout(std430, binding = 1) readonly buffer Instances {
+    uint instances[];
+};
+
+layout(push_constant) uniform Constants {
+    mat4 lightSpace;
+} constants;
+
+layout(location = 0) in vec3 inPosition;
+
+void main() {
+    mat4 transform = objects[instances[gl_InstanceIndex]].transform;
+    gl_Position = constants.lightSpace * transform * vec4(inPosition, 1.0);
+}
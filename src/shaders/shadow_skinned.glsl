@@ -0,0 +1,42 @@
+#version 450
+
+// Same as shadow.glsl, for skinned meshes
+
+struct Object {
+    mat4 transform;
+    vec4 bounds;
+    uint draw;
+    uint cpuCulled;
+    uint jointOffset;
+};
+
+layout(std430, binding = 0) readonly buffer Objects {
+    Object objects[];
+};
+
+// Every object grouped by batch, see `CullingPass::unculled_instance_buffer`
+layout(std430, binding = 1) readonly buffer Instances {
+    uint instances[];
+};
+
+// See skinned_vertex.glsl
+layout(std430, binding = 2) readonly buffer Joints {
+    mat4 joints[];
+};
+
+layout(push_constant) uniform Constants {
+    mat4 lightSpace;
+} constants;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 3) in uvec4 inJoints;
+layout(location = 4) in vec4 inWeights;
+
+void main() {
+    Object object = objects[instances[gl_InstanceIndex]];
+    mat4 skin = inWeights.x * joints[object.jointOffset + inJoints.x]
+        + inWeights.y * joints[object.jointOffset + inJoints.y]
+        + inWeights.z * joints[object.jointOffset + inJoints.z]
+        + inWeights.w * joints[object.jointOffset + inJoints.w];
+    gl_Position = constants.lightSpace * object.transform * skin * vec4(inPosition, 1.0);
+}
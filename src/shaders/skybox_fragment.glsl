@@ -0,0 +1,21 @@
+#version 450
+
+layout(push_constant) uniform Constants {
+    // Inverse of the projection times the view's rotation
+    mat4 directionFromClip;
+    float intensity;
+} constants;
+
+layout(binding = 0) uniform samplerCube environment;
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Any depth on the pixel's ray gives the direction. Halfway is finite
+    // whichever way depth runs, even without a far plane
+    vec4 position = constants.directionFromClip * vec4(fragNdc, 0.5, 1.0);
+    vec3 direction = normalize(position.xyz / position.w);
+    outColor = vec4(texture(environment, direction).rgb * constants.intensity, 1.0);
+}
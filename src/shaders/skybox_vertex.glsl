@@ -0,0 +1,13 @@
+#version 450
+
+// Like fullscreen.glsl, but on the far plane so the scene's depth hides it
+layout(location = 0) out vec2 fragNdc;
+
+// Set with reversed depth, where the far plane is at 0, see `DepthDirection`
+layout(constant_id = 0) const bool REVERSED_DEPTH = false;
+
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    fragNdc = uv * 2.0 - 1.0;
+    gl_Position = vec4(fragNdc, REVERSED_DEPTH ? 0.0 : 1.0, 1.0);
+}
@@ -0,0 +1,102 @@
+#version 450
+
+// See `SsaoPass` in ssao.rs
+layout(local_size_x = 8, local_size_y = 8) in;
+
+const uint KERNEL_SIZE = 32;
+const int NOISE_SIZE = 4;
+
+// 1 where unoccluded
+layout(binding = 0, r32f) uniform writeonly image2D outputImage;
+// View space normals in xyz and linear view depth in w, see gbuffer_fragment.glsl
+layout(binding = 1) uniform sampler2D gbuffer;
+// Random rotations around the normal in xy, tiled over the screen
+layout(binding = 2) uniform sampler2D noise;
+
+layout(binding = 3) uniform Kernel {
+    vec4 samples[KERNEL_SIZE];
+} kernel;
+
+// Matches `SsaoConstants` in ssao.rs
+layout(push_constant) uniform Constants {
+    mat4 proj;
+    float radius;
+    // Keeps flat surfaces from occluding themselves
+    float bias;
+    float intensity;
+} constants;
+
+// The viewport is flipped, so the first row is at the top of NDC
+vec2 uvToNdc(vec2 uv) {
+    return vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+vec2 ndcToUv(vec2 ndc) {
+    return vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+// Undoes the projection for a point at the given linear depth. Works for
+// perspective and orthographic projections
+vec3 viewPosition(vec2 uv, float depth) {
+    mat4 p = constants.proj;
+    float z = -depth;
+    float w = p[2][3] * z + p[3][3];
+    vec2 ndc = uvToNdc(uv);
+    return vec3(
+        (ndc.x * w - p[2][0] * z - p[3][0]) / p[0][0],
+        (ndc.y * w - p[2][1] * z - p[3][1]) / p[1][1],
+        z
+    );
+}
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    vec4 surface = texelFetch(gbuffer, id, 0);
+    // Nothing was drawn here
+    if (surface.w <= 0.0) {
+        imageStore(outputImage, id, vec4(1.0));
+        return;
+    }
+
+    vec2 uv = (vec2(id) + 0.5) / vec2(size);
+    vec3 position = viewPosition(uv, surface.w);
+    vec3 normal = normalize(surface.xyz);
+
+    // Gram-Schmidt with the tile's random vector gives a tangent frame with
+    // a random rotation around the normal
+    vec3 random = vec3(texelFetch(noise, id % NOISE_SIZE, 0).xy * 2.0 - 1.0, 0.0);
+    vec3 tangent = random - normal * dot(random, normal);
+    if (dot(tangent, tangent) < 1e-6) {
+        tangent = abs(normal.x) < 0.9 ? vec3(1.0, 0.0, 0.0) : vec3(0.0, 1.0, 0.0);
+        tangent -= normal * dot(tangent, normal);
+    }
+    tangent = normalize(tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float occlusion = 0.0;
+    for (uint i = 0; i < KERNEL_SIZE; i++) {
+        vec3 samplePosition = position + tbn * kernel.samples[i].xyz * constants.radius;
+
+        vec4 clip = constants.proj * vec4(samplePosition, 1.0);
+        vec2 sampleUv = ndcToUv(clip.xy / clip.w);
+        if (any(lessThan(sampleUv, vec2(0.0))) || any(greaterThan(sampleUv, vec2(1.0)))) {
+            continue;
+        }
+
+        float sceneDepth = texelFetch(gbuffer, ivec2(sampleUv * vec2(size)), 0).w;
+        if (sceneDepth <= 0.0) {
+            continue;
+        }
+        // Occluders much further away than the radius are a different object
+        float rangeCheck = smoothstep(0.0, 1.0, constants.radius / abs(surface.w - sceneDepth));
+        occlusion += (sceneDepth <= -samplePosition.z - constants.bias ? 1.0 : 0.0) * rangeCheck;
+    }
+
+    float ao = 1.0 - constants.intensity * occlusion / float(KERNEL_SIZE);
+    imageStore(outputImage, id, vec4(clamp(ao, 0.0, 1.0)));
+}
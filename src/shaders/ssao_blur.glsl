@@ -0,0 +1,28 @@
+#version 450
+
+// Averages a noise tile of the occlusion, which removes the pattern the
+// rotations leave, see ssao.glsl
+layout(local_size_x = 8, local_size_y = 8) in;
+
+const int NOISE_SIZE = 4;
+
+layout(binding = 0, r32f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D inputImage;
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    float sum = 0.0;
+    for (int x = 0; x < NOISE_SIZE; x++) {
+        for (int y = 0; y < NOISE_SIZE; y++) {
+            ivec2 offset = ivec2(x, y) - NOISE_SIZE / 2;
+            sum += texelFetch(inputImage, clamp(id + offset, ivec2(0), size - 1), 0).r;
+        }
+    }
+
+    imageStore(outputImage, id, vec4(sum / float(NOISE_SIZE * NOISE_SIZE)));
+}
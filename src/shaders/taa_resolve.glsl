@@ -0,0 +1,83 @@
+#version 450
+
+// Blends the frame into the reprojected history, see `TaaPass` in taa.rs
+layout(local_size_x = 8, local_size_y = 8) in;
+
+// This frame's history, which the next frame reads
+layout(binding = 0, rgba16f) uniform writeonly image2D outputImage;
+layout(binding = 1) uniform sampler2D sceneImage;
+// The previous frame's output
+layout(binding = 2) uniform sampler2D historyImage;
+// How far each pixel moved since the previous frame, in UV units
+layout(binding = 3) uniform sampler2D motionImage;
+
+// Matches `TaaConstants` in taa.rs
+layout(push_constant) uniform Constants {
+    vec2 texelSize;
+    // How much of the frame is blended into the history
+    float blend;
+    // Set when there's no history yet
+    uint reset;
+} constants;
+
+vec3 toYCoCg(vec3 color) {
+    return vec3(
+        dot(color, vec3(0.25, 0.5, 0.25)),
+        dot(color, vec3(0.5, 0.0, -0.5)),
+        dot(color, vec3(-0.25, 0.5, -0.25))
+    );
+}
+
+vec3 fromYCoCg(vec3 color) {
+    return vec3(
+        color.x + color.y - color.z,
+        color.x + color.z,
+        color.x - color.y - color.z
+    );
+}
+
+// Weighs bright samples down so a few HDR highlights don't dominate the blend
+// and flicker
+float karisWeight(vec3 color) {
+    return 1.0 / (1.0 + max(color.r, max(color.g, color.b)));
+}
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(outputImage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    vec2 uv = (vec2(id) + 0.5) * constants.texelSize;
+    vec3 current = textureLod(sceneImage, uv, 0.0).rgb;
+
+    vec2 previousUv = uv - textureLod(motionImage, uv, 0.0).xy;
+    bool offscreen = any(lessThan(previousUv, vec2(0.0))) || any(greaterThan(previousUv, vec2(1.0)));
+    if (constants.reset != 0 || offscreen) {
+        imageStore(outputImage, id, vec4(current, 1.0));
+        return;
+    }
+
+    // History outside the range of the pixel's neighborhood is stale, e.g.
+    // where something was disoccluded, and is clamped to it
+    vec3 minColor = vec3(1e9);
+    vec3 maxColor = vec3(-1e9);
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * constants.texelSize;
+            vec3 color = toYCoCg(textureLod(sceneImage, uv + offset, 0.0).rgb);
+            minColor = min(minColor, color);
+            maxColor = max(maxColor, color);
+        }
+    }
+
+    vec3 history = textureLod(historyImage, previousUv, 0.0).rgb;
+    history = fromYCoCg(clamp(toYCoCg(history), minColor, maxColor));
+
+    float currentWeight = constants.blend * karisWeight(current);
+    float historyWeight = (1.0 - constants.blend) * karisWeight(history);
+    vec3 color = (current * currentWeight + history * historyWeight) / (currentWeight + historyWeight);
+
+    imageStore(outputImage, id, vec4(color, 1.0));
+}
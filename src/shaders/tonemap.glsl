@@ -0,0 +1,84 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D hdrImage;
+
+// First level of the bloom chain, see bloom.rs
+layout(set = 0, binding = 1) uniform sampler2D bloomImage;
+
+// Matches `TonemapConstants` in tonemap.rs
+layout(push_constant) uniform Constants {
+    float exposure;
+    uint operator;
+    uint encoding;
+    float paperWhite;
+    float bloomIntensity;
+} constants;
+
+const uint OPERATOR_NONE = 0;
+const uint OPERATOR_REINHARD = 1;
+const uint OPERATOR_ACES = 2;
+
+// sRGB swapchain formats encode on write, so those get linear values
+const uint ENCODING_LINEAR = 0;
+const uint ENCODING_SRGB = 1;
+const uint ENCODING_HDR10 = 2;
+const uint ENCODING_SCRGB = 3;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 srgbEncode(vec3 x) {
+    x = clamp(x, 0.0, 1.0);
+    return mix(12.92 * x, 1.055 * pow(x, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, x));
+}
+
+// SMPTE ST.2084 inverse EOTF
+vec3 pqEncode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+void main() {
+    vec3 color = texture(hdrImage, fragUv).rgb;
+    color += texture(bloomImage, fragUv).rgb * constants.bloomIntensity;
+    color *= constants.exposure;
+
+    if (constants.operator == OPERATOR_REINHARD) {
+        color = color / (1.0 + color);
+    } else if (constants.operator == OPERATOR_ACES) {
+        color = aces(color);
+    }
+
+    if (constants.encoding == ENCODING_SRGB) {
+        color = srgbEncode(color);
+    } else if (constants.encoding == ENCODING_HDR10) {
+        color = pqEncode(BT709_TO_BT2020 * color * constants.paperWhite);
+    } else if (constants.encoding == ENCODING_SCRGB) {
+        // scRGB 1.0 is 80 nits
+        color = color * constants.paperWhite / 80.0;
+    }
+
+    outColor = vec4(color, 1.0);
+}
@@ -1,20 +1,50 @@
 #version 450
 
 layout(binding = 0) uniform UniformBufferObject {
-    mat4 model;
     mat4 view;
     mat4 proj;
+    mat4 lightSpace;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
 } ubo;
 
+struct Object {
+    mat4 transform;
+    vec4 bounds;
+    uint draw;
+    uint cpuCulled;
+    uint jointOffset;
+};
+
+layout(std430, binding = 1) readonly buffer Objects {
+    Object objects[];
+};
+
+// Written by the culling pass, see cull.glsl
+layout(std430, binding = 2) readonly buffer VisibleObjects {
+    uint visibleObjects[];
+};
+
 layout(location = 0) in vec3 inPosition;
-layout(location = 1) in vec3 inColor;
+layout(location = 1) in vec3 inNormal;
 layout(location = 2) in vec2 inTexCoord;
 
-layout(location = 0) out vec3 fragColor;
+layout(location = 0) out vec3 fragNormal;
 layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec4 fragLightSpacePosition;
+layout(location = 3) out vec3 fragPosition;
+
+// The depth prepass and the main pass have to agree exactly on depth for the
+// EQUAL depth test
+invariant gl_Position;
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
-    fragColor = inColor;
+    mat4 transform = objects[visibleObjects[gl_InstanceIndex]].transform;
+    vec4 position = transform * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * position;
+    fragNormal = mat3(transform) * inNormal;
+    fragLightSpacePosition = ubo.lightSpace * position;
+    fragPosition = position.xyz;
     fragTexCoord = inTexCoord;
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+#version 450
+#extension GL_EXT_buffer_reference : require
+
+// Same as vertex.glsl, but the vertices are read through a device address
+// instead of vertex input bindings
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    mat4 lightSpace;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} ubo;
+
+struct Object {
+    mat4 transform;
+    vec4 bounds;
+    uint draw;
+    uint cpuCulled;
+    uint jointOffset;
+};
+
+layout(std430, binding = 1) readonly buffer Objects {
+    Object objects[];
+};
+
+// Written by the culling pass, see cull.glsl
+layout(std430, binding = 2) readonly buffer VisibleObjects {
+    uint visibleObjects[];
+};
+
+// Matches `Vertex` in model.rs: position and normal as floats and the texture
+// coordinate as two halves, 7 words in all
+const uint VERTEX_WORDS = 7;
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+    uint words[];
+};
+
+layout(push_constant) uniform PushConstants {
+    Vertices vertices;
+};
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec4 fragLightSpacePosition;
+layout(location = 3) out vec3 fragPosition;
+
+// The depth prepass and the main pass have to agree exactly on depth for the
+// EQUAL depth test
+invariant gl_Position;
+
+vec3 readVec3(uint word) {
+    return uintBitsToFloat(uvec3(
+        vertices.words[word],
+        vertices.words[word + 1],
+        vertices.words[word + 2]
+    ));
+}
+
+void main() {
+    uint base = gl_VertexIndex * VERTEX_WORDS;
+    vec3 inPosition = readVec3(base);
+    vec3 inNormal = readVec3(base + 3);
+    vec2 inTexCoord = unpackHalf2x16(vertices.words[base + 6]);
+
+    mat4 transform = objects[visibleObjects[gl_InstanceIndex]].transform;
+    vec4 position = transform * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * position;
+    fragNormal = mat3(transform) * inNormal;
+    fragLightSpacePosition = ubo.lightSpace * position;
+    fragPosition = position.xyz;
+    fragTexCoord = inTexCoord;
+}
@@ -0,0 +1,377 @@
+use ash::vk;
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+
+use crate::culling::CullingPass;
+use crate::gpu::{
+    rendering_attachment, CommandBuffer, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, GraphicsPipeline, HotReload, Image, ImageConfig, ImageHandle,
+    ImageUsage, ImageView, MemoryPriority, PipelineLayout, RenderGraph, RenderingConfig, Sampler,
+    ShaderId, ShaderKind, ShaderRegistry, VertexLayout,
+};
+use crate::model::Vertex;
+use crate::render_world::RenderWorld;
+
+const FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    // Direction the light travels in
+    pub direction: Vec3,
+    // Linear color, scaled by intensity
+    pub color: Vec3,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(-0.4, -0.3, -1.0).normalize(),
+            color: Vec3::ONE,
+        }
+    }
+}
+
+// Paths of the shaders `ShadowPass` loads
+pub struct ShadowShaders<'a> {
+    pub vertex: &'a str,
+    // Skins the vertices with the culling pass's joint matrices
+    pub skinned_vertex: &'a str,
+}
+
+// Renders the depth of every renderable from a directional light into a
+// shadow map, which the main pass compares against with a comparison sampler.
+// Each frame in flight has its own map
+pub struct ShadowPass {
+    shader_id: ShaderId,
+    skinned_shader_id: ShaderId,
+    pipeline_layout: Arc<PipelineLayout>,
+    // For static meshes, and for skinned meshes, which also read the culling
+    // pass's joint matrices
+    pipelines: (Arc<GraphicsPipeline>, Arc<GraphicsPipeline>),
+    // One per frame in flight, since the culling pass's buffers are
+    descriptor_sets: Vec<DescriptorSet>,
+    images: Vec<Arc<Image>>,
+    views: Vec<Arc<ImageView>>,
+    sampler: Arc<Sampler>,
+    size: u32,
+    // Bounding sphere of the scene around the origin, which the light's
+    // projection covers. Updated by `fit`
+    radius: f32,
+}
+
+impl ShadowPass {
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        shader_registry: &mut ShaderRegistry,
+        shaders: ShadowShaders,
+        culling_pass: &CullingPass,
+        size: u32,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_id = shader_registry.load(shaders.vertex, ShaderKind::Vertex, "main");
+        let skinned_shader_id =
+            shader_registry.load(shaders.skinned_vertex, ShaderKind::Vertex, "main");
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let object_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX);
+            let instance_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX);
+            let joint_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_BUFFER)
+                .stage(vk::ShaderStageFlags::VERTEX);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[object_binding, instance_binding, joint_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<Mat4>(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipelines = (
+            ShadowPass::_create_pipeline(
+                device,
+                shader_registry,
+                shader_id,
+                &Vertex::layout(),
+                &pipeline_layout,
+            ),
+            ShadowPass::_create_pipeline(
+                device,
+                shader_registry,
+                skinned_shader_id,
+                &Vertex::skinned_layout(),
+                &pipeline_layout,
+            ),
+        );
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            frames_in_flight as u32,
+            &[(
+                vk::DescriptorType::STORAGE_BUFFER,
+                3 * frames_in_flight as u32,
+            )],
+        );
+        let descriptor_sets = descriptor_pool
+            .allocate(&vec![&*set_layout; frames_in_flight])
+            .into_vec();
+
+        let images = (0..frames_in_flight)
+            .map(|_| {
+                Image::new(
+                    device.clone(),
+                    allocator.clone(),
+                    ImageConfig::new(
+                        FORMAT,
+                        vk::Extent3D {
+                            width: size,
+                            height: size,
+                            depth: 1,
+                        },
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED,
+                    )
+                    .priority(MemoryPriority::High),
+                )
+            })
+            .collect::<Vec<_>>();
+        let views = images
+            .iter()
+            .map(|x| x.get_default_view(vk::ImageAspectFlags::DEPTH))
+            .collect();
+
+        let shadow_pass = Self {
+            shader_id,
+            skinned_shader_id,
+            pipeline_layout,
+            pipelines,
+            descriptor_sets,
+            images,
+            views,
+            sampler: Sampler::comparison(device.clone(), vk::CompareOp::LESS_OR_EQUAL),
+            size,
+            radius: 1.0,
+        };
+        for i in 0..frames_in_flight {
+            shadow_pass.write_descriptor_set(i, culling_pass);
+        }
+        shadow_pass
+    }
+
+    // Points the frame's set at the culling pass's buffers, which have to be
+    // redone whenever `CullingPass::prepare` recreates them
+    pub fn write_descriptor_set(&self, frame_index: usize, culling_pass: &CullingPass) {
+        let descriptor_set = &self.descriptor_sets[frame_index];
+        let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+        writer
+            .write_buffer(
+                descriptor_set,
+                culling_pass.object_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                0,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+            .write_buffer(
+                descriptor_set,
+                culling_pass.unculled_instance_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                1,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+            .write_buffer(
+                descriptor_set,
+                culling_pass.joint_buffer(frame_index),
+                0,
+                vk::WHOLE_SIZE,
+                2,
+                vk::DescriptorType::STORAGE_BUFFER,
+            );
+        writer.flush();
+    }
+
+    // Fits the light's projection around the world's renderables, which can
+    // move, so this is called every frame
+    pub fn fit(&mut self, world: &RenderWorld) {
+        self.radius = world.bounding_radius();
+    }
+
+    fn _create_pipeline(
+        device: &Arc<Device>,
+        shader_registry: &ShaderRegistry,
+        shader_id: ShaderId,
+        vertex_layout: &VertexLayout,
+        pipeline_layout: &PipelineLayout,
+    ) -> Arc<GraphicsPipeline> {
+        // Depth only, with a slope scaled bias against shadow acne. Culling is
+        // off so single sided geometry still casts shadows
+        GraphicsPipeline::builder()
+            .shader_modules(&shader_registry.modules(&[shader_id]))
+            .vertex_layout(vertex_layout)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .depth_test(true)
+            .depth_write(true)
+            .depth_compare_op(vk::CompareOp::LESS)
+            .depth_bias(1.25, 0.0, 1.75)
+            .depth_format(FORMAT)
+            .build(device.clone(), pipeline_layout)
+    }
+
+    pub fn view(&self, frame_index: usize) -> &Arc<ImageView> {
+        &self.views[frame_index]
+    }
+
+    pub fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    // Orthographic projection from the light covering the whole scene, with
+    // [0, 1] depth
+    pub fn light_space(&self, light: &DirectionalLight) -> Mat4 {
+        let direction = light.direction.normalize();
+        let up = if direction.z.abs() > 0.99 {
+            Vec3::Y
+        } else {
+            Vec3::Z
+        };
+        let radius = self.radius;
+        let view = Mat4::look_at_rh(-direction * 2.0 * radius, Vec3::ZERO, up);
+        let proj = Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 4.0 * radius);
+        proj * view
+    }
+
+    // Adds the pass rendering the frame's shadow map to the graph, with one
+    // draw per batch. Returns the shadow map for passes sampling it to read
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        world: &'a RenderWorld,
+        culling_pass: &'a CullingPass,
+        light_space: Mat4,
+    ) -> ImageHandle {
+        let shadow_map = graph.import_image(
+            &self.images[frame_index],
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags2::NONE,
+        );
+
+        graph
+            .add_pass("shadow")
+            .write_image(shadow_map, ImageUsage::DepthAttachment)
+            .record(move |cmd| self._record(cmd, frame_index, world, culling_pass, light_space));
+
+        shadow_map
+    }
+
+    fn _record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame_index: usize,
+        world: &RenderWorld,
+        culling_pass: &CullingPass,
+        light_space: Mat4,
+    ) {
+        let extent = vk::Extent2D {
+            width: self.size,
+            height: self.size,
+        };
+
+        let depth_attachment = rendering_attachment(
+            &self.views[frame_index],
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        );
+
+        cmd_buf.begin_rendering(RenderingConfig::new(extent).depth_attachment(depth_attachment));
+        cmd_buf.set_full_viewport_scissor(extent);
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_sets[frame_index]],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            &light_space,
+        );
+
+        // Both pipelines share the layout, so the set and push constant stay
+        // bound when switching
+        let mut bound_skinned = None;
+        for (i, batch) in world.batches().iter().enumerate() {
+            let mesh = world.mesh(batch.mesh);
+            if bound_skinned != Some(mesh.is_skinned()) {
+                let pipeline = if mesh.is_skinned() {
+                    &self.pipelines.1
+                } else {
+                    &self.pipelines.0
+                };
+                cmd_buf.bind_pipeline(pipeline.as_ref());
+                bound_skinned = Some(mesh.is_skinned());
+            }
+            cmd_buf.bind_index_buffer(
+                mesh.index_buffer().buffer(),
+                mesh.index_buffer().offset(),
+                mesh.index_type(),
+            );
+            cmd_buf.bind_vertex_buffers(0, &mesh.vertex_bindings());
+            culling_pass.draw_batch_unculled(cmd_buf, frame_index, i);
+        }
+
+        cmd_buf.end_rendering();
+    }
+}
+
+impl HotReload for ShadowPass {
+    type Pipelines = (Arc<GraphicsPipeline>, Arc<GraphicsPipeline>);
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Self::Pipelines {
+        (
+            ShadowPass::_create_pipeline(
+                self.pipeline_layout.device(),
+                shader_registry,
+                self.shader_id,
+                &Vertex::layout(),
+                &self.pipeline_layout,
+            ),
+            ShadowPass::_create_pipeline(
+                self.pipeline_layout.device(),
+                shader_registry,
+                self.skinned_shader_id,
+                &Vertex::skinned_layout(),
+                &self.pipeline_layout,
+            ),
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Self::Pipelines {
+        &mut self.pipelines
+    }
+}
@@ -0,0 +1,473 @@
+use ash::vk;
+use glam::{Mat3, Mat4, Vec3};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::gpu::{
+    opaque_blend_attachment, CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet,
+    DescriptorSetLayout, DescriptorWriter, Device, GraphicsPipeline, Image, ImageView,
+    MemoryPriority, PipelineLayout, Sampler, ShaderId, ShaderKind, ShaderModule, ShaderRegistry,
+    UploadQueue,
+};
+use crate::projection::DepthDirection;
+use crate::taa;
+
+const CUBE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const CONVERT_WORKGROUP_SIZE: u32 = 8;
+
+// Width and height of each of the cubemap's faces
+const CUBE_SIZE: u32 = 512;
+
+// Matches `Constants` in skybox_fragment.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxConstants {
+    direction_from_clip: Mat4,
+    intensity: f32,
+    _padding: [f32; 3],
+}
+
+// Linear RGBA pixels of an equirectangular image
+pub struct EquirectImage {
+    pub pixels: Vec<f32>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl EquirectImage {
+    // Radiance .hdr and OpenEXR files keep their full range. Other formats
+    // are loaded as they're stored, without converting from sRGB
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .map_err(|error| {
+                format!(
+                    "failed to load environment map {}: {}",
+                    path.display(),
+                    error
+                )
+            })?
+            .to_rgba32f();
+
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        })
+    }
+
+    // A sky blue gradient above the horizon and dark ground below it, for
+    // when no environment map is given
+    pub fn gradient() -> Self {
+        let (width, height) = (4, 64);
+        let zenith = Vec3::new(0.15, 0.3, 0.8);
+        let horizon = Vec3::new(0.7, 0.8, 0.9);
+        let ground = Vec3::new(0.1, 0.09, 0.08);
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            // 1 straight up and -1 straight down
+            let elevation = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+            let color = if elevation >= 0.0 {
+                horizon.lerp(zenith, elevation.sqrt())
+            } else {
+                horizon.lerp(ground, (-elevation * 8.0).min(1.0))
+            };
+            for _ in 0..width {
+                pixels.extend_from_slice(&[color.x, color.y, color.z, 1.0]);
+            }
+        }
+
+        Self {
+            pixels,
+            width,
+            height,
+        }
+    }
+}
+
+// Paths of the shaders `SkyboxPass` loads
+pub struct SkyboxShaders<'a> {
+    pub vertex: &'a str,
+    pub fragment: &'a str,
+    // Converts equirectangular images into the cubemap
+    pub convert: &'a str,
+}
+
+// Draws an environment cubemap behind the scene. It's recorded inside the
+// main pass after the geometry, on the far plane with depth writes off, so
+// only pixels no geometry covered are shaded
+pub struct SkyboxPass {
+    shader_ids: Vec<ShaderId>,
+    convert_shader_id: ShaderId,
+    set_layout: Arc<DescriptorSetLayout>,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_set: DescriptorSet,
+    cubemap: Arc<Image>,
+    cubemap_view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+    samples: vk::SampleCountFlags,
+    intensity: f32,
+}
+
+impl SkyboxPass {
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        uploads: &UploadQueue,
+        shader_registry: &mut ShaderRegistry,
+        shaders: SkyboxShaders,
+        environment: &EquirectImage,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(shaders.vertex, ShaderKind::Vertex, "main"),
+            shader_registry.load(shaders.fragment, ShaderKind::Fragment, "main"),
+        ];
+        let convert_shader_id = shader_registry.load(shaders.convert, ShaderKind::Compute, "main");
+
+        let cubemap = SkyboxPass::_convert_equirect(
+            device,
+            allocator,
+            uploads,
+            shader_registry.get(convert_shader_id),
+            environment,
+        );
+        let cubemap_view = cubemap.get_default_view(vk::ImageAspectFlags::COLOR);
+        let sampler = Sampler::clamped(device.clone());
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let environment_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[environment_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<SkyboxConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipeline = SkyboxPass::_create_pipeline(
+            device,
+            &shader_registry.modules(&shader_ids),
+            &pipeline_layout,
+            samples,
+            false,
+            DepthDirection::Standard,
+        );
+
+        let descriptor_set =
+            SkyboxPass::_create_descriptor_set(device, &set_layout, &sampler, &cubemap_view);
+
+        Self {
+            shader_ids,
+            convert_shader_id,
+            set_layout,
+            pipeline_layout,
+            pipeline,
+            descriptor_set,
+            cubemap,
+            cubemap_view,
+            sampler,
+            samples,
+            intensity: 1.0,
+        }
+    }
+
+    // Each cubemap gets its own set, since the current one may be in use by
+    // frames in flight when the environment changes
+    fn _create_descriptor_set(
+        device: &Arc<Device>,
+        set_layout: &DescriptorSetLayout,
+        sampler: &Sampler,
+        cubemap_view: &Arc<ImageView>,
+    ) -> DescriptorSet {
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            1,
+            &[(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)],
+        );
+        let descriptor_set = descriptor_pool
+            .allocate(&[set_layout])
+            .into_vec()
+            .pop()
+            .unwrap();
+        descriptor_set.write_image(
+            sampler,
+            cubemap_view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            0,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+        descriptor_set
+    }
+
+    // Replaces the cubemap with one converted from `environment`, keeping its
+    // size. Returns the old cubemap and the set it's bound to, which frames
+    // in flight may still be using
+    pub fn set_environment(
+        &mut self,
+        allocator: &Arc<vma::Allocator>,
+        uploads: &UploadQueue,
+        shader_registry: &ShaderRegistry,
+        environment: &EquirectImage,
+    ) -> (DescriptorSet, Arc<Image>, Arc<ImageView>) {
+        let device = self.pipeline_layout.device().clone();
+        let cubemap = SkyboxPass::_convert_equirect(
+            &device,
+            allocator,
+            uploads,
+            shader_registry.get(self.convert_shader_id),
+            environment,
+        );
+        let cubemap_view = cubemap.get_default_view(vk::ImageAspectFlags::COLOR);
+        let descriptor_set = SkyboxPass::_create_descriptor_set(
+            &device,
+            &self.set_layout,
+            &self.sampler,
+            &cubemap_view,
+        );
+
+        (
+            std::mem::replace(&mut self.descriptor_set, descriptor_set),
+            std::mem::replace(&mut self.cubemap, cubemap),
+            std::mem::replace(&mut self.cubemap_view, cubemap_view),
+        )
+    }
+
+    // Renders the equirectangular image into each face of a new cubemap with
+    // a compute shader with the next frame's uploads, which leave it in
+    // SHADER_READ_ONLY_OPTIMAL
+    fn _convert_equirect(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        uploads: &UploadQueue,
+        shader_module: &ShaderModule,
+        environment: &EquirectImage,
+    ) -> Arc<Image> {
+        let equirect = uploads.upload_image(
+            bytemuck::cast_slice(&environment.pixels),
+            vk::Format::R32G32B32A32_SFLOAT,
+            environment.width,
+            environment.height,
+        );
+        let equirect_view = equirect.get_default_view(vk::ImageAspectFlags::COLOR);
+        let equirect_sampler = Sampler::new(device.clone());
+
+        let cubemap = Image::new_cube(
+            device.clone(),
+            allocator.clone(),
+            CUBE_FORMAT,
+            CUBE_SIZE,
+            1,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            MemoryPriority::Normal,
+        );
+        let faces_view = cubemap.get_array_view(vk::ImageAspectFlags::COLOR, 0);
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let equirect_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let cube_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_IMAGE)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[equirect_binding, cube_binding],
+            )
+        };
+
+        let pipeline_layout = device.get_pipeline_layout(std::slice::from_ref(&set_layout), &[]);
+        let pipeline = ComputePipeline::new(device.clone(), shader_module, &pipeline_layout);
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            1,
+            &[
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+                (vk::DescriptorType::STORAGE_IMAGE, 1),
+            ],
+        );
+        let descriptor_set = descriptor_pool
+            .allocate(&[&*set_layout])
+            .into_vec()
+            .pop()
+            .unwrap();
+
+        {
+            let mut writer = DescriptorWriter::new(device.clone());
+            writer
+                .write_image(
+                    &descriptor_set,
+                    &equirect_sampler,
+                    &equirect_view,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    0,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_images(
+                    &descriptor_set,
+                    1,
+                    0,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    None,
+                    &[(&faces_view, vk::ImageLayout::GENERAL)],
+                );
+            writer.flush();
+        }
+
+        // Everything the dispatch uses is kept until it has run
+        let group_count = CUBE_SIZE.div_ceil(CONVERT_WORKGROUP_SIZE);
+        {
+            let cubemap = cubemap.clone();
+            let keep = (
+                equirect,
+                equirect_view,
+                equirect_sampler,
+                faces_view,
+                set_layout,
+            );
+            uploads.push(move |cmds| {
+                let _keep = &keep;
+                cmds.transition_image(
+                    &cubemap,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                );
+                cmds.bind_pipeline(pipeline.as_ref());
+                cmds.bind_descriptor_sets(
+                    vk::PipelineBindPoint::COMPUTE,
+                    &pipeline_layout,
+                    0,
+                    &[&descriptor_set],
+                );
+                cmds.dispatch(group_count, group_count, 6);
+                cmds.transition_image(
+                    &cubemap,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+            });
+        }
+
+        cubemap
+    }
+
+    fn _create_pipeline(
+        device: &Arc<Device>,
+        shader_modules: &[Arc<ShaderModule>],
+        pipeline_layout: &PipelineLayout,
+        samples: vk::SampleCountFlags,
+        motion_vectors: bool,
+        depth_direction: DepthDirection,
+    ) -> Arc<GraphicsPipeline> {
+        // Depth is tested against the cleared far plane but never written.
+        // The sky is left at the motion vectors' clear value of zero.
+        // Constant id 0 matches skybox_vertex.glsl
+        GraphicsPipeline::builder()
+            .shader_modules(shader_modules)
+            .specialization_constant(
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                (depth_direction == DepthDirection::Reversed) as u32,
+            )
+            .cull_mode(vk::CullModeFlags::NONE)
+            .depth_test(true)
+            .depth_write(false)
+            .depth_compare_op(depth_direction.compare_op_or_equal())
+            .samples(samples)
+            .color_attachments(&taa::main_pass_attachments(
+                opaque_blend_attachment(),
+                motion_vectors,
+                false,
+            ))
+            .depth_format(vk::Format::D32_SFLOAT)
+            .build(device.clone(), pipeline_layout)
+    }
+
+    // Rebuilds the pipeline after the shaders were reloaded or the main pass'
+    // sample count, attachments or depth direction changed, and returns the
+    // old one, which frames in flight may still be using
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_registry: &ShaderRegistry,
+        samples: vk::SampleCountFlags,
+        motion_vectors: bool,
+        depth_direction: DepthDirection,
+    ) -> Arc<GraphicsPipeline> {
+        self.samples = samples;
+        let pipeline = SkyboxPass::_create_pipeline(
+            self.pipeline_layout.device(),
+            &shader_registry.modules(&self.shader_ids),
+            &self.pipeline_layout,
+            samples,
+            motion_vectors,
+            depth_direction,
+        );
+        std::mem::replace(&mut self.pipeline, pipeline)
+    }
+
+    pub fn cubemap(&self) -> &Arc<Image> {
+        &self.cubemap
+    }
+
+    pub fn cubemap_view(&self) -> &Arc<ImageView> {
+        &self.cubemap_view
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    // Linear scale applied to the environment
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    // Must be recorded inside the main pass, after the geometry. Only the
+    // view's rotation is used, so the sky stays put as the camera moves
+    pub fn record(&self, cmd_buf: &CommandBuffer, view: Mat4, proj: Mat4) {
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(view));
+        let constants = SkyboxConstants {
+            direction_from_clip: (proj * rotation).inverse(),
+            intensity: self.intensity,
+            _padding: [0.0; 3],
+        };
+
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &self.pipeline_layout,
+            0,
+            &[&self.descriptor_set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        cmd_buf.draw(3, 1, 0, 0);
+    }
+}
@@ -0,0 +1,434 @@
+use ash::vk;
+use glam::{Mat4, Vec3, Vec4};
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    Buffer, CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, HotReload, Image, ImageConfig, ImageHandle, ImageUsage, ImageView,
+    MemoryPriority, PipelineLayout, RenderGraph, Sampler, ShaderId, ShaderKind, ShaderRegistry,
+    UploadQueue,
+};
+use crate::texture_cache::{ColorSpace, Texture, TextureCache};
+
+// View space normals in xyz and linear view depth in w, written by the depth
+// prepass while SSAO is on. Depth is zero where nothing was drawn. It's the
+// draw image's format, so it's multisampled like it
+pub const GBUFFER_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const AO_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+// Matches `local_size_x` and `local_size_y` in ssao.glsl and ssao_blur.glsl
+const WORKGROUP_SIZE: u32 = 8;
+
+// Matches `KERNEL_SIZE` in ssao.glsl
+const KERNEL_SIZE: usize = 32;
+
+// The noise texture is tiled over the screen, and the blur averages one tile
+// so the pattern it leaves disappears. Matches `NOISE_SIZE` in both shaders
+const NOISE_SIZE: u32 = 4;
+
+// Matches `Constants` in ssao.glsl. The blur shares the layout and ignores it
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoConstants {
+    proj: Mat4,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+struct SsaoFrame {
+    // The occlusion pass' and the blur's
+    descriptor_sets: Vec<DescriptorSet>,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// Paths of the shaders `SsaoPass` loads
+pub struct SsaoShaders<'a> {
+    pub ssao: &'a str,
+    pub blur: &'a str,
+}
+
+// Screen space ambient occlusion. Each pixel tests a hemisphere of samples
+// around its surface against the G-buffer's depth, rotated by a tiled noise
+// texture so few samples go a long way, and the noise is blurred out after.
+// The result scales the ambient light in fragment.glsl
+pub struct SsaoPass {
+    shader_ids: Vec<ShaderId>,
+    pipeline_layout: Arc<PipelineLayout>,
+    // The occlusion pass and the blur
+    pipelines: Vec<Arc<ComputePipeline>>,
+    sampler: Arc<Sampler>,
+    noise: Arc<Texture>,
+    // Sample offsets in tangent space, in a unit hemisphere around +z
+    kernel_buffer: Buffer,
+    frames: Vec<SsaoFrame>,
+    // View space distance samples reach, in world units
+    radius: f32,
+    // How dark fully occluded pixels get, from 0 to 1
+    intensity: f32,
+}
+
+impl SsaoPass {
+    pub fn new(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        uploads: &UploadQueue,
+        texture_cache: &mut TextureCache,
+        shader_registry: &mut ShaderRegistry,
+        shaders: SsaoShaders,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(shaders.ssao, ShaderKind::Compute, "main"),
+            shader_registry.load(shaders.blur, ShaderKind::Compute, "main"),
+        ];
+
+        // The output, the input and the noise and kernel, which the blur
+        // doesn't use
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let output_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_IMAGE)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let input_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let noise_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let kernel_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::UNIFORM_BUFFER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[output_binding, input_binding, noise_binding, kernel_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<SsaoConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipelines = shader_ids
+            .iter()
+            .map(|id| {
+                ComputePipeline::new(device.clone(), shader_registry.get(*id), &pipeline_layout)
+            })
+            .collect();
+
+        let noise = texture_cache.load_rgba8(
+            uploads,
+            &_noise_pixels(),
+            NOISE_SIZE,
+            NOISE_SIZE,
+            ColorSpace::Linear,
+        );
+
+        let kernel = _kernel();
+        let kernel_buffer = Buffer::new(
+            device.clone(),
+            allocator.clone(),
+            std::mem::size_of_val(&kernel),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vma::MemoryUsage::AutoPreferHost,
+            vma::AllocationCreateFlags::MAPPED
+                | vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+            MemoryPriority::Normal,
+        );
+        kernel_buffer.copy_nonoverlapping(&kernel);
+
+        let set_count = 2 * frames_in_flight as u32;
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            set_count,
+            &[
+                (vk::DescriptorType::STORAGE_IMAGE, set_count),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2 * set_count),
+                (vk::DescriptorType::UNIFORM_BUFFER, set_count),
+            ],
+        );
+        let frames = (0..frames_in_flight)
+            .map(|_| SsaoFrame {
+                descriptor_sets: descriptor_pool.allocate(&[&*set_layout; 2]).into_vec(),
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        Self {
+            shader_ids,
+            pipeline_layout,
+            pipelines,
+            sampler: Sampler::clamped(device.clone()),
+            noise,
+            kernel_buffer,
+            frames,
+            radius: 0.5,
+            intensity: 1.0,
+        }
+    }
+
+    // Single sampled, which the multisampled G-buffer resolves into. Like the
+    // draw images it's per frame and has to be recreated when the extent
+    // changes
+    pub fn create_gbuffer_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Arc<Image> {
+        SsaoPass::_create_image(
+            device,
+            allocator,
+            extent,
+            GBUFFER_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    // The raw occlusion and the blurred result
+    pub fn create_ao_images(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Vec<Arc<Image>> {
+        (0..2)
+            .map(|_| {
+                SsaoPass::_create_image(
+                    device,
+                    allocator,
+                    extent,
+                    AO_FORMAT,
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                )
+            })
+            .collect()
+    }
+
+    fn _create_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Arc<Image> {
+        Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(format, extent, usage).priority(MemoryPriority::High),
+        )
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius;
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    // Adds the occlusion and blur passes to the graph. `images` are the
+    // frame's images from `create_ao_images`, and `proj` is the projection
+    // the G-buffer was drawn with. Returns the blurred occlusion
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        gbuffer: (ImageHandle, Arc<ImageView>),
+        images: &'a [Arc<Image>],
+        proj: Mat4,
+    ) -> (ImageHandle, Arc<ImageView>) {
+        let frame = &self.frames[frame_index];
+        frame.views.borrow_mut().clear();
+
+        let targets = images
+            .iter()
+            .map(|image| {
+                let handle = graph.import_image(
+                    image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags2::NONE,
+                );
+                (handle, image.get_default_view(vk::ImageAspectFlags::COLOR))
+            })
+            .collect::<Vec<_>>();
+        let constants = SsaoConstants {
+            proj,
+            radius: self.radius,
+            bias: 0.025,
+            intensity: self.intensity,
+            _padding: 0.0,
+        };
+
+        let mut input = gbuffer;
+        for (i, output) in targets.iter().enumerate() {
+            let output = output.clone();
+            let pass = graph
+                .add_pass(if i == 0 { "ssao" } else { "ssao blur" })
+                .read_image(input.0, ImageUsage::Sampled)
+                .write_image(output.0, ImageUsage::Storage);
+            let (input_view, output_view) = (input.1, output.1.clone());
+            pass.record(move |cmd| {
+                self._record(cmd, frame, i, input_view, output_view, &constants);
+            });
+            input = output;
+        }
+
+        input
+    }
+
+    fn _record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame: &SsaoFrame,
+        pass: usize,
+        input: Arc<ImageView>,
+        output: Arc<ImageView>,
+        constants: &SsaoConstants,
+    ) {
+        let set = &frame.descriptor_sets[pass];
+
+        {
+            let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+            writer
+                .write_images(
+                    set,
+                    0,
+                    0,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    None,
+                    &[(&output, vk::ImageLayout::GENERAL)],
+                )
+                .write_image(
+                    set,
+                    &self.sampler,
+                    &input,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_image(
+                    set,
+                    self.noise.sampler(),
+                    self.noise.view(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    2,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_buffer(
+                    set,
+                    &self.kernel_buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    3,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                );
+            writer.flush();
+        }
+
+        let extent = output.image().extent();
+        cmd_buf.bind_pipeline(self.pipelines[pass].as_ref());
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            constants,
+        );
+        cmd_buf.dispatch(
+            extent.width.div_ceil(WORKGROUP_SIZE),
+            extent.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        let mut views = frame.views.borrow_mut();
+        views.push(input);
+        views.push(output);
+    }
+}
+
+impl HotReload for SsaoPass {
+    type Pipelines = Vec<Arc<ComputePipeline>>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Vec<Arc<ComputePipeline>> {
+        self.shader_ids
+            .iter()
+            .map(|id| {
+                ComputePipeline::new(
+                    self.pipeline_layout.device().clone(),
+                    shader_registry.get(*id),
+                    &self.pipeline_layout,
+                )
+            })
+            .collect()
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Vec<Arc<ComputePipeline>> {
+        &mut self.pipelines
+    }
+}
+
+// Deterministic, so the noise is the same every run. xorshift32, mapped to
+// [0, 1)
+fn _random(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    (*state >> 8) as f32 / (1 << 24) as f32
+}
+
+// Points in the +z hemisphere, more of them close to the center so nearby
+// occluders count for more
+fn _kernel() -> [Vec4; KERNEL_SIZE] {
+    let mut state = 0x9e3779b9;
+    std::array::from_fn(|i| {
+        let direction = Vec3::new(
+            _random(&mut state) * 2.0 - 1.0,
+            _random(&mut state) * 2.0 - 1.0,
+            _random(&mut state),
+        )
+        .normalize_or_zero();
+        let t = i as f32 / KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        (direction * _random(&mut state) * scale).extend(0.0)
+    })
+}
+
+// Random rotations around the surface normal, as xy directions mapped from
+// [-1, 1] to bytes
+fn _noise_pixels() -> Vec<u8> {
+    let mut state = 0x85ebca6b;
+    (0..NOISE_SIZE * NOISE_SIZE)
+        .flat_map(|_| {
+            let x = _random(&mut state);
+            let y = _random(&mut state);
+            [(x * 255.0) as u8, (y * 255.0) as u8, 0, 255]
+        })
+        .collect()
+}
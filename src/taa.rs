@@ -0,0 +1,409 @@
+use ash::vk;
+use glam::{Mat4, Vec2};
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    opaque_blend_attachment, CommandBuffer, ComputePipeline, DescriptorPool, DescriptorSet,
+    DescriptorSetLayout, DescriptorWriter, Device, HotReload, Image, ImageConfig, ImageHandle,
+    ImageUsage, ImageView, MemoryPriority, PipelineLayout, RenderGraph, Sampler, ShaderId,
+    ShaderKind, ShaderRegistry,
+};
+
+// Format of the main pass' second color attachment while TAA is on, see
+// `TaaPass`
+pub const MOTION_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+// Color attachments of the main pass, and of every pipeline drawing in it,
+// with `blend` for the draw image. Pipelines that don't `write_motion` leave
+// the motion vectors alone, e.g. blended surfaces, which keep the motion of
+// what's behind them
+pub fn main_pass_attachments(
+    blend: vk::PipelineColorBlendAttachmentState,
+    motion_vectors: bool,
+    write_motion: bool,
+) -> Vec<(vk::Format, vk::PipelineColorBlendAttachmentState)> {
+    let mut attachments = vec![(vk::Format::R16G16B16A16_SFLOAT, blend)];
+    if motion_vectors {
+        let motion_blend = if write_motion {
+            opaque_blend_attachment()
+        } else {
+            vk::PipelineColorBlendAttachmentState {
+                color_write_mask: vk::ColorComponentFlags::empty(),
+                ..opaque_blend_attachment()
+            }
+        };
+        attachments.push((MOTION_FORMAT, motion_blend));
+    }
+    attachments
+}
+
+const HISTORY_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Matches `local_size_x` and `local_size_y` in taa_resolve.glsl
+const WORKGROUP_SIZE: u32 = 8;
+
+// Length of the jitter sequence. More samples resolve finer detail but take
+// longer to converge
+const JITTER_SAMPLES: u64 = 8;
+
+// Matches `Constants` in taa_resolve.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaConstants {
+    texel_size: [f32; 2],
+    blend: f32,
+    reset: u32,
+}
+
+struct TaaFrame {
+    descriptor_set: DescriptorSet,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// Temporal anti-aliasing. The projection is jittered by a different subpixel
+// offset every frame, and the main pass writes each pixel's screen space
+// motion since the last frame. The resolve pass then blends the frame into
+// the last frame's result, reprojected by the motion and clamped to the
+// frame's neighborhood so stale history doesn't ghost. Each frame in flight
+// writes its own history image and reads the previous frame's
+pub struct TaaPass {
+    shader_id: ShaderId,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+    frames: Vec<TaaFrame>,
+    frame_number: u64,
+    // Unjittered, for the motion vectors
+    view_proj: Mat4,
+    previous_view_proj: Mat4,
+    // False after a reset until the next frame starts
+    history_valid: bool,
+    // Whether the frame being drawn blends in the previous frame's output
+    use_history: bool,
+    // How much of the current frame is blended into the history
+    blend: f32,
+}
+
+impl TaaPass {
+    pub fn new(
+        device: &Arc<Device>,
+        shader_registry: &mut ShaderRegistry,
+        shader_path: &str,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_id = shader_registry.load(shader_path, ShaderKind::Compute, "main");
+
+        // The history written this frame, then the frame, the previous
+        // history and the motion vectors
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let output_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::STORAGE_IMAGE)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let scene_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let history_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+            let motion_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::COMPUTE);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[
+                    output_binding,
+                    scene_binding,
+                    history_binding,
+                    motion_binding,
+                ],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<TaaConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            shader_registry.get(shader_id),
+            &pipeline_layout,
+        );
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            frames_in_flight as u32,
+            &[
+                (vk::DescriptorType::STORAGE_IMAGE, frames_in_flight as u32),
+                (
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    3 * frames_in_flight as u32,
+                ),
+            ],
+        );
+        let frames = descriptor_pool
+            .allocate(&vec![&*set_layout; frames_in_flight])
+            .into_vec()
+            .into_iter()
+            .map(|descriptor_set| TaaFrame {
+                descriptor_set,
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        Self {
+            shader_id,
+            pipeline_layout,
+            pipeline,
+            sampler: Sampler::clamped(device.clone()),
+            frames,
+            frame_number: 0,
+            view_proj: Mat4::IDENTITY,
+            previous_view_proj: Mat4::IDENTITY,
+            history_valid: false,
+            use_history: false,
+            blend: 0.1,
+        }
+    }
+
+    // Written by the main pass, at the draw image's size
+    pub fn create_motion_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Arc<Image> {
+        TaaPass::_create_image(
+            device,
+            allocator,
+            extent,
+            MOTION_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    // Like the draw images these are per frame and have to be recreated,
+    // followed by `reset`, when the extent changes
+    pub fn create_history_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+    ) -> Arc<Image> {
+        TaaPass::_create_image(
+            device,
+            allocator,
+            extent,
+            HISTORY_FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    fn _create_image(
+        device: &Arc<Device>,
+        allocator: &Arc<vma::Allocator>,
+        extent: vk::Extent3D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Arc<Image> {
+        Image::new(
+            device.clone(),
+            allocator.clone(),
+            ImageConfig::new(format, extent, usage).priority(MemoryPriority::High),
+        )
+    }
+
+    pub fn blend(&self) -> f32 {
+        self.blend
+    }
+
+    // Lower values are smoother but take longer to catch up with changes
+    pub fn set_blend(&mut self, blend: f32) {
+        self.blend = blend;
+    }
+
+    // Drops the history, e.g. after the images were recreated or TAA was
+    // off, so the next frame starts over from itself
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+    }
+
+    // Starts the next frame with the camera's unjittered view projection.
+    // Only called for frames drawn with TAA
+    pub fn advance(&mut self, view_proj: Mat4) {
+        self.frame_number += 1;
+        self.use_history = self.history_valid;
+        self.previous_view_proj = if self.use_history {
+            self.view_proj
+        } else {
+            view_proj
+        };
+        self.view_proj = view_proj;
+        self.history_valid = true;
+    }
+
+    pub fn previous_view_proj(&self) -> Mat4 {
+        self.previous_view_proj
+    }
+
+    // This frame's subpixel offset in normalized device coordinates, from a
+    // Halton (2, 3) sequence, which covers the pixel evenly
+    pub fn jitter(&self, extent: vk::Extent2D) -> Vec2 {
+        let i = self.frame_number % JITTER_SAMPLES + 1;
+        let offset = Vec2::new(_halton(i, 2), _halton(i, 3)) - 0.5;
+        offset * 2.0 / Vec2::new(extent.width as f32, extent.height as f32)
+    }
+
+    // Adds the resolve pass to the graph, blending `scene` into `history`,
+    // the previous frame's output, and writing `output`. The history is
+    // ignored, and may be anything, for the first frame after a `reset`.
+    // Returns `output`
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        scene: (ImageHandle, Arc<ImageView>),
+        motion: (ImageHandle, Arc<ImageView>),
+        history: (ImageHandle, Arc<ImageView>),
+        output: (ImageHandle, Arc<ImageView>),
+    ) -> ImageHandle {
+        let reset = !self.use_history;
+
+        // Without history the scene is bound in its place, so nothing is read
+        // from an image that may not have been written
+        let history = if reset { scene.clone() } else { history };
+
+        let mut pass = graph
+            .add_pass("taa resolve")
+            .read_image(scene.0, ImageUsage::Sampled)
+            .read_image(motion.0, ImageUsage::Sampled)
+            .write_image(output.0, ImageUsage::Storage);
+        if !reset {
+            pass = pass.read_image(history.0, ImageUsage::Sampled);
+        }
+        let output_handle = output.0;
+        pass.record(move |cmd| {
+            self._record(cmd, frame_index, scene.1, motion.1, history.1, output.1);
+        });
+
+        output_handle
+    }
+
+    fn _record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame_index: usize,
+        scene: Arc<ImageView>,
+        motion: Arc<ImageView>,
+        history: Arc<ImageView>,
+        output: Arc<ImageView>,
+    ) {
+        let frame = &self.frames[frame_index];
+        let reset = !self.use_history;
+
+        {
+            let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+            writer
+                .write_images(
+                    &frame.descriptor_set,
+                    0,
+                    0,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    None,
+                    &[(&output, vk::ImageLayout::GENERAL)],
+                )
+                .write_image(
+                    &frame.descriptor_set,
+                    &self.sampler,
+                    &scene,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_image(
+                    &frame.descriptor_set,
+                    &self.sampler,
+                    &history,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    2,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_image(
+                    &frame.descriptor_set,
+                    &self.sampler,
+                    &motion,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    3,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                );
+            writer.flush();
+        }
+
+        let extent = output.image().extent();
+        let constants = TaaConstants {
+            texel_size: [1.0 / extent.width as f32, 1.0 / extent.height as f32],
+            blend: self.blend,
+            reset: reset as u32,
+        };
+
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            &[&frame.descriptor_set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &constants,
+        );
+        cmd_buf.dispatch(
+            extent.width.div_ceil(WORKGROUP_SIZE),
+            extent.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        *frame.views.borrow_mut() = vec![scene, motion, history, output];
+    }
+}
+
+impl HotReload for TaaPass {
+    type Pipelines = Arc<ComputePipeline>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Arc<ComputePipeline> {
+        ComputePipeline::new(
+            self.pipeline_layout.device().clone(),
+            shader_registry.get(self.shader_id),
+            &self.pipeline_layout,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Arc<ComputePipeline> {
+        &mut self.pipeline
+    }
+}
+
+// The index'th element of the Halton sequence in the given base, in [0, 1)
+fn _halton(mut index: u64, base: u64) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
@@ -0,0 +1,234 @@
+use ash::vk;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::gpu::{Device, Image, ImageView, Sampler, UploadQueue};
+use crate::texture_file::{decode_to_rgba8, load_image_file, TextureData};
+
+// How 8-bit texel values are interpreted. Color textures are sRGB, while data
+// like normal maps and roughness are linear
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn rgba8_format(&self) -> vk::Format {
+        match self {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+pub struct Texture {
+    image: Arc<Image>,
+    view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+    color_space: ColorSpace,
+}
+
+impl Texture {
+    pub fn image(&self) -> &Arc<Image> {
+        &self.image
+    }
+
+    pub fn view(&self) -> &Arc<ImageView> {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn format(&self) -> vk::Format {
+        *self.image.format()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum TextureKey {
+    Path(PathBuf, ColorSpace),
+    // SHA-256 of the pixels and their size, for textures that don't come from
+    // a file. Collisions aren't a practical concern, unlike with a 64-bit hash
+    Content([u8; 32], u32, u32, ColorSpace),
+}
+
+// Uploads each texture once and hands out shared references to it, so
+// materials using the same image share the same GPU texture. A file or pixel
+// buffer loaded as both sRGB and linear is uploaded once for each
+pub struct TextureCache {
+    device: Arc<Device>,
+    sampler: Arc<Sampler>,
+    textures: HashMap<TextureKey, Arc<Texture>>,
+}
+
+impl TextureCache {
+    pub fn new(device: Arc<Device>, sampler: Arc<Sampler>) -> Self {
+        Self {
+            device,
+            sampler,
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>, color_space: ColorSpace) -> Option<&Arc<Texture>> {
+        let key = TextureKey::Path(path.as_ref().to_path_buf(), color_space);
+        self.textures.get(&key)
+    }
+
+    // KTX2 and DDS files are uploaded as stored, with all of their mip levels.
+    // Other image files are loaded with the `image` crate and expanded to RGBA8.
+    // Fails if the file can't be loaded or the device can't use its format
+    pub fn load(
+        &mut self,
+        uploads: &UploadQueue,
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+    ) -> Result<Arc<Texture>, String> {
+        let path = path.as_ref();
+        let key = TextureKey::Path(path.to_path_buf(), color_space);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let data = load_image_file(path, color_space)?;
+        let texture = self._upload_data(uploads, data, path, color_space)?;
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    // Uploads a new version of a file's texture, decoded with
+    // `load_image_file`, and replaces the cached one with it. Returns the new
+    // texture and the one it replaced, if the file was loaded before. Users of
+    // the old texture keep it until they switch over. Fails, keeping the old
+    // texture, if the device can't use the new one's format
+    pub fn replace(
+        &mut self,
+        uploads: &UploadQueue,
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        data: TextureData,
+    ) -> Result<(Arc<Texture>, Option<Arc<Texture>>), String> {
+        let path = path.as_ref();
+        let texture = self._upload_data(uploads, data, path, color_space)?;
+        let key = TextureKey::Path(path.to_path_buf(), color_space);
+        let old_texture = self.textures.insert(key, texture.clone());
+        Ok((texture, old_texture))
+    }
+
+    // For RGBA8 pixels that are already in memory, e.g. embedded in a model.
+    // Identical pixels are only uploaded once
+    pub fn load_rgba8(
+        &mut self,
+        uploads: &UploadQueue,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+    ) -> Arc<Texture> {
+        assert!(
+            pixels.len() == (width * height * 4) as usize,
+            "pixel data doesn't match the texture size"
+        );
+
+        let key = TextureKey::Content(Sha256::digest(pixels).into(), width, height, color_space);
+        if let Some(texture) = self.textures.get(&key) {
+            return texture.clone();
+        }
+
+        let texture = self._upload(
+            uploads,
+            &[pixels],
+            color_space.rgba8_format(),
+            width,
+            height,
+            color_space,
+        );
+        self.textures.insert(key, texture.clone());
+        texture
+    }
+
+    // Drops textures that nothing outside of the cache is using anymore. The
+    // caller is responsible for making sure the GPU is done with them
+    pub fn remove_unused(&mut self) -> usize {
+        let len = self.textures.len();
+        self.textures.retain(|_, x| Arc::strong_count(x) > 1);
+        len - self.textures.len()
+    }
+
+    // Formats the device can sample, compressed or not, are used as stored.
+    // Other compressed formats are decoded to RGBA8 where possible
+    fn _supported_texture_data(
+        &self,
+        data: TextureData,
+        path: &Path,
+    ) -> Result<TextureData, String> {
+        let physical_device = self.device.physical_device();
+        if physical_device.supports_sampled_format(data.format) {
+            return Ok(data);
+        }
+
+        match decode_to_rgba8(&data) {
+            Some(decoded) if physical_device.supports_sampled_format(decoded.format) => Ok(decoded),
+            _ => Err(format!(
+                "texture format {:?} in {} isn't supported by the device",
+                data.format,
+                path.display()
+            )),
+        }
+    }
+
+    fn _upload_data(
+        &self,
+        uploads: &UploadQueue,
+        data: TextureData,
+        path: &Path,
+        color_space: ColorSpace,
+    ) -> Result<Arc<Texture>, String> {
+        let data = self._supported_texture_data(data, path)?;
+        Ok(self._upload(
+            uploads,
+            &data.levels(),
+            data.format,
+            data.width,
+            data.height,
+            color_space,
+        ))
+    }
+
+    fn _upload(
+        &self,
+        uploads: &UploadQueue,
+        levels: &[&[u8]],
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+    ) -> Arc<Texture> {
+        let image = uploads.upload_image_levels(levels, format, width, height);
+        let view = image.get_default_view(vk::ImageAspectFlags::COLOR);
+
+        Arc::new(Texture {
+            image,
+            view,
+            sampler: self.sampler.clone(),
+            color_space,
+        })
+    }
+}
@@ -0,0 +1,591 @@
+use ash::vk;
+use ddsfile::{D3DFormat, Dds, DxgiFormat};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::texture_cache::ColorSpace;
+
+// Texture data as stored in a KTX2 or DDS file, with every mip level starting
+// with the full size image. Levels are tightly packed
+pub struct TextureData {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn levels(&self) -> Vec<&[u8]> {
+        self.levels.iter().map(|x| x.as_slice()).collect()
+    }
+}
+
+// Whether `path` is a container that stores GPU formats and mip levels, as
+// opposed to a regular image file
+pub fn is_texture_file(path: &Path) -> bool {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(extension) => {
+            extension.eq_ignore_ascii_case("ktx2") || extension.eq_ignore_ascii_case("dds")
+        }
+        None => false,
+    }
+}
+
+// KTX2 files record whether their format is sRGB, and so do most DDS files
+// with a DX10 header. `color_space` is only used for the DDS files that leave
+// it out
+pub fn load_texture_file(path: &Path, color_space: ColorSpace) -> Result<TextureData, String> {
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("ktx2") => {
+            let bytes = std::fs::read(path)
+                .map_err(|error| format!("failed to read texture {}: {}", path.display(), error))?;
+            parse_ktx2(&bytes).map_err(|error| format!("{} in {}", error, path.display()))
+        }
+        Some("dds") => {
+            let file = File::open(path)
+                .map_err(|error| format!("failed to open texture {}: {}", path.display(), error))?;
+            parse_dds(BufReader::new(file), color_space)
+                .map_err(|error| format!("{} in {}", error, path.display()))
+        }
+        _ => Err(format!("not a KTX2 or DDS file: {}", path.display())),
+    }
+}
+
+// Any image file. KTX2 and DDS files are read as stored, and other formats are
+// loaded with the `image` crate and expanded to RGBA8 with a single level
+pub fn load_image_file(path: &Path, color_space: ColorSpace) -> Result<TextureData, String> {
+    if is_texture_file(path) {
+        return load_texture_file(path, color_space);
+    }
+
+    let image_buffer = image::open(path)
+        .map_err(|error| format!("failed to load texture {}: {}", path.display(), error))?
+        .to_rgba8();
+    Ok(TextureData {
+        format: color_space.rgba8_format(),
+        width: image_buffer.width(),
+        height: image_buffer.height(),
+        levels: vec![image_buffer.into_raw()],
+    })
+}
+
+// A 2D KTX2 texture, with zstd supercompressed levels decompressed. Basis
+// Universal and zlib supercompression aren't supported
+pub fn parse_ktx2(bytes: &[u8]) -> Result<TextureData, String> {
+    let reader = ktx2::Reader::new(bytes)
+        .map_err(|error| format!("failed to parse KTX2 file: {}", error))?;
+    let header = reader.header();
+
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err("only 2D KTX2 textures are supported".to_string());
+    }
+
+    // KTX2 stores the VkFormat directly, Basis Universal files have none
+    let format = match header.format {
+        Some(format) => vk::Format::from_raw(format.0.get() as i32),
+        None => return Err("KTX2 file without a vkFormat".to_string()),
+    };
+
+    let levels = match header.supercompression_scheme {
+        None => reader.levels().map(|x| x.to_vec()).collect(),
+        Some(ktx2::SupercompressionScheme::Zstandard) => reader
+            .levels()
+            .map(_decompress_zstd)
+            .collect::<Result<_, _>>()?,
+        Some(scheme) => {
+            return Err(format!("unsupported KTX2 supercompression {:?}", scheme));
+        }
+    };
+
+    Ok(TextureData {
+        format,
+        width: header.pixel_width,
+        height: header.pixel_height,
+        levels,
+    })
+}
+
+fn _decompress_zstd(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoder = ruzstd::StreamingDecoder::new(&mut data)
+        .map_err(|error| format!("failed to read zstd level: {}", error))?;
+    let mut level = vec![];
+    decoder
+        .read_to_end(&mut level)
+        .map_err(|error| format!("failed to decompress zstd level: {}", error))?;
+    Ok(level)
+}
+
+// A DDS texture with every mip level of its first layer
+pub fn parse_dds(reader: impl Read, color_space: ColorSpace) -> Result<TextureData, String> {
+    let dds = Dds::read(reader).map_err(|error| format!("failed to parse DDS file: {}", error))?;
+
+    let format = _dds_format(&dds, color_space).ok_or("unsupported DDS format")?;
+
+    let width = dds.get_width();
+    let height = dds.get_height();
+
+    // The first layer holds every mip level back to back
+    let data = dds
+        .get_data(0)
+        .map_err(|error| format!("failed to read DDS data: {}", error))?;
+
+    let mut levels = vec![];
+    let mut offset = 0;
+    for level in 0..dds.get_num_mipmap_levels() {
+        let size = level_size(format, (width >> level).max(1), (height >> level).max(1));
+        let level_data = data
+            .get(offset..offset + size)
+            .ok_or("truncated DDS file")?;
+        levels.push(level_data.to_vec());
+        offset += size;
+    }
+
+    Ok(TextureData {
+        format,
+        width,
+        height,
+        levels,
+    })
+}
+
+// DX10 headers have separate sRGB and UNORM formats, and only the typeless
+// ones fall back to `color_space`. Legacy headers can't tell them apart
+fn _dds_format(dds: &Dds, color_space: ColorSpace) -> Option<vk::Format> {
+    let srgb = color_space == ColorSpace::Srgb;
+    let pick = |srgb_format, unorm_format| if srgb { srgb_format } else { unorm_format };
+
+    // `get_dxgi_format` also maps legacy DXTn formats, always to sRGB, so only
+    // DX10 headers are read as DXGI formats
+    if let Some(header10) = &dds.header10 {
+        let format = match header10.dxgi_format {
+            DxgiFormat::BC1_UNorm_sRGB => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            DxgiFormat::BC1_UNorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            DxgiFormat::BC1_Typeless => pick(
+                vk::Format::BC1_RGBA_SRGB_BLOCK,
+                vk::Format::BC1_RGBA_UNORM_BLOCK,
+            ),
+            DxgiFormat::BC2_UNorm_sRGB => vk::Format::BC2_SRGB_BLOCK,
+            DxgiFormat::BC2_UNorm => vk::Format::BC2_UNORM_BLOCK,
+            DxgiFormat::BC2_Typeless => {
+                pick(vk::Format::BC2_SRGB_BLOCK, vk::Format::BC2_UNORM_BLOCK)
+            }
+            DxgiFormat::BC3_UNorm_sRGB => vk::Format::BC3_SRGB_BLOCK,
+            DxgiFormat::BC3_UNorm => vk::Format::BC3_UNORM_BLOCK,
+            DxgiFormat::BC3_Typeless => {
+                pick(vk::Format::BC3_SRGB_BLOCK, vk::Format::BC3_UNORM_BLOCK)
+            }
+            DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => vk::Format::BC4_UNORM_BLOCK,
+            DxgiFormat::BC4_SNorm => vk::Format::BC4_SNORM_BLOCK,
+            DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => vk::Format::BC5_UNORM_BLOCK,
+            DxgiFormat::BC5_SNorm => vk::Format::BC5_SNORM_BLOCK,
+            DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => vk::Format::BC6H_UFLOAT_BLOCK,
+            DxgiFormat::BC6H_SF16 => vk::Format::BC6H_SFLOAT_BLOCK,
+            DxgiFormat::BC7_UNorm_sRGB => vk::Format::BC7_SRGB_BLOCK,
+            DxgiFormat::BC7_UNorm => vk::Format::BC7_UNORM_BLOCK,
+            DxgiFormat::BC7_Typeless => {
+                pick(vk::Format::BC7_SRGB_BLOCK, vk::Format::BC7_UNORM_BLOCK)
+            }
+            DxgiFormat::R8G8B8A8_UNorm_sRGB => vk::Format::R8G8B8A8_SRGB,
+            DxgiFormat::R8G8B8A8_UNorm => vk::Format::R8G8B8A8_UNORM,
+            DxgiFormat::R8G8B8A8_Typeless => {
+                pick(vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM)
+            }
+            DxgiFormat::B8G8R8A8_UNorm_sRGB => vk::Format::B8G8R8A8_SRGB,
+            DxgiFormat::B8G8R8A8_UNorm => vk::Format::B8G8R8A8_UNORM,
+            DxgiFormat::B8G8R8A8_Typeless => {
+                pick(vk::Format::B8G8R8A8_SRGB, vk::Format::B8G8R8A8_UNORM)
+            }
+            _ => return None,
+        };
+        return Some(format);
+    }
+
+    // Legacy DDS files without the DX10 header
+    let format = match dds.get_d3d_format()? {
+        D3DFormat::DXT1 => pick(
+            vk::Format::BC1_RGBA_SRGB_BLOCK,
+            vk::Format::BC1_RGBA_UNORM_BLOCK,
+        ),
+        D3DFormat::DXT2 | D3DFormat::DXT3 => {
+            pick(vk::Format::BC2_SRGB_BLOCK, vk::Format::BC2_UNORM_BLOCK)
+        }
+        D3DFormat::DXT4 | D3DFormat::DXT5 => {
+            pick(vk::Format::BC3_SRGB_BLOCK, vk::Format::BC3_UNORM_BLOCK)
+        }
+        D3DFormat::A8B8G8R8 => pick(vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM),
+        D3DFormat::A8R8G8B8 => pick(vk::Format::B8G8R8A8_SRGB, vk::Format::B8G8R8A8_UNORM),
+        _ => return None,
+    };
+    Some(format)
+}
+
+// Size in bytes of one tightly packed level, only for the formats DDS files
+// are loaded as
+fn level_size(format: vk::Format, width: u32, height: u32) -> usize {
+    let blocks = (width.div_ceil(4) * height.div_ceil(4)) as usize;
+    match format {
+        vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => blocks * 8,
+        vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK => blocks * 16,
+        _ => (width * height * 4) as usize,
+    }
+}
+
+// Decodes one 4x4 block into its texels, row by row
+type BlockDecoder = fn(&[u8]) -> [[u8; 4]; 16];
+
+// Decodes BC1-BC5 textures to RGBA8 for devices that can't sample them.
+// Returns None for other formats, e.g. BC6H, BC7 and ASTC, which are only
+// usable on devices that support them
+pub fn decode_to_rgba8(texture: &TextureData) -> Option<TextureData> {
+    let (decode_block, block_size, format): (BlockDecoder, usize, vk::Format) = match texture.format
+    {
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGBA_UNORM_BLOCK => {
+            (_decode_bc1, 8, vk::Format::R8G8B8A8_UNORM)
+        }
+        vk::Format::BC1_RGB_SRGB_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => {
+            (_decode_bc1, 8, vk::Format::R8G8B8A8_SRGB)
+        }
+        vk::Format::BC2_UNORM_BLOCK => (_decode_bc2, 16, vk::Format::R8G8B8A8_UNORM),
+        vk::Format::BC2_SRGB_BLOCK => (_decode_bc2, 16, vk::Format::R8G8B8A8_SRGB),
+        vk::Format::BC3_UNORM_BLOCK => (_decode_bc3, 16, vk::Format::R8G8B8A8_UNORM),
+        vk::Format::BC3_SRGB_BLOCK => (_decode_bc3, 16, vk::Format::R8G8B8A8_SRGB),
+        vk::Format::BC4_UNORM_BLOCK => (_decode_bc4, 8, vk::Format::R8G8B8A8_UNORM),
+        vk::Format::BC5_UNORM_BLOCK => (_decode_bc5, 16, vk::Format::R8G8B8A8_UNORM),
+        _ => return None,
+    };
+
+    let mut levels = vec![];
+    for (level, data) in texture.levels.iter().enumerate() {
+        let width = (texture.width >> level).max(1) as usize;
+        let height = (texture.height >> level).max(1) as usize;
+        let blocks_x = width.div_ceil(4);
+
+        let mut pixels = vec![0; width * height * 4];
+        for (index, block) in data.chunks_exact(block_size).enumerate() {
+            let block_x = (index % blocks_x) * 4;
+            let block_y = (index / blocks_x) * 4;
+            for (texel_index, texel) in decode_block(block).iter().enumerate() {
+                let x = block_x + texel_index % 4;
+                let y = block_y + texel_index / 4;
+                if x < width && y < height {
+                    let offset = (y * width + x) * 4;
+                    pixels[offset..offset + 4].copy_from_slice(texel);
+                }
+            }
+        }
+        levels.push(pixels);
+    }
+
+    Some(TextureData {
+        format,
+        width: texture.width,
+        height: texture.height,
+        levels,
+    })
+}
+
+fn _rgb565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1f) as u32;
+    let g = ((color >> 5) & 0x3f) as u32;
+    let b = (color & 0x1f) as u32;
+    [
+        (r * 255 / 31) as u8,
+        (g * 255 / 63) as u8,
+        (b * 255 / 31) as u8,
+    ]
+}
+
+// The 8 byte color block shared by BC1-BC3. Only BC1 has the 3 color mode
+// with transparent black
+fn _decode_color_block(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let c0 = _rgb565(color0).map(|x| x as u32);
+    let c1 = _rgb565(color1).map(|x| x as u32);
+    let mix = |a: u32, b: u32, wa: u32, wb: u32| ((a * wa + b * wb) / (wa + wb)) as u8;
+
+    let mut palette = [[0, 0, 0, 255]; 4];
+    palette[0] = [c0[0] as u8, c0[1] as u8, c0[2] as u8, 255];
+    palette[1] = [c1[0] as u8, c1[1] as u8, c1[2] as u8, 255];
+    if color0 > color1 || !allow_transparent {
+        for i in 0..3 {
+            palette[2][i] = mix(c0[i], c1[i], 2, 1);
+            palette[3][i] = mix(c0[i], c1[i], 1, 2);
+        }
+    } else {
+        for i in 0..3 {
+            palette[2][i] = mix(c0[i], c1[i], 1, 1);
+        }
+        palette[3] = [0, 0, 0, 0];
+    }
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (i * 2)) & 0x3) as usize];
+    }
+    texels
+}
+
+// The 8 byte interpolated single channel block used by BC3-BC5
+fn _decode_channel_block(block: &[u8]) -> [u8; 16] {
+    let a0 = block[0] as u32;
+    let a1 = block[1] as u32;
+    let mut index_bytes = [0; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((indices >> (i * 3)) & 0x7) as usize];
+    }
+    values
+}
+
+fn _decode_bc1(block: &[u8]) -> [[u8; 4]; 16] {
+    _decode_color_block(block, true)
+}
+
+fn _decode_bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = _decode_color_block(&block[8..], false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = ((alpha >> (i * 4)) & 0xf) as u8 * 17;
+    }
+    texels
+}
+
+fn _decode_bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = _decode_channel_block(&block[..8]);
+    let mut texels = _decode_color_block(&block[8..], false);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+// Single channel formats sample as (r, 0, 0, 1)
+fn _decode_bc4(block: &[u8]) -> [[u8; 4]; 16] {
+    _decode_channel_block(block).map(|r| [r, 0, 0, 255])
+}
+
+fn _decode_bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let red = _decode_channel_block(&block[..8]);
+    let green = _decode_channel_block(&block[8..]);
+    let mut texels = [[0, 0, 0, 255]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[0] = red[i];
+        texel[1] = green[i];
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, NewD3dParams, NewDxgiParams};
+
+    const KTX2_MAGIC: [u8; 12] = [
+        0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+    ];
+
+    // A minimal KTX2 file with an empty data format descriptor, the level
+    // index and then each level's data
+    fn _ktx2_file(
+        format: u32,
+        width: u32,
+        height: u32,
+        face_count: u32,
+        supercompression_scheme: u32,
+        levels: &[&[u8]],
+    ) -> Vec<u8> {
+        let index_size = 24 * levels.len();
+        let dfd_offset = 80 + index_size;
+        let mut level_offset = dfd_offset + 4;
+
+        let mut bytes = KTX2_MAGIC.to_vec();
+        for value in [
+            format,
+            1,
+            width,
+            height,
+            0,
+            0,
+            face_count,
+            levels.len() as u32,
+            supercompression_scheme,
+            dfd_offset as u32,
+            4,
+            0,
+            0,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 16]);
+
+        for level in levels {
+            for value in [level_offset, level.len(), level.len()] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            level_offset += level.len();
+        }
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        for level in levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    // A zstd frame holding `data` in a single uncompressed block
+    fn _zstd_raw_frame(data: &[u8]) -> Vec<u8> {
+        assert!(data.len() < 256);
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0x20, data.len() as u8];
+        let block_header = 1 | ((data.len() as u32) << 3);
+        frame.extend_from_slice(&block_header.to_le_bytes()[..3]);
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn parse_ktx2_levels() {
+        let level0 = (0..16).collect::<Vec<u8>>();
+        let level1 = [16, 17, 18, 19];
+        let format = vk::Format::R8G8B8A8_UNORM.as_raw() as u32;
+        let bytes = _ktx2_file(format, 2, 2, 1, 0, &[&level0, &level1]);
+
+        let texture = parse_ktx2(&bytes).unwrap();
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.levels, vec![level0, level1.to_vec()]);
+    }
+
+    #[test]
+    fn parse_ktx2_decompresses_zstd_levels() {
+        let level = (0..32).collect::<Vec<u8>>();
+        let format = vk::Format::BC1_RGBA_SRGB_BLOCK.as_raw() as u32;
+        let bytes = _ktx2_file(format, 8, 4, 1, 2, &[&_zstd_raw_frame(&level)]);
+
+        let texture = parse_ktx2(&bytes).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!(texture.levels, vec![level]);
+    }
+
+    #[test]
+    fn parse_ktx2_rejects_unsupported_files() {
+        let format = vk::Format::R8G8B8A8_UNORM.as_raw() as u32;
+        let level = [0; 4];
+
+        let cube = _ktx2_file(format, 1, 1, 6, 0, &[&level]);
+        assert!(parse_ktx2(&cube).is_err());
+        let basis = _ktx2_file(0, 1, 1, 1, 0, &[&level]);
+        assert!(parse_ktx2(&basis).is_err());
+        let zlib = _ktx2_file(format, 1, 1, 1, 3, &[&level]);
+        assert!(parse_ktx2(&zlib).is_err());
+        assert!(parse_ktx2(&KTX2_MAGIC).is_err());
+    }
+
+    #[test]
+    fn parse_dds_dx10_levels() {
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height: 8,
+            width: 8,
+            depth: None,
+            format: DxgiFormat::BC1_UNorm_sRGB,
+            mipmap_levels: Some(2),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        })
+        .unwrap();
+        dds.data = (0..40).collect();
+        let mut bytes = vec![];
+        dds.write(&mut bytes).unwrap();
+
+        // The DX10 format says it's sRGB, whatever the color space
+        let texture = parse_dds(bytes.as_slice(), ColorSpace::Linear).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!((texture.width, texture.height), (8, 8));
+        assert_eq!(texture.levels.len(), 2);
+        assert_eq!(texture.levels[0], (0..32).collect::<Vec<u8>>());
+        assert_eq!(texture.levels[1], (32..40).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn parse_dds_legacy_uses_color_space() {
+        let mut dds = Dds::new_d3d(NewD3dParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: D3DFormat::DXT5,
+            mipmap_levels: None,
+            caps2: None,
+        })
+        .unwrap();
+        dds.data = vec![0; 16];
+        let mut bytes = vec![];
+        dds.write(&mut bytes).unwrap();
+
+        let srgb = parse_dds(bytes.as_slice(), ColorSpace::Srgb).unwrap();
+        assert_eq!(srgb.format, vk::Format::BC3_SRGB_BLOCK);
+        let linear = parse_dds(bytes.as_slice(), ColorSpace::Linear).unwrap();
+        assert_eq!(linear.format, vk::Format::BC3_UNORM_BLOCK);
+    }
+
+    #[test]
+    fn parse_dds_rejects_truncated_data() {
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height: 8,
+            width: 8,
+            depth: None,
+            format: DxgiFormat::BC1_UNorm,
+            mipmap_levels: Some(1),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        })
+        .unwrap();
+        dds.data.truncate(16);
+        let mut bytes = vec![];
+        dds.write(&mut bytes).unwrap();
+
+        assert!(parse_dds(bytes.as_slice(), ColorSpace::Srgb).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use ash::vk;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::gpu::{
+    rendering_attachment, CommandBuffer, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+    DescriptorWriter, Device, GraphicsPipeline, HotReload, ImageView, PipelineLayout,
+    RenderingConfig, Sampler, ShaderId, ShaderKind, ShaderModule, ShaderRegistry, SurfaceFormat,
+    SurfaceFormatPreference,
+};
+
+// Values match the operators in tonemap.glsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TonemapOperator {
+    // Clamps anything brighter than white
+    None = 0,
+    Reinhard = 1,
+    #[default]
+    Aces = 2,
+}
+
+impl TonemapOperator {
+    pub fn next(&self) -> Self {
+        match self {
+            TonemapOperator::None => TonemapOperator::Reinhard,
+            TonemapOperator::Reinhard => TonemapOperator::Aces,
+            TonemapOperator::Aces => TonemapOperator::None,
+        }
+    }
+}
+
+// Matches `Constants` in tonemap.glsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapConstants {
+    exposure: f32,
+    operator: u32,
+    encoding: u32,
+    paper_white: f32,
+    bloom_intensity: f32,
+}
+
+struct TonemapFrame {
+    descriptor_set: DescriptorSet,
+    // Views the frame's commands use, kept alive until the frame is recorded
+    // again
+    views: RefCell<Vec<Arc<ImageView>>>,
+}
+
+// Fullscreen pass that maps the HDR draw image into the swapchain image with
+// a tonemap operator, and encodes it for the swapchain's color space
+pub struct TonemapPass {
+    shader_ids: Vec<ShaderId>,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    color_format: vk::Format,
+    sampler: Arc<Sampler>,
+    frames: Vec<TonemapFrame>,
+    operator: TonemapOperator,
+    exposure: f32,
+    // Brightness in nits of 1.0 on HDR swapchains
+    paper_white: f32,
+}
+
+impl TonemapPass {
+    pub fn new(
+        device: &Arc<Device>,
+        shader_registry: &mut ShaderRegistry,
+        vertex_shader_path: &str,
+        fragment_shader_path: &str,
+        color_format: vk::Format,
+        frames_in_flight: usize,
+    ) -> Self {
+        let shader_ids = vec![
+            shader_registry.load(vertex_shader_path, ShaderKind::Vertex, "main"),
+            shader_registry.load(fragment_shader_path, ShaderKind::Fragment, "main"),
+        ];
+
+        let set_layout = {
+            let mut builder = DescriptorSetLayout::builder();
+
+            let image_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+            let bloom_binding = builder
+                .binding()
+                .descriptor(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage(vk::ShaderStageFlags::FRAGMENT);
+
+            builder.build(
+                device.clone(),
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+                &[image_binding, bloom_binding],
+            )
+        };
+
+        let push_constant_ranges = PipelineLayout::push_constant_ranges()
+            .range::<TonemapConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline_layout =
+            device.get_pipeline_layout(std::slice::from_ref(&set_layout), &push_constant_ranges);
+
+        let pipeline = TonemapPass::_create_pipeline(
+            device,
+            &shader_registry.modules(&shader_ids),
+            &pipeline_layout,
+            color_format,
+        );
+
+        let descriptor_pool = DescriptorPool::new(
+            device.clone(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            frames_in_flight as u32,
+            &[(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                2 * frames_in_flight as u32,
+            )],
+        );
+        let frames = descriptor_pool
+            .allocate(&vec![&*set_layout; frames_in_flight])
+            .into_vec()
+            .into_iter()
+            .map(|descriptor_set| TonemapFrame {
+                descriptor_set,
+                views: RefCell::new(vec![]),
+            })
+            .collect();
+
+        Self {
+            shader_ids,
+            pipeline_layout,
+            pipeline,
+            color_format,
+            sampler: Sampler::clamped(device.clone()),
+            frames,
+            operator: TonemapOperator::default(),
+            exposure: 1.0,
+            paper_white: 200.0,
+        }
+    }
+
+    fn _create_pipeline(
+        device: &Arc<Device>,
+        shader_modules: &[Arc<ShaderModule>],
+        pipeline_layout: &PipelineLayout,
+        color_format: vk::Format,
+    ) -> Arc<GraphicsPipeline> {
+        GraphicsPipeline::builder()
+            .shader_modules(shader_modules)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .color_formats(&[color_format])
+            .build(device.clone(), pipeline_layout)
+    }
+
+    pub fn operator(&self) -> TonemapOperator {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: TonemapOperator) {
+        self.operator = operator;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    // Linear scale applied before the operator
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    // For when the swapchain format changed. Returns the old pipeline, which
+    // frames in flight may still be using
+    pub fn set_color_format(
+        &mut self,
+        shader_registry: &ShaderRegistry,
+        color_format: vk::Format,
+    ) -> Arc<GraphicsPipeline> {
+        self.color_format = color_format;
+        self.recreate_pipelines(shader_registry)
+    }
+
+    pub fn color_format(&self) -> vk::Format {
+        self.color_format
+    }
+
+    // Must be recorded after the frame's fence has been waited on, with
+    // `input` and `bloom` in SHADER_READ_ONLY_OPTIMAL and `output` in
+    // COLOR_ATTACHMENT_OPTIMAL. `bloom` is scaled by its intensity and added
+    // to `input`. Covers all of `output`
+    pub fn record(
+        &self,
+        cmd_buf: &CommandBuffer,
+        frame_index: usize,
+        input: Arc<ImageView>,
+        (bloom, bloom_intensity): (Arc<ImageView>, f32),
+        output: Arc<ImageView>,
+        surface_format: SurfaceFormat,
+    ) {
+        let frame = &self.frames[frame_index];
+        let output_extent = output.image().extent();
+        let extent = vk::Extent2D {
+            width: output_extent.width,
+            height: output_extent.height,
+        };
+
+        {
+            let mut writer = DescriptorWriter::new(self.pipeline_layout.device().clone());
+            writer
+                .write_image(
+                    &frame.descriptor_set,
+                    &self.sampler,
+                    &input,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    0,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                )
+                .write_image(
+                    &frame.descriptor_set,
+                    &self.sampler,
+                    &bloom,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                );
+            writer.flush();
+        }
+
+        let color_attachment = rendering_attachment(
+            &output,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AttachmentLoadOp::DONT_CARE,
+            vk::AttachmentStoreOp::STORE,
+            vk::ClearValue::default(),
+        );
+
+        let encoding = match surface_format.preference() {
+            SurfaceFormatPreference::Hdr10 => 2,
+            SurfaceFormatPreference::ScRgb => 3,
+            SurfaceFormatPreference::Sdr if surface_format.is_srgb() => 0,
+            SurfaceFormatPreference::Sdr => 1,
+        };
+        let constants = TonemapConstants {
+            exposure: self.exposure,
+            operator: self.operator as u32,
+            encoding,
+            paper_white: self.paper_white,
+            bloom_intensity,
+        };
+
+        cmd_buf
+            .begin_rendering(RenderingConfig::new(extent).color_attachments(&[color_attachment]));
+        cmd_buf.bind_pipeline(self.pipeline.as_ref());
+        cmd_buf.set_full_viewport_scissor(extent);
+        cmd_buf.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            &self.pipeline_layout,
+            0,
+            &[&frame.descriptor_set],
+        );
+        cmd_buf.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        cmd_buf.draw(3, 1, 0, 0);
+        cmd_buf.end_rendering();
+
+        *frame.views.borrow_mut() = vec![input, bloom, output];
+    }
+}
+
+impl HotReload for TonemapPass {
+    type Pipelines = Arc<GraphicsPipeline>;
+
+    fn create_pipelines(&self, shader_registry: &ShaderRegistry) -> Arc<GraphicsPipeline> {
+        TonemapPass::_create_pipeline(
+            self.pipeline_layout.device(),
+            &shader_registry.modules(&self.shader_ids),
+            &self.pipeline_layout,
+            self.color_format,
+        )
+    }
+
+    fn pipelines_mut(&mut self) -> &mut Arc<GraphicsPipeline> {
+        &mut self.pipeline
+    }
+}
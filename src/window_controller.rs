@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use winit::dpi::PhysicalSize;
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    // Takes over the monitor and switches it to the selected video mode
+    Exclusive,
+    // A borderless window covering the monitor at its current resolution
+    Borderless,
+}
+
+// Fullscreen and monitor state of the main window. Changing modes resizes the
+// window, and the resulting `Resized` event recreates the swapchain like any
+// other resize
+pub struct WindowController {
+    window: Arc<Window>,
+    // None follows whichever monitor the window is on
+    monitor: Option<MonitorHandle>,
+    // None picks the monitor's largest mode
+    video_mode: Option<VideoMode>,
+    fullscreen: Option<FullscreenMode>,
+    // Used by `toggle_fullscreen`
+    last_fullscreen: FullscreenMode,
+}
+
+impl WindowController {
+    pub fn new(window: Arc<Window>) -> Self {
+        Self {
+            window,
+            monitor: None,
+            video_mode: None,
+            fullscreen: None,
+            last_fullscreen: FullscreenMode::Borderless,
+        }
+    }
+
+    pub fn window(&self) -> &Arc<Window> {
+        &self.window
+    }
+
+    pub fn monitors(&self) -> Vec<MonitorHandle> {
+        self.window.available_monitors().collect()
+    }
+
+    // The selected monitor, or the one the window is on
+    pub fn monitor(&self) -> Option<MonitorHandle> {
+        self.monitor
+            .clone()
+            .or_else(|| self.window.current_monitor())
+            .or_else(|| self.window.primary_monitor())
+    }
+
+    // Clears the selected video mode, which belongs to the previous monitor.
+    // Reapplies the fullscreen mode if there is one
+    pub fn select_monitor(&mut self, monitor: Option<MonitorHandle>) {
+        self.monitor = monitor;
+        self.video_mode = None;
+        if let Some(mode) = self.fullscreen {
+            self.set_fullscreen(Some(mode));
+        }
+    }
+
+    // Largest first, and the highest refresh rate first for each size
+    pub fn video_modes(&self) -> Vec<VideoMode> {
+        let Some(monitor) = self.monitor() else {
+            return vec![];
+        };
+        let mut modes: Vec<VideoMode> = monitor.video_modes().collect();
+        modes.sort_by_key(|x| {
+            let size = x.size();
+            std::cmp::Reverse((
+                size.width * size.height,
+                x.refresh_rate_millihertz(),
+                x.bit_depth(),
+            ))
+        });
+        modes
+    }
+
+    // Picks the selected monitor's fastest mode with the given resolution for
+    // exclusive fullscreen. Returns false if there isn't one
+    pub fn select_resolution(&mut self, width: u32, height: u32) -> bool {
+        let mode = self
+            .video_modes()
+            .into_iter()
+            .find(|x| x.size() == PhysicalSize::new(width, height));
+        let Some(mode) = mode else {
+            return false;
+        };
+        self.video_mode = Some(mode);
+        if self.fullscreen == Some(FullscreenMode::Exclusive) {
+            self.set_fullscreen(Some(FullscreenMode::Exclusive));
+        }
+        true
+    }
+
+    pub fn fullscreen(&self) -> Option<FullscreenMode> {
+        self.fullscreen
+    }
+
+    // None returns to a window. Exclusive fullscreen falls back to borderless
+    // when the monitor doesn't report any video modes
+    pub fn set_fullscreen(&mut self, mode: Option<FullscreenMode>) {
+        let fullscreen = match mode {
+            None => None,
+            Some(FullscreenMode::Exclusive) => {
+                let video_mode = self
+                    .video_mode
+                    .clone()
+                    .or_else(|| self.video_modes().into_iter().next());
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => Some(Fullscreen::Borderless(self.monitor())),
+                }
+            }
+            Some(FullscreenMode::Borderless) => Some(Fullscreen::Borderless(self.monitor())),
+        };
+        self.window.set_fullscreen(fullscreen);
+
+        self.fullscreen = mode;
+        if let Some(mode) = mode {
+            self.last_fullscreen = mode;
+        }
+    }
+
+    // Switches between a window and the last fullscreen mode used
+    pub fn toggle_fullscreen(&mut self) {
+        let mode = match self.fullscreen {
+            Some(_) => None,
+            None => Some(self.last_fullscreen),
+        };
+        self.set_fullscreen(mode);
+    }
+
+    pub fn inner_size(&self) -> PhysicalSize<u32> {
+        self.window.inner_size()
+    }
+}